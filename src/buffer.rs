@@ -1,32 +1,45 @@
 use lockfree_object_pool::{LinearObjectPool, LinearOwnedReusable};
 use ringbuffer::{AllocRingBuffer, RingBuffer};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::process;
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
-/// Buffer is a mutable string + a reference to owning buffer pool.
-pub type Buffer = LinearOwnedReusable<String>;
+/// Buffer is a mutable byte string + a reference to owning buffer pool.
+/// Command output is not guaranteed to be valid UTF-8, hence bytes.
+pub type Buffer = LinearOwnedReusable<Vec<u8>>;
 
 /// Thread-safe buffer pool.
+/// Buffers grown above the capacity limit are shrunk when returned to the
+/// pool, so that a single long line doesn't pin memory forever.
 pub struct BufferPool {
-    obj_pool: Arc<LinearObjectPool<String>>,
+    obj_pool: Arc<LinearObjectPool<Vec<u8>>>,
 }
 
 impl BufferPool {
     /// Construct buffer pool.
-    pub fn new() -> Self {
+    /// `max_capacity` is the limit on capacity of pooled buffers.
+    pub fn new(max_capacity: Option<usize>) -> Self {
         BufferPool {
             obj_pool: Arc::new(LinearObjectPool::new(
-                || String::new(),
-                |s| {
+                || Vec::new(),
+                move |s| {
                     s.clear();
+                    if let Some(max_capacity) = max_capacity {
+                        s.shrink_to(max_capacity);
+                    }
                 },
             )),
         }
     }
 
     // Allocate empty buffer.
-    // Returns a wrapped String.
-    // Returned string has zero size (but typically non-zero capacity).
-    // When returned struct is dropped, the string is automatically
+    // Returns a wrapped Vec<u8>.
+    // Returned vector has zero size (but typically non-zero capacity).
+    // When returned struct is dropped, the vector is automatically
     // returned to the pool.
     pub fn alloc(&self) -> Buffer {
         self.obj_pool.pull_owned()
@@ -34,14 +47,22 @@ impl BufferPool {
 }
 
 /// Thread-safe bounded buffer queue.
+/// When queue is full, oldest buffers are dropped, unless queue has spill
+/// file, where newer buffers are stored until reader catches up, or is
+/// blocking, so that writer waits until reader catches up.
 pub struct BufferQueue {
     state: Mutex<BufferQueueState>, // protected state
-    cond: Condvar,
+    cond: Condvar,                  // signaled when queue becomes non-empty or drained
+    space_cond: Condvar,            // signaled when blocked writer may proceed
 }
 
 struct BufferQueueState {
     ringbuf: AllocRingBuffer<Buffer>,
+    spill: Option<Spill>,
+    blocking: bool,
     closed: bool,
+    drained: bool,
+    dropped: u64,
 }
 
 impl BufferQueue {
@@ -50,12 +71,33 @@ impl BufferQueue {
         BufferQueue {
             state: Mutex::new(BufferQueueState {
                 ringbuf: AllocRingBuffer::new(queue_size),
+                spill: None,
+                blocking: false,
                 closed: false,
+                drained: false,
+                dropped: 0,
             }),
             cond: Condvar::new(),
+            space_cond: Condvar::new(),
         }
     }
 
+    /// Construct queue with specified maximum size in memory, which
+    /// stores overflow in temporary file in given directory.
+    pub fn with_spill(queue_size: usize, spill_dir: &Path) -> io::Result<Self> {
+        let queue = BufferQueue::new(queue_size);
+        queue.state.lock().unwrap().spill = Some(Spill::create(spill_dir)?);
+        Ok(queue)
+    }
+
+    /// Choose what write() does when queue is full: wait until reader
+    /// frees space, or drop oldest buffer.
+    /// Disabling blocking wakes up blocked writes.
+    pub fn set_blocking(&self, blocking: bool) {
+        self.state.lock().unwrap().blocking = blocking;
+        self.space_cond.notify_all();
+    }
+
     /// Read buffer from queue.
     /// Blocks until queue is non-empty or is empty and closed.
     /// Returns None if queue is empty and closed.
@@ -63,35 +105,82 @@ impl BufferQueue {
         loop {
             let mut locked_state = self.state.lock().unwrap();
 
-            match locked_state.ringbuf.dequeue() {
-                Some(buf) => return Some(buf),
-                None => {
-                    if locked_state.closed {
-                        // Queue empty and closed.
-                        return None;
-                    } else {
-                        // Queue empty, but not closed.
-                        drop(self.cond.wait(locked_state).unwrap());
-                        continue;
-                    }
-                }
-            };
+            if let Some(buf) = locked_state.ringbuf.dequeue() {
+                self.space_cond.notify_all();
+                return Some(buf);
+            }
+            // Memory part is empty, continue with spilled buffers,
+            // which are newer.
+            if let Some(buf) = locked_state.read_spill() {
+                return Some(buf);
+            }
+
+            if locked_state.closed {
+                // Queue empty and closed.
+                // Wake up wait_drained().
+                locked_state.drained = true;
+                self.cond.notify_all();
+                return None;
+            } else {
+                // Queue empty, but not closed.
+                drop(self.cond.wait(locked_state).unwrap());
+                continue;
+            }
         }
     }
 
     /// Write buffer to queue.
     /// Wakes up blocked reads.
+    /// If queue is blocking, waits while it's full and not closed.
     pub fn write(&self, buf: Buffer) {
         let mut locked_state = self.state.lock().unwrap();
+        while locked_state.blocking && !locked_state.closed && locked_state.ringbuf.is_full() {
+            locked_state = self.space_cond.wait(locked_state).unwrap();
+        }
+        let state = &mut *locked_state;
 
-        if locked_state.closed {
+        if state.closed {
             return;
         }
 
-        locked_state.ringbuf.enqueue(buf);
+        // Once queue started spilling, newer buffers go to spill file until
+        // it's drained, to keep order.
+        if let Some(spill) = &mut state.spill {
+            if state.ringbuf.is_full() || spill.count > 0 {
+                if spill.push(&buf).is_ok() {
+                    self.cond.notify_all();
+                    return;
+                }
+                // Can't write to disk, fall back to dropping.
+                state.dropped += spill.count as u64;
+                spill.clear();
+            }
+        }
+
+        if state.ringbuf.is_full() {
+            state.dropped += 1;
+        }
+        state.ringbuf.enqueue(buf);
         self.cond.notify_all();
     }
 
+    /// Number of buffers waiting in queue, including spilled ones.
+    pub fn pending(&self) -> usize {
+        let locked_state = self.state.lock().unwrap();
+        locked_state.ringbuf.len() + locked_state.spill.as_ref().map_or(0, |spill| spill.count)
+    }
+
+    /// Maximum number of buffers in queue.
+    pub fn capacity(&self) -> usize {
+        self.state.lock().unwrap().ringbuf.capacity()
+    }
+
+    /// Number of buffers overwritten because queue was full (and couldn't
+    /// be spilled).
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+
     /// Closes queue.
     pub fn close(&self) {
         let mut locked_state = self.state.lock().unwrap();
@@ -103,5 +192,114 @@ impl BufferQueue {
         locked_state.closed = true;
 
         self.cond.notify_all();
+        self.space_cond.notify_all();
+    }
+
+    /// Wait until reader consumes all buffers from closed queue.
+    /// Returns false if timeout expired before that.
+    pub fn wait_drained(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut locked_state = self.state.lock().unwrap();
+
+        while !locked_state.drained {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            locked_state = self
+                .cond
+                .wait_timeout(locked_state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        true
+    }
+}
+
+impl BufferQueueState {
+    fn read_spill(&mut self) -> Option<Buffer> {
+        let spill = self.spill.as_mut()?;
+        if spill.count == 0 {
+            return None;
+        }
+        match spill.pop() {
+            Ok(buf) => Some(buf),
+            Err(_) => {
+                // Can't read back from disk, spilled buffers are lost.
+                self.dropped += spill.count as u64;
+                spill.clear();
+                None
+            }
+        }
+    }
+}
+
+/// Overflow of BufferQueue, stored on disk (--spill).
+/// Buffers are appended to file as length-prefixed records and read back
+/// in the same order; file is truncated when all of them are read.
+struct Spill {
+    file: File,
+    buf_pool: BufferPool,
+    write_pos: u64,
+    read_pos: u64,
+    count: usize,
+}
+
+impl Spill {
+    /// Create temporary file in directory.
+    /// File is removed right away, so that it doesn't outlive us.
+    fn create(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(format!("reclog-spill.{}.tmp", process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        fs::remove_file(&path)?;
+
+        Ok(Spill {
+            file,
+            buf_pool: BufferPool::new(None),
+            write_pos: 0,
+            read_pos: 0,
+            count: 0,
+        })
+    }
+
+    fn push(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(buf.len() + 8);
+        record.extend_from_slice(&(buf.len() as u64).to_le_bytes());
+        record.extend_from_slice(buf);
+        self.file.write_all_at(&record, self.write_pos)?;
+
+        self.write_pos += record.len() as u64;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> io::Result<Buffer> {
+        let mut len = [0; 8];
+        self.file.read_exact_at(&mut len, self.read_pos)?;
+        let mut buf = self.buf_pool.alloc();
+        buf.resize(u64::from_le_bytes(len) as usize, 0);
+        self.file.read_exact_at(&mut buf, self.read_pos + 8)?;
+
+        self.read_pos += 8 + buf.len() as u64;
+        self.count -= 1;
+        if self.count == 0 {
+            self.clear();
+        }
+        Ok(buf)
+    }
+
+    /// Forget all records and free disk space.
+    fn clear(&mut self) {
+        _ = self.file.set_len(0);
+        self.write_pos = 0;
+        self.read_pos = 0;
+        self.count = 0;
     }
 }
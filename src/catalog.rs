@@ -0,0 +1,355 @@
+use crate::format::{HEADER_TIME_FMT, is_footer, is_header, parse_field};
+use crate::stats::format_size;
+use chrono::{DateTime, FixedOffset};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// How many bytes to read when looking for the first or the last line;
+/// files with longer lines are not recognized.
+const MAX_LINE_SIZE: u64 = 65536;
+
+/// Recording found by `reclog list`: either a log file with header,
+/// or a session from --meta-file.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Name of the file relative to scanned directory, with "#N" suffix
+    /// for N-th session in a meta file with several sessions.
+    pub id: String,
+    /// Path of the file where session was found.
+    pub path: PathBuf,
+    /// Log file, if known.
+    pub output: Option<PathBuf>,
+    pub command: Option<String>,
+    pub start_time: Option<DateTime<FixedOffset>>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+    /// Exit code or signal name.
+    pub status: Option<String>,
+    pub output_bytes: Option<u64>,
+    pub output_lines: Option<u64>,
+    pub peak_memory: Option<u64>,
+    /// Header line of log file, if any.
+    pub header: Option<String>,
+}
+
+impl Session {
+    pub fn duration(&self) -> Option<chrono::TimeDelta> {
+        Some(self.end_time? - self.start_time?)
+    }
+}
+
+/// Find recordings in directory (not recursively).
+/// Log files described by a meta file are reported once, using metadata.
+/// Sessions are sorted by start time.
+pub fn scan(dir: &Path) -> io::Result<Vec<Session>> {
+    let mut meta_sessions = Vec::new();
+    let mut log_sessions = Vec::new();
+
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let id = entry.file_name().to_string_lossy().into_owned();
+        // Unreadable files are skipped like any other unrelated files.
+        if let Ok(mut sessions) = read_meta(&id, &entry.path()) {
+            meta_sessions.append(&mut sessions);
+        } else if let Ok(Some(session)) = read_log(&id, &entry.path()) {
+            log_sessions.push(session);
+        }
+    }
+
+    let described: Vec<PathBuf> = meta_sessions
+        .iter()
+        .filter_map(|session| session.output.as_ref())
+        .filter_map(|path| fs::canonicalize(dir.join(path)).ok())
+        .collect();
+    log_sessions.retain(|session| {
+        fs::canonicalize(&session.path).map_or(true, |path| !described.contains(&path))
+    });
+
+    let mut sessions = meta_sessions;
+    sessions.append(&mut log_sessions);
+    sessions.sort_by_key(|session| (session.start_time.is_none(), session.start_time));
+    Ok(sessions)
+}
+
+/// Find recording by ID printed by `reclog list`, or by path.
+pub fn find(dir: &Path, id: &str) -> io::Result<Option<Session>> {
+    let (name, index) = match id.rsplit_once('#') {
+        Some((name, index)) => match index.parse::<usize>() {
+            Ok(index) => (name, Some(index)),
+            Err(_) => (id, None),
+        },
+        None => (id, None),
+    };
+    let path = dir.join(name);
+
+    if let Ok(sessions) = read_meta(name, &path) {
+        let count = sessions.len();
+        return Ok(match index {
+            Some(n) => sessions.into_iter().nth(n.wrapping_sub(1)),
+            None if count > 1 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("file has {} sessions, add \"#N\" to ID", count),
+                ));
+            }
+            None => sessions.into_iter().next(),
+        });
+    }
+    if index.is_some() {
+        return Ok(None);
+    }
+    read_log(name, &path)
+}
+
+/// Print sessions as a table.
+pub fn write_table<W: Write>(sessions: &[Session], mut output: W) -> io::Result<()> {
+    let rows: Vec<[String; 6]> = sessions
+        .iter()
+        .map(|session| {
+            [
+                session.id.clone(),
+                session
+                    .start_time
+                    .map_or("-".into(), |ts| ts.format("%F %T").to_string()),
+                session.duration().map_or("-".into(), format_duration),
+                session.status.clone().unwrap_or("-".into()),
+                session.output_bytes.map_or("-".into(), format_size),
+                session.command.clone().unwrap_or("-".into()),
+            ]
+        })
+        .collect();
+
+    let titles = ["ID", "START", "DURATION", "STATUS", "SIZE", "COMMAND"];
+    let mut widths = titles.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in std::iter::once(titles.map(String::from)).chain(rows) {
+        let mut line = String::new();
+        for (n, cell) in row.iter().enumerate() {
+            if n == row.len() - 1 {
+                // Don't pad the last column.
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{:<width$}  ", cell, width = widths[n]));
+            }
+        }
+        writeln!(output, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Print all known details of session, one per line.
+pub fn write_details<W: Write>(session: &Session, mut output: W) -> io::Result<()> {
+    let mut fields: Vec<(&str, String)> = vec![
+        ("id", session.id.clone()),
+        ("file", session.path.to_string_lossy().into()),
+    ];
+    if let Some(path) = &session.output {
+        fields.push(("output", path.to_string_lossy().into()));
+    }
+    if let Some(command) = &session.command {
+        fields.push(("command", command.clone()));
+    }
+    if let Some(header) = &session.header {
+        for (name, key) in [("HOST", "host"), ("USER", "user"), ("CWD", "cwd")] {
+            if let Some(value) = parse_field(header, name) {
+                fields.push((key, unescape(header, value)));
+            }
+        }
+    }
+    if let Some(ts) = session.start_time {
+        fields.push(("start", ts.format(HEADER_TIME_FMT).to_string()));
+    }
+    if let Some(ts) = session.end_time {
+        fields.push(("end", ts.format(HEADER_TIME_FMT).to_string()));
+    }
+    if let Some(duration) = session.duration() {
+        fields.push(("duration", format_duration(duration)));
+    }
+    if let Some(status) = &session.status {
+        fields.push(("status", status.clone()));
+    }
+    if let Some(bytes) = session.output_bytes {
+        fields.push(("size", format!("{} ({} bytes)", format_size(bytes), bytes)));
+    }
+    if let Some(lines) = session.output_lines {
+        fields.push(("lines", lines.to_string()));
+    }
+    if let Some(memory) = session.peak_memory {
+        fields.push(("peak memory", format_size(memory)));
+    }
+
+    for (name, value) in fields {
+        writeln!(output, "{:<12} {}", format!("{}:", name), value)?;
+    }
+    Ok(())
+}
+
+/// Read sessions from --meta-file, one per line.
+/// Fails if file doesn't look like a meta file.
+fn read_meta(id: &str, path: &Path) -> io::Result<Vec<Session>> {
+    let first_line = read_first_line(path)?;
+    if !first_line.starts_with("{\"start_time\":") {
+        return Err(io::Error::from(io::ErrorKind::InvalidData));
+    }
+
+    let mut sessions = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.starts_with('{') {
+            continue;
+        }
+        let time = |key| {
+            json_field(&line, key)
+                .and_then(|ts| DateTime::parse_from_str(&ts, HEADER_TIME_FMT).ok())
+        };
+        let number = |key| json_field(&line, key).and_then(|n| n.parse::<u64>().ok());
+        sessions.push(Session {
+            id: id.into(),
+            path: path.into(),
+            output: json_field(&line, "output").map(PathBuf::from),
+            command: json_field(&line, "cmd"),
+            start_time: time("start_time"),
+            end_time: time("end_time"),
+            status: json_field(&line, "status"),
+            output_bytes: number("output_bytes"),
+            output_lines: number("output_lines"),
+            peak_memory: number("peak_memory"),
+            header: None,
+        });
+    }
+
+    // Several sessions appended to the same meta file.
+    if sessions.len() > 1 {
+        for (n, session) in sessions.iter_mut().enumerate() {
+            session.id = format!("{}#{}", id, n + 1);
+        }
+    }
+    Ok(sessions)
+}
+
+/// Read session from log file with header (--header or --header-json).
+/// Returns None if file has no header.
+fn read_log(id: &str, path: &Path) -> io::Result<Option<Session>> {
+    let header = read_first_line(path)?;
+    if !is_header(&header) {
+        return Ok(None);
+    }
+    let header = header.trim_end().to_string();
+
+    let field = |line: &str, name| parse_field(line, name).map(|value| unescape(line, value));
+    let time = |line: &str| {
+        field(line, "TIME").and_then(|ts| DateTime::parse_from_str(&ts, HEADER_TIME_FMT).ok())
+    };
+
+    // Footer is written when command exits, so it's missing if reclog
+    // was killed or recording is still in progress.
+    let last_line = read_last_line(path)?;
+    let footer = is_footer(&last_line).then_some(last_line.trim_end());
+
+    Ok(Some(Session {
+        id: id.into(),
+        path: path.into(),
+        output: Some(path.into()),
+        command: field(&header, "CMD"),
+        start_time: time(&header),
+        end_time: footer.and_then(time),
+        status: footer.and_then(|footer| field(footer, "STATUS")),
+        output_bytes: Some(fs::metadata(path)?.len()),
+        output_lines: None,
+        peak_memory: None,
+        header: Some(header),
+    }))
+}
+
+fn read_first_line(path: &Path) -> io::Result<String> {
+    let mut line = Vec::new();
+    BufReader::new(File::open(path)?.take(MAX_LINE_SIZE)).read_until(b'\n', &mut line)?;
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+fn read_last_line(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(size.saturating_sub(MAX_LINE_SIZE)))?;
+
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let tail = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    let start = tail.iter().rposition(|&b| b == b'\n').map_or(0, |n| n + 1);
+    Ok(String::from_utf8_lossy(&tail[start..]).into_owned())
+}
+
+/// Get value of string or number field from one-line JSON object,
+/// e.g. `"cmd":"make"` or `"exit_code":2`. Returns None for null.
+fn json_field(line: &str, key: &str) -> Option<String> {
+    let key = format!("\"{}\":", key);
+    let rest = &line[line.find(&key)? + key.len()..];
+
+    if let Some(rest) = rest.strip_prefix('"') {
+        let mut escaped = false;
+        for (pos, ch) in rest.char_indices() {
+            match ch {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => return Some(unescape_json(&rest[..pos])),
+                _ => escaped = false,
+            }
+        }
+        return None;
+    }
+
+    let end = rest.find([',', '}']).unwrap_or(rest.len());
+    match rest[..end].trim() {
+        "null" => None,
+        value => Some(value.into()),
+    }
+}
+
+/// Unescape value returned by parse_field(), which is escaped only in
+/// JSON header.
+fn unescape(line: &str, value: &str) -> String {
+    if line.starts_with("# {") {
+        unescape_json(value)
+    } else {
+        value.into()
+    }
+}
+
+fn unescape_json(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                let ch = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32);
+                result.push(ch.unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            Some(ch) => result.push(ch),
+            None => {}
+        }
+    }
+    result
+}
+
+/// Format duration as "HH:MM:SS".
+fn format_duration(duration: chrono::TimeDelta) -> String {
+    let secs = duration.num_seconds().max(0);
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,197 @@
+use crate::error::SysError;
+use crate::rlimit;
+use crate::shim;
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+/// Memory limit of command (--memory-max), in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryMax(u64);
+
+impl FromStr for MemoryMax {
+    type Err = String;
+
+    /// Parse size with optional K, M, G, or T suffix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match rlimit::parse_limit(s)? {
+            Some(n) => Ok(MemoryMax(n)),
+            None => Err("memory limit can't be unlimited".into()),
+        }
+    }
+}
+
+/// CPU limit of command (--cpu-max), in CPUs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CpuMax(f64);
+
+/// Period of CPU bandwidth control, default of cgroup.
+const CPU_PERIOD_US: u64 = 100_000;
+
+impl FromStr for CpuMax {
+    type Err = String;
+
+    /// Parse number of CPUs, e.g. "0.5" or "2".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f64>() {
+            // Kernel requires quota of at least 1ms.
+            Ok(n) if n.is_finite() && n * CPU_PERIOD_US as f64 >= 1000.0 => Ok(CpuMax(n)),
+            _ => Err(format!(
+                "invalid CPU count '{}', expected number like 0.5 or 2",
+                s
+            )),
+        }
+    }
+}
+
+/// Transient cgroup v2 for command (--cgroup).
+/// Linux only.
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create cgroup next to the one reclog is running in, and apply limits.
+    ///
+    /// Cgroup v2 doesn't allow enabling controllers for children of a cgroup
+    /// with processes, so new cgroup is created as a sibling, where memory
+    /// and cpu controllers are usually already enabled (or can be enabled
+    /// if the subtree is delegated to us).
+    pub fn create(memory_max: Option<MemoryMax>, cpu_max: Option<CpuMax>) -> io::Result<Self> {
+        let mount = find_mount()?;
+        let current = current_path()?;
+
+        let parent = match Path::new(&current).parent() {
+            Some(parent) => mount.join(parent.strip_prefix("/").unwrap_or(parent)),
+            // Root cgroup may have both processes and controllers.
+            None => mount,
+        };
+
+        // Memory controller is needed for peak usage even without limit.
+        let mut controllers = vec!["memory"];
+        if cpu_max.is_some() {
+            controllers.push("cpu");
+        }
+        enable_controllers(&parent, &controllers)?;
+
+        let path = parent.join(format!("reclog-{}", process::id()));
+        fs::create_dir(&path)?;
+        let cgroup = Cgroup { path };
+
+        if let Err(err) = cgroup.set_limits(memory_max, cpu_max) {
+            _ = cgroup.remove();
+            return Err(err);
+        }
+
+        Ok(cgroup)
+    }
+
+    fn set_limits(&self, memory_max: Option<MemoryMax>, cpu_max: Option<CpuMax>) -> io::Result<()> {
+        if let Some(MemoryMax(bytes)) = memory_max {
+            self.write("memory.max", &bytes.to_string())?;
+            // Kill command instead of swapping it to death; swap controller
+            // may be disabled, so ignore errors.
+            _ = self.write("memory.swap.max", "0");
+        }
+        if let Some(CpuMax(cpus)) = cpu_max {
+            let quota = (cpus * CPU_PERIOD_US as f64) as u64;
+            self.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD_US))?;
+        }
+
+        Ok(())
+    }
+
+    /// Path to "cgroup.procs" file, for moving child into cgroup with
+    /// enter() after fork().
+    pub fn procs_path(&self) -> CString {
+        CString::new(self.path.join("cgroup.procs").as_os_str().as_bytes()).unwrap()
+    }
+
+    /// Peak memory usage of processes in cgroup, in bytes.
+    /// Requires Linux 5.19 or later.
+    pub fn peak_memory(&self) -> Option<u64> {
+        fs::read_to_string(self.path.join("memory.peak"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Remove cgroup.
+    /// Fails if some processes are still running in it, e.g. daemons
+    /// spawned by command.
+    pub fn remove(&self) -> io::Result<()> {
+        fs::remove_dir(&self.path)
+    }
+
+    fn write(&self, name: &str, value: &str) -> io::Result<()> {
+        fs::write(self.path.join(name), value)
+            .map_err(|err| Error::new(err.kind(), format!("can't write {}: {}", name, err)))
+    }
+}
+
+/// Move current process into cgroup, given path to its "cgroup.procs".
+/// Should be called in child after fork().
+pub fn enter(procs_path: &CString) -> Result<(), SysError> {
+    // Writing "0" moves the writing process.
+    shim::write_file(procs_path, b"0").map_err(|err| SysError("cgroup.procs", err))
+}
+
+/// Find mount point of cgroup v2 hierarchy.
+fn find_mount() -> io::Result<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    // Line looks like: "ID PARENT DEV ROOT MOUNTPOINT OPTIONS... - FSTYPE SOURCE ...".
+    for line in mountinfo.lines() {
+        let Some((fields, fs_fields)) = line.split_once(" - ") else {
+            continue;
+        };
+        if fs_fields.split(' ').next() == Some("cgroup2") {
+            if let Some(mount) = fields.split(' ').nth(4) {
+                return Ok(PathBuf::from(mount));
+            }
+        }
+    }
+
+    Err(Error::new(ErrorKind::NotFound, "cgroup v2 is not mounted"))
+}
+
+/// Get cgroup v2 path of current process, relative to mount point.
+fn current_path() -> io::Result<String> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+
+    // In v2 hierarchy, line looks like: "0::/PATH".
+    match cgroups.lines().find_map(|line| line.strip_prefix("0::")) {
+        Some(path) => Ok(path.into()),
+        None => Err(Error::new(
+            ErrorKind::NotFound,
+            "process is not in cgroup v2",
+        )),
+    }
+}
+
+/// Make sure that controllers are enabled for children of cgroup.
+fn enable_controllers(path: &Path, controllers: &[&str]) -> io::Result<()> {
+    let enabled = fs::read_to_string(path.join("cgroup.subtree_control"))?;
+
+    for controller in controllers {
+        if enabled.split_whitespace().any(|c| c == *controller) {
+            continue;
+        }
+        if let Err(err) = fs::write(
+            path.join("cgroup.subtree_control"),
+            format!("+{}", controller),
+        ) {
+            return Err(Error::new(
+                err.kind(),
+                format!("can't enable {} controller: {}", controller, err),
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+/// Build shell script that runs command and then --then commands one by
+/// one, in the same pty and output file.
+///
+/// Before each step, script prints section header, e.g.:
+/// "# STEP=[2/3] CMD=[make install]".
+/// In the end, it prints trailer with exit codes of all steps, e.g.:
+/// "# STEPS=[0,2,-]", where "-" means that step was skipped.
+///
+/// Unless `keep_going` is set, script stops on first failed step.
+/// Script exits with the code of first failed step, or 0.
+pub fn script(command: &[String], then: &[String], keep_going: bool) -> String {
+    let total = then.len() + 1;
+    let mut script = String::from("__reclog_steps=''\n__reclog_rc=0\n");
+
+    let steps = std::iter::once((command.join(" "), quote_argv(command))).chain(
+        then.iter()
+            .map(|cmd| (cmd.clone(), format!("(\n{}\n)", cmd))),
+    );
+
+    for (n, (display, code)) in steps.enumerate() {
+        let header = format!("# STEP=[{}/{}] CMD=[{}]", n + 1, total, display);
+        script.push_str(&format!("printf '%s\\n' {}\n", quote(&header)));
+        script.push_str(&code);
+        script.push('\n');
+        script.push_str(
+            "__reclog_code=$?\n\
+             __reclog_steps=\"$__reclog_steps,$__reclog_code\"\n\
+             [ $__reclog_rc -ne 0 ] || __reclog_rc=$__reclog_code\n",
+        );
+        if !keep_going && n + 1 < total {
+            let skipped = ",-".repeat(total - n - 1);
+            script.push_str(&format!(
+                "[ $__reclog_code -eq 0 ] || {{ \
+                 printf '# STEPS=[%s%s]\\n' \"${{__reclog_steps#,}}\" '{}'; \
+                 exit $__reclog_code; }}\n",
+                skipped
+            ));
+        }
+    }
+
+    script.push_str("printf '# STEPS=[%s]\\n' \"${__reclog_steps#,}\"\n");
+    script.push_str("exit $__reclog_rc\n");
+    script
+}
+
+/// Quote every argument for shell.
+fn quote_argv(argv: &[String]) -> String {
+    argv.iter()
+        .map(|arg| quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quote string for shell, using single quotes.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
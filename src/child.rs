@@ -0,0 +1,331 @@
+use crate::cgroup;
+use crate::error::SysError;
+use crate::rlimit::ResourceLimit;
+use crate::sched::{IoPriority, Niceness, OomScoreAdj};
+use crate::shim::{self, Fork};
+use crate::status::*;
+use crate::user::RunAs;
+use exec::Command;
+use libc::c_char;
+use rustix::io::{Errno, retry_on_intr};
+use rustix::process::{self, Pid, Signal, WaitOptions, WaitStatus};
+use std::ffi::CString;
+use std::io::{self, Write};
+use std::os::fd::{OwnedFd, RawFd};
+use std::ptr;
+use std::sync::Mutex;
+use std::time::Instant;
+use sysconf::raw::{SysconfVariable, sysconf};
+
+/// Child process attached to some kind of I/O channel (pty or pipes).
+/// Parent writes to child's input and reads child's output using
+/// duplicated descriptors.
+pub trait ChildProc: Send + Sync {
+    /// Duplicate descriptor for writing to child's stdin.
+    fn dup_input(&self) -> Result<OwnedFd, SysError>;
+
+    /// Duplicate descriptor for reading from child's stdout and stderr.
+    fn dup_output(&self) -> Result<OwnedFd, SysError>;
+
+    /// Propagate end-of-file to child's stdin.
+    /// The writer is one created for descriptor from dup_input().
+    fn send_eof(&self, input_writer: &mut dyn Write) -> io::Result<()>;
+
+    /// Fork child process, attach it to I/O channel, apply setup, and exec
+    /// command.
+    fn spawn_child(&self, command: &mut Command, setup: &ChildSetup) -> Result<(), SysError>;
+
+    /// Close parent's copy of child's side of I/O channel.
+    /// After that, reading child's output reports end of stream when child
+    /// and all its descendants close their descriptors.
+    fn release_child_side(&self) -> Result<(), SysError>;
+
+    /// Resize child's terminal according to current parent's tty.
+    fn resize_child(&self) -> Result<(), SysError>;
+
+    /// Make child's terminal smaller than parent's tty by given number of
+    /// rows, used by us (--status-line). Takes effect on next resize.
+    fn set_reserved_rows(&self, rows: u16);
+
+    /// Enable or disable echo of input in child's terminal (--echo).
+    /// Takes effect on spawn.
+    fn set_child_echo(&self, echo: bool);
+
+    /// Check if command has hidden input in its terminal, e.g. while it
+    /// reads a password.
+    fn input_hidden(&self) -> bool;
+
+    /// Get size of child's terminal, columns and rows, or None if child
+    /// has no terminal.
+    fn tty_size(&self) -> Option<(u16, u16)>;
+
+    /// If child's terminal was resized since previous call, get when it
+    /// happened and its new size, columns and rows.
+    fn take_resize(&self) -> Option<(Instant, u16, u16)>;
+
+    /// Send signal to child's process group.
+    fn kill_child(&self, sig: Signal) -> Result<(), SysError>;
+
+    /// Wait until spawned child exits.
+    fn wait_child(&self, wait_mode: ChildWait) -> Result<Option<WaitStatus>, SysError>;
+
+    /// Get child exit status.
+    fn child_status(&self) -> WaitStatus;
+
+    /// Get child PID, if it was spawned.
+    fn child_pid(&self) -> Option<Pid>;
+}
+
+/// Settings applied in child process between fork() and exec().
+#[derive(Default)]
+pub struct ChildSetup {
+    /// Path to "cgroup.procs" of cgroup to move into (--cgroup).
+    pub cgroup_procs: Option<CString>,
+    /// Resource limits (--rlimit).
+    pub rlimits: Vec<ResourceLimit>,
+    /// Scheduling priority (--nice).
+    pub nice: Option<Niceness>,
+    /// I/O scheduling class and priority (--ionice).
+    pub ionice: Option<IoPriority>,
+    /// OOM killer score adjustment (--oom-score-adj).
+    pub oom_score_adj: Option<OomScoreAdj>,
+    /// Run command as another user (--user).
+    pub run_as: Option<RunAs>,
+    /// Environment of command, as "NAME=VALUE" strings.
+    pub env: Vec<CString>,
+    /// Signal sent to command when we die (unless --no-deathsig).
+    /// Linux only.
+    pub death_signal: Option<Signal>,
+}
+
+impl ChildSetup {
+    /// Apply settings to current process.
+    /// Should be called in child after fork().
+    /// Credentials are changed last, because other settings may require
+    /// privileges. Death signal is set after that, because changing
+    /// credentials resets it.
+    fn apply(&self, parent_pid: Pid) -> Result<(), SysError> {
+        if let Some(procs_path) = &self.cgroup_procs {
+            cgroup::enter(procs_path)?;
+        }
+        for rlimit in &self.rlimits {
+            rlimit.apply()?;
+        }
+        if let Some(nice) = &self.nice {
+            nice.apply()?;
+        }
+        if let Some(ionice) = &self.ionice {
+            ionice.apply()?;
+        }
+        if let Some(oom_score_adj) = &self.oom_score_adj {
+            oom_score_adj.apply()?;
+        }
+        if let Some(run_as) = &self.run_as {
+            run_as.apply()?;
+        }
+        if let Some(sig) = self.death_signal {
+            if let Err(err) = shim::set_pdeathsig(sig) {
+                return Err(SysError("prctl(PR_SET_PDEATHSIG)", err));
+            }
+            // Parent could die before prctl(), then we were reparented.
+            if process::getppid() != Some(parent_pid) {
+                return Err(SysError("getppid()", Errno::SRCH));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Wait mode.
+#[derive(PartialEq)]
+pub enum ChildWait {
+    Hang,
+    NoHang,
+}
+
+/// Bookkeeping of child process state, shared by ChildProc implementations.
+pub struct Child {
+    state: Mutex<ChildState>,
+}
+
+struct ChildState {
+    pid: Option<Pid>,
+    last_status: Option<WaitStatus>,
+    final_status: Option<WaitStatus>,
+}
+
+impl Child {
+    pub fn new() -> Self {
+        Child {
+            state: Mutex::new(ChildState {
+                pid: None,
+                last_status: None,
+                final_status: None,
+            }),
+        }
+    }
+
+    /// Fork child process, run prepare function in child, apply setup,
+    /// and exec command.
+    /// The prepare function should only do setup syscalls which are safe
+    /// to call after fork().
+    pub fn spawn<F>(
+        &self,
+        command: &mut Command,
+        setup: &ChildSetup,
+        prepare_child: F,
+    ) -> Result<(), SysError>
+    where
+        F: Fn() -> Result<(), SysError>,
+    {
+        let mut locked_state = self.state.lock().unwrap();
+
+        if locked_state.pid.is_some() {
+            panic!("attempt to call spawn_child() twice");
+        }
+        let parent_pid = process::getpid();
+
+        // Prepared before fork(), because child can't allocate.
+        let envp: Vec<*const c_char> = setup
+            .env
+            .iter()
+            .map(|var| var.as_ptr())
+            .chain([ptr::null()])
+            .collect();
+
+        // SAFETY: we ensure that the child process does not run any code other
+        // than setup code from prepare_child() followed by exec(). Parent
+        // continues execution normally.
+        unsafe {
+            match shim::fork() {
+                Ok(Fork::Parent(pid)) => {
+                    locked_state.pid = Some(pid);
+                }
+                Ok(Fork::Child) => {
+                    // In case of error, use fast_exit() to avoid execution
+                    // of any registered exit handlers.
+                    if let Err(_) = prepare_child().and_then(|_| setup.apply(parent_pid)) {
+                        shim::fast_exit(EXIT_FAILURE);
+                    }
+                    shim::set_environ(&envp);
+
+                    // This will call execvp() and replace child process.
+                    _ = command.exec();
+                    shim::fast_exit(EXIT_COMMAND_FAILED);
+                }
+                Err(err) => {
+                    return Err(SysError("fork()", err));
+                }
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Run function while holding the lock.
+    /// Used to serialize operations on child.
+    pub fn with_lock<R, F: FnOnce() -> R>(&self, func: F) -> R {
+        let _locked_state = self.state.lock().unwrap();
+        func()
+    }
+
+    /// Send signal to child's process group.
+    /// After child was reaped, reports ESRCH; this happens when we keep
+    /// running after child exits (--subreaper).
+    pub fn kill(&self, sig: Signal) -> Result<(), SysError> {
+        let locked_state = self.state.lock().unwrap();
+
+        if !locked_state.pid.is_some() {
+            panic!("attempt to call kill_child() before spawn_child()");
+        }
+        if locked_state.final_status.is_some() {
+            // PID may be reused already.
+            return Err(SysError("kill()", Errno::SRCH));
+        }
+
+        if let Err(err) = process::kill_process_group(locked_state.pid.unwrap(), sig) {
+            return Err(SysError("kill()", err));
+        }
+
+        Ok(())
+    }
+
+    /// Wait until spawned child exits.
+    pub fn wait(&self, wait_mode: ChildWait) -> Result<Option<WaitStatus>, SysError> {
+        let mut locked_state = self.state.lock().unwrap();
+
+        if !locked_state.pid.is_some() {
+            panic!("attempt to call wait_child() before spawn_child()");
+        }
+        if let Some(final_status) = locked_state.final_status {
+            return Ok(Some(final_status));
+        }
+
+        let mut wait_opts = WaitOptions::UNTRACED | WaitOptions::CONTINUED;
+        if wait_mode == ChildWait::NoHang {
+            wait_opts |= WaitOptions::NOHANG;
+        }
+
+        loop {
+            let wait_status = match process::waitpid(locked_state.pid, wait_opts) {
+                Ok(Some((_, status))) => status,
+                Ok(None) => return Ok(None),
+                Err(Errno::INTR) => continue,
+                Err(err) => return Err(SysError("waitpid()", err)),
+            };
+
+            locked_state.last_status = Some(wait_status);
+            if wait_status.exited() || wait_status.signaled() {
+                locked_state.final_status = Some(wait_status);
+            }
+            return Ok(Some(wait_status));
+        }
+    }
+
+    /// Get child PID, if it was spawned.
+    pub fn pid(&self) -> Option<Pid> {
+        self.state.lock().unwrap().pid
+    }
+
+    /// Get child exit status.
+    pub fn status(&self) -> WaitStatus {
+        let locked_state = self.state.lock().unwrap();
+
+        if !locked_state.last_status.is_some() {
+            panic!("attempt to call child_status() before wait_child()");
+        }
+
+        locked_state.last_status.unwrap()
+    }
+}
+
+/// Close file descriptors except stdin/stdout/stderr.
+/// Should be called in child right before exec().
+pub fn close_extra_fds() -> Result<(), SysError> {
+    let max_fd = match sysconf(SysconfVariable::ScOpenMax) {
+        Ok(n) => n,
+        Err(_) => return Err(SysError("sysconf(_SC_OPEN_MAX)", Errno::NOTSUP)),
+    };
+    unsafe {
+        for fd in 3..=max_fd {
+            // SAFETY: this breaks invariants of opened OwnedFd, BorrowFd, etc.
+            // However, we call this function right before exec(), in the context
+            // where we guaranteedely have only one thread (after forking), so
+            // these broken invariants don't have a chance to have any effect.
+            shim::close_raw(fd as RawFd);
+        }
+    };
+
+    Ok(())
+}
+
+/// Create new session and become session leader.
+/// Should be called in child after fork().
+pub fn new_session() -> Result<(), SysError> {
+    if let Err(err) = retry_on_intr(|| process::setsid()) {
+        return Err(SysError("setsid()", err));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use chrono::{DateTime, Local, TimeDelta};
+use std::cell::Cell;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Source of current time for formatter, timeouts, and rate limiting.
+pub trait Clock {
+    /// Monotonic time, used for elapsed and delta timestamps and deadlines.
+    fn now(&self) -> Instant;
+
+    /// Wallclock time, used for wallclock timestamps, header, and footer.
+    fn wall(&self) -> DateTime<Local>;
+
+    /// Wait until given time passes.
+    fn sleep(&self, duration: Duration);
+}
+
+/// Real system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall(&self) -> DateTime<Local> {
+        Local::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Clock that doesn't depend on system time and is advanced explicitly,
+/// e.g. by delays from timing file. Wallclock time starts at given time,
+/// or at Unix epoch if it's unknown.
+pub struct VirtualClock {
+    origin: Instant,
+    start_time: Cell<Option<DateTime<Local>>>,
+    elapsed: Cell<Duration>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock {
+            origin: Instant::now(),
+            start_time: Cell::new(None),
+            elapsed: Cell::new(Duration::ZERO),
+        }
+    }
+
+    /// Restart clock from given wallclock time.
+    pub fn restart(&self, start_time: DateTime<Local>) {
+        self.start_time.set(Some(start_time));
+        self.elapsed.set(Duration::ZERO);
+    }
+
+    /// Check if wallclock time is known.
+    pub fn has_start_time(&self) -> bool {
+        self.start_time.get().is_some()
+    }
+
+    /// Move clock forward.
+    /// Returns false if resulting time can't be represented; the clock
+    /// isn't changed then.
+    pub fn advance(&self, delay: Duration) -> bool {
+        let Some(elapsed) = self.elapsed.get().checked_add(delay) else {
+            return false;
+        };
+        if self.origin.checked_add(elapsed).is_none() || self.wall_at(elapsed).is_none() {
+            return false;
+        }
+        self.elapsed.set(elapsed);
+        true
+    }
+
+    fn wall_at(&self, elapsed: Duration) -> Option<DateTime<Local>> {
+        self.wall_start()
+            .checked_add_signed(TimeDelta::from_std(elapsed).ok()?)
+    }
+
+    fn wall_start(&self) -> DateTime<Local> {
+        self.start_time
+            .get()
+            .unwrap_or(DateTime::UNIX_EPOCH.with_timezone(&Local))
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed.get()
+    }
+
+    fn wall(&self) -> DateTime<Local> {
+        // advance() doesn't go out of range, so fallback is never used.
+        self.wall_at(self.elapsed.get())
+            .unwrap_or_else(|| self.wall_start())
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Moment when timeout expires, measured by a clock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// Deadline after given period from now.
+    /// Returns None if period is so long that deadline is never reached.
+    pub fn after(clock: &dyn Clock, period: Duration) -> Option<Self> {
+        clock.now().checked_add(period).map(Deadline)
+    }
+
+    /// Check if deadline is reached.
+    pub fn expired(&self, clock: &dyn Clock) -> bool {
+        self.0 <= clock.now()
+    }
+
+    /// Time left until deadline, zero if it's reached.
+    pub fn remaining(&self, clock: &dyn Clock) -> Duration {
+        self.0.saturating_duration_since(clock.now())
+    }
+}
+
+/// Start of virtual clock, parsed from "YYYY-MM-DD HH:MM:SS[.FFF] +ZZZZ"
+/// (--virtual-time).
+#[derive(Clone, Copy, Debug)]
+pub struct VirtualTime(pub DateTime<Local>);
+
+impl FromStr for VirtualTime {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match DateTime::parse_from_str(s, "%F %T%.f %z") {
+            Ok(ts) => Ok(VirtualTime(ts.with_timezone(&Local))),
+            Err(_) => Err("expected YYYY-MM-DD HH:MM:SS +ZZZZ".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline() {
+        let clock = VirtualClock::new();
+        let deadline = Deadline::after(&clock, Duration::from_secs(5)).unwrap();
+        assert!(!deadline.expired(&clock));
+        assert_eq!(deadline.remaining(&clock), Duration::from_secs(5));
+
+        clock.sleep(Duration::from_secs(3));
+        assert!(!deadline.expired(&clock));
+        assert_eq!(deadline.remaining(&clock), Duration::from_secs(2));
+
+        clock.sleep(Duration::from_secs(2));
+        assert!(deadline.expired(&clock));
+        assert_eq!(deadline.remaining(&clock), Duration::ZERO);
+
+        assert!(Deadline::after(&clock, Duration::MAX).is_none());
+    }
+
+    #[test]
+    fn virtual_clock() {
+        let clock = VirtualClock::new();
+        assert!(!clock.has_start_time());
+        assert_eq!(clock.wall().timestamp(), 0);
+
+        let start: VirtualTime = "2024-01-01 00:00:00.5 +0000".parse().unwrap();
+        clock.restart(start.0);
+        assert!(clock.advance(Duration::from_millis(1500)));
+        assert_eq!(clock.wall().timestamp(), 1704067202);
+
+        // Out of range advance is rejected and doesn't move the clock.
+        let now = clock.now();
+        assert!(!clock.advance(Duration::from_secs(u64::MAX / 2)));
+        assert_eq!(clock.now(), now);
+        assert_eq!(clock.wall().timestamp(), 1704067202);
+    }
+
+    #[test]
+    fn virtual_time() {
+        let time: VirtualTime = "2024-01-01 02:00:00 +0200".parse().unwrap();
+        assert_eq!(time.0.timestamp(), 1704067200);
+        assert!("2024-01-01".parse::<VirtualTime>().is_err());
+        assert!("2024-01-01 00:00:00".parse::<VirtualTime>().is_err());
+    }
+}
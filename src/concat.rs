@@ -0,0 +1,177 @@
+use crate::format::{HEADER_TIME_FMT, TimeSource, is_footer, is_header, parse_field};
+use chrono::format::{self, Parsed, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveTime, TimeDelta};
+use std::io::{self, BufRead, Error, ErrorKind, Write};
+use std::time::Duration;
+
+/// One input recording.
+pub struct Segment {
+    name: String,
+    lines: Vec<String>,
+    header: Option<usize>,
+    footer: Option<usize>,
+}
+
+impl Segment {
+    /// Read text log (written without --raw).
+    pub fn read<R: BufRead>(name: &str, mut input: R) -> io::Result<Self> {
+        let mut lines = Vec::new();
+        let mut line = Vec::new();
+
+        while input.read_until(b'\n', &mut line)? != 0 {
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+            line.clear();
+        }
+
+        let header = match lines.first() {
+            Some(line) if is_header(line) => Some(0),
+            _ => None,
+        };
+        let footer = match lines.last() {
+            Some(line) if is_footer(line) => Some(lines.len() - 1),
+            _ => None,
+        };
+
+        Ok(Segment {
+            name: name.into(),
+            lines,
+            header,
+            footer,
+        })
+    }
+
+    fn header_field(&self, name: &str) -> Option<&str> {
+        self.header.and_then(|n| parse_field(&self.lines[n], name))
+    }
+
+    fn start_time(&self) -> io::Result<Option<DateTime<FixedOffset>>> {
+        match self.header_field("TIME") {
+            Some(s) => match DateTime::parse_from_str(s, HEADER_TIME_FMT) {
+                Ok(ts) => Ok(Some(ts)),
+                Err(_) => Err(self.error("malformed TIME in header")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn error(&self, msg: &str) -> Error {
+        Error::new(ErrorKind::InvalidData, format!("{}: {}", self.name, msg))
+    }
+}
+
+/// Join sequential recordings into one log, with a separator line before
+/// each segment.
+/// With elapsed timestamps, each segment is shifted so that timestamps
+/// continue from the previous segment instead of starting from zero.
+pub fn concat<W: Write>(
+    segments: &[Segment],
+    mut output: W,
+    time_format: &str,
+    time_source: TimeSource,
+) -> io::Result<()> {
+    validate(segments)?;
+
+    let base_time = match segments.first() {
+        Some(segment) => segment.start_time()?,
+        None => None,
+    };
+    let mut offset = Duration::ZERO;
+
+    for (n, segment) in segments.iter().enumerate() {
+        // Header time is more accurate because it accounts for gaps
+        // between segments, but has only seconds precision.
+        if let (Some(base_time), Some(start_time)) = (base_time, segment.start_time()?) {
+            if let Ok(header_offset) = (start_time - base_time).to_std() {
+                offset = offset.max(header_offset);
+            }
+        }
+
+        writeln!(
+            &mut output,
+            "# SEGMENT=[{}/{}] FILE=[{}]",
+            n + 1,
+            segments.len(),
+            segment.name
+        )?;
+
+        let mut last_ts = Duration::ZERO;
+
+        for (line_num, line) in segment.lines.iter().enumerate() {
+            let is_extra = Some(line_num) == segment.header || Some(line_num) == segment.footer;
+
+            if time_source == TimeSource::Elapsed && !is_extra {
+                if let Some((ts, rest)) = parse_timestamp(line, time_format) {
+                    let adjusted = DateTime::UNIX_EPOCH + TimeDelta::from_std(offset + ts).unwrap();
+                    write!(&mut output, "{}{}", adjusted.format(time_format), rest)?;
+                    last_ts = ts;
+                    continue;
+                }
+            }
+
+            output.write_all(line.as_bytes())?;
+        }
+
+        // Segment may end without newline.
+        if segment
+            .lines
+            .last()
+            .is_some_and(|line| !line.ends_with('\n'))
+        {
+            writeln!(&mut output)?;
+        }
+
+        offset += last_ts;
+    }
+
+    output.flush()
+}
+
+/// Check that segments were produced by the same kind of invocation.
+fn validate(segments: &[Segment]) -> io::Result<()> {
+    let first = match segments.first() {
+        Some(segment) => segment,
+        None => return Ok(()),
+    };
+
+    let mut prev_time = first.start_time()?;
+
+    for segment in &segments[1..] {
+        if segment.header.is_some() != first.header.is_some() {
+            return Err(segment.error(&format!("header presence differs from {}", first.name)));
+        }
+        if segment.footer.is_some() != first.footer.is_some() {
+            return Err(segment.error(&format!("footer presence differs from {}", first.name)));
+        }
+        if segment.header_field("CMD") != first.header_field("CMD") {
+            return Err(segment.error(&format!("command differs from {}", first.name)));
+        }
+
+        let start_time = segment.start_time()?;
+        if let (Some(prev_time), Some(start_time)) = (prev_time, start_time) {
+            if start_time < prev_time {
+                return Err(segment.error("segment starts before previous one"));
+            }
+        }
+        prev_time = start_time;
+    }
+
+    Ok(())
+}
+
+/// Parse elapsed timestamp at the beginning of line.
+/// Returns timestamp and the rest of the line.
+fn parse_timestamp<'a>(line: &'a str, time_format: &str) -> Option<(Duration, &'a str)> {
+    let mut parsed = Parsed::new();
+    let rest =
+        format::parse_and_remainder(&mut parsed, line, StrftimeItems::new(time_format)).ok()?;
+
+    let ts = match parsed.to_naive_date() {
+        Ok(date) => {
+            let time = parsed.to_naive_time().unwrap_or(NaiveTime::MIN);
+            date.and_time(time).and_utc() - DateTime::UNIX_EPOCH
+        }
+        Err(_) => parsed.to_naive_time().ok()? - NaiveTime::MIN,
+    };
+
+    Some((ts.to_std().ok()?, rest))
+}
@@ -0,0 +1,418 @@
+use crate::error::Error;
+use clap::builder::BoolishValueParser;
+use clap::{Arg, ArgAction, Command};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Options that can't be set in config file.
+const FORBIDDEN_KEYS: &[&str] = &["config", "no-config", "profile", "help", "version", "man"];
+
+/// Options that can't be set via environment.
+const FORBIDDEN_VARS: &[&str] = &["help", "version", "man"];
+
+/// Default config path: $XDG_CONFIG_HOME/reclog/config.toml,
+/// or ~/.config/reclog/config.toml.
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("reclog").join("config.toml"))
+}
+
+/// Environment variable for option, e.g. RECLOG_TS_FMT for --ts-fmt.
+pub fn env_name(long: &str) -> String {
+    format!("RECLOG_{}", long.to_uppercase().replace('-', "_"))
+}
+
+/// Allow to set every option of given command via environment variable.
+/// Command line takes precedence over environment, and environment takes
+/// precedence over defaults, so this should be applied after with_config().
+pub fn with_env(command: Command) -> Command {
+    command.mut_args(|arg| match arg.get_long() {
+        Some(long) if !FORBIDDEN_VARS.contains(&long) => {
+            let name = env_name(long);
+            match arg.get_action() {
+                // clap treats flag set via environment as present even if the
+                // value is false, which triggers conflicts with other options,
+                // so turn false value into default instead.
+                ArgAction::SetTrue if env::var(&name).is_ok_and(|value| is_false(&value)) => {
+                    arg.default_value("false")
+                }
+                // Accept 1/0, yes/no, on/off for flags.
+                ArgAction::SetTrue => arg.env(name).value_parser(BoolishValueParser::new()),
+                // Accept count too for flags that can be repeated, like -DD.
+                ArgAction::Count => arg.env(name).value_parser(parse_count),
+                _ => arg.env(name),
+            }
+        }
+        _ => arg,
+    })
+}
+
+/// Parse value of repeatable flag from environment: count or boolean.
+fn parse_count(value: &str) -> Result<u8, String> {
+    match value.parse() {
+        Ok(count) => Ok(count),
+        Err(_) if is_false(value) => Ok(0),
+        Err(_) if is_true(value) => Ok(1),
+        Err(_) => Err("expected count or boolean".into()),
+    }
+}
+
+/// Check if flag value is false, like BoolishValueParser does.
+fn is_false(value: &str) -> bool {
+    ["n", "no", "f", "false", "off", "0"].contains(&value.to_lowercase().as_str())
+}
+
+/// Check if flag value is true, like BoolishValueParser does.
+fn is_true(value: &str) -> bool {
+    ["y", "yes", "t", "true", "on", "1"].contains(&value.to_lowercase().as_str())
+}
+
+/// Read config file and use its values as defaults for options of given
+/// command. Command line and environment take precedence over defaults.
+///
+/// Keys are long option names, e.g. `ts-fmt = "%T"`. Flags are set with
+/// booleans, options that can be repeated are set with arrays.
+///
+/// If `profile` is given, options from `[profile.NAME]` table are applied
+/// on top of top-level options.
+pub fn with_config(path: &Path, command: Command, profile: Option<&str>) -> Result<Command, Error> {
+    let content = fs::read_to_string(path)?;
+    let mut table = content
+        .parse::<Table>()
+        .map_err(|err| Error::Config(err.to_string().trim_end().to_string()))?;
+
+    let profiles = match table.remove("profile") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err(Error::Config("'profile' should be a table".into())),
+        None => Table::new(),
+    };
+
+    let mut command = apply_table(command, &table).map_err(Error::Config)?;
+
+    if let Some(name) = profile {
+        command = match profiles.get(name) {
+            Some(Value::Table(profile_table)) => apply_table(command, profile_table)
+                .map_err(|err| Error::Config(format!("profile '{}': {}", name, err)))?,
+            Some(_) => {
+                return Err(Error::Config(format!(
+                    "profile '{}' should be a table",
+                    name
+                )));
+            }
+            None => return Err(Error::Config(format!("unknown profile '{}'", name))),
+        };
+    }
+
+    Ok(command)
+}
+
+/// Use values from config table as defaults for options of given command.
+fn apply_table(mut command: Command, table: &Table) -> Result<Command, String> {
+    for (key, value) in table {
+        let arg = match command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()))
+        {
+            Some(arg) if !FORBIDDEN_KEYS.contains(&key.as_str()) => arg,
+            Some(_) => return Err(format!("option '{}' can't be set in config file", key)),
+            None => return Err(format!("unknown option '{}'", key)),
+        };
+
+        let values = match value {
+            // Option with optional value, like --fail-if-silent, is disabled
+            // by omitting it.
+            Value::Boolean(false) if missing_value(arg).is_some() => vec![],
+            Value::Array(items) if matches!(arg.get_action(), ArgAction::Append) => items
+                .iter()
+                .map(|item| option_value(key, arg, item))
+                .collect::<Result<Vec<_>, _>>()?,
+            value => vec![option_value(key, arg, value)?],
+        };
+
+        let id = arg.get_id().clone();
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+
+    Ok(command)
+}
+
+/// Convert single config value to default value of option.
+fn option_value(key: &str, arg: &Arg, value: &Value) -> Result<OsString, String> {
+    let takes_values = arg.get_action().takes_values();
+
+    let value = match value {
+        // Flag that can be repeated, like -DD, is set with boolean or count.
+        Value::Boolean(b) if matches!(arg.get_action(), ArgAction::Count) => {
+            u8::from(*b).to_string()
+        }
+        Value::Integer(n) if matches!(arg.get_action(), ArgAction::Count) => n.to_string(),
+        // Flag.
+        Value::Boolean(b) if !takes_values => b.to_string(),
+        // Option with optional value, like --fail-if-silent.
+        Value::Boolean(true) if takes_values => match missing_value(arg) {
+            Some(value) => return Ok(value),
+            None => return Err(format!("invalid value for option '{}'", key)),
+        },
+        Value::String(s) if takes_values => s.clone(),
+        Value::Integer(n) if takes_values => n.to_string(),
+        Value::Float(f) if takes_values => f.to_string(),
+        _ => return Err(format!("invalid value for option '{}'", key)),
+    };
+
+    Ok(value.into())
+}
+
+/// Get value that option gets when specified without value, if it's allowed.
+fn missing_value(arg: &Arg) -> Option<OsString> {
+    if !arg.get_num_args().is_some_and(|n| n.min_values() == 0) {
+        return None;
+    }
+
+    // clap doesn't expose default_missing_value, so parse option alone.
+    let long = arg.get_long()?;
+    let matches = Command::new("reclog")
+        .arg(arg.clone())
+        .try_get_matches_from(["reclog".to_string(), format!("--{}", long)])
+        .ok()?;
+
+    matches
+        .get_raw(arg.get_id().as_str())?
+        .next()
+        .map(|value| value.to_os_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ArgMatches;
+    use std::process;
+
+    fn command() -> Command {
+        Command::new("reclog")
+            .arg(Arg::new("ts").long("ts").action(ArgAction::SetTrue))
+            .arg(
+                Arg::new("debug")
+                    .short('D')
+                    .long("debug")
+                    .action(ArgAction::Count),
+            )
+            .arg(Arg::new("env").long("env").action(ArgAction::Append))
+            .arg(Arg::new("ts-fmt").long("ts-fmt"))
+            .arg(
+                Arg::new("fail-if-silent")
+                    .long("fail-if-silent")
+                    .num_args(0..=1)
+                    .require_equals(true)
+                    .default_missing_value("0"),
+            )
+            .arg(Arg::new("config").long("config"))
+    }
+
+    fn apply(config: &str) -> Result<Command, String> {
+        apply_table(command(), &config.parse::<Table>().unwrap())
+    }
+
+    fn parse(command: Command, args: &[&str]) -> ArgMatches {
+        command
+            .try_get_matches_from(["reclog"].iter().chain(args))
+            .unwrap()
+    }
+
+    fn strings(matches: &ArgMatches, id: &str) -> Vec<String> {
+        matches
+            .get_many::<String>(id)
+            .map_or(vec![], |values| values.cloned().collect())
+    }
+
+    #[test]
+    fn flags() {
+        let matches = parse(apply("ts = true\ndebug = 2").unwrap(), &[]);
+        assert!(matches.get_flag("ts"));
+        assert_eq!(matches.get_count("debug"), 2);
+
+        let matches = parse(apply("ts = false\ndebug = true").unwrap(), &[]);
+        assert!(!matches.get_flag("ts"));
+        assert_eq!(matches.get_count("debug"), 1);
+
+        // Command line adds to count from config.
+        let matches = parse(apply("debug = 1").unwrap(), &["-DD"]);
+        assert_eq!(matches.get_count("debug"), 2);
+    }
+
+    #[test]
+    fn values() {
+        let matches = parse(
+            apply("ts-fmt = \"%T\"\nenv = [\"A=1\", \"B=2\"]").unwrap(),
+            &[],
+        );
+        assert_eq!(strings(&matches, "ts-fmt"), ["%T"]);
+        assert_eq!(strings(&matches, "env"), ["A=1", "B=2"]);
+
+        // Command line overrides config.
+        let matches = parse(
+            apply("ts-fmt = \"%T\"\nenv = [\"A=1\"]").unwrap(),
+            &["--ts-fmt=%F", "--env=C=3"],
+        );
+        assert_eq!(strings(&matches, "ts-fmt"), ["%F"]);
+        assert_eq!(strings(&matches, "env"), ["C=3"]);
+
+        let matches = parse(apply("ts-fmt = 5").unwrap(), &[]);
+        assert_eq!(strings(&matches, "ts-fmt"), ["5"]);
+    }
+
+    #[test]
+    fn optional_value() {
+        let matches = parse(apply("fail-if-silent = true").unwrap(), &[]);
+        assert_eq!(strings(&matches, "fail-if-silent"), ["0"]);
+
+        let matches = parse(apply("fail-if-silent = \"5m\"").unwrap(), &[]);
+        assert_eq!(strings(&matches, "fail-if-silent"), ["5m"]);
+
+        let matches = parse(apply("fail-if-silent = false").unwrap(), &[]);
+        assert!(!matches.contains_id("fail-if-silent"));
+    }
+
+    #[test]
+    fn bad_values() {
+        for config in [
+            "ts = \"yes\"",
+            "ts-fmt = true",
+            "ts-fmt = false",
+            "ts-fmt = [\"%T\"]",
+            "env = [[\"A=1\"]]",
+            "debug = \"2\"",
+        ] {
+            assert_eq!(
+                apply(config).err(),
+                Some(format!(
+                    "invalid value for option '{}'",
+                    config.split(' ').next().unwrap()
+                )),
+                "{}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn bad_keys() {
+        assert_eq!(
+            apply("config = \"other.toml\"").err(),
+            Some("option 'config' can't be set in config file".into())
+        );
+        assert_eq!(
+            apply("no-such-option = 1").err(),
+            Some("unknown option 'no-such-option'".into())
+        );
+    }
+
+    #[test]
+    fn profiles() {
+        let path = env::temp_dir().join(format!("reclog-config-{}.toml", process::id()));
+        fs::write(
+            &path,
+            "ts = true\n\
+             ts-fmt = \"%T\"\n\
+             [profile.ci]\n\
+             ts-fmt = \"%F %T\"\n\
+             debug = 1\n\
+             [profile.bad]\n\
+             ts-fmt = false\n",
+        )
+        .unwrap();
+
+        let matches = parse(with_config(&path, command(), None).unwrap(), &[]);
+        assert!(matches.get_flag("ts"));
+        assert_eq!(strings(&matches, "ts-fmt"), ["%T"]);
+        assert_eq!(matches.get_count("debug"), 0);
+
+        // Profile is applied on top of top-level options.
+        let matches = parse(with_config(&path, command(), Some("ci")).unwrap(), &[]);
+        assert!(matches.get_flag("ts"));
+        assert_eq!(strings(&matches, "ts-fmt"), ["%F %T"]);
+        assert_eq!(matches.get_count("debug"), 1);
+
+        let err = |profile| {
+            with_config(&path, command(), Some(profile))
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(err("none"), "unknown profile 'none'");
+        assert_eq!(
+            err("bad"),
+            "profile 'bad': invalid value for option 'ts-fmt'"
+        );
+
+        _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn env_overrides() {
+        let command = || {
+            Command::new("reclog")
+                .arg(
+                    Arg::new("env-test-flag")
+                        .long("env-test-flag")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("env-test-off")
+                        .long("env-test-off")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("env-test-flag"),
+                )
+                .arg(
+                    Arg::new("env-test-count")
+                        .long("env-test-count")
+                        .action(ArgAction::Count),
+                )
+                .arg(Arg::new("env-test-value").long("env-test-value"))
+        };
+        // Names are unique to this test, so other tests aren't affected.
+        unsafe {
+            env::set_var("RECLOG_ENV_TEST_FLAG", "yes");
+            env::set_var("RECLOG_ENV_TEST_OFF", "off");
+            env::set_var("RECLOG_ENV_TEST_COUNT", "3");
+            env::set_var("RECLOG_ENV_TEST_VALUE", "env");
+        }
+
+        let matches = parse(with_env(command()), &[]);
+        assert!(matches.get_flag("env-test-flag"));
+        // False flag doesn't conflict with other options.
+        assert!(!matches.get_flag("env-test-off"));
+        assert_eq!(matches.get_count("env-test-count"), 3);
+        assert_eq!(strings(&matches, "env-test-value"), ["env"]);
+
+        // Environment overrides config.
+        let config = "env-test-value = \"config\"".parse::<Table>().unwrap();
+        let matches = parse(with_env(apply_table(command(), &config).unwrap()), &[]);
+        assert_eq!(strings(&matches, "env-test-value"), ["env"]);
+
+        // Command line overrides environment.
+        let matches = parse(with_env(command()), &["--env-test-value=cli"]);
+        assert_eq!(strings(&matches, "env-test-value"), ["cli"]);
+
+        unsafe {
+            env::set_var("RECLOG_ENV_TEST_COUNT", "no");
+        }
+        let matches = parse(with_env(command()), &[]);
+        assert_eq!(matches.get_count("env-test-count"), 0);
+
+        unsafe {
+            env::set_var("RECLOG_ENV_TEST_COUNT", "many");
+        }
+        assert!(
+            with_env(command())
+                .try_get_matches_from(["reclog"])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+/// Program used for encryption and decryption.
+/// Cryptography is delegated to age(1) (or compatible rage(1)), instead of
+/// implementing it here.
+const AGE_PROGRAM: &str = "age";
+
+/// Encrypts data written to output file (--encrypt), by piping it through
+/// age(1) process, which writes encrypted stream to the file.
+pub struct Encryptor {
+    child: Child,
+    stdin: Arc<Mutex<Option<ChildStdin>>>,
+}
+
+impl Encryptor {
+    /// Start age(1) with given recipients, writing to file.
+    /// Recipient is either a public key (e.g. "age1..." or "ssh-ed25519 ..."),
+    /// or path to file with recipients, one per line.
+    pub fn start(recipients: &[String], output: File) -> io::Result<Self> {
+        let mut command = Command::new(AGE_PROGRAM);
+        command.arg("--encrypt");
+        for recipient in recipients {
+            if Path::new(recipient).is_file() {
+                command.arg("--recipients-file").arg(recipient);
+            } else {
+                command.arg("--recipient").arg(recipient);
+            }
+        }
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(output)
+            .spawn()
+            .map_err(|err| Error::new(err.kind(), format!("can't run {}: {}", AGE_PROGRAM, err)))?;
+
+        let stdin = child.stdin.take();
+        Ok(Encryptor {
+            child,
+            stdin: Arc::new(Mutex::new(stdin)),
+        })
+    }
+
+    /// Writer for plain data; it goes to age(1) stdin.
+    pub fn writer(&self) -> EncryptWriter {
+        EncryptWriter {
+            stdin: Arc::clone(&self.stdin),
+        }
+    }
+
+    /// Finish encrypted stream and wait until it's written.
+    /// Data written after this point is rejected.
+    pub fn finish(mut self) -> io::Result<()> {
+        // Closing pipe tells age(1) to write final chunk and exit.
+        drop(self.stdin.lock().unwrap().take());
+
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(Error::other(format!(
+                "{} failed with {}",
+                AGE_PROGRAM, status
+            )))
+        }
+    }
+}
+
+/// Writer returned by Encryptor::writer().
+pub struct EncryptWriter {
+    stdin: Arc<Mutex<Option<ChildStdin>>>,
+}
+
+impl Write for EncryptWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *self.stdin.lock().unwrap() {
+            Some(stdin) => stdin.write(buf),
+            None => Err(Error::from(ErrorKind::BrokenPipe)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.stdin.lock().unwrap() {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Decrypt file using identity files, writing plain data to output
+/// (`reclog decrypt`).
+pub fn decrypt(input: &Path, identities: &[String], output: Stdio) -> io::Result<()> {
+    let mut command = Command::new(AGE_PROGRAM);
+    command.arg("--decrypt");
+    for identity in identities {
+        command.arg("--identity").arg(identity);
+    }
+    command.arg(input);
+
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(output)
+        .status()
+        .map_err(|err| Error::new(err.kind(), format!("can't run {}: {}", AGE_PROGRAM, err)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!(
+            "{} failed with {}",
+            AGE_PROGRAM, status
+        )))
+    }
+}
@@ -0,0 +1,311 @@
+use crate::ttyrec::{TtyrecReader, TtyrecWriter};
+use chrono::TimeDelta;
+use reclog::timing::{TimingEntry, TimingReader, TimingWriter};
+use std::io::{self, BufRead, Error, ErrorKind, Read, Write};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Position in recording, relative to its start (--from, --to).
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Offset(pub Duration);
+
+impl FromStr for Offset {
+    type Err = String;
+
+    /// Parse "[[HH:]MM:]SS[.FFF]", e.g. "00:05:00", "5:00", or "300".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid time '{}', expected [[HH:]MM:]SS[.FFF]", s);
+
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() > 3 {
+            return Err(err());
+        }
+        let mut secs = 0.0;
+        for (n, part) in parts.iter().enumerate() {
+            let value = if n == parts.len() - 1 {
+                part.parse::<f64>().ok()
+            } else {
+                part.parse::<u64>().ok().map(|v| v as f64)
+            };
+            match value {
+                Some(value) if value >= 0.0 && value.is_finite() => secs = secs * 60.0 + value,
+                _ => return Err(err()),
+            }
+        }
+
+        Duration::try_from_secs_f64(secs)
+            .map(Offset)
+            .map_err(|_| err())
+    }
+}
+
+/// Copy part of raw recording with timing file, between `from` and `to`.
+/// Output timing file is adjusted so that it starts at `from`. Other
+/// entries (input, resize) in the range are copied too; headers and the
+/// last resize before `from` are moved to the start, so that the part is
+/// played with right terminal size.
+pub fn cut_timing<R: Read, T: BufRead, W: Write, U: Write>(
+    mut recording: R,
+    timing: T,
+    mut output: W,
+    timing_output: Option<U>,
+    from: Offset,
+    to: Option<Offset>,
+) -> io::Result<()> {
+    let mut timing_reader = TimingReader::new(timing);
+    let mut timing_writer = timing_output.map(TimingWriter::new);
+
+    let mut has_start_time = false;
+    let mut elapsed = Duration::ZERO;
+    // Time of the last written entry, relative to the recording start.
+    let mut last_written = from.0;
+    // Last resize before `from`, written before the first entry in range.
+    let mut last_resize = None;
+    let mut chunk = Vec::new();
+
+    while let Some(entry) = timing_reader.read_entry()? {
+        let size = match entry {
+            TimingEntry::StartTime(ts) => {
+                // Only the first session matters, later ones (if recording
+                // was appended) continue from it.
+                if !has_start_time {
+                    has_start_time = true;
+                    if let Some(writer) = &mut timing_writer {
+                        let start_time = TimeDelta::from_std(from.0)
+                            .ok()
+                            .and_then(|from| ts.checked_add_signed(from))
+                            .ok_or_else(|| {
+                                Error::new(ErrorKind::InvalidInput, "start time out of range")
+                            })?;
+                        writer.write_header(start_time)?;
+                    }
+                }
+                continue;
+            }
+            TimingEntry::Other { delay, kind, data } => {
+                elapsed += delay;
+                if to.is_some_and(|to| elapsed >= to.0) {
+                    break;
+                }
+                let Some(writer) = &mut timing_writer else {
+                    continue;
+                };
+                if elapsed < from.0 {
+                    match kind.as_str() {
+                        // Headers describe the whole recording.
+                        "H" => writer.write_other(Duration::ZERO, &kind, &data)?,
+                        "S" if data.starts_with("SIGWINCH ") => last_resize = Some(data),
+                        _ => {}
+                    }
+                    continue;
+                }
+                if let Some(resize) = last_resize.take() {
+                    writer.write_other(Duration::ZERO, "S", &resize)?;
+                }
+                writer.write_other(elapsed - last_written, &kind, &data)?;
+                last_written = elapsed;
+                continue;
+            }
+            TimingEntry::Output(delay, size) => {
+                elapsed += delay;
+                size
+            }
+        };
+
+        if to.is_some_and(|to| elapsed >= to.0) {
+            break;
+        }
+
+        chunk.resize(size, 0);
+        if let Err(err) = recording.read_exact(&mut chunk) {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "recording is shorter than timing file",
+                ));
+            }
+            return Err(err);
+        }
+        if elapsed < from.0 {
+            continue;
+        }
+
+        output.write_all(&chunk)?;
+        if let Some(writer) = &mut timing_writer {
+            if let Some(resize) = last_resize.take() {
+                writer.write_other(Duration::ZERO, "S", &resize)?;
+            }
+            writer.write_entry(elapsed - last_written, size)?;
+        }
+        last_written = elapsed;
+    }
+
+    output.flush()
+}
+
+/// Copy records of ttyrec recording between `from` and `to`.
+/// Records keep their original time, since players use only the difference
+/// between records.
+pub fn cut_ttyrec<R: Read, W: Write>(
+    input: R,
+    output: W,
+    from: Offset,
+    to: Option<Offset>,
+) -> io::Result<()> {
+    let mut reader = TtyrecReader::new(input);
+    let mut writer = TtyrecWriter::new(output);
+    let mut start_ts = None;
+
+    while let Some((ts, data)) = reader.read_record()? {
+        let start_ts = *start_ts.get_or_insert(ts);
+        let elapsed = ts.duration_since(start_ts).unwrap_or_default();
+
+        if to.is_some_and(|to| elapsed >= to.0) {
+            break;
+        }
+        if elapsed >= from.0 {
+            writer.write_output(ts, &data)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn secs(secs: f64) -> Offset {
+        Offset(Duration::from_secs_f64(secs))
+    }
+
+    #[test]
+    fn parse_offset() {
+        assert_eq!("300".parse(), Ok(secs(300.0)));
+        assert_eq!("1.5".parse(), Ok(secs(1.5)));
+        assert_eq!("5:00".parse(), Ok(secs(300.0)));
+        assert_eq!("01:02:03.25".parse(), Ok(secs(3723.25)));
+        assert_eq!("0".parse(), Ok(secs(0.0)));
+
+        for s in [
+            "", "-1", "1:-1", "1.5:00", "1:2:3:4", "abc", "inf", "NaN", "1e300",
+        ] {
+            assert!(s.parse::<Offset>().is_err(), "{:?}", s);
+        }
+    }
+
+    const TIMING: &str = "H 0.000000 START_TIME 2024-01-02 03:04:05.000000 +0000\n\
+                          H 0.000000 TERM xterm\n\
+                          O 1.000000 2\n\
+                          S 0.500000 SIGWINCH ROWS=24 COLS=80\n\
+                          O 0.500000 2\n\
+                          I 0.500000 1\n\
+                          O 0.500000 2\n\
+                          O 1.000000 2\n";
+
+    const RECORDING: &[u8] = b"a\nb\nc\nd\n";
+
+    fn cut(from: f64, to: Option<f64>) -> io::Result<(String, String)> {
+        let mut output = Vec::new();
+        let mut timing_output = Vec::new();
+        cut_timing(
+            RECORDING,
+            TIMING.as_bytes(),
+            &mut output,
+            Some(&mut timing_output),
+            secs(from),
+            to.map(secs),
+        )?;
+        Ok((
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(timing_output).unwrap(),
+        ))
+    }
+
+    #[test]
+    fn timing_whole() {
+        let (output, timing) = cut(0.0, None).unwrap();
+        assert_eq!(output, "a\nb\nc\nd\n");
+        assert_eq!(
+            timing,
+            "H 0.000000 START_TIME 2024-01-02 03:04:05.000000 +0000\n\
+             H 0.000000 TERM xterm\n\
+             O 1.000000 2\n\
+             S 0.500000 SIGWINCH ROWS=24 COLS=80\n\
+             O 0.500000 2\n\
+             I 0.500000 1\n\
+             O 0.500000 2\n\
+             O 1.000000 2\n"
+        );
+    }
+
+    #[test]
+    fn timing_range() {
+        // Chunk produced exactly at `from` is included, and exactly at `to`
+        // is excluded.
+        let (output, timing) = cut(2.0, Some(3.5)).unwrap();
+        assert_eq!(output, "b\nc\n");
+        assert_eq!(
+            timing,
+            "H 0.000000 START_TIME 2024-01-02 03:04:07.000000 +0000\n\
+             H 0.000000 TERM xterm\n\
+             S 0.000000 SIGWINCH ROWS=24 COLS=80\n\
+             O 0.000000 2\n\
+             I 0.500000 1\n\
+             O 0.500000 2\n"
+        );
+
+        let (output, _) = cut(2.1, Some(3.1)).unwrap();
+        assert_eq!(output, "c\n");
+
+        let (output, timing) = cut(10.0, None).unwrap();
+        assert_eq!(output, "");
+        assert!(timing.ends_with("TERM xterm\n"), "{}", timing);
+    }
+
+    #[test]
+    fn timing_errors() {
+        let err = cut(1e15, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let err = cut_timing(
+            &RECORDING[..5],
+            TIMING.as_bytes(),
+            io::sink(),
+            None::<io::Sink>,
+            secs(0.0),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn ttyrec_range() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut input = Vec::new();
+        let mut writer = TtyrecWriter::new(&mut input);
+        for (n, data) in ["a", "b", "c", "d"].iter().enumerate() {
+            writer
+                .write_output(start + Duration::from_secs(n as u64), data.as_bytes())
+                .unwrap();
+        }
+
+        let mut output = Vec::new();
+        cut_ttyrec(input.as_slice(), &mut output, secs(1.0), Some(secs(3.0))).unwrap();
+
+        let mut reader = TtyrecReader::new(output.as_slice());
+        let mut records: Vec<(SystemTime, Vec<u8>)> = Vec::new();
+        while let Some(record) = reader.read_record().unwrap() {
+            records.push(record);
+        }
+        assert_eq!(
+            records,
+            [
+                (start + Duration::from_secs(1), b"b".to_vec()),
+                (start + Duration::from_secs(2), b"c".to_vec()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Time period, e.g. "500ms", "2s", "1.5m", or "1h".
+/// Number without unit means milliseconds, for compatibility.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Period(pub Duration);
+
+impl FromStr for Period {
+    type Err = String;
+
+    /// Parse number with optional ms, s, m, or h unit.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_duration(s).map(Period)
+    }
+}
+
+/// Time limit: period, or None if there is no limit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limit(pub Option<Duration>);
+
+impl FromStr for Limit {
+    type Err = String;
+
+    /// Parse period or "inf".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "inf" || s == "infinite" {
+            return Ok(Limit(None));
+        }
+        parse_duration(s).map(|period| Limit(Some(period)))
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let err = || {
+        format!(
+            "invalid duration '{}', expected number with optional unit ms, s, m, or h",
+            s
+        )
+    };
+
+    let unit_pos = s
+        .find(|ch: char| !ch.is_ascii_digit() && ch != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(unit_pos);
+    let nanos_per_unit = match unit {
+        "" | "ms" => 1e6,
+        "s" => 1e9,
+        "m" => 60e9,
+        "h" => 3600e9,
+        _ => return Err(err()),
+    };
+
+    let nanos = number.parse::<f64>().map_err(|_| err())? * nanos_per_unit;
+    if !nanos.is_finite() || nanos >= u64::MAX as f64 {
+        return Err(err());
+    }
+    Ok(Duration::from_nanos(nanos.round() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        let period = |s: &str| s.parse::<Period>().unwrap().0;
+        assert_eq!(period("250"), Duration::from_millis(250));
+        assert_eq!(period("250ms"), Duration::from_millis(250));
+        assert_eq!(period("2s"), Duration::from_secs(2));
+        assert_eq!(period("1.5s"), Duration::from_millis(1500));
+        assert_eq!(period("1.5m"), Duration::from_secs(90));
+        assert_eq!(period("2h"), Duration::from_secs(7200));
+        assert_eq!(period("0"), Duration::ZERO);
+        assert_eq!(period(".5s"), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn limit() {
+        let limit = |s: &str| s.parse::<Limit>().unwrap().0;
+        assert_eq!(limit("inf"), None);
+        assert_eq!(limit("infinite"), None);
+        assert_eq!(limit("3s"), Some(Duration::from_secs(3)));
+        assert_eq!(limit("100"), Some(Duration::from_millis(100)));
+
+        assert!("inf".parse::<Period>().is_err());
+        assert!("infs".parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        assert!("5000000h".parse::<Period>().is_ok());
+        assert!("6000000h".parse::<Period>().is_err());
+        assert!("1".repeat(400).parse::<Period>().is_err());
+        assert!(format!("{}s", "9".repeat(30)).parse::<Limit>().is_err());
+    }
+
+    #[test]
+    fn bad_values() {
+        for s in [
+            "", "s", "-1", "-1s", "+1", "1e3", "1e3ms", "1.2.3", "1 s", "1sec", "1d", "NaN",
+        ] {
+            assert!(s.parse::<Period>().is_err(), "{:?}", s);
+            assert!(s.parse::<Limit>().is_err(), "{:?}", s);
+        }
+    }
+}
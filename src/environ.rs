@@ -0,0 +1,179 @@
+use crate::user::RunAs;
+use std::env;
+use std::ffi::{CString, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path;
+use std::str::FromStr;
+
+/// Variables set by --sanitize-env.
+const SANITIZED_VARS: &[(&str, &str)] = &[
+    ("TERM", "xterm-256color"),
+    ("LANG", "C.UTF-8"),
+    ("LC_ALL", "C.UTF-8"),
+];
+
+/// Variables removed by --sanitize-env.
+/// These affect formatting of output (width, language, colors) and usually
+/// differ between machines.
+const MANGLING_VARS: &[&str] = &[
+    "LANGUAGE",
+    "COLUMNS",
+    "LINES",
+    "COLORTERM",
+    "TERM_PROGRAM",
+    "TERM_PROGRAM_VERSION",
+    "CLICOLOR",
+    "CLICOLOR_FORCE",
+    "FORCE_COLOR",
+    "NO_COLOR",
+    "GCC_COLORS",
+    "GREP_COLORS",
+    "LS_COLORS",
+];
+
+/// Environment variable assignment, parsed from "NAME=VALUE".
+#[derive(Clone, Debug)]
+pub struct EnvVar {
+    name: String,
+    value: String,
+}
+
+impl FromStr for EnvVar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some(parts) => parts,
+            None => return Err("expected NAME=VALUE".into()),
+        };
+        if name.is_empty() {
+            return Err("variable name can't be empty".into());
+        }
+
+        Ok(EnvVar {
+            name: name.into(),
+            value: value.into(),
+        })
+    }
+}
+
+impl EnvVar {
+    fn new(name: &str, value: &str) -> Self {
+        EnvVar {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+}
+
+/// Variables describing the recording, so that the command can detect that
+/// it runs under reclog (like tools detect CI).
+/// Names don't clash with RECLOG_<OPTION> variables, so that nested reclog
+/// doesn't pick them up as options.
+/// `output` is None if output file is not written (--null).
+pub fn recording_vars(output: Option<&str>, ts: bool, buffer: usize) -> Vec<EnvVar> {
+    let mut vars = vec![EnvVar::new("RECLOG", "1")];
+
+    if let Some(output) = output {
+        // Command may change directory, so report absolute path.
+        let output = path::absolute(output).map_or(output.into(), |p| p.display().to_string());
+        vars.push(EnvVar::new("RECLOG_SESSION_OUTPUT", &output));
+    }
+    vars.push(EnvVar::new("RECLOG_SESSION_TS", if ts { "1" } else { "0" }));
+    vars.push(EnvVar::new("RECLOG_SESSION_BUFFER", &buffer.to_string()));
+
+    vars
+}
+
+/// Variables describing user for command run with --user, like su(1) sets.
+pub fn user_vars(run_as: &RunAs) -> Vec<EnvVar> {
+    vec![
+        EnvVar::new("USER", &run_as.name),
+        EnvVar::new("LOGNAME", &run_as.name),
+        EnvVar::new("HOME", &run_as.home),
+    ]
+}
+
+/// Build environment of child: our environment, optionally cleared, then
+/// optionally sanitized, then with explicit assignments applied.
+///
+/// Our own environment is left intact; the result is installed in child
+/// after fork() (see ChildSetup).
+pub fn child_env(clear: bool, sanitize: bool, vars: &[EnvVar]) -> Vec<CString> {
+    let mut result: Vec<(OsString, OsString)> = if clear {
+        Vec::new()
+    } else {
+        env::vars_os().collect()
+    };
+
+    if sanitize {
+        result.retain(|(name, _)| match name.to_str() {
+            Some(name) => !name.starts_with("LC_") && !MANGLING_VARS.contains(&name),
+            None => true,
+        });
+        for (name, value) in SANITIZED_VARS {
+            assign(&mut result, name.into(), value.into());
+        }
+    }
+
+    for var in vars {
+        assign(&mut result, (&var.name).into(), (&var.value).into());
+    }
+
+    result
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let mut entry = name.into_vec();
+            entry.push(b'=');
+            entry.extend(value.into_vec());
+            CString::new(entry).ok()
+        })
+        .collect()
+}
+
+fn assign(env: &mut Vec<(OsString, OsString)>, name: OsString, value: OsString) {
+    match env.iter_mut().find(|(n, _)| *n == name) {
+        Some((_, v)) => *v = value,
+        None => env.push((name, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(list: &[&str]) -> Vec<EnvVar> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn strings(env: Vec<CString>) -> Vec<String> {
+        env.into_iter()
+            .map(|var| var.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn clear() {
+        let env = child_env(true, false, &vars(&["A=1", "B=2", "A=3"]));
+        assert_eq!(strings(env), ["A=3", "B=2"]);
+    }
+
+    #[test]
+    fn sanitize() {
+        let env = strings(child_env(false, true, &vars(&["TERM=dumb"])));
+        assert!(env.contains(&"TERM=dumb".into()));
+        assert!(env.contains(&"LC_ALL=C.UTF-8".into()));
+        assert!(!env.iter().any(|var| var.starts_with("COLUMNS=")));
+        assert!(
+            !env.iter()
+                .any(|var| var.starts_with("LC_") && var != "LC_ALL=C.UTF-8")
+        );
+    }
+
+    #[test]
+    fn inherit() {
+        let env = strings(child_env(false, false, &[]));
+        let path = env::var("PATH").unwrap();
+        assert!(env.contains(&format!("PATH={}", path)));
+    }
+}
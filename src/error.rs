@@ -1,6 +1,11 @@
+use crate::format::{json_number, json_string};
 use rustix::io::Errno;
+use std::borrow::Cow;
+use std::error;
 use std::fmt;
+use std::io;
 
+/// System call failed: name of the call and its errno.
 #[derive(Debug)]
 pub struct SysError(pub &'static str, pub Errno);
 
@@ -9,3 +14,231 @@ impl fmt::Display for SysError {
         write!(f, "{}: {}", self.0, self.1)
     }
 }
+
+impl error::Error for SysError {}
+
+/// Error classified by what failed, with optional context.
+/// Context keeps its cause, available via source().
+#[derive(Debug)]
+pub enum Error {
+    /// System call failed.
+    Sys(SysError),
+    /// I/O on file, pipe, or socket failed.
+    Io(io::Error),
+    /// Formatting of header, footer, or other line failed.
+    Format(fmt::Error),
+    /// Config file or environment is invalid.
+    Config(String),
+    /// Command couldn't be started.
+    Spawn(Box<Error>),
+    /// Cause with description of what we were doing, e.g. "can't open file".
+    Context(String, Box<Error>),
+}
+
+impl Error {
+    /// Wrap error with description of what we were doing.
+    pub fn context(self, message: impl Into<String>) -> Self {
+        Error::Context(message.into(), Box::new(self))
+    }
+
+    /// Name of failure class: "sys", "io", "format", "config", or "spawn".
+    /// Context doesn't change class of its cause.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Error::Sys(_) => "sys",
+            Error::Io(_) => "io",
+            Error::Format(_) => "format",
+            Error::Config(_) => "config",
+            Error::Spawn(_) => "spawn",
+            Error::Context(_, cause) => cause.class(),
+        }
+    }
+
+    /// Innermost error in chain of causes.
+    fn root(&self) -> &Error {
+        match self {
+            Error::Spawn(cause) | Error::Context(_, cause) => cause.root(),
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Sys(err) => err.fmt(f),
+            Error::Io(err) => err.fmt(f),
+            Error::Format(err) => err.fmt(f),
+            Error::Config(msg) => f.write_str(msg),
+            Error::Spawn(cause) => cause.fmt(f),
+            Error::Context(msg, cause) => write!(f, "{}: {}", msg, cause),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            // Class wrappers are transparent.
+            Error::Sys(_) | Error::Format(_) | Error::Config(_) => None,
+            Error::Io(err) => err.source(),
+            Error::Spawn(cause) => cause.source(),
+            Error::Context(_, cause) => Some(cause.as_ref()),
+        }
+    }
+}
+
+impl From<SysError> for Error {
+    fn from(err: SysError) -> Self {
+        Error::Sys(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<fmt::Error> for Error {
+    fn from(err: fmt::Error) -> Self {
+        Error::Format(err)
+    }
+}
+
+/// Failed system call and its errno, if known.
+/// Implemented for values that are passed to terminate!() as the last
+/// argument, which is usually the error.
+pub trait ErrorCause {
+    /// Failure class, see Error::class().
+    fn class(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn syscall(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn errno(&self) -> Option<Errno> {
+        None
+    }
+}
+
+impl ErrorCause for Error {
+    fn class(&self) -> Option<&'static str> {
+        Some(Error::class(self))
+    }
+
+    fn syscall(&self) -> Option<&'static str> {
+        match self.root() {
+            Error::Sys(err) => err.syscall(),
+            _ => None,
+        }
+    }
+
+    fn errno(&self) -> Option<Errno> {
+        match self.root() {
+            Error::Sys(err) => err.errno(),
+            Error::Io(err) => err.errno(),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorCause for SysError {
+    fn class(&self) -> Option<&'static str> {
+        Some("sys")
+    }
+
+    fn syscall(&self) -> Option<&'static str> {
+        Some(self.0)
+    }
+
+    fn errno(&self) -> Option<Errno> {
+        Some(self.1)
+    }
+}
+
+impl ErrorCause for Errno {
+    fn class(&self) -> Option<&'static str> {
+        Some("sys")
+    }
+
+    fn errno(&self) -> Option<Errno> {
+        Some(*self)
+    }
+}
+
+impl ErrorCause for io::Error {
+    fn class(&self) -> Option<&'static str> {
+        Some("io")
+    }
+
+    fn errno(&self) -> Option<Errno> {
+        Errno::from_io_error(self)
+    }
+}
+
+impl<T: ErrorCause + ?Sized> ErrorCause for &T {
+    fn class(&self) -> Option<&'static str> {
+        (**self).class()
+    }
+
+    fn syscall(&self) -> Option<&'static str> {
+        (**self).syscall()
+    }
+
+    fn errno(&self) -> Option<Errno> {
+        (**self).errno()
+    }
+}
+
+impl ErrorCause for fmt::Error {
+    fn class(&self) -> Option<&'static str> {
+        Some("format")
+    }
+}
+
+impl ErrorCause for str {}
+impl ErrorCause for String {}
+impl ErrorCause for Cow<'_, str> {}
+impl ErrorCause for i32 {}
+
+/// Fatal error, reported as JSON object with --errors-json.
+pub struct ErrorReport<'a> {
+    /// Exit code.
+    pub code: i32,
+    /// Failure class, see Error::class().
+    pub class: Option<&'static str>,
+    /// Failed system call, e.g. "waitpid()".
+    pub syscall: Option<&'static str>,
+    /// Error number of failed system call or I/O operation.
+    pub errno: Option<Errno>,
+    /// Human-readable message, same as without --errors-json.
+    pub message: &'a str,
+}
+
+impl<'a> ErrorReport<'a> {
+    /// Build report from message and error that caused it.
+    pub fn new(code: i32, message: &'a str, cause: &impl ErrorCause) -> Self {
+        ErrorReport {
+            code,
+            class: cause.class(),
+            syscall: cause.syscall(),
+            errno: cause.errno(),
+            message,
+        }
+    }
+
+    /// Format report as single-line JSON object.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"code\":{},\"class\":{},\"syscall\":{},\"errno\":{},\"message\":{}}}",
+            self.code,
+            self.class.map_or("null".into(), json_string),
+            self.syscall.map_or("null".into(), json_string),
+            json_number(self.errno.map(|errno| errno.raw_os_error())),
+            json_string(self.message)
+        )
+    }
+}
@@ -0,0 +1,147 @@
+use crate::buffer::Buffer;
+use crate::format::LineStyle;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::time::Instant;
+
+/// What kind of data is carried by CaptureEvent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    /// Separator between sessions appended to one file (--separator).
+    Separator,
+    /// Header line (--header).
+    Header,
+    /// Line of command output, possibly prefixed with timestamp.
+    Output,
+    /// Footer line (--footer).
+    Footer,
+    /// Marker line added from keyboard (--command-key).
+    Marker,
+    /// Lifecycle event of reclog itself (--trace-self).
+    Trace,
+    /// Command's terminal was resized; carries no data, only goes to
+    /// recordings that support it (--timing, --ttyrec).
+    Resize { cols: u16, rows: u16 },
+}
+
+/// Piece of recording emitted by capture loop.
+pub struct CaptureEvent {
+    /// When data was read from command or formatted.
+    pub ts: Instant,
+    pub stream: Stream,
+    /// Line, as it goes to output file (before stripping).
+    pub bytes: Buffer,
+    /// Length of timestamp prefix at the beginning of bytes, which is cut
+    /// by sinks without timestamps (--sink-format).
+    pub ts_len: usize,
+    /// How line is rendered on terminal, or None if it's not displayed.
+    pub display: Option<LineStyle>,
+}
+
+/// Consumer of capture events.
+/// Sinks are invoked from capture loop one by one, so a sink should never
+/// block for long, otherwise it delays other sinks and reading of command
+/// output. A sink that may be slow should hand events over to its own
+/// thread, see channel().
+pub trait EventSink {
+    fn consume(&mut self, event: &Arc<CaptureEvent>);
+
+    /// Invoked when there are no more pending events, before capture loop
+    /// blocks waiting for command output.
+    fn idle(&mut self) {}
+}
+
+impl<T: EventSink + ?Sized> EventSink for &mut T {
+    fn consume(&mut self, event: &Arc<CaptureEvent>) {
+        (**self).consume(event);
+    }
+
+    fn idle(&mut self) {
+        (**self).idle();
+    }
+}
+
+/// Delivers every event to all subscribed sinks.
+/// Events are shared, so sinks that need to modify data (e.g. to colorize
+/// it) make their own copy.
+#[derive(Default)]
+pub struct EventBus<'a> {
+    sinks: Vec<Box<dyn EventSink + 'a>>,
+}
+
+impl<'a> EventBus<'a> {
+    /// Add sink; sinks get events in order of subscription.
+    pub fn subscribe(&mut self, sink: impl EventSink + 'a) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub fn emit(&mut self, event: CaptureEvent) {
+        let event = Arc::new(event);
+        for sink in &mut self.sinks {
+            sink.consume(&event);
+        }
+    }
+
+    /// Tell sinks that capture loop is going to wait for more output.
+    pub fn idle(&mut self) {
+        for sink in &mut self.sinks {
+            sink.idle();
+        }
+    }
+}
+
+/// Capacity of event queue: number of events, or None if unbounded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueSize(pub Option<usize>);
+
+impl FromStr for QueueSize {
+    type Err = String;
+
+    /// Parse number or "unlimited".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "unlimited" {
+            return Ok(QueueSize(None));
+        }
+        match s.parse::<usize>() {
+            Ok(n) => Ok(QueueSize(Some(n))),
+            Err(_) => Err(format!(
+                "invalid size '{}', expected number or 'unlimited'",
+                s
+            )),
+        }
+    }
+}
+
+/// Sink that passes events to another thread via channel.
+/// When bounded channel is full, consume() blocks until the thread catches
+/// up, so that no events are lost.
+pub enum ChannelSink {
+    Bounded(SyncSender<Arc<CaptureEvent>>),
+    Unbounded(Sender<Arc<CaptureEvent>>),
+}
+
+impl EventSink for ChannelSink {
+    fn consume(&mut self, event: &Arc<CaptureEvent>) {
+        // Receiver never goes away before sender.
+        _ = match self {
+            ChannelSink::Bounded(sender) => sender.send(Arc::clone(event)),
+            ChannelSink::Unbounded(sender) => sender.send(Arc::clone(event)),
+        };
+    }
+}
+
+/// Create channel that holds up to `capacity` events.
+/// Receiver gets disconnected when sink is dropped together with its bus.
+pub fn channel(capacity: QueueSize) -> (ChannelSink, Receiver<Arc<CaptureEvent>>) {
+    match capacity {
+        QueueSize(Some(capacity)) => {
+            let (sender, receiver) = mpsc::sync_channel(capacity);
+            (ChannelSink::Bounded(sender), receiver)
+        }
+        QueueSize(None) => {
+            let (sender, receiver) = mpsc::channel();
+            (ChannelSink::Unbounded(sender), receiver)
+        }
+    }
+}
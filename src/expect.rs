@@ -0,0 +1,132 @@
+use crate::term;
+use regex_lite::Regex;
+use rustix::io::Errno;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+/// How much of current line is kept for matching; prompts are short.
+const MAX_TAIL: usize = 4096;
+
+/// Prompt and answer, parsed from "REGEX=>RESPONSE".
+/// When command output matches the regex, response is written to command's
+/// input. Response may contain escapes: \n, \r, \t, \e, \\.
+#[derive(Clone, Debug)]
+pub struct ExpectRule {
+    pattern: Regex,
+    response: Vec<u8>,
+}
+
+impl FromStr for ExpectRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (regex, response) = match s.split_once("=>") {
+            Some(parts) => parts,
+            None => return Err("expected REGEX=>RESPONSE".into()),
+        };
+        if regex.is_empty() {
+            return Err("regex can't be empty".into());
+        }
+
+        Ok(ExpectRule {
+            pattern: Regex::new(regex).map_err(|err| err.to_string())?,
+            response: unescape(response)?,
+        })
+    }
+}
+
+/// Expand escapes in response.
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut result = Vec::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            result.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        result.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('e') => b'\x1b',
+            Some('\\') => b'\\',
+            Some(c) => return Err(format!("unknown escape '\\{}' in response", c)),
+            None => return Err("trailing '\\' in response".into()),
+        });
+    }
+
+    Ok(result)
+}
+
+/// Reader that watches command output for prompts and answers them by
+/// writing responses to command's input (--expect).
+/// Prompts usually don't end with newline, so output is matched as it
+/// arrives, before it's split into lines. Each rule is matched against
+/// current line so far, with ANSI escape codes stripped; after response
+/// is sent, the line is forgotten, so that the same prompt isn't answered
+/// twice.
+/// Without rules, reads are passed through as is.
+pub struct ExpectReader<R: Read, W: Write> {
+    input: R,
+    output: W,
+    rules: Vec<ExpectRule>,
+    // Current line, up to MAX_TAIL last bytes.
+    tail: Vec<u8>,
+}
+
+impl<R: Read, W: Write> ExpectReader<R, W> {
+    pub fn new(input: R, output: W, rules: Vec<ExpectRule>) -> Self {
+        ExpectReader {
+            input,
+            output,
+            rules,
+            tail: Vec::new(),
+        }
+    }
+
+    /// Append output to current line and answer prompt if it matches.
+    fn feed(&mut self, chunk: &[u8]) -> io::Result<()> {
+        for segment in chunk.split_inclusive(|&b| b == b'\n') {
+            self.tail.extend_from_slice(segment);
+            if self.tail.len() > MAX_TAIL {
+                self.tail.drain(..self.tail.len() - MAX_TAIL);
+            }
+
+            let line = term::strip_ansi(&self.tail);
+            if let Some(rule) = self.rules.iter().find(|rule| rule.pattern.is_match(&line)) {
+                self.tail.clear();
+                respond(&mut self.output, &rule.response)?;
+            }
+
+            if segment.ends_with(b"\n") {
+                self.tail.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read, W: Write> Read for ExpectReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.input.read(buf)?;
+        if !self.rules.is_empty() {
+            self.feed(&buf[..size])?;
+        }
+        Ok(size)
+    }
+}
+
+/// Write response to command's input.
+fn respond<W: Write>(output: &mut W, response: &[u8]) -> io::Result<()> {
+    match output.write_all(response).and_then(|_| output.flush()) {
+        Ok(()) => Ok(()),
+        // Command exited, nobody to answer; reading will report it.
+        Err(err) if matches!(Errno::from_io_error(&err), Some(Errno::IO | Errno::PIPE)) => Ok(()),
+        Err(err) => Err(io::Error::new(
+            err.kind(),
+            format!("can't write response: {}", err),
+        )),
+    }
+}
@@ -0,0 +1,341 @@
+use crate::term::{AnsiStripper, CrMode, StripLevel};
+use std::fmt::Write as _;
+use std::io::{self, Error, Read, Write};
+
+/// Default colors of page, also used for inverse video.
+const DEFAULT_FG: &str = "#d0d0d0";
+const DEFAULT_BG: &str = "#1c1c1c";
+
+/// Standard and bright colors (SGR 30-37 and 90-97), as in xterm.
+const BASE_COLORS: [&str; 16] = [
+    "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+    "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+];
+
+/// Render raw recording into standalone HTML page, with colors and text
+/// attributes preserved (`reclog export --html`).
+///
+/// Recording is first passed through stripper that keeps only SGR codes
+/// and applies carriage returns like a terminal, so that progress bars
+/// show their final state. Remaining text and SGR codes are converted to
+/// HTML by HtmlWriter.
+pub fn export_html<R: Read, W: Write>(mut input: R, mut output: W, title: &str) -> io::Result<()> {
+    write!(
+        output,
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <title>{}</title>\n\
+         <style>\n\
+         body {{ background: {}; color: {}; margin: 1em; }}\n\
+         pre {{ font-family: monospace; white-space: pre-wrap; margin: 0; }}\n\
+         </style>\n\
+         </head>\n\
+         <body>\n\
+         <pre>",
+        escape_html(title),
+        DEFAULT_BG,
+        DEFAULT_FG,
+    )?;
+
+    let mut stripper = AnsiStripper::with_modes(
+        HtmlWriter::new(&mut output),
+        StripLevel::Control,
+        CrMode::Overwrite,
+    );
+    io::copy(&mut input, &mut stripper)?;
+    stripper.flush()?;
+    drop(stripper);
+
+    write!(output, "</pre>\n</body>\n</html>\n")?;
+    output.flush()
+}
+
+/// Color set by SGR code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Color {
+    /// One of 256 palette colors.
+    Index(u8),
+    /// 24-bit color.
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    fn to_css(self) -> String {
+        match self {
+            Color::Index(n @ 0..16) => BASE_COLORS[n as usize].into(),
+            // 6x6x6 color cube.
+            Color::Index(n @ 16..232) => {
+                let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+                let n = n - 16;
+                format!(
+                    "#{:02x}{:02x}{:02x}",
+                    level(n / 36),
+                    level(n / 6 % 6),
+                    level(n % 6)
+                )
+            }
+            // Grayscale ramp.
+            Color::Index(n) => {
+                let v = 8 + (n - 232) * 10;
+                format!("#{:02x}{:02x}{:02x}", v, v, v)
+            }
+            Color::Rgb(r, g, b) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        }
+    }
+}
+
+/// Text style accumulated from SGR codes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Style {
+    fg: Option<Color>,
+    bg: Option<Color>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    inverse: bool,
+    strike: bool,
+}
+
+impl Style {
+    /// Apply SGR sequence parameters.
+    fn apply(&mut self, params: &vte::Params) {
+        // Extended colors may be given either as subparameters
+        // ("38:5:N") or as subsequent parameters ("38;5;N").
+        let params: Vec<&[u16]> = params.iter().collect();
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+
+        let mut n = 0;
+        while n < params.len() {
+            let param = params[n];
+            n += 1;
+            match param[0] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                9 => self.strike = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                29 => self.strike = false,
+                code @ 30..=37 => self.fg = Some(Color::Index(code as u8 - 30)),
+                39 => self.fg = None,
+                code @ 40..=47 => self.bg = Some(Color::Index(code as u8 - 40)),
+                49 => self.bg = None,
+                code @ 90..=97 => self.fg = Some(Color::Index(code as u8 - 90 + 8)),
+                code @ 100..=107 => self.bg = Some(Color::Index(code as u8 - 100 + 8)),
+                code @ (38 | 48) => {
+                    let color = if param.len() > 1 {
+                        parse_color(&param[1..])
+                    } else {
+                        let rest: Vec<u16> = params[n..].iter().map(|p| p[0]).collect();
+                        let color = parse_color(&rest);
+                        n += match rest.first() {
+                            Some(5) => 2,
+                            Some(2) => 4,
+                            _ => 0,
+                        };
+                        color
+                    };
+                    if code == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// CSS declarations for span, empty for default style.
+    fn to_css(self) -> String {
+        let (mut fg, mut bg) = (self.fg.map(Color::to_css), self.bg.map(Color::to_css));
+        if self.inverse {
+            (fg, bg) = (
+                Some(bg.unwrap_or(DEFAULT_BG.into())),
+                Some(fg.unwrap_or(DEFAULT_FG.into())),
+            );
+        }
+
+        let mut css = String::new();
+        if let Some(fg) = fg {
+            _ = write!(css, "color:{};", fg);
+        }
+        if let Some(bg) = bg {
+            _ = write!(css, "background:{};", bg);
+        }
+        if self.bold {
+            css.push_str("font-weight:bold;");
+        }
+        if self.dim {
+            css.push_str("opacity:0.6;");
+        }
+        if self.italic {
+            css.push_str("font-style:italic;");
+        }
+        match (self.underline, self.strike) {
+            (true, true) => css.push_str("text-decoration:underline line-through;"),
+            (true, false) => css.push_str("text-decoration:underline;"),
+            (false, true) => css.push_str("text-decoration:line-through;"),
+            (false, false) => {}
+        }
+        css
+    }
+}
+
+/// Parse extended color: "5;N" or "2;R;G;B".
+fn parse_color(params: &[u16]) -> Option<Color> {
+    match params {
+        [5, n, ..] => Some(Color::Index(*n as u8)),
+        [2, r, g, b, ..] => Some(Color::Rgb(*r as u8, *g as u8, *b as u8)),
+        _ => None,
+    }
+}
+
+/// Escape text for HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Writer that converts text with SGR codes into HTML, wrapping styled
+/// text into spans.
+struct HtmlWriter<W: Write> {
+    parser: vte::Parser,
+    performer: HtmlPerformer<W>,
+}
+
+impl<W: Write> HtmlWriter<W> {
+    fn new(output: W) -> Self {
+        HtmlWriter {
+            parser: vte::Parser::new(),
+            performer: HtmlPerformer {
+                output,
+                style: Style::default(),
+                span_open: false,
+                last_err: None,
+            },
+        }
+    }
+}
+
+impl<W: Write> Write for HtmlWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        // Page is declared as UTF-8, so invalid sequences are replaced.
+        for chunk in buf.utf8_chunks() {
+            self.parser
+                .advance(&mut self.performer, chunk.valid().as_bytes());
+            if !chunk.invalid().is_empty() {
+                vte::Perform::print(&mut self.performer, char::REPLACEMENT_CHARACTER);
+            }
+        }
+
+        if let Some(err) = self.performer.last_err.take() {
+            return Err(err);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.performer.close_span();
+
+        if let Some(err) = self.performer.last_err.take() {
+            return Err(err);
+        }
+
+        self.performer.output.flush()
+    }
+}
+
+/// Implements callbacks for vte::Parser.
+struct HtmlPerformer<W: Write> {
+    output: W,
+    style: Style,
+    // Span is opened lazily before first character with non-default style.
+    span_open: bool,
+    last_err: Option<Error>,
+}
+
+impl<W: Write> HtmlPerformer<W> {
+    fn write_str(&mut self, text: &str) {
+        if self.last_err.is_none() {
+            self.last_err = self.output.write_all(text.as_bytes()).err();
+        }
+    }
+
+    fn open_span(&mut self) {
+        if self.span_open {
+            return;
+        }
+        let css = self.style.to_css();
+        if !css.is_empty() {
+            self.write_str(&format!("<span style=\"{}\">", css));
+            self.span_open = true;
+        }
+    }
+
+    fn close_span(&mut self) {
+        if self.span_open {
+            self.write_str("</span>");
+            self.span_open = false;
+        }
+    }
+}
+
+impl<W: Write> vte::Perform for HtmlPerformer<W> {
+    fn print(&mut self, c: char) {
+        self.open_span();
+        match c {
+            '&' => self.write_str("&amp;"),
+            '<' => self.write_str("&lt;"),
+            '>' => self.write_str("&gt;"),
+            _ => {
+                let mut buf = [0u8; 4];
+                self.write_str(c.encode_utf8(&mut buf));
+            }
+        }
+    }
+
+    fn execute(&mut self, b: u8) {
+        match b {
+            b'\n' => self.write_str("\n"),
+            b'\t' => {
+                self.open_span();
+                self.write_str("\t");
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(
+        &mut self,
+        params: &vte::Params,
+        intermediates: &[u8],
+        ignore: bool,
+        action: char,
+    ) {
+        if ignore || !intermediates.is_empty() || action != 'm' {
+            return;
+        }
+
+        let mut style = self.style;
+        style.apply(params);
+        if style != self.style {
+            self.close_span();
+            self.style = style;
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use crate::format::is_footer;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::Duration;
+
+/// How often file is checked for changes if there is no notification
+/// mechanism.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often file is checked for changes with notifications, as a safety
+/// net for missed ones (e.g. on network filesystems).
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many last bytes of followed recording are kept to find footer.
+const FOOTER_SIZE: usize = 4096;
+
+/// Follow file like `tail -F` and copy data appended to it to output.
+/// Used by helper process of `reclog follow`.
+///
+/// Starts from the end of file. If file is truncated, it's read from the
+/// beginning again. If file is replaced (e.g. rotated), the rest of old
+/// file is read and then new file is followed from the beginning.
+///
+/// Runs until error, e.g. until output is closed.
+pub fn follow<W: Write>(path: &Path, output: W) -> io::Result<()> {
+    follow_file(path, SeekFrom::End(0), output, |_| false)
+}
+
+/// Follow recording written by another reclog and copy it to output,
+/// starting from last `lines` lines (or from the beginning if None).
+/// Used by `reclog attach`.
+///
+/// Returns when recording is finished, i.e. when footer is the last line
+/// of file.
+pub fn follow_recording<W: Write>(path: &Path, lines: Option<u64>, output: W) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let start = match lines {
+        Some(lines) => tail_offset(&mut file, lines)?,
+        None => 0,
+    };
+
+    // Footer may be already written before start.
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(start.saturating_sub(FOOTER_SIZE as u64)))?;
+    file.take(start.min(FOOTER_SIZE as u64))
+        .read_to_end(&mut tail)?;
+
+    follow_file(path, SeekFrom::Start(start), output, |data| {
+        tail.extend_from_slice(data);
+        if tail.len() > FOOTER_SIZE {
+            tail.drain(..tail.len() - FOOTER_SIZE);
+        }
+        let Some(text) = tail.strip_suffix(b"\n") else {
+            return false;
+        };
+        let line_start = text.iter().rposition(|&b| b == b'\n').map_or(0, |n| n + 1);
+        is_footer(&String::from_utf8_lossy(&text[line_start..]))
+    })
+}
+
+/// Follow file starting from given position, until `done` returns true
+/// for data read so far.
+fn follow_file<W: Write>(
+    path: &Path,
+    start: SeekFrom,
+    mut output: W,
+    mut done: impl FnMut(&[u8]) -> bool,
+) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    file.seek(start)?;
+    let mut cur_id = file_id(&file.metadata()?);
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let watcher = Watcher::new(dir)?;
+
+    let mut buf = vec![0; 65536];
+    let mut finished = done(&[]);
+    loop {
+        // Copy everything appended since last time.
+        loop {
+            let size = file.read(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+            output.write_all(&buf[..size])?;
+            finished = done(&buf[..size]);
+        }
+        output.flush()?;
+        if finished {
+            return Ok(());
+        }
+
+        match fs::metadata(path) {
+            // File was replaced, switch to new one.
+            Ok(meta) if file_id(&meta) != cur_id => match File::open(path) {
+                Ok(new_file) => {
+                    cur_id = file_id(&new_file.metadata()?);
+                    file = new_file;
+                    continue;
+                }
+                // Removed again meanwhile.
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            },
+            // File was truncated, start over.
+            Ok(meta) if meta.len() < file.stream_position()? => {
+                file.seek(SeekFrom::Start(0))?;
+                continue;
+            }
+            // Unchanged, or removed and not created yet.
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        watcher.wait()?;
+    }
+}
+
+/// Find offset of the beginning of last `lines` lines of file.
+fn tail_offset(file: &mut File, lines: u64) -> io::Result<u64> {
+    let mut pos = file.seek(SeekFrom::End(0))?;
+    if lines == 0 {
+        return Ok(pos);
+    }
+    let mut buf = vec![0; 65536];
+    // Newline at the very end doesn't start a new line.
+    let mut count = 0;
+    let mut at_end = true;
+
+    while pos > 0 {
+        let size = buf.len().min(pos as usize);
+        pos -= size as u64;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut buf[..size])?;
+
+        for n in (0..size).rev() {
+            if buf[n] != b'\n' {
+                at_end = false;
+                continue;
+            }
+            if at_end {
+                at_end = false;
+                continue;
+            }
+            count += 1;
+            if count == lines {
+                return Ok(pos + n as u64 + 1);
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+/// Device and inode, to detect when file is replaced.
+fn file_id(meta: &fs::Metadata) -> (u64, u64) {
+    (meta.dev(), meta.ino())
+}
+
+/// Waits until something changes in directory, using inotify.
+#[cfg(target_os = "linux")]
+struct Watcher {
+    fd: std::os::fd::OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    fn new(dir: &Path) -> io::Result<Self> {
+        use rustix::fs::inotify::{self, CreateFlags, WatchFlags};
+
+        let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)?;
+        // Directory watch reports events for files in it too, including
+        // files created after rotation.
+        inotify::add_watch(
+            &fd,
+            dir,
+            WatchFlags::MODIFY
+                | WatchFlags::ATTRIB
+                | WatchFlags::CREATE
+                | WatchFlags::DELETE
+                | WatchFlags::MOVED_FROM
+                | WatchFlags::MOVED_TO,
+        )?;
+
+        Ok(Watcher { fd })
+    }
+
+    /// Wait for events or timeout, and discard events.
+    /// Caller re-checks the file anyway.
+    fn wait(&self) -> io::Result<()> {
+        use crate::shim::{self, WaitFd};
+        use std::os::fd::AsFd;
+
+        let mut wait_fd = WaitFd {
+            fd: self.fd.as_fd(),
+            mask: WaitFd::READABLE,
+        };
+        shim::wait_fds(&mut [&mut wait_fd], Some(RECHECK_INTERVAL))?;
+
+        let mut buf = [0; 4096];
+        loop {
+            match rustix::io::read(&self.fd, &mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => continue,
+                Err(err) if err == rustix::io::Errno::AGAIN => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+/// Fallback for systems without inotify: periodic checks.
+#[cfg(not(target_os = "linux"))]
+struct Watcher;
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    fn new(_dir: &Path) -> io::Result<Self> {
+        Ok(Watcher)
+    }
+
+    fn wait(&self) -> io::Result<()> {
+        std::thread::sleep(POLL_INTERVAL);
+        Ok(())
+    }
+}
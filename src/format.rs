@@ -1,7 +1,12 @@
-use chrono::{DateTime, Local, TimeDelta};
+use crate::clock::{Clock, SystemClock};
+use crate::git;
+use chrono::{DateTime, FixedOffset, Local, TimeDelta};
 use clap::ValueEnum;
-use rustix::system;
-use std::fmt;
+use rustix::{process, system};
+use std::env;
+use std::fmt::{self, Write};
+use std::rc::Rc;
+use std::str::FromStr;
 use std::time::Instant;
 
 /// How to calculate timestamps.
@@ -13,19 +18,192 @@ pub enum TimeSource {
     Delta,
 }
 
+/// Format of TIME field in header and footer.
+pub const HEADER_TIME_FMT: &str = "%F %T %z";
+
+/// Format of TIME field in trace lines (--trace-self), with milliseconds.
+pub const TRACE_TIME_FMT: &str = "%F %T%.3f %z";
+
+/// Version of reclog, for VERSION header field and metadata.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Default format of separator between sessions appended to one file.
+pub const DEFAULT_SEPARATOR_FMT: &str = "# SESSION TIME=[%t] PID=[%p] CMD=[%c]";
+
+/// When to use colors on terminal.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+/// When to ring terminal bell after command finishes.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum BellMode {
+    OnFailure,
+    Always,
+}
+
+/// Escape sequences used to render lines on terminal.
+const DIM_START: &str = "\x1b[2m";
+const DIM_END: &str = "\x1b[22m";
+const HIGHLIGHT_START: &str = "\x1b[1;33m";
+const HIGHLIGHT_END: &str = "\x1b[0m";
+const SUCCESS_START: &str = "\x1b[32m";
+const FAILURE_START: &str = "\x1b[1;31m";
+const STATUS_END: &str = "\x1b[0m";
+
+/// How to render line on terminal.
+#[derive(Clone, Copy)]
+pub struct LineStyle {
+    /// Length of extras formatted by us at the beginning of the line
+    /// (timestamp, or whole header/footer line); rendered dimmed.
+    pub prefix_len: usize,
+    /// Whether the rest of the line is highlighted.
+    pub highlight: bool,
+    /// For footer, whether command succeeded; footer is rendered
+    /// green or red instead of dimmed.
+    pub success: Option<bool>,
+}
+
+/// Field of header, selected with --header-fmt.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderField {
+    Host,
+    Os,
+    Kernel,
+    Time,
+    Cmd,
+    User,
+    Cwd,
+    Tty,
+    Session,
+    Git,
+    Term,
+    Locale,
+    Version,
+    Env(String),
+}
+
+impl HeaderField {
+    /// Fields used when --header-fmt is not specified.
+    pub fn defaults(json: bool) -> Vec<HeaderField> {
+        use HeaderField::*;
+        if json {
+            vec![
+                Host, Os, Time, Cmd, Cwd, User, Tty, Session, Term, Locale, Version,
+            ]
+        } else {
+            vec![Host, Os, Time, Cmd]
+        }
+    }
+
+    /// Name of "NAME=[VALUE]" field.
+    fn name(&self) -> String {
+        match self {
+            HeaderField::Session => "SESSION".into(),
+            HeaderField::Env(var) => format!("ENV_{}", var),
+            field => field.key().to_uppercase(),
+        }
+    }
+
+    /// Key of JSON field.
+    fn key(&self) -> String {
+        match self {
+            HeaderField::Host => "host".into(),
+            HeaderField::Os => "os".into(),
+            HeaderField::Kernel => "kernel".into(),
+            HeaderField::Time => "time".into(),
+            HeaderField::Cmd => "cmd".into(),
+            HeaderField::User => "user".into(),
+            HeaderField::Cwd => "cwd".into(),
+            HeaderField::Tty => "tty".into(),
+            HeaderField::Session => "session_id".into(),
+            HeaderField::Git => "git".into(),
+            HeaderField::Term => "term".into(),
+            HeaderField::Locale => "locale".into(),
+            HeaderField::Version => "version".into(),
+            HeaderField::Env(var) => format!("env_{}", var),
+        }
+    }
+}
+
+impl FromStr for HeaderField {
+    type Err = String;
+
+    /// Parse field name, e.g. "host" or "env:PATH".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(var) = s.strip_prefix("env:") {
+            if var.is_empty() || var.contains(['=', '[', ']', ' ', '"', '\\']) {
+                return Err(format!("invalid variable name '{}'", var));
+            }
+            return Ok(HeaderField::Env(var.into()));
+        }
+
+        match s {
+            "host" => Ok(HeaderField::Host),
+            "os" => Ok(HeaderField::Os),
+            "kernel" => Ok(HeaderField::Kernel),
+            "time" => Ok(HeaderField::Time),
+            "cmd" => Ok(HeaderField::Cmd),
+            "user" => Ok(HeaderField::User),
+            "cwd" => Ok(HeaderField::Cwd),
+            "tty" => Ok(HeaderField::Tty),
+            "session" => Ok(HeaderField::Session),
+            "git" => Ok(HeaderField::Git),
+            "term" => Ok(HeaderField::Term),
+            "locale" => Ok(HeaderField::Locale),
+            "version" => Ok(HeaderField::Version),
+            _ => Err(format!(
+                "unknown field '{}', expected one of: host, os, kernel, time, cmd, \
+                 user, cwd, tty, session, git, term, locale, version, env:NAME",
+                s
+            )),
+        }
+    }
+}
+
+/// Value of header field.
+enum FieldValue {
+    Text(Option<String>),
+    Number(Option<i32>),
+    Size(Option<(u16, u16)>),
+}
+
+/// Additional header fields, known only after the command is started.
+pub struct SessionInfo {
+    /// Session ID of the command (equal to its PID).
+    pub session_id: Option<i32>,
+    /// Terminal size, columns and rows.
+    pub tty_size: Option<(u16, u16)>,
+}
+
 /// Formats extras: header and timestamps.
 pub struct Formatter {
     enable_header: bool,
+    enable_footer: bool,
     enable_time: bool,
     time_format: String,
     time_source: TimeSource,
     command: String,
+    header_fields: Vec<HeaderField>,
+    header_json: bool,
+    session: Option<SessionInfo>,
+    separator: Option<(String, Option<i32>)>,
     base_ts: Option<Instant>,
+    clock: Rc<dyn Clock>,
+    resumed: bool,
+    resumed_newline: bool,
+    marks: u64,
 }
 
 impl Formatter {
     pub fn new(
         enable_header: bool,
+        enable_footer: bool,
         enable_time: bool,
         time_format: &str,
         time_source: TimeSource,
@@ -33,11 +211,20 @@ impl Formatter {
     ) -> Self {
         Formatter {
             enable_header,
+            enable_footer,
             enable_time,
             time_format: time_format.into(),
             time_source,
             command: command.join(" "),
+            header_fields: HeaderField::defaults(false),
+            header_json: false,
+            session: None,
+            separator: None,
             base_ts: None,
+            clock: Rc::new(SystemClock),
+            resumed: false,
+            resumed_newline: false,
+            marks: 0,
         }
     }
 
@@ -46,21 +233,252 @@ impl Formatter {
         self.enable_header
     }
 
+    /// Take time from given clock instead of system clock.
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Get clock used for timestamps.
+    pub fn clock(&self) -> Rc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    /// Select header fields and their order.
+    /// If `json` is set, header is formatted as JSON object instead of
+    /// "NAME=[VALUE]" fields.
+    pub fn set_header_format(&mut self, fields: Vec<HeaderField>, json: bool) {
+        self.header_fields = fields;
+        self.header_json = json;
+    }
+
+    /// Provide values of header fields describing running command.
+    pub fn set_session_info(&mut self, info: SessionInfo) {
+        self.session = Some(info);
+    }
+
+    /// Write separator line before the new session appended to non-empty
+    /// file. In `format`, "%t" is replaced with time, "%c" with command,
+    /// "%p" with `pid`, and "%%" with "%".
+    pub fn set_separator(&mut self, format: &str, pid: Option<i32>) {
+        self.separator = Some((format.into(), pid));
+    }
+
+    /// True if separator should be formatted.
+    pub fn need_separator(&self) -> bool {
+        self.separator.is_some()
+    }
+
+    /// Format separator to string.
+    pub fn format_separator(&mut self, result: &mut String) -> fmt::Result {
+        let Some((format, pid)) = self.separator.take() else {
+            return Ok(());
+        };
+
+        let mut chars = format.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                result.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('t') => self.clock.wall().format(HEADER_TIME_FMT).write_to(result)?,
+                Some('c') => result.push_str(&self.command),
+                Some('p') => match pid {
+                    Some(pid) => write!(result, "{}", pid)?,
+                    None => result.push('-'),
+                },
+                Some('%') => result.push('%'),
+                Some(other) => {
+                    result.push('%');
+                    result.push(other);
+                }
+                None => result.push('%'),
+            }
+        }
+        result.push('\n');
+
+        Ok(())
+    }
+
+    /// Continue existing recording instead of starting a new one.
+    /// Header is replaced with a "resumed" marker, and elapsed time is
+    /// counted from `start_time` (taken from the header of the recording).
+    /// If `need_newline` is set, the marker is preceded with a newline to
+    /// terminate incomplete last line of the recording.
+    pub fn set_resumed(&mut self, start_time: Option<DateTime<FixedOffset>>, need_newline: bool) {
+        self.resumed = true;
+        self.resumed_newline = need_newline;
+        self.enable_header = true;
+
+        if let Some(start_time) = start_time {
+            if let Ok(elapsed) = (self.clock.wall().fixed_offset() - start_time).to_std() {
+                self.base_ts = self.clock.now().checked_sub(elapsed);
+            }
+        }
+    }
+
     /// Format header to string.
     pub fn format_header(&mut self, result: &mut String) -> fmt::Result {
-        let date = Local::now().format("%F %T %z");
+        let date = self.clock.wall().format(HEADER_TIME_FMT);
+
+        if self.resumed {
+            if self.resumed_newline {
+                result.push('\n');
+            }
+            result.push_str(&format!("# RESUMED TIME=[{}]\n", date));
+            self.enable_header = false;
+            return Ok(());
+        }
+
         let info = system::uname();
+        let mut fields = Vec::new();
+
+        for field in &self.header_fields {
+            let value = match field {
+                HeaderField::Host => {
+                    FieldValue::Text(Some(info.nodename().to_string_lossy().into_owned()))
+                }
+                HeaderField::Os => FieldValue::Text(Some(format!(
+                    "{}_{}",
+                    info.sysname().to_string_lossy().to_lowercase(),
+                    info.machine().to_string_lossy()
+                ))),
+                HeaderField::Kernel => {
+                    FieldValue::Text(Some(info.release().to_string_lossy().into_owned()))
+                }
+                HeaderField::Time => FieldValue::Text(Some(date.to_string())),
+                HeaderField::Cmd => FieldValue::Text(Some(self.command.clone())),
+                HeaderField::User => FieldValue::Text(Some(
+                    env::var("USER")
+                        .or_else(|_| env::var("LOGNAME"))
+                        .unwrap_or_else(|_| process::getuid().as_raw().to_string()),
+                )),
+                HeaderField::Cwd => FieldValue::Text(
+                    env::current_dir()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .ok(),
+                ),
+                HeaderField::Tty => {
+                    FieldValue::Size(self.session.as_ref().and_then(|s| s.tty_size))
+                }
+                HeaderField::Session => {
+                    FieldValue::Number(self.session.as_ref().and_then(|s| s.session_id))
+                }
+                HeaderField::Git => {
+                    FieldValue::Text(env::current_dir().ok().and_then(|dir| git::revision(&dir)))
+                }
+                HeaderField::Term => FieldValue::Text(env::var("TERM").ok()),
+                HeaderField::Locale => FieldValue::Text(locale()),
+                HeaderField::Version => FieldValue::Text(Some(VERSION.into())),
+                HeaderField::Env(var) => FieldValue::Text(env::var(var).ok()),
+            };
+
+            if self.header_json {
+                let key = field.key();
+                match value {
+                    FieldValue::Text(text) => fields.push(format!(
+                        "{}:{}",
+                        json_string(&key),
+                        text.as_deref().map_or("null".into(), json_string)
+                    )),
+                    FieldValue::Number(number) => {
+                        fields.push(format!("{}:{}", json_string(&key), json_number(number)))
+                    }
+                    FieldValue::Size(size) => {
+                        fields.push(format!(
+                            "{}:{}",
+                            json_string(&format!("{}_cols", key)),
+                            json_number(size.map(|(cols, _)| cols))
+                        ));
+                        fields.push(format!(
+                            "{}:{}",
+                            json_string(&format!("{}_rows", key)),
+                            json_number(size.map(|(_, rows)| rows))
+                        ));
+                    }
+                }
+            } else {
+                let text = match value {
+                    FieldValue::Text(text) => text,
+                    FieldValue::Number(number) => number.map(|n| n.to_string()),
+                    FieldValue::Size(size) => size.map(|(cols, rows)| format!("{}x{}", cols, rows)),
+                };
+                fields.push(format!(
+                    "{}=[{}]",
+                    field.name(),
+                    text.as_deref().unwrap_or("-")
+                ));
+            }
+        }
+
+        if self.header_json {
+            result.push_str(&format!("# {{{}}}\n", fields.join(",")));
+        } else {
+            result.push_str(&format!("# {}\n", fields.join(" ")));
+        }
+
+        self.enable_header = false;
+
+        Ok(())
+    }
+
+    /// Get configured time source.
+    pub fn time_source(&self) -> TimeSource {
+        self.time_source
+    }
+
+    /// True if footer should be formatted.
+    pub fn need_footer(&self) -> bool {
+        self.enable_footer
+    }
+
+    /// Format footer to string.
+    /// `status` is exit code or signal name, `stream_end` is the reason
+    /// why reading of command output was finished.
+    pub fn format_footer(
+        &mut self,
+        result: &mut String,
+        status: &str,
+        stream_end: &str,
+    ) -> fmt::Result {
+        let date = self.clock.wall().format(HEADER_TIME_FMT);
 
         result.push_str(&format!(
-            "# HOST=[{}] OS=[{}_{}] TIME=[{}] CMD=[{}]\n",
-            info.nodename().to_str().unwrap(),
-            info.sysname().to_str().unwrap().to_lowercase(),
-            info.machine().to_str().unwrap(),
-            date,
-            self.command
+            "# TIME=[{}] STATUS=[{}] EOF=[{}]\n",
+            date, status, stream_end
         ));
 
-        self.enable_header = false;
+        self.enable_footer = false;
+
+        Ok(())
+    }
+
+    /// Format marker requested by user at given time to string.
+    /// Markers are numbered from 1.
+    pub fn format_marker(&mut self, result: &mut String, time: DateTime<Local>) -> fmt::Result {
+        self.marks += 1;
+
+        result.push_str(&format!(
+            "# MARK TIME=[{}] N=[{}]\n",
+            time.format(HEADER_TIME_FMT),
+            self.marks
+        ));
+
+        Ok(())
+    }
+
+    /// Format lifecycle event of reclog recorded at given time to string.
+    pub fn format_trace(
+        &mut self,
+        result: &mut String,
+        time: DateTime<Local>,
+        event: &impl fmt::Display,
+    ) -> fmt::Result {
+        result.push_str(&format!(
+            "# TRACE TIME=[{}] {}\n",
+            time.format(TRACE_TIME_FMT),
+            event
+        ));
 
         Ok(())
     }
@@ -72,19 +490,24 @@ impl Formatter {
 
     /// Format timestamp to string.
     pub fn format_timestamp(&mut self, result: &mut String) -> fmt::Result {
+        let now = self.clock.now();
+
         match self.time_source {
             TimeSource::Wall => {
-                let now = Local::now();
-                now.format(&self.time_format).write_to(result)?;
+                self.clock
+                    .wall()
+                    .format(&self.time_format)
+                    .write_to(result)?;
             }
             TimeSource::Elapsed | TimeSource::Delta => {
-                let now = Instant::now();
                 if self.base_ts.is_none() {
                     self.base_ts = Some(now);
                 }
 
-                let delta = DateTime::UNIX_EPOCH
-                    + TimeDelta::from_std(now - self.base_ts.unwrap()).unwrap();
+                let delta = TimeDelta::from_std(now - self.base_ts.unwrap())
+                    .ok()
+                    .and_then(|delta| DateTime::UNIX_EPOCH.checked_add_signed(delta))
+                    .ok_or(fmt::Error)?;
                 delta.format(&self.time_format).write_to(result)?;
 
                 if self.time_source == TimeSource::Delta {
@@ -96,3 +519,210 @@ impl Formatter {
         Ok(())
     }
 }
+
+/// Renders lines on terminal: colorizes extras and adds terminal-only
+/// fields, so that the file gets plain lines.
+pub struct Painter {
+    enable_color: bool,
+    display_path: Option<String>,
+}
+
+impl Painter {
+    pub fn new(enable_color: bool) -> Self {
+        Painter {
+            enable_color,
+            display_path: None,
+        }
+    }
+
+    /// Show output path in terminal variant of the footer.
+    pub fn set_display_path(&mut self, path: &str) {
+        self.display_path = Some(path.into());
+    }
+
+    /// True if extras should be colorized on terminal.
+    pub fn need_color(&self) -> bool {
+        self.enable_color
+    }
+
+    /// Add terminal-only fields to the footer.
+    pub fn extend_footer(&self, line: &mut Vec<u8>) {
+        if let Some(path) = &self.display_path {
+            let text_len = text_len(line);
+            insert_bytes(line, text_len, &format!(" FILE=[{}]", path));
+        }
+    }
+
+    /// Turn file variant of the line into terminal variant.
+    pub fn colorize_line(&self, line: &mut Vec<u8>, style: &LineStyle) {
+        let text_len = text_len(line);
+        let prefix_len = style.prefix_len.min(text_len);
+
+        if let Some(success) = style.success {
+            if prefix_len > 0 {
+                insert_bytes(line, prefix_len, STATUS_END);
+                insert_bytes(
+                    line,
+                    0,
+                    if success {
+                        SUCCESS_START
+                    } else {
+                        FAILURE_START
+                    },
+                );
+            }
+            return;
+        }
+
+        if style.highlight && prefix_len < text_len {
+            insert_bytes(line, text_len, HIGHLIGHT_END);
+            insert_bytes(line, prefix_len, HIGHLIGHT_START);
+        }
+        if prefix_len > 0 {
+            insert_bytes(line, prefix_len, DIM_END);
+            insert_bytes(line, 0, DIM_START);
+        }
+    }
+}
+
+/// Get locale used for character classification, as selected by
+/// environment variables, e.g. "en_US.UTF-8".
+pub fn locale() -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Format string as JSON string literal.
+pub fn json_string(text: &str) -> String {
+    let mut result = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Format optional number as JSON number or null.
+pub fn json_number<T: fmt::Display>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "null".into(),
+    }
+}
+
+/// Length of the line without trailing CR and LF.
+fn text_len(line: &[u8]) -> usize {
+    line.len()
+        - line
+            .iter()
+            .rev()
+            .take_while(|&&b| b == b'\r' || b == b'\n')
+            .count()
+}
+
+fn insert_bytes(line: &mut Vec<u8>, pos: usize, text: &str) {
+    line.splice(pos..pos, text.bytes());
+}
+
+/// Names of "NAME=[VALUE]" header fields, besides ENV_*.
+const HEADER_NAMES: &[&str] = &[
+    "HOST", "OS", "KERNEL", "TIME", "CMD", "USER", "CWD", "TTY", "SESSION", "GIT",
+];
+
+/// True if line is a header, in either format.
+/// Header may start with any field selected by --header-fmt.
+pub fn is_header(line: &str) -> bool {
+    if let Some(rest) = line.strip_prefix("# {\"") {
+        let Some((key, _)) = rest.split_once('"') else {
+            return false;
+        };
+        return key.starts_with("env_")
+            || ["session_id", "tty_cols"].contains(&key)
+            || HEADER_NAMES.contains(&key.to_uppercase().as_str());
+    }
+
+    let Some((name, _)) = line
+        .strip_prefix("# ")
+        .and_then(|rest| rest.split_once("=["))
+    else {
+        return false;
+    };
+    // Footer starts with TIME too.
+    if name == "TIME" && line.contains(" STATUS=[") {
+        return false;
+    }
+    name.starts_with("ENV_") || HEADER_NAMES.contains(&name)
+}
+
+/// True if line is a footer.
+pub fn is_footer(line: &str) -> bool {
+    line.starts_with("# TIME=[") && line.contains(" STATUS=[")
+}
+
+/// Get value of "NAME=[VALUE]" field from header or footer.
+/// The last field may contain brackets (e.g. command).
+/// For JSON header, gets value of "name" string field, without unescaping.
+pub fn parse_field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let line = line.trim_end();
+    if line.starts_with("# {") {
+        return parse_json_field(line, name);
+    }
+    let start = line.find(&format!("{}=[", name))? + name.len() + 2;
+    let rest = &line[start..];
+
+    // Value ends at "] NEXT=[" or at the end of line.
+    let mut pos = 0;
+    while let Some(n) = rest[pos..].find("] ") {
+        let end = pos + n;
+        let next = &rest[end + 2..];
+        let next_name_len = next.find("=[").unwrap_or(0);
+        if next_name_len > 0
+            && next[..next_name_len]
+                .bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+        {
+            return Some(&rest[..end]);
+        }
+        pos = end + 2;
+    }
+
+    rest.strip_suffix(']')
+}
+
+fn parse_json_field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{}\":\"", name.to_lowercase());
+    let start = line.find(&key)? + key.len();
+    let rest = &line[start..];
+
+    // Value ends at first quote that is not escaped.
+    let mut escaped = false;
+    for (pos, ch) in rest.char_indices() {
+        match ch {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(&rest[..pos]),
+            _ => escaped = false,
+        }
+    }
+
+    None
+}
+
+/// Get start time from header line.
+/// Returns None if line is not a header or time is malformed.
+pub fn parse_header_time(line: &str) -> Option<DateTime<FixedOffset>> {
+    if !is_header(line) {
+        return None;
+    }
+    let time = parse_field(line, "TIME")?;
+    DateTime::parse_from_str(time, HEADER_TIME_FMT).ok()
+}
@@ -0,0 +1,331 @@
+use crate::format::json_string;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use rustix::system;
+use std::io::{self, Error, ErrorKind, Write};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::process;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default port of Graylog GELF UDP input.
+const DEFAULT_PORT: u16 = 12201;
+
+/// Maximum size of chunk payload; with 12-byte chunk header, datagram
+/// fits into 8192 bytes accepted by Graylog.
+const CHUNK_SIZE: usize = 8180;
+
+/// Maximum number of chunks per message, larger messages are dropped.
+const MAX_CHUNKS: usize = 128;
+
+/// Magic bytes of chunked message.
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+
+/// Syslog severity assigned to lines without recognized level.
+const DEFAULT_LEVEL: u8 = 6;
+
+/// Words that define syslog severity of a line, in lower case.
+const LEVEL_WORDS: &[(&str, u8)] = &[
+    ("emerg", 0),
+    ("emergency", 0),
+    ("alert", 1),
+    ("crit", 2),
+    ("critical", 2),
+    ("fatal", 2),
+    ("panic", 2),
+    ("err", 3),
+    ("error", 3),
+    ("warn", 4),
+    ("warning", 4),
+    ("notice", 5),
+    ("info", 6),
+    ("debug", 7),
+    ("trace", 7),
+];
+
+/// Graylog endpoint (--gelf).
+#[derive(Debug, Clone, PartialEq)]
+pub struct GelfUrl {
+    host: String,
+    port: u16,
+}
+
+impl FromStr for GelfUrl {
+    type Err = String;
+
+    /// Parse "udp://HOST[:PORT]".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = match s.strip_prefix("udp://") {
+            Some(rest) => rest.trim_end_matches('/'),
+            None => return Err(format!("invalid URL '{}', expected udp://HOST[:PORT]", s)),
+        };
+
+        // IPv6 address is enclosed in brackets, e.g. "[::1]:12201".
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => match port.parse::<u16>() {
+                Ok(port) => (host, port),
+                Err(_) => return Err(format!("invalid port '{}' in URL", port)),
+            },
+            _ => (rest, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in URL '{}'", s));
+        }
+
+        Ok(GelfUrl {
+            host: host.into(),
+            port,
+        })
+    }
+}
+
+/// Sends lines to Graylog as GELF messages over UDP.
+/// Messages are compressed with zlib when it makes them smaller, and split
+/// into chunks when they don't fit into one datagram.
+/// Sending never blocks: if socket buffer is full or server is
+/// unreachable, messages are dropped.
+pub struct GelfSender {
+    socket: UdpSocket,
+    host: String,
+    command: String,
+    message_id: u64,
+}
+
+impl GelfSender {
+    /// Resolve address and create socket.
+    /// `command` is attached to every message as additional field.
+    pub fn connect(url: &GelfUrl, command: &str) -> io::Result<Self> {
+        let host = url.host.trim_start_matches('[').trim_end_matches(']');
+        let addr = match (host, url.port).to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => return Err(Error::new(ErrorKind::NotFound, "can't resolve host")),
+        };
+
+        let socket = UdpSocket::bind(if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        })?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+
+        // Message IDs must be unique across senders, so start from a
+        // value derived from time and pid.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+
+        Ok(GelfSender {
+            socket,
+            host: system::uname().nodename().to_string_lossy().into_owned(),
+            command: command.into(),
+            message_id: nanos ^ ((process::id() as u64) << 32),
+        })
+    }
+
+    /// Send line (without ANSI escape codes and newline) as a message.
+    /// Empty lines are skipped.
+    pub fn send_line(&mut self, line: &str) {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let message = format!(
+            "{{\"version\":\"1.1\",\"host\":{},\"short_message\":{},\"timestamp\":{}.{:03},\"level\":{},\"_command\":{}}}",
+            json_string(&self.host),
+            json_string(line),
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            line_level(line),
+            json_string(&self.command),
+        );
+
+        let compressed = zlib_compress(message.as_bytes());
+        let payload = match &compressed {
+            Ok(compressed) if compressed.len() < message.len() => compressed,
+            _ => message.as_bytes(),
+        };
+
+        // Errors (e.g. full buffer or ICMP unreachable) are ignored, like
+        // any UDP loss.
+        _ = self.send_payload(payload);
+    }
+
+    fn send_payload(&mut self, payload: &[u8]) -> io::Result<()> {
+        if payload.len() <= CHUNK_SIZE {
+            self.socket.send(payload)?;
+            return Ok(());
+        }
+
+        let count = payload.len().div_ceil(CHUNK_SIZE);
+        if count > MAX_CHUNKS {
+            return Err(Error::new(ErrorKind::InvalidInput, "message too large"));
+        }
+
+        self.message_id = self.message_id.wrapping_add(1);
+        let mut datagram = Vec::with_capacity(CHUNK_SIZE + 12);
+        for (seq, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+            datagram.clear();
+            datagram.extend_from_slice(&CHUNK_MAGIC);
+            datagram.extend_from_slice(&self.message_id.to_be_bytes());
+            datagram.push(seq as u8);
+            datagram.push(count as u8);
+            datagram.extend_from_slice(chunk);
+            self.socket.send(&datagram)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compress data into zlib stream.
+fn zlib_compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Guess syslog severity of line from the first word that looks like
+/// a log level, e.g. "ERROR" or "[warn]".
+fn line_level(line: &str) -> u8 {
+    line.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .find_map(|word| {
+            LEVEL_WORDS
+                .iter()
+                .find(|(name, _)| word.eq_ignore_ascii_case(name))
+                .map(|(_, level)| *level)
+        })
+        .unwrap_or(DEFAULT_LEVEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+    use std::time::Duration;
+
+    /// Receive message sent by GelfSender, reassembling chunks and
+    /// decompressing it; also returns number of datagrams.
+    fn receive(socket: &UdpSocket) -> (String, usize) {
+        let mut buf = vec![0; 65536];
+        let mut chunks: Vec<Option<Vec<u8>>> = Vec::new();
+        let mut datagrams = 0;
+        let payload = loop {
+            let len = socket.recv(&mut buf).unwrap();
+            datagrams += 1;
+            let datagram = &buf[..len];
+            if !datagram.starts_with(&CHUNK_MAGIC) {
+                break datagram.to_vec();
+            }
+            let (seq, count) = (datagram[10] as usize, datagram[11] as usize);
+            chunks.resize(count, None);
+            chunks[seq] = Some(datagram[12..].to_vec());
+            if chunks.iter().all(Option::is_some) {
+                break chunks.into_iter().flatten().flatten().collect();
+            }
+        };
+
+        // Zlib stream starts with 0x78.
+        let message = if payload[0] == 0x78 {
+            let mut message = String::new();
+            ZlibDecoder::new(payload.as_slice())
+                .read_to_string(&mut message)
+                .unwrap();
+            message
+        } else {
+            String::from_utf8(payload).unwrap()
+        };
+        (message, datagrams)
+    }
+
+    fn server() -> (UdpSocket, GelfUrl) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let url = format!("udp://127.0.0.1:{}", socket.local_addr().unwrap().port());
+        (socket, url.parse().unwrap())
+    }
+
+    #[test]
+    fn url() {
+        let url: GelfUrl = "udp://graylog".parse().unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("graylog", DEFAULT_PORT));
+        let url: GelfUrl = "udp://[::1]:1234/".parse().unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("[::1]", 1234));
+
+        assert!("tcp://graylog".parse::<GelfUrl>().is_err());
+        assert!("udp://graylog:port".parse::<GelfUrl>().is_err());
+        assert!("udp://:1234".parse::<GelfUrl>().is_err());
+    }
+
+    #[test]
+    fn level() {
+        assert_eq!(line_level("2024-01-02 ERROR: disk full"), 3);
+        assert_eq!(line_level("[warn] low memory"), 4);
+        assert_eq!(line_level("level=Debug msg=x"), 7);
+        assert_eq!(line_level("FATAL then error"), 2);
+        // Only whole words count.
+        assert_eq!(line_level("errors: 0, warnings: 0"), DEFAULT_LEVEL);
+        assert_eq!(line_level("building"), DEFAULT_LEVEL);
+    }
+
+    #[test]
+    fn compress() {
+        let data = "hello hello hello hello hello".repeat(100);
+        let compressed = zlib_compress(data.as_bytes()).unwrap();
+        assert!(compressed.len() < data.len());
+
+        let mut decompressed = String::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    #[test]
+    fn send() {
+        let (socket, url) = server();
+        let mut sender = GelfSender::connect(&url, "make \"all\"").unwrap();
+
+        // Empty lines are skipped.
+        sender.send_line("  \n");
+        sender.send_line("ERROR: \"quoted\"\r\n");
+
+        let (message, datagrams) = receive(&socket);
+        assert_eq!(datagrams, 1);
+        assert!(message.contains("\"short_message\":\"ERROR: \\\"quoted\\\"\""));
+        assert!(message.contains("\"level\":3"));
+        assert!(message.contains("\"_command\":\"make \\\"all\\\"\""));
+    }
+
+    #[test]
+    fn send_chunked() {
+        let (socket, url) = server();
+        let mut sender = GelfSender::connect(&url, "cmd").unwrap();
+
+        // Pseudo-random text, so that it stays large after compression.
+        let mut state = 1u32;
+        let line: String = (0..30000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                char::from(b'a' + (state >> 16) as u8 % 26)
+            })
+            .collect();
+        sender.send_line(&line);
+
+        let (message, datagrams) = receive(&socket);
+        assert!(datagrams > 1);
+        assert!(message.contains(&format!("\"short_message\":\"{}\"", line)));
+
+        // Message that needs too many chunks is dropped.
+        let line = line.repeat(MAX_CHUNKS / 2);
+        assert!(sender.send_payload(line.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Get revision checked out in git repository containing `dir`.
+/// Repository files are read directly, without running git.
+/// Returns None if `dir` is not inside a repository, or if there are
+/// no commits yet.
+pub fn revision(dir: &Path) -> Option<String> {
+    let git_dir = find_git_dir(dir)?;
+    let head = fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    match head.strip_prefix("ref: ") {
+        Some(name) => resolve_ref(&git_dir, name),
+        // Detached HEAD contains hash itself.
+        None => Some(head.into()),
+    }
+}
+
+fn find_git_dir(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let path = dir.join(".git");
+        if path.is_dir() {
+            return Some(path);
+        }
+        if path.is_file() {
+            // In worktrees and submodules, ".git" is a file with path
+            // to the real git dir.
+            let text = fs::read_to_string(&path).ok()?;
+            let target = text.trim().strip_prefix("gitdir: ")?;
+            return Some(dir.join(target));
+        }
+    }
+
+    None
+}
+
+fn resolve_ref(git_dir: &Path, name: &str) -> Option<String> {
+    // In worktrees, HEAD is per-worktree, but refs are in common dir.
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(text) => git_dir.join(text.trim()),
+        Err(_) => git_dir.into(),
+    };
+
+    for dir in [git_dir, &common_dir] {
+        if let Ok(text) = fs::read_to_string(dir.join(name)) {
+            return Some(text.trim().into());
+        }
+    }
+
+    // Ref may be packed, "HASH NAME" per line.
+    let packed = fs::read_to_string(common_dir.join("packed-refs")).ok()?;
+    packed.lines().find_map(|line| match line.split_once(' ') {
+        Some((hash, ref_name)) if ref_name == name => Some(hash.into()),
+        _ => None,
+    })
+}
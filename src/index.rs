@@ -0,0 +1,206 @@
+use std::io::{self, BufRead, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+/// Lines of stripped log produced from one chunk of raw capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineSpan {
+    /// Number of the first line in stripped log, starting from 1.
+    pub line: u64,
+    /// Number of lines in stripped log; may be zero if the chunk was
+    /// stripped completely.
+    pub line_count: u64,
+    /// Byte offset of the chunk in raw capture.
+    pub raw_offset: u64,
+    /// Size of the chunk in raw capture.
+    pub raw_len: u64,
+}
+
+impl LineSpan {
+    /// Byte range of the chunk in raw capture.
+    pub fn raw_range(&self) -> Range<u64> {
+        self.raw_offset..self.raw_offset + self.raw_len
+    }
+}
+
+/// Writes index file.
+/// Each entry is a line "LINE COUNT OFFSET SIZE", see LineSpan.
+pub struct IndexWriter<W: Write> {
+    output: W,
+}
+
+impl<W: Write> IndexWriter<W> {
+    pub fn new(output: W) -> Self {
+        IndexWriter { output }
+    }
+
+    /// Write entry for one chunk.
+    pub fn write_span(&mut self, span: &LineSpan) -> io::Result<()> {
+        writeln!(
+            &mut self.output,
+            "{} {} {} {}",
+            span.line, span.line_count, span.raw_offset, span.raw_len
+        )?;
+        self.output.flush()
+    }
+}
+
+/// Maps lines of stripped log (--output) to byte ranges in raw capture
+/// (--raw-output), using index file written by --index.
+pub struct LineIndex {
+    spans: Vec<LineSpan>,
+}
+
+impl LineIndex {
+    /// Parse index file.
+    pub fn read<R: BufRead>(input: R) -> io::Result<Self> {
+        let mut spans = Vec::new();
+
+        for line in input.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields = line
+                .split(' ')
+                .map(|s| s.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>();
+            let span = match fields.as_deref() {
+                Ok(&[line, line_count, raw_offset, raw_len]) => LineSpan {
+                    line,
+                    line_count,
+                    raw_offset,
+                    raw_len,
+                },
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("malformed index entry: {:?}", line),
+                    ));
+                }
+            };
+
+            spans.push(span);
+        }
+
+        Ok(LineIndex { spans })
+    }
+
+    /// All entries, ordered by line number.
+    pub fn spans(&self) -> &[LineSpan] {
+        &self.spans
+    }
+
+    /// Find entry containing given line of stripped log (starting from 1).
+    pub fn find_line(&self, line: u64) -> Option<&LineSpan> {
+        let pos = self
+            .spans
+            .partition_point(|span| span.line + span.line_count <= line);
+        self.spans
+            .get(pos)
+            .filter(|span| span.line <= line && line < span.line + span.line_count)
+    }
+
+    /// Byte range in raw capture for given line of stripped log.
+    /// If several lines were produced from one chunk (e.g. with
+    /// --cr-mode=newline), they share the same range.
+    pub fn raw_range(&self, line: u64) -> Option<Range<u64>> {
+        self.find_line(line).map(LineSpan::raw_range)
+    }
+
+    /// Read original bytes for given line of stripped log from raw capture.
+    pub fn read_raw<R: Read + Seek>(&self, mut raw: R, line: u64) -> io::Result<Option<Vec<u8>>> {
+        let range = match self.raw_range(line) {
+            Some(range) => range,
+            None => return Ok(None),
+        };
+
+        let mut data = vec![0; (range.end - range.start) as usize];
+        raw.seek(SeekFrom::Start(range.start))?;
+        raw.read_exact(&mut data)?;
+
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn span(line: u64, line_count: u64, raw_offset: u64, raw_len: u64) -> LineSpan {
+        LineSpan {
+            line,
+            line_count,
+            raw_offset,
+            raw_len,
+        }
+    }
+
+    fn spans() -> Vec<LineSpan> {
+        vec![
+            span(1, 1, 0, 6),
+            span(2, 0, 6, 4),
+            span(2, 3, 10, 10),
+            span(5, 1, 20, 2),
+        ]
+    }
+
+    fn write_index(spans: &[LineSpan]) -> Vec<u8> {
+        let mut writer = IndexWriter::new(Vec::new());
+        for span in spans {
+            writer.write_span(span).unwrap();
+        }
+        writer.output
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = write_index(&spans());
+        assert_eq!(
+            String::from_utf8_lossy(&data),
+            "1 1 0 6\n2 0 6 4\n2 3 10 10\n5 1 20 2\n"
+        );
+
+        let index = LineIndex::read(data.as_slice()).unwrap();
+        assert_eq!(index.spans(), spans().as_slice());
+    }
+
+    #[test]
+    fn find_line() {
+        let index = LineIndex::read(write_index(&spans()).as_slice()).unwrap();
+
+        assert_eq!(index.raw_range(0), None);
+        assert_eq!(index.raw_range(1), Some(0..6));
+        // Lines produced from one chunk share its range.
+        assert_eq!(index.raw_range(2), Some(10..20));
+        assert_eq!(index.raw_range(4), Some(10..20));
+        assert_eq!(index.raw_range(5), Some(20..22));
+        assert_eq!(index.raw_range(6), None);
+    }
+
+    #[test]
+    fn read_raw() {
+        let index = LineIndex::read(write_index(&spans()).as_slice()).unwrap();
+        let raw = b"\x1b[1mA\n\x1b[0mB\rC\rD\r\n\x1b[KE\n";
+
+        let read = |line| index.read_raw(Cursor::new(raw), line).unwrap();
+        assert_eq!(read(1), Some(b"\x1b[1mA\n".to_vec()));
+        assert_eq!(read(3), Some(b"B\rC\rD\r\n\x1b[K".to_vec()));
+        assert_eq!(read(5), Some(b"E\n".to_vec()));
+        assert_eq!(read(6), None);
+
+        // Raw capture is shorter than index.
+        let err = index.read_raw(Cursor::new(&raw[..21]), 5).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn malformed() {
+        for input in ["1 1 0\n", "1 1 0 6 7\n", "1 x 0 6\n", "1 1 -1 6\n"] {
+            let err = LineIndex::read(input.as_bytes()).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{:?}", input);
+        }
+        assert!(LineIndex::read("\n1 1 0 6\n\n".as_bytes()).is_ok());
+    }
+}
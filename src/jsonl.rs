@@ -0,0 +1,190 @@
+use crate::event::Stream;
+use crate::format::json_string;
+use crate::term::{self, CrMode, StripLevel};
+use chrono::{DateTime, Local, SecondsFormat};
+use clap::ValueEnum;
+use serde_json::Value;
+use std::fmt::Write;
+
+/// Format of output file.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum OutputFormat {
+    /// Lines are written as is.
+    Text,
+    /// Every line is wrapped into JSON object.
+    Jsonl,
+}
+
+/// Formats lines of output file as JSON objects, one per line, e.g.:
+/// `{"seq":1,"time":"2024-01-02T03:04:05.000000+00:00","stream":"output","line":"text"}`
+pub struct JsonlFormatter {
+    strip: StripLevel,
+    cr_mode: CrMode,
+    passthrough_json: bool,
+    seq: u64,
+}
+
+impl JsonlFormatter {
+    /// Create formatter that strips escape codes from lines according to
+    /// level and mode. If `passthrough_json` is set, lines that are JSON
+    /// objects themselves are embedded as nested objects instead of strings.
+    pub fn new(strip: StripLevel, cr_mode: CrMode, passthrough_json: bool) -> Self {
+        JsonlFormatter {
+            strip,
+            cr_mode,
+            passthrough_json,
+            seq: 0,
+        }
+    }
+
+    /// Format line (without timestamp prefix) produced at given moment.
+    /// Nothing is formatted if the line was stripped completely.
+    pub fn format_line(
+        &mut self,
+        result: &mut String,
+        time: DateTime<Local>,
+        stream: Stream,
+        line: &[u8],
+    ) -> std::fmt::Result {
+        let text = term::strip_ansi_modes(line, self.strip, self.cr_mode);
+        if text.is_empty() {
+            return Ok(());
+        }
+        let text = text.trim_end_matches(['\r', '\n']);
+
+        self.seq += 1;
+        write!(
+            result,
+            "{{\"seq\":{},\"time\":\"{}\",\"stream\":\"{}\",\"line\":",
+            self.seq,
+            time.to_rfc3339_opts(SecondsFormat::Micros, false),
+            stream_name(stream)
+        )?;
+        if self.passthrough_json && is_json_object(text) {
+            result.push_str(text);
+        } else {
+            result.push_str(&json_string(text));
+        }
+        result.push_str("}\n");
+
+        Ok(())
+    }
+}
+
+/// Value of "stream" field.
+fn stream_name(stream: Stream) -> &'static str {
+    match stream {
+        Stream::Separator => "separator",
+        Stream::Header => "header",
+        Stream::Output => "output",
+        Stream::Footer => "footer",
+        Stream::Marker => "marker",
+        Stream::Trace => "trace",
+        Stream::Resize { .. } => "resize",
+    }
+}
+
+/// True if text is a single-line JSON object, which can be embedded as is.
+fn is_json_object(text: &str) -> bool {
+    text.starts_with('{')
+        && !text.contains('\n')
+        && serde_json::from_str::<Value>(text).is_ok_and(|value| value.is_object())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn format(formatter: &mut JsonlFormatter, stream: Stream, line: &[u8]) -> String {
+        let time = Local.timestamp_opt(1_700_000_000, 123_456_000).unwrap();
+        let mut result = String::new();
+        formatter
+            .format_line(&mut result, time, stream, line)
+            .unwrap();
+        // Time depends on local timezone.
+        let time = time.to_rfc3339_opts(SecondsFormat::Micros, false);
+        result.replace(&time, "TIME")
+    }
+
+    #[test]
+    fn envelope() {
+        let mut formatter = JsonlFormatter::new(StripLevel::All, CrMode::Drop, false);
+        assert_eq!(
+            format(&mut formatter, Stream::Header, b"# HOST=[h]\n"),
+            "{\"seq\":1,\"time\":\"TIME\",\"stream\":\"header\",\"line\":\"# HOST=[h]\"}\n"
+        );
+        assert_eq!(
+            format(
+                &mut formatter,
+                Stream::Output,
+                b"\x1b[31m\"a\"\tb\x1b[0m\r\n"
+            ),
+            "{\"seq\":2,\"time\":\"TIME\",\"stream\":\"output\",\"line\":\"\\\"a\\\"\\tb\"}\n"
+        );
+        // Incomplete line.
+        assert_eq!(
+            format(&mut formatter, Stream::Output, b"c"),
+            "{\"seq\":3,\"time\":\"TIME\",\"stream\":\"output\",\"line\":\"c\"}\n"
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let mut formatter = JsonlFormatter::new(StripLevel::All, CrMode::Drop, false);
+        // Line stripped completely is skipped.
+        assert_eq!(format(&mut formatter, Stream::Output, b"\x1b[2J"), "");
+        // Empty line is kept.
+        assert_eq!(
+            format(&mut formatter, Stream::Output, b"\n"),
+            "{\"seq\":1,\"time\":\"TIME\",\"stream\":\"output\",\"line\":\"\"}\n"
+        );
+    }
+
+    #[test]
+    fn cr_mode() {
+        let mut formatter = JsonlFormatter::new(StripLevel::All, CrMode::Overwrite, false);
+        assert_eq!(
+            format(&mut formatter, Stream::Output, b"10%\r100%\n"),
+            "{\"seq\":1,\"time\":\"TIME\",\"stream\":\"output\",\"line\":\"100%\"}\n"
+        );
+    }
+
+    #[test]
+    fn passthrough_json() {
+        let mut formatter = JsonlFormatter::new(StripLevel::All, CrMode::Drop, true);
+        assert_eq!(
+            format(
+                &mut formatter,
+                Stream::Output,
+                b"{\"level\":\"info\",\"n\":[1,2]}\n"
+            ),
+            "{\"seq\":1,\"time\":\"TIME\",\"stream\":\"output\",\
+             \"line\":{\"level\":\"info\",\"n\":[1,2]}}\n"
+        );
+        // Escape codes are stripped before detection.
+        assert_eq!(
+            format(&mut formatter, Stream::Output, b"\x1b[2m{\"a\":1}\x1b[0m\n"),
+            "{\"seq\":2,\"time\":\"TIME\",\"stream\":\"output\",\"line\":{\"a\":1}}\n"
+        );
+        // Other JSON values and malformed objects stay strings.
+        assert_eq!(
+            format(&mut formatter, Stream::Output, b"[1,2]\n"),
+            "{\"seq\":3,\"time\":\"TIME\",\"stream\":\"output\",\"line\":\"[1,2]\"}\n"
+        );
+        assert_eq!(
+            format(&mut formatter, Stream::Output, b"{\"a\":1} tail\n"),
+            "{\"seq\":4,\"time\":\"TIME\",\"stream\":\"output\",\"line\":\"{\\\"a\\\":1} tail\"}\n"
+        );
+    }
+
+    #[test]
+    fn passthrough_disabled() {
+        let mut formatter = JsonlFormatter::new(StripLevel::All, CrMode::Drop, false);
+        assert_eq!(
+            format(&mut formatter, Stream::Output, b"{\"a\":1}\n"),
+            "{\"seq\":1,\"time\":\"TIME\",\"stream\":\"output\",\"line\":\"{\\\"a\\\":1}\"}\n"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use chrono::{DateTime, Local};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Prefix key of keyboard commands (--command-key), as control character,
+/// or None if commands are disabled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandKey(pub Option<u8>);
+
+impl FromStr for CommandKey {
+    type Err = String;
+
+    /// Parse "^X" (Ctrl-X) or "none".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(CommandKey(None));
+        }
+        match s.as_bytes() {
+            [b'^', letter] if letter.is_ascii_alphabetic() => {
+                Ok(CommandKey(Some(letter.to_ascii_uppercase() - b'@')))
+            }
+            _ => Err(format!("invalid key '{}', expected ^A..^Z or 'none'", s)),
+        }
+    }
+}
+
+impl CommandKey {
+    /// Human-readable name, e.g. "Ctrl-A".
+    pub fn name(&self) -> String {
+        match self.0 {
+            Some(ch) => format!("Ctrl-{}", (ch + b'@') as char),
+            None => "none".into(),
+        }
+    }
+}
+
+/// Command entered from keyboard after prefix key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyCommand {
+    /// Pause or resume output ("p").
+    TogglePause,
+    /// Add marker to recording ("m").
+    Mark,
+    /// Show or hide status line ("s").
+    ToggleStatusLine,
+}
+
+/// Finds commands in input from terminal and removes them from data that
+/// goes to command.
+/// Prefix key followed by unknown key is passed through as is, and prefix
+/// key pressed twice is passed as single prefix key.
+pub struct KeyParser {
+    prefix: u8,
+    // Prefix key was the last byte of previous input.
+    after_prefix: bool,
+}
+
+impl KeyParser {
+    pub fn new(prefix: u8) -> Self {
+        KeyParser {
+            prefix,
+            after_prefix: false,
+        }
+    }
+
+    /// Split input into data for command, appended to `data`, and
+    /// commands, appended to `commands`.
+    pub fn parse(&mut self, input: &[u8], data: &mut Vec<u8>, commands: &mut Vec<KeyCommand>) {
+        for &ch in input {
+            if !self.after_prefix {
+                if ch == self.prefix {
+                    self.after_prefix = true;
+                } else {
+                    data.push(ch);
+                }
+                continue;
+            }
+
+            self.after_prefix = false;
+            match ch {
+                b'p' => commands.push(KeyCommand::TogglePause),
+                b'm' => commands.push(KeyCommand::Mark),
+                b's' => commands.push(KeyCommand::ToggleStatusLine),
+                _ if ch == self.prefix => data.push(ch),
+                _ => data.extend_from_slice(&[self.prefix, ch]),
+            }
+        }
+    }
+}
+
+/// State changed by keyboard commands from stdin_2_pty(), and used by
+/// threads producing output.
+pub struct Controls {
+    paused: AtomicBool,
+    pause_output: bool,                 // --pause-output
+    marks: Mutex<Vec<DateTime<Local>>>, // times of pending markers
+    status_line: AtomicBool,            // --status-line
+}
+
+impl Controls {
+    pub fn new(pause_output: bool, status_line: bool) -> Self {
+        Controls {
+            paused: AtomicBool::new(false),
+            pause_output,
+            marks: Mutex::new(Vec::new()),
+            status_line: AtomicBool::new(status_line),
+        }
+    }
+
+    /// Pause or resume, returns true if paused.
+    pub fn toggle_pause(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// Check if command output shouldn't be written to stdout.
+    pub fn stdout_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Check if command output shouldn't be captured at all (--pause-output).
+    pub fn capture_paused(&self) -> bool {
+        self.pause_output && self.stdout_paused()
+    }
+
+    /// Request marker with given time.
+    pub fn add_mark(&self, time: DateTime<Local>) {
+        self.marks.lock().unwrap().push(time);
+    }
+
+    /// Get times of markers requested since previous call.
+    pub fn take_marks(&self) -> Vec<DateTime<Local>> {
+        std::mem::take(&mut *self.marks.lock().unwrap())
+    }
+
+    /// Show or hide status line.
+    pub fn toggle_status_line(&self) {
+        self.status_line.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    /// Check if status line should be shown.
+    pub fn status_line(&self) -> bool {
+        self.status_line.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,10 @@
+//! Library part of reclog.
+//!
+//! Provides access to recordings made with `reclog --raw --timing`, for
+//! programs that want to implement their own playback (e.g. GUIs and TUIs),
+//! and to index files made with `reclog --raw-output --index`, for tools
+//! that map lines of clean log back to original output.
+
+pub mod index;
+pub mod player;
+pub mod timing;
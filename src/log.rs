@@ -0,0 +1,141 @@
+use crate::shim;
+use std::fs::File;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+/// Category of debug message, for --debug-filter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    /// Startup, threads, hooks.
+    Main,
+    /// Signal handling and forwarding.
+    Signal,
+    /// Spawning, waiting, and exit status of command.
+    Child,
+    /// Reading command output.
+    Pty,
+    /// Forwarding our stdin to command.
+    Stdin,
+    /// Writing to our stdout and terminal.
+    Stdout,
+    /// Output files.
+    File,
+    /// Network sinks and notifications.
+    Net,
+}
+
+impl Category {
+    const ALL: [Category; 8] = [
+        Category::Main,
+        Category::Signal,
+        Category::Child,
+        Category::Pty,
+        Category::Stdin,
+        Category::Stdout,
+        Category::File,
+        Category::Net,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Main => "main",
+            Category::Signal => "signal",
+            Category::Child => "child",
+            Category::Pty => "pty",
+            Category::Stdin => "stdin",
+            Category::Stdout => "stdout",
+            Category::File => "file",
+            Category::Net => "net",
+        }
+    }
+
+    fn mask(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Set of categories: comma-separated list, e.g. "signal,pty", or "all".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter(u32);
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mask = 0;
+        for name in s.split(',').map(str::trim) {
+            if name == "all" {
+                mask |= u32::MAX;
+                continue;
+            }
+            match Category::ALL.iter().find(|cat| cat.as_str() == name) {
+                Some(cat) => mask |= cat.mask(),
+                None => {
+                    let names: Vec<_> = Category::ALL.iter().map(|cat| cat.as_str()).collect();
+                    return Err(format!(
+                        "unknown category '{}', expected comma-separated list of: all, {}",
+                        name,
+                        names.join(", ")
+                    ));
+                }
+            }
+        }
+        Ok(Filter(mask))
+    }
+}
+
+/// Verbosity: 0 is disabled, 1 is -D, 2 is -DD.
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// Enabled categories.
+static FILTER: AtomicU32 = AtomicU32::new(u32::MAX);
+
+/// File for messages (--debug-file), instead of stderr.
+static FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Enable debug messages up to given level, of given categories (all if
+/// None), and send them to given file (stderr if None).
+pub fn init(level: u8, filter: Option<Filter>, path: Option<&str>) -> io::Result<()> {
+    if let Some(path) = path {
+        let file = File::create(path)?;
+        _ = FILE.set(Mutex::new(file));
+    }
+    if let Some(Filter(mask)) = filter {
+        FILTER.store(mask, Ordering::SeqCst);
+    }
+    LEVEL.store(level, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Check if messages of given level and category are enabled.
+pub fn enabled(level: u8, category: Category) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level && FILTER.load(Ordering::Relaxed) & category.mask() != 0
+}
+
+/// Write message, prefixed with category and current thread.
+/// Messages in file are also prefixed with time.
+pub fn write(category: Category, msg: &str) {
+    let thread = thread::current();
+    let thread_name = thread.name().unwrap_or("unnamed");
+
+    match FILE.get() {
+        Some(file) => {
+            let line = format!(
+                "{} [{}] {}: {}\n",
+                chrono::Local::now().format("%H:%M:%S%.6f"),
+                category.as_str(),
+                thread_name,
+                msg
+            );
+            let file = file.lock().unwrap_or_else(|err| err.into_inner());
+            _ = shim::write_all(&*file, line.as_bytes());
+        }
+        None => {
+            let line = format!("reclog: [{}] {}: {}\n", category.as_str(), thread_name, msg);
+            _ = shim::write_all(io::stderr(), line.as_bytes());
+        }
+    }
+}
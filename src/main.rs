@@ -1,48 +1,176 @@
 mod buffer;
+mod catalog;
+mod cgroup;
+mod chain;
+mod child;
+mod clock;
+mod concat;
+mod config;
+mod crypt;
+mod cut;
+mod duration;
+mod environ;
 mod error;
+mod event;
+mod expect;
+mod export;
+mod follow;
 mod format;
+mod gelf;
+mod git;
+mod jsonl;
+mod keys;
+mod log;
+mod meta;
+mod metrics;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod multi;
+mod notify;
+mod perm;
+mod pipe;
+mod proctree;
+mod prune;
 mod pty;
+mod publish;
 mod reader;
+mod reaper;
+mod render;
+mod rlimit;
+mod sample;
+mod sched;
+mod selftrace;
 mod shim;
+mod sign;
 mod signal;
+mod sink;
+mod stats;
 mod status;
+mod statusline;
 mod term;
+mod throttle;
+mod ttyrec;
+mod user;
+mod watch;
+mod watchdog;
 mod writer;
 
 use crate::buffer::{BufferPool, BufferQueue};
-use crate::error::SysError;
-use crate::format::{Formatter, TimeSource};
-use crate::pty::{PtyProc, PtyWait};
-use crate::reader::InterruptibleReader;
+use crate::cgroup::{Cgroup, CpuMax, MemoryMax};
+use crate::child::{ChildProc, ChildSetup, ChildWait};
+use crate::clock::{Clock, Deadline, SystemClock, VirtualTime};
+use crate::concat::Segment;
+use crate::crypt::Encryptor;
+use crate::cut::Offset;
+use crate::duration::{Limit, Period};
+use crate::environ::EnvVar;
+use crate::error::{Error, ErrorReport, SysError};
+use crate::event::{CaptureEvent, EventBus, EventSink, QueueSize, Stream};
+use crate::expect::{ExpectReader, ExpectRule};
+use crate::format::{
+    BellMode, ColorMode, DEFAULT_SEPARATOR_FMT, Formatter, HeaderField, LineStyle, Painter,
+    SessionInfo, TimeSource,
+};
+use crate::gelf::{GelfSender, GelfUrl};
+use crate::jsonl::{JsonlFormatter, OutputFormat};
+use crate::keys::{CommandKey, Controls, KeyCommand, KeyParser};
+use crate::meta::SessionMeta;
+use crate::metrics::MetricsServer;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::{MqttSender, MqttUrl};
+use crate::multi::MultiCommand;
+use crate::notify::{Completion, WebhookUrl};
+use crate::perm::{FileGroup, FileMode};
+use crate::pipe::PipeProc;
+use crate::proctree::KillTree;
+use crate::prune::{Retention, TotalSize};
+use crate::pty::PtyProc;
+use crate::publish::Publisher;
+use crate::reader::{EofPolicy, InterruptibleReader, ReaderEnd};
+use crate::reaper::Reaper;
+use crate::rlimit::ResourceLimit;
+use crate::sample::{SampleAction, SampleRule, Sampler};
+use crate::sched::{IoPriority, Niceness, OomScoreAdj};
+use crate::selftrace::TraceEvent;
 use crate::signal::SignalEvent;
+use crate::sink::{NetworkSinks, OutputFiles, Sink, SinkFormat, SinkStyle, SinkStyles, StdoutSink};
+use crate::stats::{Progress, Stats};
 use crate::status::*;
-use crate::term::{AnsiStripper, TtyMode};
-use crate::writer::InterruptibleWriter;
-use clap::Parser;
+use crate::statusline::StatusLine;
+use crate::term::{AnsiStripper, CrMode, EchoMode, Encoding, StripLevel, TtyMode};
+use crate::throttle::{RateLimit, ThrottledReader};
+use crate::ttyrec::TtyrecWriter;
+use crate::user::RunAs;
+use crate::watch::Watcher;
+use crate::watchdog::Watchdog;
+use crate::writer::{
+    FifoPolicy, FifoWriter, HashingWriter, InterruptibleWriter, LazyFile, LineCounter,
+};
+use chrono::{DateTime, FixedOffset, Local};
 use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser};
 use exec::Command;
+use reclog::index::IndexWriter;
+use reclog::timing::TimingWriter;
+use regex_lite::Regex;
 use rustix::io::Errno;
-use rustix::process::Signal;
+use rustix::process::{Pid, Signal, WaitStatus};
 use rustix::stdio;
 use rustix::termios::Termios;
-use std::fs::OpenOptions;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
 use std::hint;
-use std::io::{self, BufRead, BufReader, BufWriter, Stdin, Stdout, Write};
-use std::os::fd::OwnedFd;
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Stdout, Write};
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::atomic::{AtomicI32, Ordering};
-use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU16, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_override_self = true)]
 struct Args {
     /// Before start, print header line (hostname, os, time, command).
     #[arg(short = 'H', long, default_value_t = false)]
     header: bool,
 
+    /// Like --header, but print header as JSON object with more fields (cwd,
+    /// user, tty size, session id).
+    #[arg(long, default_value_t = false)]
+    header_json: bool,
+
+    /// Comma-separated list of header fields, in order: host, os, kernel,
+    /// time, cmd, user, cwd, tty, session, git, env:NAME.
+    #[arg(long, value_delimiter = ',', value_name = "FIELDS")]
+    header_fmt: Vec<HeaderField>,
+
+    /// With --append, write header to output file only if it's empty;
+    /// stdout still gets header.
+    #[arg(long, default_value_t = false)]
+    header_once_per_file: bool,
+
+    /// With --append, when --output file is not empty, write separator line
+    /// before the new session; "%t" is replaced with time, "%c" with command,
+    /// "%p" with command PID. Empty string disables separator.
+    #[arg(long, default_value = DEFAULT_SEPARATOR_FMT, value_name = "FMT")]
+    separator: String,
+
+    /// After finish, print footer line (time, exit status, end of stream reason).
+    #[arg(short = 'F', long, default_value_t = false)]
+    footer: bool,
+
+    /// Write reclog's own lifecycle events (command spawned, signals,
+    /// dropped lines, exit) as "# TRACE" lines to --output file.
+    #[arg(long, default_value_t = false)]
+    trace_self: bool,
+
     /// Prepend each line of the command output with current time.
     #[arg(short, long, default_value_t = false)]
     ts: bool,
@@ -56,6 +184,11 @@ struct Args {
     #[arg(long, default_value = "wall", value_enum, value_name = "SRC")]
     ts_src: TimeSource,
 
+    /// When to colorize timestamps and header on terminal; --output file
+    /// always gets them plain.
+    #[arg(long, default_value = "auto", value_enum, value_name = "WHEN")]
+    color: ColorMode,
+
     /// Output file path (if omitted, select automatically).
     #[arg(
         short,
@@ -74,769 +207,4432 @@ struct Args {
     #[arg(conflicts_with = "force", short, long, default_value_t = false)]
     append: bool,
 
-    /// Don't write --output file at all.
+    /// Continue recording into partially written --output file from a crashed
+    /// run: append "resumed" marker instead of header and count elapsed time
+    /// from the original header.
     #[arg(
         conflicts_with_all = ["output", "force", "append"],
+        long,
+        value_name = "PATH"
+    )]
+    resume: Option<String>,
+
+    /// Set permissions of --output file (and --timing, --raw-output, --ttyrec,
+    /// and --index files), as octal number.
+    #[arg(long, value_name = "MODE")]
+    mode: Option<FileMode>,
+
+    /// Set group of --output file (and --timing, --raw-output, --ttyrec, and
+    /// --index files), by name or ID; usually requires root.
+    #[arg(long, value_name = "GROUP")]
+    group: Option<FileGroup>,
+
+    /// Write --output file (and --timing, --raw-output, --ttyrec, and --index
+    /// files) to temporary file and rename it to the final path when
+    /// recording is finished.
+    #[arg(conflicts_with_all = ["append", "resume", "lazy_output"], long, default_value_t = false)]
+    atomic: bool,
+
+    /// Create --output file only when command produces first output; if it
+    /// produces nothing, don't leave empty file behind.
+    #[arg(conflicts_with = "null", long, default_value_t = false)]
+    lazy_output: bool,
+
+    /// If --output file is a FIFO, what to do when it has no reader or the
+    /// reader is slow: wait, drop data, or fail.
+    #[arg(long, default_value = "drop", value_enum, value_name = "POLICY")]
+    fifo_policy: FifoPolicy,
+
+    /// Remove --output file (and --timing, --raw-output, --ttyrec, and --index
+    /// files) if command succeeds; keep them only if it fails.
+    #[arg(conflicts_with_all = ["append", "resume"], long, default_value_t = false)]
+    keep_on_failure: bool,
+
+    /// Don't write --output file at all.
+    #[arg(
+        conflicts_with_all = [
+            "output", "force", "append", "resume", "lazy_output", "keep_on_failure", "mode", "group", "atomic"
+        ],
         short = 'N',
         long,
         default_value_t = false
     )]
     null: bool,
 
+    /// After command exits, open --output file in $PAGER, at the first line
+    /// matching --error-regex if command failed, or at the end otherwise;
+    /// only if stdout is a tty.
+    #[arg(conflicts_with = "null", long, default_value_t = false)]
+    review: bool,
+
     /// Don't strip ANSI escape codes when writing to --output file.
     #[arg(short = 'R', long, default_value_t = false)]
     raw: bool,
 
-    /// Don't print anything to stdout.
-    #[arg(short, long, default_value_t = false)]
-    silent: bool,
+    /// Which ANSI escape codes to strip when writing to --output file: all,
+    /// control (keep colors), or none (same as --raw).
+    #[arg(
+        conflicts_with = "raw",
+        long,
+        default_value = "all",
+        value_enum,
+        value_name = "LEVEL"
+    )]
+    strip_level: StripLevel,
 
-    /// How long to wait for buffered data after getting EOF. Also how long to wait
-    /// for child to exit voluntarily until killing it forcibly.
-    #[arg(short, long, default_value_t = 15, value_name = "MILLISECONDS")]
-    quit: u64,
+    /// How to decode command output: utf8 (invalid sequences are replaced),
+    /// latin1, or binary (bytes are passed through as is).
+    #[arg(long, default_value = "utf8", value_enum, value_name = "ENC")]
+    encoding: Encoding,
 
-    /// When stdout is slower than command output, buffer at max the specified number
-    /// of lines; doesn't affect --output file.
-    #[arg(short, long, default_value_t = 10_000, value_name = "LINES")]
-    buffer: usize,
+    /// How to handle carriage returns when writing to --output file:
+    /// overwrite line (keep final state of progress bars), start new line,
+    /// or drop.
+    #[arg(long, default_value = "drop", value_enum, value_name = "MODE")]
+    cr_mode: CrMode,
 
-    /// Enable debug logging to stderr.
-    #[arg(short = 'D', long, default_value_t = false)]
-    debug: bool,
+    /// Format of --output file: text, or jsonl (JSON object per line, with
+    /// sequence number, time, and stream).
+    #[arg(long, default_value = "text", value_enum, value_name = "FORMAT")]
+    output_format: OutputFormat,
 
-    /// Print man page (troff).
+    /// With --output-format=jsonl, embed lines that are JSON objects as
+    /// nested objects instead of strings.
     #[arg(long, default_value_t = false)]
-    man: bool,
+    passthrough_json: bool,
 
-    /// Command to run.
+    /// Strip ANSI escape codes when writing to stdout too.
+    #[arg(long, default_value_t = false)]
+    strip_stdout: bool,
+
+    /// Override formatting of one sink (stdout, output, publish, gelf,
+    /// or mqtt), e.g. "stdout:ts=no" or "gelf:strip=control"; keys are
+    /// ts (yes, no) and strip (all, control, none); can be repeated.
+    #[arg(long, value_name = "SINK:KEY=VALUE")]
+    sink_format: Vec<SinkFormat>,
+
+    /// Write timing file for --output file, for use with `reclog render`
+    /// or scriptreplay(1); requires --raw.
     #[arg(
-        required_unless_present = "man",
-        trailing_var_arg = true,
-        allow_hyphen_values = true
+        requires = "raw",
+        conflicts_with = "null",
+        short = 'T',
+        long,
+        value_name = "PATH"
     )]
-    command: Vec<String>,
-}
+    timing: Option<String>,
 
-/// Print usage error to stderr and exit with EXIT_USAGE code.
-macro_rules! usage_error {
-    ($fmt:expr $(,$args:expr)*) => ({
-        use crate::status::*;
-        eprint!(concat!("error: ", $fmt, "\n\nFor more information, try '--help'.\n"),
-                $($args),*);
-        std::process::exit(EXIT_USAGE);
-    });
-}
+    /// Also write unmodified output (like --raw) to this file, when --output
+    /// file is stripped.
+    #[arg(long, value_name = "PATH")]
+    raw_output: Option<String>,
 
-/// Parse CLI arguments.
-/// Also handles --man, --help, --version, and usage errors.
-fn parse_args() -> Args {
-    match Args::try_parse() {
-        Ok(args) => {
-            if args.man {
-                print!("{}", include_str!("../reclog.1"));
-                process::exit(EXIT_SUCCESS);
-            }
+    /// Also write unmodified output to this file in ttyrec format, for
+    /// ttyplay(1), ipbt(1), and other ttyrec players.
+    #[arg(long, value_name = "PATH")]
+    ttyrec: Option<String>,
 
-            if args.command.is_empty() {
-                usage_error!("command can't be empty");
-            }
-            if args.command[0].starts_with('-') {
-                usage_error!("unknown option '{}'", args.command[0]);
-            }
+    /// Write index file mapping each line of --output file to its byte range
+    /// in --raw-output file.
+    #[arg(
+        requires = "raw_output",
+        conflicts_with = "null",
+        long,
+        value_name = "PATH"
+    )]
+    index: Option<String>,
 
-            if args.debug {
-                DEBUG.store(1, Ordering::SeqCst);
-            }
+    /// Encrypt --output file for this age(1) recipient: public key, or file
+    /// with keys; can be repeated. Use `reclog decrypt` to read it.
+    #[arg(
+        conflicts_with_all = [
+            "null", "append", "resume", "lazy_output", "review", "timing", "raw_output", "ttyrec", "index"
+        ],
+        long,
+        value_name = "RECIPIENT"
+    )]
+    encrypt: Vec<String>,
 
-            args
-        }
-        Err(err) if err.kind() == ErrorKind::DisplayHelp => {
-            print!("{}", err);
-            process::exit(EXIT_SUCCESS);
-        }
-        Err(err) if err.kind() == ErrorKind::DisplayVersion => {
-            print!(
-                "{} {}\nCopyright (C) {}\n",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION"),
-                env!("CARGO_PKG_AUTHORS")
-            );
-            process::exit(EXIT_SUCCESS);
-        }
-        Err(err) => {
-            eprint!("{}", err);
-            process::exit(EXIT_USAGE);
-        }
-    }
-}
+    /// After finish, write SHA-256 of --output file to this file, in
+    /// sha256sum(1) format, to verify later that the log wasn't modified.
+    #[arg(
+        conflicts_with_all = ["null", "append", "resume", "encrypt"],
+        long,
+        value_name = "PATH"
+    )]
+    checksum: Option<String>,
 
-/// Choose output path.
-fn choose_output(args: &Args) -> String {
-    if args.null {
-        return String::new();
-    }
+    /// After finish, sign --output file with this SSH private key (ed25519
+    /// recommended), writing detached signature to "<output>.sig". Use
+    /// `reclog verify` to check it.
+    #[arg(conflicts_with_all = ["null", "lazy_output"], long, value_name = "KEYFILE")]
+    sign: Option<String>,
 
-    if !args.output.is_empty() {
-        return args.output.clone();
-    }
+    /// Before start, remove recordings older than N days from directory of
+    /// --output file, like `reclog prune --keep-days`.
+    #[arg(conflicts_with = "null", long, value_name = "N")]
+    prune_keep_days: Option<u64>,
 
-    let base_name = match Path::new(&args.command[0]).file_stem() {
-        Some(name) => name.to_str().unwrap().to_string(),
-        None => usage_error!("invalid command '{}'", args.command[0]),
-    };
+    /// Before start, remove oldest recordings from directory of --output
+    /// file, until the rest fit into SIZE, like `reclog prune
+    /// --keep-total-size`.
+    #[arg(conflicts_with = "null", long, value_name = "SIZE")]
+    prune_keep_total_size: Option<TotalSize>,
 
-    let mut out_path = format!("{}.log", base_name);
+    /// After finish, write session metadata (start and end time, command,
+    /// exit status, output path, byte and line counts) to this file as JSON.
+    #[arg(long, value_name = "PATH")]
+    meta_file: Option<String>,
 
-    if !args.force {
-        let mut suffix = 1;
-        while Path::new(&out_path).exists() {
-            out_path = format!("{}-{}.log", base_name, suffix);
-            suffix += 1;
-        }
-    }
+    /// After command is started, write its PID and then our own PID to this
+    /// file, one per line; the file is removed when we exit.
+    #[arg(long, value_name = "PATH")]
+    pid_file: Option<String>,
 
-    out_path
-}
+    /// After command is started, report its PID and our own PID to stderr.
+    #[arg(long, default_value_t = false)]
+    print_pid: bool,
 
-/// Enable debug logs.
-static DEBUG: AtomicI32 = AtomicI32::new(0);
+    /// Keep only every Nth line matching REGEX, annotating kept lines with
+    /// the number of skipped ones; can be repeated.
+    #[arg(long, value_name = "REGEX=1/N")]
+    sample: Vec<SampleRule>,
 
-/// Print message to stderr if debug logs are enabled.
-macro_rules! debug {
-    ($fmt:expr $(,$args:expr)*) => ({
-        if DEBUG.load(Ordering::Relaxed) != 0 {
-            let msg = format!(concat!("reclog: {}: ", $fmt, "\n"),
-                thread::current().name().unwrap_or("unnamed"),
-                              $($args),*);
-            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
-        }
-    });
-}
+    /// Answer prompt: when command output matches REGEX, write RESPONSE to
+    /// command's input; can be repeated.
+    #[arg(long, value_name = "REGEX=>RESPONSE")]
+    expect: Vec<ExpectRule>,
 
-/// Concurrent termination guard.
-static TERMINATE: AtomicI32 = AtomicI32::new(0);
+    /// After command exits, when to consider its output finished: when pty
+    /// is closed by all processes (eio), when there is no data during --quit
+    /// timeout (timeout), or whichever happens first (both).
+    #[arg(long, default_value = "timeout", value_enum, value_name = "COND")]
+    eof_on: EofPolicy,
 
-/// Print message to stderr, perform cleanup, and exit with given code.
-/// Error message is optional.
-/// Takes care of global cleanup.
-macro_rules! terminate {
-    ($code:expr) => {{
-        if (TERMINATE.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_ok()) {
-            before_exit();
-            process::exit($code);
-        } else {
-            loop {
-                hint::spin_loop();
-            }
-        }
-    }};
-    ($code:expr; $fmt:expr) => ({
-        if (TERMINATE.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_ok()) {
-            let msg = format!(concat!("reclog: ", $fmt, "\n"));
-            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
-            before_exit();
-            process::exit($code);
-        } else {
-            loop {
-                hint::spin_loop();
-            }
-        }
-    });
-    ($code:expr; $fmt:expr, $($args:expr),+) => ({
-        if (TERMINATE.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_ok()) {
-            let msg = format!(concat!("reclog: ", $fmt, "\n"), $($args),+);
-            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
-            before_exit();
-            process::exit($code);
-        } else {
-            loop {
-                hint::spin_loop();
-            }
-        }
-    });
-}
+    /// After command exits, keep capturing until pty is closed by all
+    /// processes, e.g. by daemons spawned by command (implies --eof-on=eio);
+    /// if value is given, wait not longer than that.
+    #[arg(
+        conflicts_with = "eof_on",
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "inf",
+        value_name = "DURATION"
+    )]
+    wait_eof: Option<Limit>,
 
-/// Deliver signal to current process.
-/// If it's a deadly signal like SIGTERM, kills current process.
-/// If it's a stop signal like SIGTSTP, stops process until it receives SIGCONT.
-/// Takes care of global cleanup.
-fn raise_signal(sig: Signal) -> Result<(), SysError> {
-    debug!("raising signal {}", signal::display_name(sig));
-    before_exit();
-    signal::deliver_signal(sig)?;
+    /// Run command with plain pipes instead of a pty; stdout and stderr are
+    /// redirected to the same pipe.
+    #[arg(long, default_value_t = false)]
+    no_pty: bool,
 
-    // Awake after SIGCONT.
-    before_start(StartMode::Wakeup);
-    debug!("returned from signal {}", signal::display_name(sig));
+    /// Record our own stdin instead of running command; same as "-" used as
+    /// command. Implies --no-pty.
+    #[arg(long, default_value_t = false)]
+    stdin: bool,
 
-    Ok(())
-}
+    /// Feed command's stdin from file instead of our stdin, e.g. to record
+    /// scripted interactive session.
+    #[arg(long, conflicts_with = "stdin", value_name = "PATH")]
+    stdin_file: Option<String>,
 
-/// Saved original TTY state.
-static TTY_STATE: OnceLock<Termios> = OnceLock::new();
+    /// With --stdin-file, wait the specified period before sending each line.
+    #[arg(long, requires = "stdin_file", value_name = "DURATION")]
+    stdin_delay: Option<Period>,
 
-#[derive(PartialEq)]
-enum StartMode {
-    Startup, // Initial startup
-    Wakeup,  // Wake up by SIGCONT
-}
+    /// Don't read our stdin at all, and send EOF to command right away,
+    /// e.g. to run in background without stealing input from terminal.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["stdin", "stdin_file"])]
+    no_stdin: bool,
 
-/// Global initialization.
-/// Called at startup and wakeup after SIGCONT.
-fn before_start(mode: StartMode) {
-    debug!("running before_start hook");
+    /// Who echoes input forwarded to command: "on" means command's terminal,
+    /// and input is recorded; "off" means our terminal, and input is not
+    /// recorded; "auto" means "on" with --stdin-file and "off" otherwise.
+    #[arg(long, default_value = "auto", value_enum, value_name = "WHEN")]
+    echo: EchoMode,
 
-    if mode == StartMode::Startup {
-        // Setup default dispositions and block all signals.
-        debug!("initializing signals");
-        if let Err(err) = signal::init_parent_signals() {
-            terminate!(EXIT_FAILURE; "can't initialize signal handlers: {}", err);
-        }
-    }
+    /// After command, run another shell command in the same session and
+    /// output file; can be used multiple times.
+    #[arg(long, value_name = "CMD")]
+    then: Vec<String>,
 
-    if term::is_tty(stdio::stdin()) {
-        if mode == StartMode::Startup {
-            // Save original tty state.
-            debug!("saving tty state of stdin");
-            let state = match term::save_tty_state(stdio::stdin()) {
-                Ok(state) => state,
-                Err(err) => {
-                    terminate!(EXIT_FAILURE; "can't read tty state: {}", err);
-                }
-            };
-            TTY_STATE.set(state).unwrap();
-        }
+    /// With --then, run remaining commands even if one of them fails.
+    #[arg(long, default_value_t = false, requires = "then")]
+    keep_going: bool,
 
-        // Enable canonical mode for stdin.
-        debug!("enabling canonical mode for stdin");
-        if let Err(err) = term::set_tty_mode(stdio::stdin(), TtyMode::Canon) {
-            terminate!(EXIT_FAILURE; "can't switch tty to canonical mode: {}", err);
-        }
-    }
-}
+    /// Rerun command whenever watched file, or any file in watched
+    /// directory, changes; every run is recorded to new numbered log;
+    /// can be used multiple times.
+    #[arg(long, value_name = "PATH")]
+    watch: Vec<String>,
 
-/// Global cleanup.
-/// Called before stop or exit.
-fn before_exit() {
-    debug!("running before_exit hook");
+    /// Print to stdout only lines matching --error-regex; --output file still
+    /// gets everything.
+    #[arg(conflicts_with = "silent", long, default_value_t = false)]
+    only_errors: bool,
 
-    // Restore original tty state if it was saved.
-    debug!("restoring tty state of stdin");
-    if let Some(state) = TTY_STATE.get() {
-        _ = term::restore_tty_state(stdio::stdin(), state);
-    }
-}
+    /// If --only-errors or --review is used, defines which lines are considered
+    /// errors.
+    #[arg(
+        long,
+        default_value = r"(?i)\b(error|fatal|panic|fail(ed|ure)?)\b",
+        value_name = "REGEX"
+    )]
+    error_regex: Regex,
 
-/// Thread that waits for next signal and processes it, in a loop.
-/// All threads block all signals that we want to process, and this thread
-/// fetches them one by one using sigwait().
-/// Possible signals are SIGCHILD (child exited), various termination
-/// signals, and stop/resume signals.
-fn process_signals(pty_proc: Arc<PtyProc>, timeout: Duration) -> Option<Signal> {
-    debug!("entering process_signals thread");
+    /// Set environment variable for the command; can be used multiple times.
+    #[arg(short = 'E', long = "env", value_name = "NAME=VALUE")]
+    env_vars: Vec<EnvVar>,
 
-    let mut pending_interrupt = None;
-    let mut pending_stop = None;
+    /// Run command with empty environment (except variables from --env
+    /// and --sanitize-env).
+    #[arg(long, default_value_t = false)]
+    clear_env: bool,
 
-    'wait_signal: loop {
-        // Wait for SIGCHILD or other signal.
-        debug!("waiting for next signal");
-        let event = match signal::wait_signal(None) {
-            Ok(ev) => ev,
-            Err(err) => terminate!(EXIT_FAILURE; "can't wait for signal: {}", err),
-        };
+    /// Run command with predictable TERM and locale, and without variables
+    /// affecting output formatting (COLUMNS, LS_COLORS, etc).
+    #[arg(long, default_value_t = false)]
+    sanitize_env: bool,
 
-        debug!("received event: {:?}", event);
-        match event {
-            // Interrupt signal received first time.
-            SignalEvent::Interrupt(sig) if pending_interrupt.is_none() => {
-                // Ask child to exit and wait for SIGCHILD.
-                debug!("sending signal {} to child", signal::display_name(sig));
-                _ = pty_proc.kill_child(sig);
-                pending_interrupt = Some(sig);
-                continue 'wait_signal;
-            }
+    /// Run command as another user, and optionally group, by name or numeric
+    /// ID; requires root. Files are still written by current user.
+    #[arg(long, value_name = "USER[:GROUP]")]
+    user: Option<RunAs>,
 
-            // Interrupt signal received second time, or quit signal received.
-            SignalEvent::Interrupt(sig) | SignalEvent::Quit(sig) => {
-                // Ask child to exit, if not asked before, wait until it exits, OR timeout expires,
-                // OR termination signal is received again (e.g. user hits ^\ twice).
-                if pending_interrupt.is_none() {
-                    debug!("sending signal {} to child", signal::display_name(sig));
-                    _ = pty_proc.kill_child(sig);
-
-                    debug!("waiting for any signal or timeout");
-                    match signal::wait_signal(Some(timeout)) {
-                        Ok(SignalEvent::Timeout) => debug!("timeout expired"),
-                        Ok(ev) => debug!("received event: {:?}", ev),
-                        Err(err) => terminate!(EXIT_FAILURE; "can't wait for signal: {}", err),
-                    }
-                }
-                match pty_proc.wait_child(PtyWait::NoHang) {
-                    Ok(Some(status)) if status.exited() || status.signaled() => {
-                        debug!("child exited");
-                    }
-                    _ => {
-                        // If child is still alive, kill it forcibly.
-                        debug!("child still running, sending SIGKILL");
-                        _ = pty_proc.kill_child(Signal::KILL);
-                    }
-                }
-                // Deliver signal to ourselves, which should kill us.
-                debug!("sending signal {} to ourselves", signal::display_name(sig));
-                if let Err(err) = raise_signal(sig) {
-                    terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
-                }
-            }
+    /// Set resource limit of command, e.g. core=0 or nofile=1024:4096; can be
+    /// used multiple times.
+    #[arg(long, value_name = "NAME=SOFT[:HARD]")]
+    rlimit: Vec<ResourceLimit>,
 
-            // Stop signal received first time.
-            SignalEvent::Stop(sig) if pending_stop.is_none() => {
-                // Ask child to stop and wait for SIGCHILD.
-                debug!("sending signal SIGSTOP to child");
-                _ = pty_proc.kill_child(Signal::STOP);
+    /// Run command in transient cgroup (v2), to report its peak memory usage
+    /// in --meta-file; Linux only.
+    #[arg(long, default_value_t = false)]
+    cgroup: bool,
+
+    /// On termination, signal all descendants of command, including those
+    /// that left its process group; Linux only.
+    #[arg(long, default_value_t = false)]
+    kill_tree: bool,
+
+    /// Become subreaper of command's descendants and finish only when all of
+    /// them exit, including daemonized ones; Linux only.
+    #[arg(long, default_value_t = false)]
+    subreaper: bool,
+
+    /// Limit memory usage of command, e.g. 2G; implies --cgroup.
+    #[arg(long, value_name = "SIZE")]
+    memory_max: Option<MemoryMax>,
+
+    /// Limit CPU usage of command, in CPUs, e.g. 0.5; implies --cgroup.
+    #[arg(long, value_name = "CPUS")]
+    cpu_max: Option<CpuMax>,
+
+    /// Run command with given niceness, from -20 (highest priority) to 19
+    /// (lowest).
+    #[arg(long, allow_negative_numbers = true, value_name = "N")]
+    nice: Option<Niceness>,
+
+    /// Run command with given I/O scheduling class (realtime, best-effort,
+    /// idle) and level (0..7); Linux only.
+    #[arg(long, value_name = "CLASS[:LEVEL]")]
+    ionice: Option<IoPriority>,
+
+    /// Adjust OOM killer score of command, from -1000 (never kill) to 1000
+    /// (kill first); Linux only.
+    #[arg(long, allow_negative_numbers = true, value_name = "N")]
+    oom_score_adj: Option<OomScoreAdj>,
+
+    /// Don't kill command with SIGKILL when reclog dies without cleanup
+    /// (e.g. from SIGKILL); by default, command doesn't outlive reclog.
+    #[arg(long, default_value_t = false)]
+    no_deathsig: bool,
+
+    /// Highlight lines matching regex on terminal; can be used multiple times.
+    #[arg(long, value_name = "REGEX")]
+    highlight: Vec<Regex>,
+
+    /// Don't print anything to stdout.
+    #[arg(short, long, default_value_t = false)]
+    silent: bool,
+
+    /// Ring terminal bell when command finishes: always, or only if it
+    /// fails (default); only if stdout is a terminal.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "on-failure",
+        value_enum,
+        value_name = "WHEN"
+    )]
+    bell: Option<BellMode>,
+
+    /// Periodically print capture statistics to stderr on a single updating
+    /// line; only if stderr is a terminal.
+    #[arg(long, default_value_t = false)]
+    progress: bool,
+
+    /// Show elapsed time, output file, and number of dropped lines in the
+    /// bottom row of terminal; only if stdout is a terminal.
+    #[arg(long, default_value_t = false)]
+    status_line: bool,
+
+    /// Serve capture statistics over HTTP in Prometheus text format at
+    /// /metrics, e.g. 127.0.0.1:9300.
+    #[arg(long, value_name = "ADDR:PORT")]
+    metrics_listen: Option<String>,
+
+    /// Forward every line to Graylog as GELF message over UDP, e.g.
+    /// udp://graylog:12201; level is guessed from the line.
+    #[arg(long, value_name = "URL")]
+    gelf: Option<GelfUrl>,
+
+    /// Stream recording live to clients connected to unix socket at PATH,
+    /// e.g. with `reclog attach PATH`.
+    #[arg(long, value_name = "PATH")]
+    publish: Option<String>,
+
+    /// Publish every line to MQTT broker, e.g. mqtt://broker:1883;
+    /// requires --mqtt-topic.
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt_topic", value_name = "URL")]
+    mqtt: Option<MqttUrl>,
+
+    /// MQTT topic for --mqtt.
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt", value_name = "TOPIC")]
+    mqtt_topic: Option<String>,
+
+    /// With --mqtt, publish lines that are ready at the same time as JSON
+    /// array, up to N lines per message.
+    #[cfg(feature = "mqtt")]
+    #[arg(long, requires = "mqtt", default_value_t = 1, value_name = "N")]
+    mqtt_batch: usize,
+
+    /// Show desktop notification when command finishes.
+    #[arg(long, default_value_t = false)]
+    notify: bool,
+
+    /// Run shell command when command finishes; exit status, duration, and
+    /// output path are passed in RECLOG_* environment variables.
+    #[arg(long, value_name = "CMD")]
+    notify_exec: Option<String>,
+
+    /// Send HTTP POST request with JSON info when command finishes;
+    /// only http:// URLs are supported.
+    #[arg(long, value_name = "URL")]
+    notify_webhook: Option<WebhookUrl>,
+
+    /// How long to wait for buffered data after getting EOF, e.g. 500ms or 2s
+    /// (milliseconds without unit), or "inf" to wait until pty is closed.
+    /// Also how long to wait for child to exit voluntarily until killing it
+    /// forcibly.
+    #[arg(short, long, default_value = "15", value_name = "DURATION")]
+    quit: Limit,
+
+    /// When interrupted by signal, how long to wait until lines queued for stdout
+    /// are written before exiting.
+    #[arg(long, default_value = "1000", value_name = "DURATION")]
+    drain_stdout: Period,
+
+    /// Stop writing to stdout if it isn't writable during this period
+    /// (e.g. stalled SSH connection); by default, wait forever.
+    #[arg(long, value_name = "DURATION")]
+    stdout_timeout: Option<Period>,
+
+    /// Propagate window resize to the command only after there were no
+    /// resizes during this period; 0 to propagate immediately.
+    #[arg(long, default_value = "0", value_name = "DURATION")]
+    resize_debounce: Period,
+
+    /// Prefix key of keyboard commands when stdin is a terminal: prefix
+    /// followed by "p" pauses or resumes output, by "m" adds marker, by "s"
+    /// toggles --status-line; "none" to disable.
+    #[arg(long, default_value = "^A", value_name = "KEY")]
+    command_key: CommandKey,
+
+    /// Pausing from keyboard stops writing command output to --output file
+    /// and other destinations too, not only to stdout.
+    #[arg(long, default_value_t = false)]
+    pause_output: bool,
+
+    /// Exit with code 3 if command exited with code 0 but produced no output;
+    /// if value is given, no output during the specified period after start.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0",
+        value_name = "DURATION"
+    )]
+    fail_if_silent: Option<Period>,
+
+    /// Exit with code 4 if command exited with code 0 but some line of its
+    /// output matched regex.
+    #[arg(long, value_name = "REGEX")]
+    fail_on_match: Option<Regex>,
+
+    /// Exit with code 5 if command produced no output and didn't exit during
+    /// the specified period after start; command is killed.
+    #[arg(long, value_name = "DURATION")]
+    first_output_timeout: Option<Period>,
+
+    /// If command exits with code FROM, exit with code TO instead; can be
+    /// used multiple times.
+    #[arg(long, value_name = "FROM=TO")]
+    map_exit: Vec<ExitMapping>,
+
+    /// Exit with code 0 whatever code the command exited with (unless it was
+    /// killed by signal).
+    #[arg(long, default_value_t = false)]
+    ignore_exit_code: bool,
+
+    /// What exit code reflects: exit status of command, always success (when
+    /// used purely as recorder), or whether output matched --fail-on-match.
+    #[arg(long, default_value = "child", value_enum, value_name = "POLICY")]
+    exit_status: ExitPolicy,
+
+    /// When stdout is slower than command output, buffer at max the specified number
+    /// of lines; doesn't affect --output file.
+    #[arg(short, long, default_value_t = 10_000, value_name = "LINES")]
+    buffer: usize,
+
+    /// When --buffer is full, write overflow lines to temporary file in
+    /// the specified directory and print them later, instead of dropping.
+    #[arg(long, value_name = "DIR")]
+    spill: Option<String>,
+
+    /// When --buffer is full, pause reading command output until stdout
+    /// catches up, instead of dropping lines; slows down the command.
+    #[arg(conflicts_with = "spill", long, default_value_t = false)]
+    no_drop: bool,
+
+    /// Read command output at max the specified number of bytes per second
+    /// (K, M, G suffixes allowed), slowing down the command.
+    #[arg(long, value_name = "BYTES")]
+    rate_limit: Option<RateLimit>,
+
+    /// Truncate lines longer than the specified number of bytes; 0 for no limit.
+    #[arg(long, default_value_t = 0, value_name = "BYTES")]
+    max_line_bytes: usize,
+
+    /// Batch writes to --output file up to the specified number of bytes,
+    /// while command produces output faster than we write it; 0 to write
+    /// every line immediately.
+    #[arg(long, default_value_t = 65536, value_name = "BYTES")]
+    write_buffer: usize,
+
+    /// When --output file (or other files) is slower than command output,
+    /// queue at max the specified number of lines, or "unlimited"; when the
+    /// queue is full, reading of command output is paused.
+    #[arg(
+        conflicts_with = "file_sync",
+        long,
+        default_value = "1024",
+        value_name = "LINES"
+    )]
+    file_buffer: QueueSize,
+
+    /// Write files right when lines are read, instead of queueing them for
+    /// separate thread; slow file slows down the command.
+    #[arg(long, default_value_t = false)]
+    file_sync: bool,
+
+    /// Read default values of options from config file
+    /// [default: ~/.config/reclog/config.toml].
+    #[arg(long, value_name = "PATH")]
+    config: Option<String>,
+
+    /// Don't read config file.
+    #[arg(long, conflicts_with = "config", default_value_t = false)]
+    no_config: bool,
+
+    /// Use options from [profile.NAME] table of config file, on top of
+    /// top-level options.
+    #[arg(long, conflicts_with = "no_config", value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Print fatal errors to stderr as JSON objects with code, syscall,
+    /// errno, and message fields.
+    #[arg(long, default_value_t = false)]
+    errors_json: bool,
+
+    /// Enable debug logging to stderr; repeat for more verbose logging.
+    #[arg(short = 'D', long, action = ArgAction::Count)]
+    debug: u8,
+
+    /// Log only given debug categories, comma-separated list of: main,
+    /// signal, child, pty, stdin, stdout, file, net.
+    #[arg(long, value_name = "CATEGORIES")]
+    debug_filter: Option<log::Filter>,
+
+    /// Write debug logs to file instead of stderr.
+    #[arg(long, value_name = "PATH")]
+    debug_file: Option<String>,
+
+    /// Print man page (troff).
+    #[arg(long, default_value_t = false)]
+    man: bool,
+
+    /// File followed by `reclog follow`, instead of running command.
+    #[arg(skip)]
+    follow: Option<String>,
+
+    /// Command to run; everything after "--" is passed to it as is.
+    #[arg(
+        required_unless_present_any = ["man", "stdin"],
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    command: Vec<String>,
+}
+
+/// Arguments of `reclog render` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog render",
+    bin_name = "reclog render",
+    version,
+    about = "Render raw recording into a stripped and timestamped text log.",
+    long_about = None
+)]
+struct RenderArgs {
+    /// Timing file written by --timing during recording.
+    #[arg(short = 'T', long, value_name = "PATH")]
+    timing: String,
+
+    /// Defines strftime() format string.
+    #[arg(long, default_value = "%T%.3f ", value_name = "FMT")]
+    ts_fmt: String,
+
+    /// Defines what timestamps to use: wallclock, elapsed time since recording
+    /// start, or delta between subsequent timestamps.
+    #[arg(long, default_value = "wall", value_enum, value_name = "SRC")]
+    ts_src: TimeSource,
+
+    /// Output file path (if omitted, print to stdout).
+    #[arg(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        value_name = "PATH"
+    )]
+    output: String,
+
+    /// Overwrite --output file if it exists.
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+
+    /// Start clock at given time and ignore START_TIME of timing file, so
+    /// that output doesn't depend on when recording was made (for tests).
+    #[arg(long, hide = true, value_name = "TIME")]
+    virtual_time: Option<VirtualTime>,
+
+    /// Raw recording written with --raw during recording.
+    #[arg(value_name = "FILE")]
+    file: String,
+}
+
+/// Arguments of `reclog concat` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog concat",
+    bin_name = "reclog concat",
+    version,
+    about = "Join sequential text logs into one, with a separator before each segment.",
+    long_about = None
+)]
+struct ConcatArgs {
+    /// Defines strftime() format string used during recording.
+    #[arg(long, default_value = "%T%.3f ", value_name = "FMT")]
+    ts_fmt: String,
+
+    /// Defines what timestamps were used during recording; elapsed timestamps
+    /// are adjusted to continue from previous segment.
+    #[arg(long, default_value = "wall", value_enum, value_name = "SRC")]
+    ts_src: TimeSource,
+
+    /// Output file path (if omitted, print to stdout).
+    #[arg(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        value_name = "PATH"
+    )]
+    output: String,
+
+    /// Overwrite --output file if it exists.
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+
+    /// Text logs to join, in order.
+    #[arg(required = true, value_name = "FILE")]
+    files: Vec<String>,
+}
+
+/// Arguments of `reclog slice` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog slice",
+    bin_name = "reclog slice",
+    version,
+    about = "Extract part of recording between two points in time.",
+    long_about = None
+)]
+struct SliceArgs {
+    /// Timing file of raw recording, written by --timing.
+    #[arg(short = 'T', long, value_name = "PATH")]
+    timing: Option<String>,
+
+    /// Write timing file for extracted part.
+    #[arg(requires = "timing", long, value_name = "PATH")]
+    timing_output: Option<String>,
+
+    /// Recording is in ttyrec format, written by --ttyrec.
+    #[arg(conflicts_with = "timing", long, default_value_t = false)]
+    ttyrec: bool,
+
+    /// Start of extracted part, as [[HH:]MM:]SS[.FFF] since recording start.
+    #[arg(long, default_value = "0", value_name = "TIME")]
+    from: Offset,
+
+    /// End of extracted part (if omitted, until the end of recording).
+    #[arg(long, value_name = "TIME")]
+    to: Option<Offset>,
+
+    /// Overwrite output files if they exist.
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+
+    /// Recording to read.
+    #[arg(value_name = "IN")]
+    input: String,
+
+    /// File to write extracted part to.
+    #[arg(value_name = "OUT")]
+    output: String,
+}
+
+/// Arguments of `reclog export` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog export",
+    bin_name = "reclog export",
+    version,
+    about = "Convert raw recording into another format, preserving colors.",
+    long_about = None
+)]
+struct ExportArgs {
+    /// Export to standalone HTML page.
+    #[arg(long, default_value_t = false)]
+    html: bool,
+
+    /// Page title (default is name of recording).
+    #[arg(long, value_name = "TEXT")]
+    title: Option<String>,
+
+    /// Output file path (if omitted, print to stdout).
+    #[arg(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        value_name = "PATH"
+    )]
+    output: String,
+
+    /// Overwrite --output file if it exists.
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+
+    /// Raw recording written with --raw or --raw-output during recording.
+    #[arg(value_name = "FILE")]
+    file: String,
+}
+
+/// Arguments of `reclog list` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog list",
+    bin_name = "reclog list",
+    version,
+    about = "List recordings in directory, found by header or meta file.",
+    long_about = None
+)]
+struct ListArgs {
+    /// Directory to scan.
+    #[arg(default_value = ".", value_name = "DIR")]
+    dir: String,
+}
+
+/// Arguments of `reclog show` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog show",
+    bin_name = "reclog show",
+    version,
+    about = "Print details of recording listed by `reclog list`.",
+    long_about = None
+)]
+struct ShowArgs {
+    /// Directory where recording was listed.
+    #[arg(short, long, default_value = ".", value_name = "DIR")]
+    dir: String,
+
+    /// Recording ID printed by `reclog list`.
+    #[arg(value_name = "ID")]
+    id: String,
+}
+
+/// Arguments of `reclog prune` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog prune",
+    bin_name = "reclog prune",
+    version,
+    about = "Remove old recordings from directory according to retention policy.",
+    long_about = None
+)]
+struct PruneArgs {
+    /// Remove recordings older than N days.
+    #[arg(long, value_name = "N")]
+    keep_days: Option<u64>,
+
+    /// Remove oldest recordings until the rest fit into SIZE (with optional
+    /// K, M, G, or T suffix).
+    #[arg(long, value_name = "SIZE")]
+    keep_total_size: Option<TotalSize>,
+
+    /// Only print files that would be removed.
+    #[arg(short = 'n', long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Directory with recordings.
+    #[arg(value_name = "DIR")]
+    dir: String,
+}
+
+/// Arguments of `reclog attach` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog attach",
+    bin_name = "reclog attach",
+    version,
+    about = "Print live recording of another reclog, from --publish socket or from file.",
+    long_about = None
+)]
+struct AttachArgs {
+    /// Start from last N lines of recording file, instead of the beginning.
+    #[arg(short = 'n', long, value_name = "N")]
+    lines: Option<u64>,
+
+    /// Socket created by --publish, or file being recorded.
+    #[arg(value_name = "PATH")]
+    path: String,
+}
+
+/// Arguments of `reclog decrypt` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog decrypt",
+    bin_name = "reclog decrypt",
+    version,
+    about = "Decrypt recording written with --encrypt, using age(1).",
+    long_about = None
+)]
+struct DecryptArgs {
+    /// File with age or SSH private key; can be repeated.
+    #[arg(short, long = "identity", required = true, value_name = "PATH")]
+    identities: Vec<String>,
+
+    /// Output file path (if omitted, print to stdout).
+    #[arg(
+        short,
+        long,
+        default_value = "",
+        hide_default_value = true,
+        value_name = "PATH"
+    )]
+    output: String,
+
+    /// Overwrite --output file if it exists.
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+
+    /// Recording written with --encrypt.
+    #[arg(value_name = "FILE")]
+    file: String,
+}
+
+/// Arguments of `reclog verify` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog verify",
+    bin_name = "reclog verify",
+    version,
+    about = "Verify signature of recording written with --sign.",
+    long_about = None
+)]
+struct VerifyArgs {
+    /// SSH public key of the signer.
+    #[arg(short, long, value_name = "PATH")]
+    key: String,
+
+    /// Signature file (default is FILE with ".sig" suffix).
+    #[arg(short, long, value_name = "PATH")]
+    signature: Option<String>,
+
+    /// Recording written with --sign.
+    #[arg(value_name = "FILE")]
+    file: String,
+}
+
+/// Arguments of `reclog multi` subcommand.
+#[derive(Parser, Debug)]
+#[command(
+    name = "reclog multi",
+    bin_name = "reclog multi",
+    version,
+    about = "Run commands concurrently, each with its own log file, and interleave their output.",
+    long_about = None
+)]
+struct MultiArgs {
+    /// Directory for log files, named after commands.
+    #[arg(short, long, default_value = ".", value_name = "DIR")]
+    dir: String,
+
+    /// Overwrite log files if they exist.
+    #[arg(short, long, default_value_t = false)]
+    force: bool,
+
+    /// When to colorize line prefixes on terminal.
+    #[arg(long, default_value = "auto", value_enum, value_name = "WHEN")]
+    color: ColorMode,
+
+    /// Shell commands to run, optionally separated with '--'.
+    #[arg(
+        required = true,
+        value_name = "CMD",
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    commands: Vec<String>,
+}
+
+/// Print usage error to stderr and exit with EXIT_USAGE code.
+macro_rules! usage_error {
+    ($fmt:expr $(,$args:expr)*) => ({
+        use crate::status::*;
+        eprint!(concat!("error: ", $fmt, "\n\nFor more information, try '--help'.\n"),
+                $($args),*);
+        std::process::exit(EXIT_USAGE);
+    });
+}
+
+/// Parse CLI arguments.
+/// Also handles --man, --help, --version, and usage errors.
+/// If `follow` is set, arguments are parsed for `reclog follow`, where
+/// the only positional argument is a file instead of command.
+fn parse_args(follow: bool) -> Args {
+    let mut argv: Vec<OsString> = env::args_os().collect();
+    if follow {
+        argv.remove(1);
+    }
+    // Run of --watch is started with output file chosen by supervising reclog.
+    let watch_output = if argv.len() > 2 && argv[1] == WATCH_HELPER {
+        argv.remove(1);
+        Some(argv.remove(1))
+    } else {
+        None
+    };
+    let mut args: Args = parse_command(config::with_env(Args::command()), argv.clone());
+
+    // Options from config file become defaults, so that command line and
+    // environment override them.
+    let config_path = if args.no_config {
+        None
+    } else if let Some(path) = &args.config {
+        Some(PathBuf::from(path))
+    } else {
+        config::default_path().filter(|path| path.exists())
+    };
+    if args.profile.is_some() && config_path.is_none() {
+        usage_error!("--profile requires config file");
+    }
+    if let Some(path) = config_path {
+        let command = match config::with_config(&path, Args::command(), args.profile.as_deref()) {
+            Ok(command) => command,
+            Err(err) => {
+                let err = err.context(format!("can't read config file \"{}\"", path.display()));
+                if args.errors_json {
+                    let msg = err.to_string();
+                    eprintln!("{}", ErrorReport::new(EXIT_USAGE, &msg, &err).to_json());
+                    process::exit(EXIT_USAGE);
+                }
+                usage_error!("{}", err);
+            }
+        };
+        args = parse_command(config::with_env(command), argv.clone());
+    }
+
+    if args.man {
+        print!("{}", include_str!("../reclog.1"));
+        process::exit(EXIT_SUCCESS);
+    }
+
+    // Stdin is read by helper process instead of command.
+    if !follow && args.command.first().is_some_and(|arg| arg == "-") {
+        if args.command.len() > 1 {
+            usage_error!("unexpected argument '{}' after \"-\"", args.command[1]);
+        }
+        args.stdin = true;
+        args.command.clear();
+    }
+    if args.stdin {
+        if follow {
+            usage_error!("--stdin can't be used with follow");
+        }
+        if !args.command.is_empty() {
+            usage_error!("--stdin can't be used with command");
+        }
+        if !args.then.is_empty() {
+            usage_error!("--then can't be used with --stdin");
+        }
+        args.command = vec!["-".into()];
+        args.no_pty = true;
+    }
+
+    // Keyboard commands come only from user typing on our stdin.
+    if args.no_stdin || args.stdin_file.is_some() {
+        args.command_key = CommandKey(None);
+    }
+    // Pipes don't echo.
+    if args.echo == EchoMode::On && args.no_pty {
+        usage_error!("--echo=on can't be used with --no-pty");
+    }
+
+    if args.command.is_empty() {
+        usage_error!("command can't be empty");
+    }
+    // Without "--", command starting with '-' is most likely a misspelled
+    // option. After "--", everything is passed to command as is.
+    if !args.stdin && args.command[0].starts_with('-') && !after_separator(&argv, &args.command) {
+        usage_error!("unknown option '{}'", args.command[0]);
+    }
+
+    // Followed file is read by helper process instead of command.
+    if follow {
+        if args.command.len() != 1 {
+            usage_error!("follow requires exactly one file");
+        }
+        if !args.then.is_empty() {
+            usage_error!("--then can't be used with follow");
+        }
+        let path = args.command[0].clone();
+        if let Err(err) = File::open(&path) {
+            usage_error!("can't follow \"{}\": {}", path, err);
+        }
+        args.command = vec!["follow".into(), path.clone()];
+        args.follow = Some(path);
+        args.no_pty = true;
+    }
+
+    if args.errors_json {
+        ERRORS_JSON.store(true, Ordering::SeqCst);
+    }
+
+    if args.no_stdin {
+        STDIN_IGNORED.store(true, Ordering::SeqCst);
+    }
+    if child_echo(&args) {
+        LOCAL_ECHO.store(false, Ordering::SeqCst);
+    }
+
+    if args.trace_self {
+        selftrace::enable();
+    }
+
+    if args.debug > 0 {
+        if let Err(err) = log::init(args.debug, args.debug_filter, args.debug_file.as_deref()) {
+            usage_error!(
+                "can't open debug file \"{}\": {}",
+                args.debug_file.as_deref().unwrap_or_default(),
+                err
+            );
+        }
+    }
+
+    match args.exit_status {
+        ExitPolicy::Child => {}
+        ExitPolicy::AlwaysZero if args.fail_on_match.is_some() || args.keep_on_failure => {
+            usage_error!(
+                "--exit-status=always-zero can't be used with --fail-on-match or --keep-on-failure"
+            );
+        }
+        ExitPolicy::FromMatch if args.fail_on_match.is_none() => {
+            usage_error!("--exit-status=from-match requires --fail-on-match");
+        }
+        _ if args.fail_if_silent.is_some() => {
+            usage_error!("--fail-if-silent requires --exit-status=child");
+        }
+        _ => {}
+    }
+
+    // Carriage returns are handled while stripping escape codes, so they
+    // would be silently kept in raw output.
+    let output_strip = if args.raw {
+        StripLevel::None
+    } else {
+        args.strip_level
+    };
+    let output_style = SinkStyle {
+        ts: args.ts,
+        strip: output_strip,
+    };
+    if args.cr_mode != CrMode::Drop
+        && SinkStyle::resolve(&args.sink_format, Sink::Output, output_style).strip
+            == StripLevel::None
+    {
+        usage_error!("--cr-mode can't be used with --raw or --strip-level=none");
+    }
+    if args.passthrough_json && args.output_format != OutputFormat::Jsonl {
+        usage_error!("--passthrough-json requires --output-format=jsonl");
+    }
+
+    if !cfg!(target_os = "linux") && (args.ionice.is_some() || args.oom_score_adj.is_some()) {
+        usage_error!("--ionice and --oom-score-adj are supported only on Linux");
+    }
+
+    // Limits are applied via cgroup.
+    if args.memory_max.is_some() || args.cpu_max.is_some() {
+        args.cgroup = true;
+    }
+    if !cfg!(target_os = "linux") && args.cgroup {
+        usage_error!("--cgroup is supported only on Linux");
+    }
+    // Without --quit timeout, output ends only when pty is closed.
+    if args.wait_eof.is_some() || args.quit.0.is_none() {
+        args.eof_on = EofPolicy::Eio;
+    }
+
+    if !cfg!(target_os = "linux") && args.subreaper {
+        usage_error!("--subreaper is supported only on Linux");
+    }
+    if !cfg!(target_os = "linux") && args.kill_tree {
+        usage_error!("--kill-tree is supported only on Linux");
+    }
+
+    // Child reports failed setuid() only by exit code, so check it early.
+    if args.user.is_some() && !rustix::process::geteuid().is_root() {
+        usage_error!("--user requires root");
+    }
+
+    // Resumed recording is appended to existing file.
+    if let Some(path) = &args.resume {
+        args.output = path.clone();
+        args.append = true;
+    }
+
+    if let Some(path) = watch_output {
+        args.watch.clear();
+        if !args.null {
+            args.output = path.to_string_lossy().into();
+        }
+    }
+    if !args.watch.is_empty() {
+        if follow {
+            usage_error!("--watch can't be used with follow");
+        }
+        if args.stdin {
+            usage_error!("--watch can't be used with --stdin");
+        }
+        if args.resume.is_some() {
+            usage_error!("--watch can't be used with --resume");
+        }
+        for path in &args.watch {
+            if let Err(err) = fs::metadata(path) {
+                usage_error!("can't watch \"{}\": {}", path, err);
+            }
+        }
+    }
+
+    args
+}
+
+/// Check if command was specified after "--" separator.
+fn after_separator(argv: &[OsString], command: &[String]) -> bool {
+    match argv.iter().skip(1).position(|arg| arg == "--") {
+        Some(pos) => {
+            let tail = &argv[pos + 2..];
+            tail.len() == command.len() && tail.iter().zip(command).all(|(a, b)| a == b.as_str())
+        }
+        None => false,
+    }
+}
+
+/// Parse CLI arguments of main command or subcommand.
+/// Handles --help, --version, and usage errors.
+fn parse_cli<T: Parser>(argv: impl IntoIterator<Item = OsString>) -> T {
+    parse_command(T::command(), argv)
+}
+
+/// Like parse_cli(), but with customized command definition.
+fn parse_command<T: FromArgMatches>(
+    command: clap::Command,
+    argv: impl IntoIterator<Item = OsString>,
+) -> T {
+    match command
+        .try_get_matches_from(argv)
+        .and_then(|mut matches| T::from_arg_matches_mut(&mut matches))
+    {
+        Ok(args) => args,
+        Err(err) if err.kind() == ErrorKind::DisplayHelp => {
+            print!("{}", err);
+            process::exit(EXIT_SUCCESS);
+        }
+        Err(err) if err.kind() == ErrorKind::DisplayVersion => {
+            print!(
+                "{} {}\nCopyright (C) {}\n",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION"),
+                env!("CARGO_PKG_AUTHORS")
+            );
+            process::exit(EXIT_SUCCESS);
+        }
+        Err(err) => {
+            eprint!("{}", err);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Choose output path.
+fn choose_output(args: &Args) -> String {
+    if args.null {
+        return String::new();
+    }
+
+    if !args.output.is_empty() {
+        return args.output.clone();
+    }
+
+    let base_name = output_base_name(args);
+    let mut out_path = format!("{}.log", base_name);
+
+    if !args.force {
+        let mut suffix = 1;
+        while Path::new(&out_path).exists() {
+            out_path = format!("{}-{}.log", base_name, suffix);
+            suffix += 1;
+        }
+    }
+
+    out_path
+}
+
+/// Name of output file without extension, when it's not specified.
+fn output_base_name(args: &Args) -> String {
+    match &args.follow {
+        // Followed file is usually named like our default output.
+        Some(path) => match Path::new(path).file_stem() {
+            Some(name) => format!("{}-follow", name.to_string_lossy()),
+            None => usage_error!("invalid file '{}'", path),
+        },
+        None if args.stdin => "stdin".into(),
+        None => match Path::new(&args.command[0]).file_stem() {
+            Some(name) => name.to_str().unwrap().to_string(),
+            None => usage_error!("invalid command '{}'", args.command[0]),
+        },
+    }
+}
+
+/// Log message of given category if debug logs are enabled (-D).
+macro_rules! debug {
+    ($cat:ident, $fmt:expr $(,$args:expr)*) => ({
+        if log::enabled(1, log::Category::$cat) {
+            log::write(log::Category::$cat, &format!($fmt, $($args),*));
+        }
+    });
+}
+
+/// Log message of given category if verbose debug logs are enabled (-DD).
+macro_rules! trace {
+    ($cat:ident, $fmt:expr $(,$args:expr)*) => ({
+        if log::enabled(2, log::Category::$cat) {
+            log::write(log::Category::$cat, &format!($fmt, $($args),*));
+        }
+    });
+}
+
+/// Concurrent termination guard.
+static TERMINATE: AtomicI32 = AtomicI32::new(0);
+
+/// Print message to stderr, perform cleanup, and exit with given code.
+/// Error message is optional.
+/// Takes care of global cleanup.
+macro_rules! terminate {
+    ($code:expr) => {{
+        if (TERMINATE.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_ok()) {
+            remove_pid_file();
+            before_exit();
+            process::exit($code);
+        } else {
+            loop {
+                hint::spin_loop();
+            }
+        }
+    }};
+    ($code:expr; $fmt:expr) => ({
+        if (TERMINATE.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_ok()) {
+            let msg = format!($fmt);
+            report_error(&ErrorReport::new($code, &msg, &msg.as_str()));
+            remove_pid_file();
+            before_exit();
+            process::exit($code);
+        } else {
+            loop {
+                hint::spin_loop();
+            }
+        }
+    });
+    ($code:expr; $fmt:expr, $($args:expr),+) => ({
+        if (TERMINATE.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).is_ok()) {
+            // Last argument is usually the error.
+            let msg = format!($fmt, $($args),+);
+            report_error(&ErrorReport::new($code, &msg, &last_arg!($($args),+)));
+            remove_pid_file();
+            before_exit();
+            process::exit($code);
+        } else {
+            loop {
+                hint::spin_loop();
+            }
+        }
+    });
+}
+
+/// Expand to last of given arguments.
+macro_rules! last_arg {
+    ($last:expr) => {
+        $last
+    };
+    ($first:expr, $($rest:expr),+) => {
+        last_arg!($($rest),+)
+    };
+}
+
+/// Report fatal errors as JSON (--errors-json).
+static ERRORS_JSON: AtomicBool = AtomicBool::new(false);
+
+/// Print fatal error to stderr, as text or as JSON.
+fn report_error(report: &ErrorReport) {
+    let msg = if ERRORS_JSON.load(Ordering::Relaxed) {
+        format!("{}\n", report.to_json())
+    } else {
+        format!("reclog: {}\n", report.message)
+    };
+    _ = shim::write_all(std::io::stderr(), msg.as_bytes());
+}
+
+/// Deliver signal to current process.
+/// If it's a deadly signal like SIGTERM, kills current process.
+/// If it's a stop signal like SIGTSTP, stops process until it receives SIGCONT.
+/// Takes care of global cleanup.
+fn raise_signal(sig: Signal) -> Result<(), SysError> {
+    debug!(Signal, "raising signal {}", signal::display_name(sig));
+    if !signal::is_stop_signal(sig) {
+        remove_pid_file();
+    }
+    before_exit();
+    signal::deliver_signal(sig)?;
+
+    // Awake after SIGCONT.
+    before_start(StartMode::Wakeup);
+    debug!(Signal, "returned from signal {}", signal::display_name(sig));
+
+    Ok(())
+}
+
+/// Our stdin isn't used (--no-stdin), so its tty state isn't touched.
+static STDIN_IGNORED: AtomicBool = AtomicBool::new(false);
+
+/// Our stdin tty echoes input (unless --echo=on).
+static LOCAL_ECHO: AtomicBool = AtomicBool::new(true);
+
+/// Command has hidden its input, e.g. while reading a password, so our
+/// stdin tty doesn't echo it either.
+static INPUT_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Serializes changes of stdin tty state.
+/// True while tty is switched to our mode, between before_start() and
+/// before_exit().
+static TTY_ACTIVE: Mutex<bool> = Mutex::new(false);
+
+/// Saved original TTY state.
+static TTY_STATE: OnceLock<Termios> = OnceLock::new();
+
+/// Height of terminal with status line, or 0 if it's not shown.
+static STATUS_ROWS: AtomicU16 = AtomicU16::new(0);
+
+/// Path of --pid-file, set after it was written.
+static PID_FILE: OnceLock<String> = OnceLock::new();
+
+/// Write PID of command and our own PID to --pid-file.
+fn write_pid_file(path: &str, child_pid: u32) -> io::Result<()> {
+    fs::write(path, format!("{}\n{}\n", child_pid, process::id()))?;
+    _ = PID_FILE.set(path.into());
+    Ok(())
+}
+
+/// Remove --pid-file, if it was written.
+/// Called before exit, but not before stop.
+fn remove_pid_file() {
+    if let Some(path) = PID_FILE.get() {
+        debug!(File, "removing pid file");
+        _ = fs::remove_file(path);
+    }
+}
+
+/// Why reading of command output was finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StreamEnd {
+    Eof,     // Got EOF from pty or pipe
+    Eio,     // Got EIO from pty (all slave fds were closed)
+    Timeout, // No data during --quit timeout
+    Closed,  // Reader was closed
+}
+
+impl StreamEnd {
+    fn as_str(&self) -> &'static str {
+        match self {
+            StreamEnd::Eof => "eof",
+            StreamEnd::Eio => "eio",
+            StreamEnd::Timeout => "timeout",
+            StreamEnd::Closed => "closed",
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum StartMode {
+    Startup, // Initial startup
+    Wakeup,  // Wake up by SIGCONT
+}
+
+/// Global initialization.
+/// Called at startup and wakeup after SIGCONT.
+fn before_start(mode: StartMode) {
+    debug!(Main, "running before_start hook");
+
+    if mode == StartMode::Startup {
+        // Setup default dispositions and block all signals.
+        debug!(Signal, "initializing signals");
+        if let Err(err) = signal::init_parent_signals() {
+            terminate!(EXIT_FAILURE; "can't initialize signal handlers: {}", err);
+        }
+    }
+
+    // Changing tty state from background job would stop us with SIGTTOU.
+    if !STDIN_IGNORED.load(Ordering::Relaxed) && term::is_tty(stdio::stdin()) {
+        if mode == StartMode::Startup {
+            // Save original tty state.
+            debug!(Stdin, "saving tty state of stdin");
+            let state = match term::save_tty_state(stdio::stdin()) {
+                Ok(state) => state,
+                Err(err) => {
+                    terminate!(EXIT_FAILURE; "can't read tty state: {}", err);
+                }
+            };
+            TTY_STATE.set(state).unwrap();
+        }
+
+        // Enable canonical mode for stdin.
+        // If command's terminal echoes input, ours doesn't, to avoid
+        // showing it twice.
+        debug!(Stdin, "enabling canonical mode for stdin");
+        // Lock is released before terminate!(), which takes it again.
+        let result = {
+            let mut active = TTY_ACTIVE.lock().unwrap();
+            let result = term::set_tty_mode(stdio::stdin(), local_tty_mode());
+            *active = result.is_ok();
+            result
+        };
+        if let Err(err) = result {
+            terminate!(EXIT_FAILURE; "can't switch tty to canonical mode: {}", err);
+        }
+    }
+}
+
+/// Mode of stdin tty while command is running.
+fn local_tty_mode() -> TtyMode {
+    if LOCAL_ECHO.load(Ordering::Relaxed) && !INPUT_HIDDEN.load(Ordering::Relaxed) {
+        TtyMode::Canon
+    } else {
+        TtyMode::CanonNoEcho
+    }
+}
+
+/// Global cleanup.
+/// Called before stop or exit.
+fn before_exit() {
+    debug!(Main, "running before_exit hook");
+
+    // Restore original tty state if it was saved.
+    debug!(Stdin, "restoring tty state of stdin");
+    if let Some(state) = TTY_STATE.get() {
+        let mut active = TTY_ACTIVE.lock().unwrap();
+        _ = term::restore_tty_state(stdio::stdin(), state);
+        *active = false;
+    }
+
+    // Give bottom row back, report_status() will take it again on wakeup.
+    let rows = STATUS_ROWS.swap(0, Ordering::SeqCst);
+    if rows != 0 {
+        debug!(Stdout, "removing status line");
+        _ = shim::write_all(
+            std::io::stdout(),
+            statusline::hide_sequence(rows).as_bytes(),
+        );
+    }
+}
+
+/// Thread that waits for next signal and processes it, in a loop.
+/// All threads block all signals that we want to process, and this thread
+/// fetches them one by one using sigwait().
+/// Possible signals are SIGCHILD (child exited), various termination
+/// signals, and stop/resume signals.
+/// Resizes are debounced: child is resized only after there were no
+/// SIGWINCH during `resize_debounce` period.
+/// If `first_output_timeout` is set and `first_output` is not set when it
+/// expires, child is killed.
+/// With `reaper` (--subreaper), after child exits, waits until all its
+/// descendants adopted by us exit too.
+/// With `kill_tree` (--kill-tree), signals to child go to all descendants.
+/// Returns pending interruption signal, and whether child was killed
+/// because of `first_output_timeout`.
+#[allow(clippy::too_many_arguments)]
+fn process_signals(
+    clock: &dyn Clock,
+    child_proc: Arc<dyn ChildProc>,
+    buf_queue: Arc<BufferQueue>,
+    first_output: Arc<OnceLock<Instant>>,
+    first_output_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    drain_timeout: Duration,
+    resize_debounce: Duration,
+    reaper: Option<Reaper>,
+    kill_tree: Option<KillTree>,
+) -> (Option<Signal>, bool) {
+    debug!(Signal, "entering process_signals thread");
+
+    let mut pending_interrupt = None;
+    let mut pending_stop = None;
+    let mut pending_resize: Option<Deadline> = None;
+    let mut output_deadline =
+        first_output_timeout.and_then(|period| Deadline::after(clock, period));
+    let mut output_expired = false;
+    let mut exit_traced = false;
+
+    'wait_signal: loop {
+        // Wait for SIGCHILD or other signal.
+        // If resize is pending, wake up when debounce period expires.
+        // If waiting for first output, wake up when timeout expires.
+        trace!(Signal, "waiting for next signal");
+        let wait_timeout = [pending_resize, output_deadline]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|deadline| deadline.remaining(clock));
+        let event = match signal::wait_signal(wait_timeout) {
+            Ok(ev) => ev,
+            Err(err) => terminate!(EXIT_FAILURE; "can't wait for signal: {}", err),
+        };
+
+        trace!(Signal, "received event: {:?}", event);
+        trace_signal(&event);
+        match event {
+            // Interrupt signal received first time.
+            SignalEvent::Interrupt(sig) if pending_interrupt.is_none() => {
+                // Ask child to exit and wait for SIGCHILD.
+                debug!(
+                    Signal,
+                    "sending signal {} to child",
+                    signal::display_name(sig)
+                );
+                kill_command(&child_proc, sig, &reaper, &kill_tree);
+                pending_interrupt = Some(sig);
+                continue 'wait_signal;
+            }
+
+            // Interrupt signal received second time, or quit signal received.
+            SignalEvent::Interrupt(sig) | SignalEvent::Quit(sig) => {
+                // Ask child to exit, if not asked before, wait until it exits, OR timeout expires,
+                // OR termination signal is received again (e.g. user hits ^\ twice).
+                if pending_interrupt.is_none() {
+                    debug!(
+                        Signal,
+                        "sending signal {} to child",
+                        signal::display_name(sig)
+                    );
+                    kill_command(&child_proc, sig, &reaper, &kill_tree);
+
+                    wait_child_exit(clock, &*child_proc, timeout);
+                }
+                match child_proc.wait_child(ChildWait::NoHang) {
+                    Ok(Some(status)) if status.exited() || status.signaled() => {
+                        debug!(Signal, "child exited");
+                        trace_exit(status, &mut exit_traced);
+                    }
+                    _ => {
+                        // If child is still alive, kill it forcibly.
+                        debug!(Signal, "child still running, sending SIGKILL");
+                        kill_command(&child_proc, Signal::KILL, &reaper, &kill_tree);
+                    }
+                }
+                // Give pty_2_stdout() a chance to show lines that child managed
+                // to emit before it was killed.
+                drain_stdout(&buf_queue, drain_timeout);
+                // Deliver signal to ourselves, which should kill us.
+                debug!(
+                    Signal,
+                    "sending signal {} to ourselves",
+                    signal::display_name(sig)
+                );
+                if let Err(err) = raise_signal(sig) {
+                    terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
+                }
+            }
+
+            // Stop signal received first time.
+            SignalEvent::Stop(sig) if pending_stop.is_none() => {
+                // Ask child to stop and wait for SIGCHILD.
+                debug!(Signal, "sending signal SIGSTOP to child");
+                kill_command(&child_proc, Signal::STOP, &reaper, &kill_tree);
                 pending_stop = Some(sig);
                 continue 'wait_signal;
             }
 
-            // Stop signal received second time.
-            SignalEvent::Stop(sig) => {
-                // Forcibly stop child, stop ourselves until we get SIGCONT.
-                debug!("sending signal SIGSTOP to child");
-                _ = pty_proc.kill_child(Signal::STOP);
+            // Stop signal received second time.
+            SignalEvent::Stop(sig) => {
+                // Forcibly stop child, stop ourselves until we get SIGCONT.
+                debug!(Signal, "sending signal SIGSTOP to child");
+                kill_command(&child_proc, Signal::STOP, &reaper, &kill_tree);
+
+                debug!(
+                    Signal,
+                    "sending signal {} to ourselves",
+                    signal::display_name(sig)
+                );
+                if let Err(err) = raise_signal(sig) {
+                    terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
+                }
+
+                // We received SIGCONT.
+                debug!(Signal, "fetching SIGCONT signal");
+                if let Err(err) = signal::drop_signal(Signal::CONT) {
+                    terminate!(EXIT_FAILURE; "can't drop signal: {}", err);
+                }
+
+                debug!(Signal, "sending SIGCONT signal to child");
+                kill_command(&child_proc, Signal::CONT, &reaper, &kill_tree);
+                pending_stop = None;
+                continue 'wait_signal;
+            }
+
+            // Resume signal received while we were NOT stopped.
+            SignalEvent::Continue(_) => {
+                // Re-ensure child is running.
+                debug!(Signal, "sending SIGCONT signal to child");
+                kill_command(&child_proc, Signal::CONT, &reaper, &kill_tree);
+                pending_stop = None;
+                continue 'wait_signal;
+            }
+
+            // Parent tty window change (SIGWINCH).
+            SignalEvent::Resize(_) if !resize_debounce.is_zero() => {
+                // Postpone resize until debounce period expires.
+                trace!(Signal, "postponing tty window resize");
+                pending_resize = Deadline::after(clock, resize_debounce);
+                continue 'wait_signal;
+            }
+
+            // First output timeout expired.
+            SignalEvent::Timeout if output_deadline.is_some_and(|d| d.expired(clock)) => {
+                output_deadline = None;
+                if first_output.get().is_some() {
+                    continue 'wait_signal;
+                }
+
+                // Ask child to exit, wait until it exits or timeout expires.
+                debug!(
+                    Signal,
+                    "no output from child in time, sending SIGTERM to child"
+                );
+                output_expired = true;
+                kill_command(&child_proc, Signal::TERM, &reaper, &kill_tree);
+
+                wait_child_exit(clock, &*child_proc, timeout);
+                match child_proc.wait_child(ChildWait::NoHang) {
+                    Ok(Some(status)) if status.exited() || status.signaled() => {
+                        debug!(Signal, "child exited, terminating wait loop");
+                        trace_exit(status, &mut exit_traced);
+                        break 'wait_signal;
+                    }
+                    _ => {
+                        // If child is still alive, kill it forcibly and wait
+                        // for SIGCHILD.
+                        debug!(Signal, "child still running, sending SIGKILL");
+                        kill_command(&child_proc, Signal::KILL, &reaper, &kill_tree);
+                        continue 'wait_signal;
+                    }
+                }
+            }
+
+            // Parent tty window change (SIGWINCH), or debounce period expired.
+            SignalEvent::Resize(_) | SignalEvent::Timeout => {
+                // Propagate resize to child.
+                trace!(Signal, "propagating tty window resize");
+                pending_resize = None;
+                if let Err(err) = child_proc.resize_child() {
+                    terminate!(EXIT_FAILURE; "can't resize pty: {}", err);
+                }
+                continue 'wait_signal;
+            }
+
+            // Child exited or stopped or resumed, or adopted descendant exited.
+            SignalEvent::Child(_) => {
+                // When child exits, its children are reparented before we
+                // get its status, so they're reaped or counted here.
+                let wait_result = child_proc.wait_child(ChildWait::NoHang);
+                let descendants_running = reap_descendants(&child_proc, &reaper);
+                match wait_result {
+                    // Child exited, but its descendants are still running.
+                    Ok(Some(status))
+                        if (status.exited() || status.signaled()) && descendants_running =>
+                    {
+                        debug!(Signal, "child exited, waiting for adopted descendants");
+                        trace_exit(status, &mut exit_traced);
+                        continue 'wait_signal;
+                    }
+                    // Child exited.
+                    Ok(Some(status)) if status.exited() || status.signaled() => {
+                        debug!(Signal, "child exited, terminating wait loop");
+                        trace_exit(status, &mut exit_traced);
+                        break 'wait_signal;
+                    }
+                    // Child stopped.
+                    Ok(Some(status)) if status.stopped() => {
+                        debug!(Signal, "child stopped");
+                        if let Some(stop_sig) = pending_stop {
+                            // Stop ourselves until we get SIGCONT.
+                            debug!(
+                                Signal,
+                                "sending signal {} to ourselves",
+                                signal::display_name(stop_sig)
+                            );
+                            if let Err(err) = raise_signal(stop_sig) {
+                                terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
+                            }
+
+                            // We received SIGCONT.
+                            debug!(Signal, "fetching SIGCONT signal");
+                            if let Err(err) = signal::drop_signal(Signal::CONT) {
+                                terminate!(EXIT_FAILURE; "can't drop signal: {}", err);
+                            }
+
+                            debug!(Signal, "sending SIGCONT signal to child");
+                            kill_command(&child_proc, Signal::CONT, &reaper, &kill_tree);
+                            pending_stop = None;
+                            continue 'wait_signal;
+                        }
+                    }
+                    Ok(_) => {
+                        trace!(Signal, "ignoring child event");
+                        continue 'wait_signal;
+                    }
+                    Err(err) => {
+                        terminate!(EXIT_COMMAND_FAILED; "can't wait child process: {}", err);
+                    }
+                }
+            }
+
+            _ => {
+                // Nothing interesting.
+                trace!(Signal, "ignoring event");
+                continue 'wait_signal;
+            }
+        }
+    }
+
+    debug!(Signal, "leaving process_signals thread");
+
+    (pending_interrupt, output_expired)
+}
+
+/// Record signal received by us (--trace-self).
+fn trace_signal(event: &SignalEvent) {
+    match *event {
+        SignalEvent::Interrupt(sig)
+        | SignalEvent::Quit(sig)
+        | SignalEvent::Stop(sig)
+        | SignalEvent::Continue(sig) => selftrace::record(TraceEvent::Signal(sig)),
+        _ => {}
+    }
+}
+
+/// Record exit of child, unless already recorded (--trace-self).
+/// Exit status is cached, so it's seen again with --subreaper.
+fn trace_exit(status: WaitStatus, traced: &mut bool) {
+    if !*traced {
+        selftrace::record(TraceEvent::Exit(describe_status(status)));
+        *traced = true;
+    }
+}
+
+/// Wait until child exits after it was asked to, OR timeout expires,
+/// OR termination signal is received again (e.g. user hits ^\ twice).
+/// Without timeout (--quit=inf), waits until child exits or termination
+/// signal. Other events, like resize or exit of adopted descendant, don't
+/// stop waiting.
+fn wait_child_exit(clock: &dyn Clock, child_proc: &dyn ChildProc, timeout: Option<Duration>) {
+    let deadline = timeout.and_then(|period| Deadline::after(clock, period));
+
+    debug!(
+        Signal,
+        "waiting for child exit, termination signal, or timeout"
+    );
+    loop {
+        let event = match signal::wait_signal(deadline.map(|d| d.remaining(clock))) {
+            Ok(ev) => ev,
+            Err(err) => terminate!(EXIT_FAILURE; "can't wait for signal: {}", err),
+        };
+
+        trace!(Signal, "received event: {:?}", event);
+        trace_signal(&event);
+        match event {
+            SignalEvent::Timeout => {
+                debug!(Signal, "timeout expired");
+                return;
+            }
+            SignalEvent::Interrupt(_) | SignalEvent::Quit(_) => return,
+            _ => {}
+        }
+
+        if let Ok(Some(status)) = child_proc.wait_child(ChildWait::NoHang)
+            && (status.exited() || status.signaled())
+        {
+            return;
+        }
+    }
+}
+
+/// Send signal to child's process group, and with `kill_tree` (--kill-tree),
+/// to all descendants of child, including adopted by us (--subreaper).
+fn kill_command(
+    child_proc: &Arc<dyn ChildProc>,
+    sig: Signal,
+    reaper: &Option<Reaper>,
+    kill_tree: &Option<KillTree>,
+) {
+    selftrace::record(TraceEvent::Kill(sig));
+
+    let kill_tree = match kill_tree {
+        Some(kill_tree) => kill_tree,
+        None => {
+            _ = child_proc.kill_child(sig);
+            return;
+        }
+    };
+
+    let child_pid = child_proc.child_pid().unwrap();
+    let mut children = vec![child_pid];
+    if let Some(reaper) = reaper {
+        children.extend(reaper.adopted(child_pid).unwrap_or_default());
+    }
+    debug!(
+        Signal,
+        "sending signal {} to process tree",
+        signal::display_name(sig)
+    );
+    let result = kill_tree.kill(&children, sig, || {
+        // Fails if child was already reaped.
+        child_proc.kill_child(sig).ok().map(|_| child_pid)
+    });
+    if let Err(err) = result {
+        debug!(Signal, "can't signal process tree: {}", err);
+    }
+}
+
+/// Reap exited descendants of child adopted by us (--subreaper).
+/// Returns true if some of them are still running.
+fn reap_descendants(child_proc: &Arc<dyn ChildProc>, reaper: &Option<Reaper>) -> bool {
+    match reaper {
+        Some(reaper) => match reaper.reap(child_proc.child_pid().unwrap()) {
+            Ok(running) => running > 0,
+            Err(err) => terminate!(EXIT_FAILURE; "can't reap descendants: {}", err),
+        },
+        None => false,
+    }
+}
+
+/// Close buffer queue and wait until pty_2_stdout() writes pending lines,
+/// but not longer than timeout.
+/// Returns false if timeout expired.
+fn drain_stdout(buf_queue: &BufferQueue, timeout: Duration) -> bool {
+    debug!(Stdout, "closing buffer queue");
+    buf_queue.close();
+
+    debug!(Stdout, "draining stdout queue");
+    if !buf_queue.wait_drained(timeout) {
+        debug!(
+            Stdout,
+            "stdout drain timeout expired, dropping pending lines"
+        );
+        return false;
+    }
+
+    true
+}
+
+/// Wait until pty_2_stdout() writes all pending lines, after queue was closed.
+/// Returns termination signal if it was received before that.
+/// Expects that all signals are blocked.
+fn wait_stdout_drained(buf_queue: &BufferQueue) -> Option<Signal> {
+    debug!(Stdout, "waiting for stdout queue");
+    loop {
+        if buf_queue.wait_drained(Duration::from_millis(10)) {
+            return None;
+        }
+
+        match signal::wait_signal(Some(Duration::ZERO)) {
+            Ok(SignalEvent::Interrupt(sig) | SignalEvent::Quit(sig)) => {
+                debug!(
+                    Stdout,
+                    "received signal {} while draining stdout",
+                    signal::display_name(sig)
+                );
+                return Some(sig);
+            }
+            Ok(_) => {}
+            Err(err) => terminate!(EXIT_FAILURE; "can't wait for signal: {}", err),
+        }
+    }
+}
+
+/// Thread that reads lines from stdin (or --stdin-file) and writes to
+/// master pty (i.e. to child's stdin).
+/// If `line_delay` is set, waits that long before writing each line.
+fn stdin_2_pty(
+    child_proc: Arc<dyn ChildProc>,
+    pty_writer: Arc<InterruptibleWriter<OwnedFd>>,
+    stdin_reader: Arc<InterruptibleReader<OwnedFd>>,
+    line_delay: Option<Duration>,
+    command_key: CommandKey,
+    controls: Arc<Controls>,
+) {
+    debug!(Stdin, "entering stdin_2_pty thread");
+
+    let mut pty_line_writer = BufWriter::new(pty_writer.blocking_writer());
+
+    let mut buf_reader = BufReader::new(stdin_reader.blocking_reader());
+    let mut buf = Vec::new();
+
+    // Keyboard commands are recognized only when user types on terminal.
+    let mut key_parser = match command_key.0 {
+        Some(prefix) if term::is_tty(stdio::stdin()) => Some(KeyParser::new(prefix)),
+        _ => None,
+    };
+    let mut data = Vec::new();
+    let mut commands = Vec::new();
+
+    let mut stdin_eof = false;
+    while !stdin_eof {
+        buf.clear();
+        let size = match buf_reader.read_until(b'\n', &mut buf) {
+            Ok(size) => size,
+            Err(err) => terminate!(EXIT_FAILURE; "can't read from stdin: {}", err),
+        };
+
+        stdin_eof = size == 0;
+
+        // Stdin is in canonical mode, so commands arrive together with the
+        // rest of the line, when user presses Enter (or Ctrl-D).
+        if let Some(key_parser) = &mut key_parser {
+            data.clear();
+            key_parser.parse(&buf, &mut data, &mut commands);
+            std::mem::swap(&mut buf, &mut data);
+
+            // Line with only commands isn't forwarded, including Enter.
+            if !commands.is_empty() && buf == b"\n" {
+                buf.clear();
+            }
+            for command in commands.drain(..) {
+                run_key_command(command, command_key, &controls);
+            }
+            if !stdin_eof && buf.is_empty() {
+                continue;
+            }
+        }
+
+        // Pace lines from --stdin-file, unless we're shutting down.
+        if let Some(delay) = line_delay
+            && !stdin_eof
+        {
+            match stdin_reader.pause(delay) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => terminate!(EXIT_FAILURE; "can't wait for input: {}", err),
+            }
+        }
+
+        let result = if stdin_eof {
+            // Propagate EOF to child. For pty, this writes VEOF to master PTY,
+            // and canonical mode translates this symbol to end-of-file condition.
+            // For pipes, this shuts down writing side.
+            debug!(Stdin, "got eof from stdin, propagating to child");
+            child_proc.send_eof(&mut pty_line_writer)
+        } else {
+            trace!(Stdin, "writing {} bytes to pty", buf.len());
+            pty_line_writer
+                .write_all(&buf)
+                .and_then(|_| pty_line_writer.flush())
+        };
+
+        if let Err(err) = result {
+            match Errno::from_io_error(&err) {
+                Some(Errno::IO | Errno::PIPE) => {
+                    // This happens if child process exits but we haven't received
+                    // SIGCHLD yet. Don't exit, instead finish I/O and wait SIGCHLD.
+                    debug!(
+                        Stdin,
+                        "got error when writing to pty, exiting io loop: {}", err
+                    );
+                    break;
+                }
+                // Unexpected error.
+                _ => terminate!(EXIT_FAILURE; "can't write to pty: {}", err),
+            }
+        }
+    }
+
+    debug!(Stdin, "leaving stdin_2_pty thread");
+}
+
+/// Execute command entered from keyboard after --command-key.
+fn run_key_command(command: KeyCommand, command_key: CommandKey, controls: &Controls) {
+    match command {
+        KeyCommand::TogglePause => {
+            let msg = if controls.toggle_pause() {
+                debug!(Stdin, "pausing output");
+                format!(
+                    "reclog: output paused, press {} p to resume\n",
+                    command_key.name()
+                )
+            } else {
+                debug!(Stdin, "resuming output");
+                "reclog: output resumed\n".to_string()
+            };
+            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
+        }
+        KeyCommand::Mark => {
+            debug!(Stdin, "adding marker");
+            controls.add_mark(Local::now());
+        }
+        KeyCommand::ToggleStatusLine => {
+            debug!(Stdin, "toggling status line");
+            controls.toggle_status_line();
+        }
+    }
+}
+
+/// Thread that reads lines from buffer queue and writes them to stdout.
+/// ANSI escape codes are removed before writing according to strip level.
+/// Writes are serialized with report_status() using `stdout_lock`.
+fn queue_2_stdout(
+    buf_queue: Arc<BufferQueue>,
+    stdout_writer: Arc<InterruptibleWriter<Stdout>>,
+    stdout_lock: Arc<Mutex<()>>,
+    strip: StripLevel,
+) {
+    debug!(Stdout, "entering queue_2_stdout thread");
+
+    let stdout_buf_writer = BufWriter::new(stdout_writer.blocking_writer());
+    let mut stdout_line_writer: Box<dyn Write> = if strip == StripLevel::None {
+        Box::new(stdout_buf_writer)
+    } else {
+        Box::new(AnsiStripper::with_modes(
+            stdout_buf_writer,
+            strip,
+            CrMode::Drop,
+        ))
+    };
+
+    loop {
+        let buf = match buf_queue.read() {
+            Some(buf) => buf,
+            None => break, // queue closed, exit loop
+        };
+
+        let guard = stdout_lock.lock().unwrap();
+        let result = stdout_line_writer
+            .write_all(&buf)
+            .and_then(|_| stdout_line_writer.flush());
+        drop(guard);
+
+        if let Err(err) = result {
+            if err.kind() != io::ErrorKind::TimedOut {
+                terminate!(EXIT_FAILURE; "can't write to stdout: {}", err);
+            }
+            // --stdout-timeout expired, closed writer will discard the rest
+            debug!(Stdout, "stdout is stalled, stop writing to it");
+            _ = stdout_writer.close();
+        }
+
+        // buf is returned to pool here
+    }
+
+    debug!(Stdout, "leaving queue_2_stdout thread");
+}
+
+/// How often --progress line is updated.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Thread that periodically prints capture statistics to stderr (--progress).
+/// Status is redrawn in place on a single line, which is cleared when
+/// `stop` is signaled or dropped.
+fn report_progress(stats: Arc<Stats>, buf_queue: Arc<BufferQueue>, stop: mpsc::Receiver<()>) {
+    debug!(Stdout, "entering report_progress thread");
+
+    let mut progress = Progress::default();
+    let mut stderr = io::stderr();
+
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(PROGRESS_INTERVAL) {
+        let line = progress.format(&stats, &buf_queue);
+        // Errors are ignored, progress isn't essential.
+        _ = write!(stderr, "\r[reclog] {}\x1b[K", line).and_then(|_| stderr.flush());
+    }
+    _ = write!(stderr, "\r\x1b[K").and_then(|_| stderr.flush());
+
+    debug!(Stdout, "leaving report_progress thread");
+}
+
+/// How often command's terminal is checked for hidden input.
+const INPUT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Thread that disables echo of our stdin tty while command has hidden
+/// input in its terminal, e.g. while reading a password (--echo=off).
+/// Echo is enabled back when `stop` is signaled or dropped.
+fn watch_input(child_proc: Arc<dyn ChildProc>, stop: mpsc::Receiver<()>) {
+    debug!(Stdin, "entering watch_input thread");
+
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(INPUT_CHECK_INTERVAL) {
+        hide_input(child_proc.input_hidden());
+    }
+    hide_input(false);
+
+    debug!(Stdin, "leaving watch_input thread");
+}
+
+/// Disable or enable echo of our stdin tty, if tty is in our mode.
+fn hide_input(hidden: bool) {
+    let active = TTY_ACTIVE.lock().unwrap();
+    if INPUT_HIDDEN.swap(hidden, Ordering::Relaxed) == hidden || !*active {
+        return;
+    }
+
+    debug!(
+        Stdin,
+        "command {} input",
+        if hidden { "hid" } else { "showed" }
+    );
+    // Errors are ignored, stdin still works with wrong echo.
+    _ = term::set_tty_mode(stdio::stdin(), local_tty_mode());
+}
+
+/// How often status line is updated, and checked for toggling and resize.
+const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Thread that draws status line in the bottom row of terminal, while it's
+/// enabled (--status-line or keyboard command).
+/// Child's terminal is kept one row shorter while status line is shown.
+/// Status line is removed when `stop` is signaled or dropped.
+#[allow(clippy::too_many_arguments)]
+fn report_status(
+    child_proc: Arc<dyn ChildProc>,
+    stats: Arc<Stats>,
+    buf_queue: Arc<BufferQueue>,
+    controls: Arc<Controls>,
+    stdout_writer: Arc<InterruptibleWriter<Stdout>>,
+    stdout_lock: Arc<Mutex<()>>,
+    out_path: String,
+    reverse: bool,
+    stop: mpsc::Receiver<()>,
+) {
+    debug!(Stdout, "entering report_status thread");
+
+    let mut status_line = StatusLine::new(reverse);
+    let mut stdout = stdout_writer.blocking_writer();
+    let mut out = String::new();
+
+    loop {
+        let stopped = !matches!(
+            stop.recv_timeout(STATUS_INTERVAL),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        // Status line was removed by before_exit() when we were stopped.
+        if STATUS_ROWS.load(Ordering::SeqCst) != status_line.rows() {
+            status_line.forget();
+        }
+
+        let shown = status_line.rows() != 0;
+        out.clear();
+        match term::tty_size(stdio::stdout()) {
+            Ok((cols, rows)) if !stopped && controls.status_line() && rows >= 2 => {
+                let text = statusline::format_status(
+                    &stats,
+                    &buf_queue,
+                    &out_path,
+                    controls.stdout_paused(),
+                );
+                status_line.show(&mut out, cols, rows, &text);
+            }
+            _ => status_line.hide(&mut out),
+        }
+
+        if !out.is_empty() {
+            let _guard = stdout_lock.lock().unwrap();
+            // Errors are ignored, status line isn't essential.
+            _ = stdout.write_all(out.as_bytes());
+            STATUS_ROWS.store(status_line.rows(), Ordering::SeqCst);
+        }
+
+        // Tell command about its new terminal size.
+        if shown != (status_line.rows() != 0) {
+            child_proc.set_reserved_rows(if shown { 0 } else { 1 });
+            if let Err(err) = child_proc.resize_child() {
+                terminate!(EXIT_FAILURE; "can't resize pty: {}", err);
+            }
+        }
+
+        if stopped {
+            break;
+        }
+    }
+
+    debug!(Stdout, "leaving report_status thread");
+}
+
+/// Per-line processing of command output.
+struct LineRules {
+    encoding: Encoding,            // --encoding
+    max_line_bytes: Option<usize>, // --max-line-bytes
+    rate_limit: Option<RateLimit>, // --rate-limit
+    sampler: Sampler,              // --sample
+    display_filter: Option<Regex>, // --only-errors
+    highlights: Vec<Regex>,        // --highlight
+    fail_filter: Option<Regex>,    // --fail-on-match
+    fail_matched: bool,            // some line matched fail_filter
+    expect: Vec<ExpectRule>,       // --expect
+}
+
+impl LineRules {
+    fn is_empty(&self) -> bool {
+        self.sampler.is_empty()
+            && self.display_filter.is_none()
+            && self.highlights.is_empty()
+            && self.fail_filter.is_none()
+    }
+}
+
+/// Capture loop: reads lines from master pty (i.e. child's stdout), formats
+/// them, and emits them to event bus, which delivers them to stdout, files,
+/// and network sinks.
+#[allow(clippy::too_many_arguments)]
+fn pty_2_events(
+    child_proc: &Arc<dyn ChildProc>,
+    pty_reader: &Arc<InterruptibleReader<OwnedFd>>,
+    pty_writer: &Arc<InterruptibleWriter<OwnedFd>>,
+    bus: &mut EventBus,
+    buf_pool: &Arc<BufferPool>,
+    fm: &mut Formatter,
+    rules: &mut LineRules,
+    stats: &Stats,
+    controls: &Controls,
+    first_output: &OnceLock<Instant>,
+) -> StreamEnd {
+    debug!(Pty, "entering pty_2_events thread");
+
+    // Prompts are answered before output is throttled, so that command
+    // doesn't wait for answer longer than needed.
+    let mut pty_line_reader = BufReader::new(ThrottledReader::new(
+        ExpectReader::new(
+            pty_reader.blocking_reader(),
+            pty_writer.blocking_writer(),
+            rules.expect.clone(),
+        ),
+        rules.rate_limit,
+        fm.clock(),
+    ));
+    let mut extras = String::new();
+
+    let stream_end = loop {
+        let mut buf = buf_pool.alloc();
+        let mut display = true;
+        let mut highlight = false;
+        let stream;
+        let prefix_len;
+        // Length of timestamp prefix, which may be cut per sink.
+        let mut ts_len = 0;
+
+        // Separator goes only to files, stdout shows one session anyway.
+        if fm.need_separator() {
+            if let Err(err) = fm.format_separator(&mut extras) {
+                terminate!(EXIT_FAILURE; "can't format separator: {}", err);
+            }
+            buf.extend_from_slice(extras.as_bytes());
+            extras.clear();
+            bus.emit(CaptureEvent {
+                ts: Instant::now(),
+                stream: Stream::Separator,
+                bytes: buf,
+                ts_len: 0,
+                display: None,
+            });
+            continue;
+        }
+
+        if fm.need_header() {
+            if let Err(err) = fm.format_header(&mut extras) {
+                terminate!(EXIT_FAILURE; "can't format header: {}", err);
+            }
+            buf.extend_from_slice(extras.as_bytes());
+            extras.clear();
+            stream = Stream::Header;
+            prefix_len = buf.len();
+        } else {
+            if fm.need_timestamp() {
+                if let Err(err) = fm.format_timestamp(&mut extras) {
+                    terminate!(EXIT_FAILURE; "can't format timestamp: {}", err);
+                }
+                buf.extend_from_slice(extras.as_bytes());
+                extras.clear();
+            }
+            let line_start = buf.len();
+            stream = Stream::Output;
+            prefix_len = line_start;
+            ts_len = line_start;
+            if pty_line_reader.buffer().is_empty() {
+                bus.idle();
+            }
+            let (size, dropped) =
+                match read_line_bounded(&mut pty_line_reader, &mut buf, rules.max_line_bytes) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        match Errno::from_io_error(&err) {
+                            Some(Errno::IO) => {
+                                // This happens if child process exits but we haven't received
+                                // SIGCHLD yet. Don't exit, instead finish I/O and wait SIGCHLD.
+                                debug!(
+                                    Pty,
+                                    "got error when reading from pty, exiting io loop: {}", err
+                                );
+                                break StreamEnd::Eio;
+                            }
+                            // Unexpected error.
+                            _ => terminate!(EXIT_FAILURE; "can't read from pty: {}", err),
+                        }
+                    }
+                };
+            if size == 0 {
+                // EOF, exit loop
+                debug!(Pty, "got eof from pty, exiting io loop");
+                break end_of_stream(pty_reader);
+            }
+            trace!(Pty, "read {} bytes from pty", size);
+            // Resize usually makes command redraw, so it goes before the line.
+            if let Some((ts, cols, rows)) = child_proc.take_resize() {
+                bus.emit(CaptureEvent {
+                    ts,
+                    stream: Stream::Resize { cols, rows },
+                    bytes: buf_pool.alloc(),
+                    ts_len: 0,
+                    display: None,
+                });
+            }
+            // Markers and traces recorded while we were waiting go before
+            // the line.
+            emit_markers(bus, buf_pool, fm, controls);
+            emit_traces(bus, buf_pool, fm);
+            first_output.get_or_init(Instant::now);
+            stats.add_line(size);
+
+            // Mark line cut according to --max-line-bytes.
+            if dropped > 0 {
+                truncate_line(&mut buf, line_start, dropped, rules.encoding);
+            }
+
+            // Convert line to UTF-8 according to --encoding.
+            term::decode_line(&mut buf, line_start, rules.encoding);
+
+            // Lines are matched with ANSI escape codes stripped.
+            if !rules.is_empty() {
+                let plain_line = term::strip_ansi(&buf[line_start..]);
+
+                // Remember that output matched --fail-on-match, even if line
+                // is dropped below.
+                if let Some(pattern) = &rules.fail_filter {
+                    if !rules.fail_matched && pattern.is_match(&plain_line) {
+                        rules.fail_matched = true;
+                    }
+                }
+
+                // Drop or annotate line according to --sample rules.
+                match rules.sampler.sample(&plain_line) {
+                    SampleAction::Keep => {}
+                    SampleAction::Sample(skipped) => sample::annotate_line(&mut buf, skipped),
+                    SampleAction::Skip => continue,
+                }
+
+                // Hide line from stdout according to --only-errors.
+                if let Some(pattern) = &rules.display_filter {
+                    display = pattern.is_match(&plain_line);
+                }
+
+                // Highlight line on stdout according to --highlight.
+                highlight = rules.highlights.iter().any(|p| p.is_match(&plain_line));
+            }
+
+            // Drop line paused from keyboard, if --pause-output is used.
+            if controls.capture_paused() {
+                continue;
+            }
+        }
+
+        bus.emit(CaptureEvent {
+            ts: Instant::now(),
+            stream,
+            bytes: buf,
+            ts_len,
+            display: display.then_some(LineStyle {
+                prefix_len,
+                highlight,
+                success: None,
+            }),
+        });
+    };
+
+    // Lines skipped by --sample after the last kept line are not reported
+    // by any annotation, so report them separately.
+    for skipped in rules.sampler.take_skipped() {
+        let mut buf = buf_pool.alloc();
+        if fm.need_timestamp() {
+            if let Err(err) = fm.format_timestamp(&mut extras) {
+                terminate!(EXIT_FAILURE; "can't format timestamp: {}", err);
+            }
+            buf.extend_from_slice(extras.as_bytes());
+            extras.clear();
+        }
+        let ts_len = buf.len();
+        sample::skipped_line(&mut buf, skipped);
+
+        bus.emit(CaptureEvent {
+            ts: Instant::now(),
+            stream: Stream::Output,
+            bytes: buf,
+            ts_len,
+            display: Some(LineStyle {
+                prefix_len: ts_len,
+                highlight: false,
+                success: None,
+            }),
+        });
+    }
+
+    // Markers and traces recorded while waiting for output that never came.
+    emit_markers(bus, buf_pool, fm, controls);
+    emit_traces(bus, buf_pool, fm);
+
+    debug!(Pty, "leaving pty_2_events thread");
+
+    stream_end
+}
+
+/// Emit markers requested from keyboard (--command-key).
+fn emit_markers(
+    bus: &mut EventBus,
+    buf_pool: &Arc<BufferPool>,
+    fm: &mut Formatter,
+    controls: &Controls,
+) {
+    for time in controls.take_marks() {
+        let mut marker = String::new();
+        if let Err(err) = fm.format_marker(&mut marker, time) {
+            terminate!(EXIT_FAILURE; "can't format marker: {}", err);
+        }
+        let mut buf = buf_pool.alloc();
+        buf.extend_from_slice(marker.as_bytes());
+
+        let prefix_len = buf.len();
+        bus.emit(CaptureEvent {
+            ts: Instant::now(),
+            stream: Stream::Marker,
+            bytes: buf,
+            ts_len: 0,
+            display: Some(LineStyle {
+                prefix_len,
+                highlight: false,
+                success: None,
+            }),
+        });
+    }
+}
+
+/// Emit lifecycle events of reclog recorded since previous call (--trace-self).
+fn emit_traces(bus: &mut EventBus, buf_pool: &Arc<BufferPool>, fm: &mut Formatter) {
+    for (time, event) in selftrace::take() {
+        let mut trace = String::new();
+        if let Err(err) = fm.format_trace(&mut trace, time, &event) {
+            terminate!(EXIT_FAILURE; "can't format trace: {}", err);
+        }
+        let mut buf = buf_pool.alloc();
+        buf.extend_from_slice(trace.as_bytes());
+
+        bus.emit(CaptureEvent {
+            ts: Instant::now(),
+            stream: Stream::Trace,
+            bytes: buf,
+            ts_len: 0,
+            display: None,
+        });
+    }
+}
+
+/// Get reason of end of stream reported by pty reader.
+fn end_of_stream(pty_reader: &Arc<InterruptibleReader<OwnedFd>>) -> StreamEnd {
+    match pty_reader.end_reason() {
+        Some(ReaderEnd::Timeout) => StreamEnd::Timeout,
+        Some(ReaderEnd::Closed) => StreamEnd::Closed,
+        _ => StreamEnd::Eof,
+    }
+}
+
+/// Check if input forwarded to command is echoed by command's terminal
+/// (and thus recorded), rather than by ours (--echo).
+fn child_echo(args: &Args) -> bool {
+    match args.echo {
+        EchoMode::On => true,
+        EchoMode::Off => false,
+        // Nobody else echoes input from file.
+        EchoMode::Auto => args.stdin_file.is_some() && !args.no_pty,
+    }
+}
+
+/// Check if output can be copied from pty to output file as is, without
+/// any per-line processing, using pty_2_file_splice().
+fn can_splice(args: &Args) -> bool {
+    #[cfg(feature = "mqtt")]
+    if args.mqtt.is_some() {
+        return false;
+    }
+
+    cfg!(target_os = "linux")
+        && args.silent
+        && !args.null
+        && !args.append
+        && (args.raw || args.strip_level == StripLevel::None)
+        && args.encoding == Encoding::Binary
+        && !args.ts
+        && args.sample.is_empty()
+        && args.expect.is_empty()
+        && args.highlight.is_empty()
+        && args.max_line_bytes == 0
+        && args.rate_limit.is_none()
+        && args.timing.is_none()
+        && args.raw_output.is_none()
+        && args.ttyrec.is_none()
+        && args.index.is_none()
+        && args.fail_on_match.is_none()
+        && args.meta_file.is_none()
+        && args.checksum.is_none()
+        && !args.progress
+        && args.metrics_listen.is_none()
+        && args.gelf.is_none()
+        && args.publish.is_none()
+        && args.encrypt.is_empty()
+        && args.sink_format.is_empty()
+        && args.output_format == OutputFormat::Text
+        && !args.trace_self
+}
+
+/// How many bytes pty_2_file_splice() moves at once (default pipe capacity).
+const SPLICE_CHUNK_SIZE: usize = 65536;
+
+/// How pty_2_file_splice() finished.
+enum SpliceEnd {
+    Finished(StreamEnd), // Reached end of stream
+    Unsupported,         // Kernel can't splice, use generic path
+}
+
+/// Fast path for pty_2_events() on Linux.
+/// Moves command output from master pty to output file using splice(),
+/// without copying it through userspace.
+/// `out_file` is a duplicate of the descriptor wrapped into `files`.
+/// `first_output` is set when command produces first output.
+#[cfg(target_os = "linux")]
+fn pty_2_file_splice(
+    pty_reader: &Arc<InterruptibleReader<OwnedFd>>,
+    out_file: &File,
+    files: &mut OutputFiles,
+    fm: &mut Formatter,
+    first_output: &OnceLock<Instant>,
+) -> SpliceEnd {
+    debug!(Pty, "entering pty_2_file_splice thread");
+
+    // Header is written via generic path.
+    if fm.need_header() {
+        let mut header = String::new();
+        if let Err(err) = fm.format_header(&mut header) {
+            terminate!(EXIT_FAILURE; "can't format header: {}", err);
+        }
+        if files.header {
+            if let Err(err) = files.out_writer.write_all(header.as_bytes()) {
+                terminate!(EXIT_FAILURE; "can't write output file: {}", err);
+            }
+        }
+    }
+    if let Err(err) = files.flush() {
+        terminate!(EXIT_FAILURE; "{}", err);
+    }
+
+    // splice() requires one side to be a pipe.
+    let (pipe_rd, pipe_wr) = match rustix::io::retry_on_intr(|| rustix::pipe::pipe()) {
+        Ok(fds) => fds,
+        Err(err) => terminate!(EXIT_FAILURE; "can't create pipe: {}", err),
+    };
+
+    let splice_end = loop {
+        let size = match pty_reader.splice_to(&pipe_wr, SPLICE_CHUNK_SIZE) {
+            Ok(0) => {
+                debug!(Pty, "got eof from pty, exiting io loop");
+                break SpliceEnd::Finished(end_of_stream(pty_reader));
+            }
+            Ok(size) => size,
+            Err(err) => match Errno::from_io_error(&err) {
+                Some(Errno::IO) => {
+                    // See pty_2_events().
+                    debug!(
+                        Pty,
+                        "got error when reading from pty, exiting io loop: {}", err
+                    );
+                    break SpliceEnd::Finished(StreamEnd::Eio);
+                }
+                Some(Errno::INVAL) => {
+                    debug!(Pty, "can't splice from pty, switching to generic path");
+                    break SpliceEnd::Unsupported;
+                }
+                _ => terminate!(EXIT_FAILURE; "can't read from pty: {}", err),
+            },
+        };
+        first_output.get_or_init(Instant::now);
+
+        // Move everything from pipe to file.
+        let mut remaining = size;
+        while remaining > 0 {
+            match rustix::io::retry_on_intr(|| {
+                rustix::pipe::splice(
+                    &pipe_rd,
+                    None,
+                    out_file,
+                    None,
+                    remaining,
+                    rustix::pipe::SpliceFlags::MOVE,
+                )
+            }) {
+                Ok(size) => remaining -= size,
+                Err(Errno::INVAL) => {
+                    // File doesn't support splice, copy the rest of the pipe
+                    // through userspace and give up.
+                    debug!(
+                        Pty,
+                        "can't splice to output file, switching to generic path"
+                    );
+                    let mut buf = vec![0; remaining];
+                    if let Err(err) = io::Read::read_exact(&mut File::from(pipe_rd), &mut buf)
+                        .and_then(|_| files.out_writer.write_all(&buf))
+                    {
+                        terminate!(EXIT_FAILURE; "can't write output file: {}", err);
+                    }
+                    return SpliceEnd::Unsupported;
+                }
+                Err(err) => terminate!(EXIT_FAILURE; "can't write output file: {}", err),
+            }
+        }
+    };
+
+    debug!(Pty, "leaving pty_2_file_splice thread");
+
+    splice_end
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pty_2_file_splice(
+    _pty_reader: &Arc<InterruptibleReader<OwnedFd>>,
+    _out_file: &File,
+    _files: &mut OutputFiles,
+    _fm: &mut Formatter,
+    _first_output: &OnceLock<Instant>,
+) -> SpliceEnd {
+    SpliceEnd::Unsupported
+}
+
+/// Read line like read_until(), but keep at most `limit` bytes of it.
+/// The rest of the line is consumed and discarded, except line terminator
+/// (LF or CR LF), which is always kept.
+/// Returns number of bytes read and number of bytes discarded.
+fn read_line_bounded<R: BufRead>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    limit: Option<usize>,
+) -> io::Result<(usize, usize)> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return reader.read_until(b'\n', buf).map(|size| (size, 0)),
+    };
+
+    // Line (or EOF) fits the limit.
+    let mut size = reader.by_ref().take(limit as u64).read_until(b'\n', buf)?;
+    if size < limit || buf.last() == Some(&b'\n') {
+        return Ok((size, 0));
+    }
+
+    // Skip the rest of the line, remembering last skipped byte to find
+    // out whether terminator is CR LF. If nothing is skipped, CR may be
+    // the last kept byte, then it stays in buf.
+    let mut dropped = 0;
+    let mut last = None;
+    let mut found = false;
+    loop {
+        let chunk = reader.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let len = match chunk.iter().position(|&b| b == b'\n') {
+            Some(pos) => {
+                found = true;
+                pos
+            }
+            None => chunk.len(),
+        };
+        if len > 0 {
+            last = Some(chunk[len - 1]);
+        }
+        reader.consume(len + found as usize);
+        size += len + found as usize;
+        dropped += len;
+        if found {
+            break;
+        }
+    }
+
+    if found {
+        if last == Some(b'\r') {
+            dropped -= 1;
+            buf.extend_from_slice(b"\r\n");
+        } else {
+            buf.push(b'\n');
+        }
+    }
+
+    Ok((size, dropped))
+}
+
+/// Finish line cut by read_line_bounded() with a truncation marker.
+/// Marker goes before line terminator, which is kept as is.
+fn truncate_line(buf: &mut Vec<u8>, line_start: usize, mut dropped: usize, encoding: Encoding) {
+    let eol: &[u8] = if buf[line_start..].ends_with(b"\r\n") {
+        b"\r\n"
+    } else {
+        b"\n"
+    };
+    if buf[line_start..].ends_with(eol) {
+        buf.truncate(buf.len() - eol.len());
+    }
+
+    // Don't leave incomplete character at the end.
+    if encoding == Encoding::Utf8 {
+        if let Err(err) = str::from_utf8(&buf[line_start..]) {
+            if err.error_len().is_none() {
+                let end = line_start + err.valid_up_to();
+                dropped += buf.len() - end;
+                buf.truncate(end);
+            }
+        }
+    }
+
+    buf.extend_from_slice(format!(" [truncated, {} bytes dropped]", dropped).as_bytes());
+    buf.extend_from_slice(eol);
+}
+
+/// Thread that receives events from capture loop and writes them to
+/// output file and other files.
+/// It has its own queue (--file-buffer), so that slow file doesn't delay
+/// terminal display and reading of command output.
+fn events_2_file(events: Receiver<Arc<CaptureEvent>>, files: &mut OutputFiles) {
+    debug!(File, "entering events_2_file thread");
+
+    loop {
+        let event = match events.try_recv() {
+            Ok(event) => event,
+            Err(TryRecvError::Empty) => {
+                files.idle();
+                match events.recv() {
+                    Ok(event) => event,
+                    Err(_) => break, // bus closed, exit loop
+                }
+            }
+            Err(TryRecvError::Disconnected) => break, // bus closed, exit loop
+        };
+
+        files.consume(&event);
+
+        // buf is returned to pool here, when other sinks are done with it
+    }
+
+    files.idle();
+
+    debug!(File, "leaving events_2_file thread");
+}
+
+/// Report write error of output files and exit.
+fn fail_output(err: Error) -> ! {
+    terminate!(EXIT_FAILURE; "{}", err);
+}
+
+/// Terminal bell character, for --bell.
+const BELL: u8 = 0x07;
+
+/// Tell all threads to unblock and exit.
+fn initiate_shutdown(
+    stdin_reader: Arc<InterruptibleReader<OwnedFd>>,
+    pty_reader: Arc<InterruptibleReader<OwnedFd>>,
+    pty_writer: Arc<InterruptibleWriter<OwnedFd>>,
+    child_proc: Arc<dyn ChildProc>,
+    eof_policy: EofPolicy,
+    timeout: Option<Duration>,
+) {
+    // Close our copy of child's side of the pty. After all processes close
+    // their copies, pty_2_events() gets EIO and exits.
+    if eof_policy == EofPolicy::Eio || eof_policy == EofPolicy::Both {
+        debug!(Pty, "releasing child side of pty");
+        if let Err(err) = child_proc.release_child_side() {
+            terminate!(EXIT_FAILURE; "can't close slave pty: {}", err);
+        }
+    }
+
+    // Set timeout for reading from child. After there is no data during timeout,
+    // pty_2_events() gets EOF and exits. Timeout allows to be sure we've
+    // read all pending data buffered in the pty.
+    // Without timeout (--quit=inf), policy is always eio.
+    if let Some(timeout) = timeout
+        && (eof_policy == EofPolicy::Timeout || eof_policy == EofPolicy::Both)
+    {
+        debug!(Pty, "setting pty reader timeout to {:?}", timeout);
+        if let Err(err) = pty_reader.set_timeout(timeout) {
+            terminate!(EXIT_FAILURE; "can't set pty read timeout: {}", err);
+        }
+    }
+
+    // Interrupt stdin_2_pty().
+    // It may be blocked on stdin or pty.
+    // This will unblock pty writer and tell stdin reader to return EOF.
+    debug!(Pty, "closing pty writer");
+    if let Err(err) = pty_writer.close() {
+        terminate!(EXIT_FAILURE; "can't close pty writer: {}", err);
+    }
+    debug!(Stdin, "closing stdin reader");
+    if let Err(err) = stdin_reader.close() {
+        terminate!(EXIT_FAILURE; "can't close stdin: {}", err);
+    }
+}
+
+/// Wait until capture loop finishes reading command output after pty is
+/// closed by all processes (--wait-eof), but not longer than `max_wait`,
+/// if it's set. Then close reader to stop capture loop.
+/// Returns termination signal if it was received meanwhile.
+/// Expects that all signals are blocked.
+fn wait_stream_end(
+    clock: &dyn Clock,
+    pty_reader: &InterruptibleReader<OwnedFd>,
+    stream_finished: &AtomicBool,
+    max_wait: Option<Duration>,
+) -> Option<Signal> {
+    debug!(Pty, "waiting until pty is closed");
+    let deadline = max_wait.and_then(|max_wait| Deadline::after(clock, max_wait));
+    let mut interrupt = None;
+
+    while !stream_finished.load(Ordering::SeqCst) {
+        if deadline.is_some_and(|deadline| deadline.expired(clock)) {
+            debug!(Pty, "wait for pty close expired");
+            break;
+        }
+        match signal::wait_signal(Some(Duration::from_millis(10))) {
+            Ok(SignalEvent::Interrupt(sig) | SignalEvent::Quit(sig)) => {
+                debug!(
+                    Pty,
+                    "received signal {} while waiting for pty close",
+                    signal::display_name(sig)
+                );
+                interrupt = Some(sig);
+                break;
+            }
+            Ok(_) => {}
+            Err(err) => terminate!(EXIT_FAILURE; "can't wait for signal: {}", err),
+        }
+    }
+
+    debug!(Pty, "closing pty reader");
+    if let Err(err) = pty_reader.close() {
+        terminate!(EXIT_FAILURE; "can't close pty reader: {}", err);
+    }
+
+    interrupt
+}
+
+/// Format footer and emit it to event bus.
+fn write_footer(
+    child_proc: &Arc<dyn ChildProc>,
+    stream_end: StreamEnd,
+    bus: &mut EventBus,
+    buf_pool: &Arc<BufferPool>,
+    fm: &mut Formatter,
+) {
+    let mut buf = buf_pool.alloc();
+
+    let mut footer = String::new();
+
+    let child_status = child_proc.child_status();
+    let status = describe_status(child_status);
+    if let Err(err) = fm.format_footer(&mut footer, &status, stream_end.as_str()) {
+        terminate!(EXIT_FAILURE; "can't format footer: {}", err);
+    }
+    buf.extend_from_slice(footer.as_bytes());
+
+    let prefix_len = buf.len();
+    bus.emit(CaptureEvent {
+        ts: Instant::now(),
+        stream: Stream::Footer,
+        bytes: buf,
+        ts_len: 0,
+        display: Some(LineStyle {
+            prefix_len,
+            highlight: false,
+            success: Some(child_status.exit_status() == Some(EXIT_SUCCESS)),
+        }),
+    });
+}
+
+/// Get human-readable child status.
+fn describe_status(status: WaitStatus) -> String {
+    if let Some(code) = status.exit_status() {
+        code.to_string()
+    } else if let Some(sig_number) = status.terminating_signal() {
+        match Signal::from_named_raw(sig_number) {
+            Some(sig) => signal::display_name(sig),
+            None => format!("signal {}", sig_number),
+        }
+    } else {
+        "unknown".to_string()
+    }
+}
+
+/// Get child process exit code and exit with same code.
+/// If `expired` is set, child was killed by --first-output-timeout.
+/// If `silent` is set, successful exit is reported as failure (--fail-if-silent).
+/// If `matched` is set, successful exit is reported as failure (--fail-on-match).
+/// If exit is successful, `remove_files` are removed (--keep-on-failure).
+/// With `policy` other than child (--exit-status), status of command is
+/// ignored, and so are `expired` and `silent`.
+#[allow(clippy::too_many_arguments)]
+fn forward_exit_status(
+    child_proc: Arc<dyn ChildProc>,
+    policy: ExitPolicy,
+    pending_interrupt: Option<Signal>,
+    expired: bool,
+    silent: bool,
+    matched: bool,
+    exit_map: &[ExitMapping],
+    ignore_exit_code: bool,
+    remove_files: &[&str],
+) -> ! {
+    if policy != ExitPolicy::Child {
+        // We were interrupted ourselves (e.g. ^C), which isn't a result of
+        // command and is reported as usual.
+        if let Some(sig) = pending_interrupt {
+            debug!(
+                Child,
+                "delivering pending signal {} to ourselves",
+                signal::display_name(sig)
+            );
+            if let Err(err) = raise_signal(sig) {
+                terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
+            }
+        }
+        if policy == ExitPolicy::FromMatch && matched {
+            terminate!(EXIT_OUTPUT_MATCHED; "command output matched --fail-on-match");
+        }
+        remove_recording(remove_files);
+        debug!(Child, "exiting with code {}", EXIT_SUCCESS);
+        terminate!(EXIT_SUCCESS);
+    }
+
+    // We killed command ourselves, its status doesn't matter.
+    if expired && pending_interrupt.is_none() {
+        terminate!(EXIT_OUTPUT_TIMEOUT;
+                   "command produced no output during --first-output-timeout, killed it");
+    }
+
+    match child_proc.child_status() {
+        // Command exited normally.
+        status if status.exited() => {
+            let command_code = status.exit_status().unwrap();
+            // Apply --ignore-exit-code and --map-exit.
+            let exit_code = if ignore_exit_code {
+                EXIT_SUCCESS
+            } else {
+                ExitMapping::apply(exit_map, command_code)
+            };
+            if exit_code == EXIT_SUCCESS && silent {
+                terminate!(EXIT_COMMAND_SILENT;
+                           "command exited with code {}, but produced no output in time",
+                           command_code
+                );
+            } else if exit_code == EXIT_SUCCESS && matched {
+                terminate!(EXIT_OUTPUT_MATCHED;
+                           "command exited with code {}, but its output matched --fail-on-match",
+                           command_code
+                );
+            } else if exit_code == EXIT_SUCCESS {
+                remove_recording(remove_files);
+                debug!(Child, "exiting with code {}", exit_code);
+                terminate!(exit_code);
+            } else {
+                terminate!(exit_code; "command exited with code {}", command_code);
+            }
+        }
+
+        // Command killed by signal.
+        status if status.signaled() => {
+            if let Some(sig) = pending_interrupt {
+                // Command was not killed by itself - we killed it because *we* received
+                // death signal from user (e.g. ^C) - then we don't need to print any error
+                // message, just process original signal and die.
+                debug!(
+                    Child,
+                    "delivering pending signal {} to ourselves",
+                    signal::display_name(sig)
+                );
+                if let Err(err) = raise_signal(sig) {
+                    terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
+                }
+            }
+
+            // Command was killed unexpectedly, not by us - then report error and
+            // forward death signal N as exit code 128+N.
+            let sig_number = status.terminating_signal().unwrap();
+            let exit_code = EXIT_COMMAND_SIGNALED + sig_number;
+
+            if let Some(sig) = Signal::from_named_raw(sig_number) {
+                terminate!(exit_code;
+                           "command terminated by signal {}",
+                           signal::display_name(sig)
+                );
+            } else {
+                terminate!(exit_code;
+                    "command terminated by signal {}",
+                    sig_number
+                );
+            }
+        }
+
+        // Should not happen.
+        _ => {
+            terminate!(EXIT_COMMAND_FAILED; "command failed");
+        }
+    };
+}
+
+/// Remove files written during recording, for --keep-on-failure.
+fn remove_recording(paths: &[&str]) {
+    for path in paths {
+        debug!(File, "removing file: {}", path);
+        match fs::remove_file(path) {
+            Ok(()) => {}
+            // With --lazy-output, file may be not created.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => terminate!(EXIT_FAILURE; "can't remove file \"{}\": {}", path, err),
+        }
+    }
+}
+
+/// Options for opening files written during recording, according to --force
+/// and --append.
+fn output_options(args: &Args) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options
+        .write(true)
+        .create(args.force || args.append)
+        .create_new(!(args.force || args.append))
+        .append(args.append)
+        .truncate(!args.append);
+    if let Some(mode) = args.mode {
+        // Don't make file accessible to others even for a moment.
+        options.mode(mode.bits());
+    }
+    options
+}
+
+/// Open one of the files written during recording, according to --force
+/// and --append.
+/// With --atomic, file is opened under temporary path.
+fn open_output_file(path: &str, args: &Args, kind: &str) -> File {
+    let open_path = if args.atomic {
+        // Temporary file is always new, so check final path instead.
+        if !args.force && fs::symlink_metadata(path).is_ok() {
+            terminate!(
+                EXIT_FAILURE; "can't open {} file \"{}\": {}",
+                kind, path, io::Error::from(Errno::EXIST)
+            );
+        }
+        temp_path(path)
+    } else {
+        path.to_string()
+    };
+    let file = match output_options(args).open(&open_path) {
+        Ok(file) => file,
+        Err(err) => terminate!(
+            EXIT_FAILURE; "can't open {} file \"{}\": {}",
+            kind, path, err
+        ),
+    };
+    // Applied explicitly, because mode from open() is affected by umask
+    // and isn't applied to existing files.
+    if let Err(err) = perm::set_perm(&file, args.mode, args.group) {
+        terminate!(EXIT_FAILURE; "can't set permissions of {} file \"{}\": {}", kind, path, err);
+    }
+    file
+}
+
+/// Temporary path used with --atomic: hidden file in the same directory,
+/// so that it can be renamed to the final path.
+fn temp_path(path: &str) -> String {
+    let path = Path::new(path);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}.tmp", name, process::id()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Path of detached signature written with --sign.
+fn signature_path(path: &str) -> String {
+    format!("{}.sig", path)
+}
+
+/// Rename temporary files to final paths, for --atomic.
+fn finalize_recording(paths: &[&str]) {
+    for path in paths {
+        debug!(File, "renaming file: {}", path);
+        if let Err(err) = fs::rename(temp_path(path), path) {
+            terminate!(EXIT_FAILURE; "can't rename file \"{}\": {}", path, err);
+        }
+    }
+}
+
+/// Count lines in existing file, to continue numbering when appending.
+fn count_lines(path: &str) -> u64 {
+    let mut reader = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(_) => return 0,
+    };
+    let mut count = 0;
+    loop {
+        let chunk = match reader.fill_buf() {
+            Ok(chunk) => chunk,
+            Err(err) => terminate!(EXIT_FAILURE; "can't read output file \"{}\": {}", path, err),
+        };
+        if chunk.is_empty() {
+            return count;
+        }
+        count += chunk.iter().filter(|&&b| b == b'\n').count() as u64;
+        let len = chunk.len();
+        reader.consume(len);
+    }
+}
+
+/// Open output file in pager for --review.
+/// If `error_regex` is given, pager is positioned at the first matching
+/// line, otherwise at the end of file.
+fn review_output(path: &str, error_regex: Option<&Regex>) {
+    let mut position = "+G".to_string();
+
+    if let Some(pattern) = error_regex {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => terminate!(EXIT_FAILURE; "can't open output file \"{}\": {}", path, err),
+        };
+        let mut reader = BufReader::new(file);
+        let mut line = Vec::new();
+        let mut line_num = 0;
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => line_num += 1,
+                Err(err) => {
+                    terminate!(EXIT_FAILURE; "can't read output file \"{}\": {}", path, err)
+                }
+            }
+            if pattern.is_match(&term::strip_ansi(&line)) {
+                position = format!("+{}", line_num);
+                break;
+            }
+        }
+    }
+
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or("less".to_string());
+    let mut pager_args = pager.split_whitespace();
+    let pager_name = pager_args.next().unwrap();
+
+    // Give the terminal to pager.
+    before_exit();
+    if let Err(err) = signal::block_tty_signals() {
+        terminate!(EXIT_FAILURE; "can't block signals: {}", err);
+    }
+
+    debug!(Main, "running pager: {} {} {}", pager, position, path);
+    let result = process::Command::new(pager_name)
+        .args(pager_args)
+        .arg(&position)
+        .arg(path)
+        .status();
+
+    if let Err(err) = signal::unblock_tty_signals() {
+        terminate!(EXIT_FAILURE; "can't unblock signals: {}", err);
+    }
+    if let Err(err) = result {
+        let msg = format!("reclog: can't run pager \"{}\": {}\n", pager_name, err);
+        _ = shim::write_all(std::io::stderr(), msg.as_bytes());
+    }
+}
+
+/// Run --notify, --notify-exec, and --notify-webhook hooks.
+/// Failures are reported, but don't affect exit status.
+fn notify_completion(args: &Args, completion: Completion) {
+    if args.notify {
+        debug!(Net, "sending desktop notification");
+        if let Err(err) = notify::send_desktop(&completion) {
+            let msg = format!("reclog: can't send desktop notification: {}\n", err);
+            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
+        }
+    }
+    if let Some(command) = &args.notify_exec {
+        debug!(Net, "running notify command: {}", command);
+        if let Err(err) = notify::run_exec(command, &completion) {
+            let msg = format!("reclog: can't run notify command: {}\n", err);
+            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
+        }
+    }
+    if let Some(url) = &args.notify_webhook {
+        debug!(Net, "sending notify webhook");
+        if let Err(err) = notify::post_webhook(url, &completion) {
+            let msg = format!("reclog: can't send notify webhook: {}\n", err);
+            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
+        }
+    }
+}
+
+/// Inspect partially written recording for --resume.
+/// Returns start time from header (if any) and whether the last line
+/// is incomplete.
+fn inspect_resumed(path: &str) -> (Option<DateTime<FixedOffset>>, bool) {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => terminate!(EXIT_FAILURE; "can't open recording \"{}\": {}", path, err),
+    };
+
+    let mut first_line = Vec::new();
+    let mut last_byte = [b'\n'];
+    let result = BufReader::new(&mut file)
+        .read_until(b'\n', &mut first_line)
+        .and_then(|_| match file.seek(SeekFrom::End(-1)) {
+            Ok(_) => file.read_exact(&mut last_byte),
+            Err(_) => Ok(()), // empty file
+        });
+    if let Err(err) = result {
+        terminate!(EXIT_FAILURE; "can't read recording \"{}\": {}", path, err);
+    }
+
+    let start_time = format::parse_header_time(&String::from_utf8_lossy(&first_line));
+    (start_time, last_byte[0] != b'\n')
+}
+
+/// Run `reclog render` subcommand and exit.
+fn render_main(argv: Vec<OsString>) -> ! {
+    let args: RenderArgs = parse_cli(argv);
+
+    let recording = match File::open(&args.file) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => terminate!(EXIT_FAILURE; "can't open recording \"{}\": {}", args.file, err),
+    };
+    let timing = match File::open(&args.timing) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => {
+            terminate!(EXIT_FAILURE; "can't open timing file \"{}\": {}", args.timing, err)
+        }
+    };
+
+    let output: Box<dyn Write> = if args.output.is_empty() {
+        Box::new(io::stdout())
+    } else {
+        match OpenOptions::new()
+            .write(true)
+            .create(args.force)
+            .create_new(!args.force)
+            .truncate(true)
+            .open(&args.output)
+        {
+            Ok(file) => Box::new(file),
+            Err(err) => terminate!(
+                EXIT_FAILURE; "can't open output file \"{}\": {}",
+                args.output, err
+            ),
+        }
+    };
+
+    let mut formatter = Formatter::new(false, false, true, &args.ts_fmt, args.ts_src, &[]);
+
+    let start_time = args.virtual_time.map(|time| time.0);
+    if let Err(err) = render::render(recording, timing, output, &mut formatter, start_time) {
+        terminate!(EXIT_FAILURE; "can't render recording \"{}\": {}", args.file, err);
+    }
+
+    terminate!(EXIT_SUCCESS);
+}
+
+/// Run `reclog concat` subcommand and exit.
+fn concat_main(argv: Vec<OsString>) -> ! {
+    let args: ConcatArgs = parse_cli(argv);
+
+    let mut segments = Vec::new();
+    for path in &args.files {
+        let file = match File::open(path) {
+            Ok(file) => BufReader::new(file),
+            Err(err) => terminate!(EXIT_FAILURE; "can't open log \"{}\": {}", path, err),
+        };
+        match Segment::read(path, file) {
+            Ok(segment) => segments.push(segment),
+            Err(err) => terminate!(EXIT_FAILURE; "can't read log \"{}\": {}", path, err),
+        }
+    }
+
+    let output: Box<dyn Write> = if args.output.is_empty() {
+        Box::new(io::stdout())
+    } else {
+        match OpenOptions::new()
+            .write(true)
+            .create(args.force)
+            .create_new(!args.force)
+            .truncate(true)
+            .open(&args.output)
+        {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => terminate!(
+                EXIT_FAILURE; "can't open output file \"{}\": {}",
+                args.output, err
+            ),
+        }
+    };
+
+    if let Err(err) = concat::concat(&segments, output, &args.ts_fmt, args.ts_src) {
+        terminate!(EXIT_FAILURE; "can't concatenate logs: {}", err);
+    }
+
+    terminate!(EXIT_SUCCESS);
+}
+
+/// Run `reclog slice` subcommand and exit.
+fn slice_main(argv: Vec<OsString>) -> ! {
+    let args: SliceArgs = parse_cli(argv);
+
+    if args.timing.is_none() && !args.ttyrec {
+        usage_error!("recording format is required, e.g. --timing or --ttyrec");
+    }
+    if args.to.is_some_and(|to| to <= args.from) {
+        usage_error!("--to must be after --from");
+    }
+
+    let open_output = |path: &str| match OpenOptions::new()
+        .write(true)
+        .create(args.force)
+        .create_new(!args.force)
+        .truncate(true)
+        .open(path)
+    {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => terminate!(EXIT_FAILURE; "can't open output file \"{}\": {}", path, err),
+    };
+
+    let recording = match File::open(&args.input) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => terminate!(EXIT_FAILURE; "can't open recording \"{}\": {}", args.input, err),
+    };
+
+    let result = match &args.timing {
+        Some(timing_path) => {
+            let timing = match File::open(timing_path) {
+                Ok(file) => BufReader::new(file),
+                Err(err) => {
+                    terminate!(EXIT_FAILURE; "can't open timing file \"{}\": {}", timing_path, err)
+                }
+            };
+            let output = open_output(&args.output);
+            let timing_output = args.timing_output.as_deref().map(open_output);
+            cut::cut_timing(recording, timing, output, timing_output, args.from, args.to)
+        }
+        None => cut::cut_ttyrec(recording, open_output(&args.output), args.from, args.to),
+    };
+    if let Err(err) = result {
+        terminate!(EXIT_FAILURE; "can't cut recording \"{}\": {}", args.input, err);
+    }
+
+    terminate!(EXIT_SUCCESS);
+}
+
+/// Run `reclog export` subcommand and exit.
+fn export_main(argv: Vec<OsString>) -> ! {
+    let args: ExportArgs = parse_cli(argv);
+
+    if !args.html {
+        usage_error!("output format is required, e.g. --html");
+    }
+
+    let recording = match File::open(&args.file) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => terminate!(EXIT_FAILURE; "can't open recording \"{}\": {}", args.file, err),
+    };
+
+    let output: Box<dyn Write> = if args.output.is_empty() {
+        Box::new(io::stdout())
+    } else {
+        match OpenOptions::new()
+            .write(true)
+            .create(args.force)
+            .create_new(!args.force)
+            .truncate(true)
+            .open(&args.output)
+        {
+            Ok(file) => Box::new(BufWriter::new(file)),
+            Err(err) => terminate!(
+                EXIT_FAILURE; "can't open output file \"{}\": {}",
+                args.output, err
+            ),
+        }
+    };
+
+    let title = match &args.title {
+        Some(title) => title.clone(),
+        None => Path::new(&args.file)
+            .file_name()
+            .map_or(args.file.clone(), |name| name.to_string_lossy().into()),
+    };
+
+    if let Err(err) = export::export_html(recording, output, &title) {
+        terminate!(EXIT_FAILURE; "can't export recording \"{}\": {}", args.file, err);
+    }
+
+    terminate!(EXIT_SUCCESS);
+}
+
+/// Run `reclog list` subcommand and exit.
+fn list_main(argv: Vec<OsString>) -> ! {
+    let args: ListArgs = parse_cli(argv);
+
+    let sessions = match catalog::scan(Path::new(&args.dir)) {
+        Ok(sessions) => sessions,
+        Err(err) => terminate!(EXIT_FAILURE; "can't scan directory \"{}\": {}", args.dir, err),
+    };
 
-                debug!("sending signal {} to ourselves", signal::display_name(sig));
-                if let Err(err) = raise_signal(sig) {
-                    terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
-                }
+    if let Err(err) = catalog::write_table(&sessions, io::stdout().lock()) {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            terminate!(EXIT_FAILURE; "can't write to stdout: {}", err);
+        }
+    }
 
-                // We received SIGCONT.
-                debug!("fetching SIGCONT signal");
-                if let Err(err) = signal::drop_signal(Signal::CONT) {
-                    terminate!(EXIT_FAILURE; "can't drop signal: {}", err);
-                }
+    terminate!(EXIT_SUCCESS);
+}
 
-                debug!("sending SIGCONT signal to child");
-                _ = pty_proc.kill_child(Signal::CONT);
-                pending_stop = None;
-                continue 'wait_signal;
-            }
+/// Run `reclog show` subcommand and exit.
+fn show_main(argv: Vec<OsString>) -> ! {
+    let args: ShowArgs = parse_cli(argv);
 
-            // Resume signal received while we were NOT stopped.
-            SignalEvent::Continue(_) => {
-                // Re-ensure child is running.
-                debug!("sending SIGCONT signal to child");
-                _ = pty_proc.kill_child(Signal::CONT);
-                pending_stop = None;
-                continue 'wait_signal;
-            }
+    let session = match catalog::find(Path::new(&args.dir), &args.id) {
+        Ok(Some(session)) => session,
+        Ok(None) => terminate!(EXIT_FAILURE; "recording \"{}\" not found", args.id),
+        Err(err) => terminate!(EXIT_FAILURE; "can't read recording \"{}\": {}", args.id, err),
+    };
 
-            // Parent tty window change (SIGWINCH).
-            SignalEvent::Resize(_) => {
-                // Propagate resize to child.
-                debug!("propagating tty window resize");
-                if let Err(err) = pty_proc.resize_child() {
-                    terminate!(EXIT_FAILURE; "can't resize pty: {}", err);
-                }
-                continue 'wait_signal;
-            }
+    if let Err(err) = catalog::write_details(&session, io::stdout().lock()) {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            terminate!(EXIT_FAILURE; "can't write to stdout: {}", err);
+        }
+    }
 
-            // Child exited or stopped or resumed.
-            SignalEvent::Child(_) => {
-                match pty_proc.wait_child(PtyWait::NoHang) {
-                    // Child exited.
-                    Ok(Some(status)) if status.exited() || status.signaled() => {
-                        debug!("child exited, terminating wait loop");
-                        break 'wait_signal;
-                    }
-                    // Child stopped.
-                    Ok(Some(status)) if status.stopped() => {
-                        debug!("child stopped");
-                        if let Some(stop_sig) = pending_stop {
-                            // Stop ourselves until we get SIGCONT.
-                            debug!(
-                                "sending signal {} to ourselves",
-                                signal::display_name(stop_sig)
-                            );
-                            if let Err(err) = raise_signal(stop_sig) {
-                                terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
-                            }
+    terminate!(EXIT_SUCCESS);
+}
 
-                            // We received SIGCONT.
-                            debug!("fetching SIGCONT signal");
-                            if let Err(err) = signal::drop_signal(Signal::CONT) {
-                                terminate!(EXIT_FAILURE; "can't drop signal: {}", err);
-                            }
+/// Run `reclog prune` subcommand and exit.
+fn prune_main(argv: Vec<OsString>) -> ! {
+    let args: PruneArgs = parse_cli(argv);
 
-                            debug!("sending SIGCONT signal to child");
-                            _ = pty_proc.kill_child(Signal::CONT);
-                            pending_stop = None;
-                            continue 'wait_signal;
-                        }
-                    }
-                    Ok(_) => {
-                        debug!("ignoring child event");
-                        continue 'wait_signal;
-                    }
-                    Err(err) => {
-                        terminate!(EXIT_COMMAND_FAILED; "can't wait child process: {}", err);
-                    }
-                }
-            }
+    if args.keep_days.is_none() && args.keep_total_size.is_none() {
+        usage_error!("retention policy is required, e.g. --keep-days");
+    }
+    let retention = Retention {
+        keep_days: args.keep_days,
+        keep_total_size: args.keep_total_size,
+    };
 
-            _ => {
-                // Nothing interesting.
-                debug!("ignoring event");
-                continue 'wait_signal;
+    match prune::prune(Path::new(&args.dir), &retention, &[], args.dry_run) {
+        Ok(removed) => {
+            if args.dry_run {
+                for path in removed {
+                    println!("{}", path.to_string_lossy());
+                }
             }
         }
+        Err(err) => terminate!(EXIT_FAILURE; "can't prune directory \"{}\": {}", args.dir, err),
     }
 
-    debug!("leaving process_signals thread");
-
-    pending_interrupt
+    terminate!(EXIT_SUCCESS);
 }
 
-/// Thread that reads lines from stdin and writes to master pty
-/// (i.e. to child's stdin).
-fn stdin_2_pty(
-    pty_proc: Arc<PtyProc>,
-    pty_writer: Arc<InterruptibleWriter<OwnedFd>>,
-    stdin_reader: Arc<InterruptibleReader<Stdin>>,
-) {
-    debug!("entering stdin_2_pty thread");
+/// Run `reclog attach` subcommand and exit.
+fn attach_main(argv: Vec<OsString>) -> ! {
+    let args: AttachArgs = parse_cli(argv);
 
-    let tty_codes = {
-        let slave_fd = match pty_proc.dup_slave() {
-            Ok(fd) => fd,
-            Err(err) => terminate!(EXIT_FAILURE; "can't duplicate slave fd: {}", err),
-        };
-        match term::get_tty_codes(&slave_fd) {
-            Ok(codes) => codes,
-            Err(err) => terminate!(EXIT_FAILURE; "can't read pty attributes: {}", err),
+    let is_socket = fs::metadata(&args.path).is_ok_and(|meta| meta.file_type().is_socket());
+    if is_socket && args.lines.is_some() {
+        usage_error!("--lines can't be used with socket");
+    }
+
+    // Recording is streamed until it's finished.
+    let result = if is_socket {
+        match UnixStream::connect(&args.path) {
+            Ok(mut stream) => io::copy(&mut stream, &mut io::stdout()).map(|_| ()),
+            Err(err) => terminate!(EXIT_FAILURE; "can't connect to \"{}\": {}", args.path, err),
         }
+    } else {
+        follow::follow_recording(Path::new(&args.path), args.lines, io::stdout())
     };
+    if let Err(err) = result {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            terminate!(EXIT_FAILURE; "can't read from \"{}\": {}", args.path, err);
+        }
+    }
 
-    let mut pty_line_writer = BufWriter::new(pty_writer.blocking_writer());
-
-    let mut buf_reader = BufReader::new(stdin_reader.blocking_reader());
-    let mut buf = String::new();
-
-    let mut stdin_eof = false;
-    while !stdin_eof {
-        buf.clear();
-        let size = match buf_reader.read_line(&mut buf) {
-            Ok(size) => size,
-            Err(err) => terminate!(EXIT_FAILURE; "can't read from stdin: {}", err),
-        };
+    terminate!(EXIT_SUCCESS);
+}
 
-        stdin_eof = size == 0;
-        if stdin_eof {
-            // Propagate EOF by writing VEOF to master PTY.
-            // We've enabled canonical mode, which should translate this
-            // symbol to end-of-file condition.
-            debug!("got eof from stdin, propagating to child");
-            buf.clear();
-            buf.push(tty_codes.VEOF);
-        }
+/// Run `reclog decrypt` subcommand and exit.
+fn decrypt_main(argv: Vec<OsString>) -> ! {
+    let args: DecryptArgs = parse_cli(argv);
 
-        let mut result = pty_line_writer.write_all(buf.as_bytes());
-        if result.is_ok() {
-            result = pty_line_writer.flush();
+    let output = if args.output.is_empty() {
+        process::Stdio::inherit()
+    } else {
+        match OpenOptions::new()
+            .write(true)
+            .create(args.force)
+            .create_new(!args.force)
+            .truncate(true)
+            .open(&args.output)
+        {
+            Ok(file) => process::Stdio::from(file),
+            Err(err) => terminate!(
+                EXIT_FAILURE; "can't open output file \"{}\": {}",
+                args.output, err
+            ),
         }
+    };
 
-        if let Err(err) = result {
-            match Errno::from_io_error(&err) {
-                Some(Errno::IO | Errno::PIPE) => {
-                    // This happens if child process exits but we haven't received
-                    // SIGCHLD yet. Don't exit, instead finish I/O and wait SIGCHLD.
-                    debug!("got error when writing to pty, exiting io loop: {}", err);
-                    break;
-                }
-                // Unexpected error.
-                _ => terminate!(EXIT_FAILURE; "can't write to pty: {}", err),
-            }
-        }
+    if let Err(err) = crypt::decrypt(Path::new(&args.file), &args.identities, output) {
+        terminate!(EXIT_FAILURE; "can't decrypt recording \"{}\": {}", args.file, err);
     }
 
-    debug!("leaving stdin_2_pty thread");
+    terminate!(EXIT_SUCCESS);
 }
 
-/// Thread that reads lines from buffer queue and writes them to stdout.
-fn queue_2_stdout(buf_queue: Arc<BufferQueue>, stdout_writer: Arc<InterruptibleWriter<Stdout>>) {
-    debug!("entering queue_2_stdout thread");
+/// Run `reclog verify` subcommand and exit.
+fn verify_main(argv: Vec<OsString>) -> ! {
+    let args: VerifyArgs = parse_cli(argv);
 
-    let mut stdout_line_writer = BufWriter::new(stdout_writer.blocking_writer());
+    let signature = match &args.signature {
+        Some(path) => path.clone(),
+        None => signature_path(&args.file),
+    };
+    let recording = match File::open(&args.file) {
+        Ok(file) => file,
+        Err(err) => terminate!(EXIT_FAILURE; "can't open recording \"{}\": {}", args.file, err),
+    };
 
-    loop {
-        let buf = match buf_queue.read() {
-            Some(buf) => buf,
-            None => break, // queue closed, exit loop
-        };
+    match sign::verify(Path::new(&args.key), Path::new(&signature), recording) {
+        Ok(true) => println!("{}: signature OK", args.file),
+        Ok(false) => terminate!(EXIT_FAILURE; "{}: signature doesn't match", args.file),
+        Err(err) => terminate!(EXIT_FAILURE; "can't verify recording \"{}\": {}", args.file, err),
+    }
 
-        if let Err(err) = stdout_line_writer.write_all(buf.as_bytes()) {
-            terminate!(EXIT_FAILURE; "can't write to stdout: {}", err);
-        }
-        if let Err(err) = stdout_line_writer.flush() {
-            terminate!(EXIT_FAILURE; "can't write to stdout: {}", err);
-        }
+    terminate!(EXIT_SUCCESS);
+}
 
-        // buf is returned to pool here
+/// Run `reclog multi` subcommand and exit.
+fn multi_main(argv: Vec<OsString>) -> ! {
+    let args: MultiArgs = parse_cli(argv);
+
+    let commands: Vec<String> = args
+        .commands
+        .into_iter()
+        .filter(|cmd| cmd != "--")
+        .collect();
+    if commands.is_empty() {
+        usage_error!("no commands specified");
+    }
+
+    let commands = match MultiCommand::plan(&commands, &args.dir, args.force) {
+        Ok(commands) => commands,
+        Err(err) => usage_error!("{}", err),
+    };
+
+    if let Err(err) = signal::init_multi_signals() {
+        terminate!(EXIT_FAILURE; "can't init signals: {}", err);
     }
 
-    debug!("leaving queue_2_stdout thread");
+    let color = match args.color {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => term::is_tty(stdio::stdout()),
+    };
+    let reclog_args: Vec<String> = if args.force {
+        vec!["--force".into()]
+    } else {
+        vec![]
+    };
+
+    let statuses = match multi::run(&commands, &reclog_args, color) {
+        Ok(statuses) => statuses,
+        Err(err) => terminate!(EXIT_FAILURE; "can't run commands: {}", err),
+    };
+
+    // Report first failure, if any.
+    let exit_code = statuses
+        .into_iter()
+        .map(multi::exit_code)
+        .find(|&code| code != EXIT_SUCCESS)
+        .unwrap_or(EXIT_SUCCESS);
+
+    terminate!(exit_code);
 }
 
-/// Thread that reads lines from master pty (i.e. child's stdout) and writes
-/// them to output file and to buffer queue.
-fn pty_2_queue_and_file(
-    pty_reader: &Arc<InterruptibleReader<OwnedFd>>,
-    out_writer: &mut dyn Write,
-    buf_queue: &Arc<BufferQueue>,
-    buf_pool: &Arc<BufferPool>,
-    fm: &mut Formatter,
-) {
-    debug!("entering pty_2_queue_and_file thread");
+/// Hidden first argument of reclog process started by --watch for every
+/// run, followed by output file of the run.
+const WATCH_HELPER: &str = "__watch";
 
-    let mut pty_line_reader = BufReader::new(pty_reader.blocking_reader());
+/// Run command under --watch and exit.
+/// Every run is done by a separate reclog process, with the same arguments,
+/// but its own numbered output file. When watched files change, current run
+/// is terminated, and new one is started.
+fn watch_main(args: &Args, argv: &[OsString]) -> ! {
+    if let Err(err) = signal::init_multi_signals() {
+        terminate!(EXIT_FAILURE; "can't init signals: {}", err);
+    }
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => terminate!(EXIT_FAILURE; "can't find reclog executable: {}", err),
+    };
 
-    loop {
-        let mut buf = buf_pool.alloc();
+    let base_path = if args.null {
+        String::new()
+    } else if !args.output.is_empty() {
+        args.output.clone()
+    } else {
+        format!("{}.log", output_base_name(args))
+    };
 
-        if fm.need_header() {
-            if let Err(err) = fm.format_header(&mut buf) {
-                terminate!(EXIT_FAILURE; "can't format header: {}", err);
+    let mut watcher = Watcher::new(&args.watch);
+    let mut run = 0;
+    let mut child: Option<process::Child> = None;
+    let mut exit_code = EXIT_SUCCESS;
+    let mut restart = true;
+
+    loop {
+        if restart {
+            restart = false;
+            if let Some(mut running) = child.take() {
+                debug!(Main, "terminating run {}", run);
+                if let Some(pid) = Pid::from_raw(running.id() as i32) {
+                    _ = rustix::process::kill_process(pid, Signal::TERM);
+                }
+                _ = running.wait();
             }
-        } else {
-            if fm.need_timestamp() {
-                if let Err(err) = fm.format_timestamp(&mut buf) {
-                    terminate!(EXIT_FAILURE; "can't format timestamp: {}", err);
+
+            // Existing logs are kept, unless --force is used.
+            let mut out_path;
+            loop {
+                run += 1;
+                out_path = if base_path.is_empty() {
+                    String::new()
+                } else {
+                    watch::run_path(&base_path, run)
+                };
+                if args.force || out_path.is_empty() || !Path::new(&out_path).exists() {
+                    break;
                 }
             }
-            let size = match pty_line_reader.read_line(&mut buf) {
-                Ok(size) => size,
-                Err(err) => {
-                    match Errno::from_io_error(&err) {
-                        Some(Errno::IO) => {
-                            // This happens if child process exits but we haven't received
-                            // SIGCHLD yet. Don't exit, instead finish I/O and wait SIGCHLD.
-                            debug!("got error when reading from pty, exiting io loop: {}", err);
-                            break;
-                        }
-                        // Unexpected error.
-                        _ => terminate!(EXIT_FAILURE; "can't read from pty: {}", err),
+            if !out_path.is_empty() {
+                watcher.ignore(&out_path);
+            }
+
+            debug!(Main, "starting run {} with output \"{}\"", run, out_path);
+            let spawned = process::Command::new(&exe)
+                .arg(WATCH_HELPER)
+                .arg(&out_path)
+                .args(&argv[1..])
+                .spawn();
+            child = match spawned {
+                Ok(child) => Some(child),
+                Err(err) => terminate!(EXIT_FAILURE; "can't start reclog: {}", err),
+            };
+        }
+
+        // Signal ends watching. Tty signals reach current run without us.
+        match signal::wait_watch_signal(watch::POLL_INTERVAL) {
+            Ok(Some(sig)) => {
+                debug!(Signal, "got signal {}", signal::display_name(sig));
+                if let Some(mut running) = child.take() {
+                    if !signal::is_tty_signal(sig)
+                        && let Some(pid) = Pid::from_raw(running.id() as i32)
+                    {
+                        _ = rustix::process::kill_process(pid, sig);
+                    }
+                    if let Ok(status) = running.wait() {
+                        exit_code = multi::exit_code(status);
                     }
                 }
-            };
-            if size == 0 {
-                // EOF, exit loop
-                debug!("got eof from pty, exiting io loop");
-                break;
+                terminate!(exit_code);
             }
+            Ok(None) => {}
+            Err(err) => terminate!(EXIT_FAILURE; "can't wait for signal: {}", err),
         }
 
-        // Write buffer to output file, synchronously.
-        // If stripping is enabled, this writer will also remove ANSI escape codes.
-        let mut result = out_writer.write_all(buf.as_bytes());
-        if result.is_ok() {
-            result = out_writer.flush();
+        if let Some(running) = &mut child
+            && let Ok(Some(status)) = running.try_wait()
+        {
+            exit_code = multi::exit_code(status);
+            child = None;
+            _ = shim::write_all(std::io::stderr(), b"reclog: waiting for changes\n");
         }
-        if let Err(err) = result {
-            terminate!(EXIT_FAILURE; "can't write output file: {}", err);
+
+        if watcher.changed() {
+            // Let changes settle, e.g. when several files are saved at once.
+            loop {
+                thread::sleep(watch::POLL_INTERVAL);
+                if !watcher.changed() {
+                    break;
+                }
+            }
+            _ = shim::write_all(std::io::stderr(), b"reclog: files changed, restarting\n");
+            restart = true;
         }
+    }
+}
 
-        // Move buffer to queue.
-        // pty_2_stdout_thread will fetch it, write to stdout, and return buffer to pool.
-        // If queue is full, oldest elements are removed. That's fine - our stdout is
-        // supposed to be a TTY, and if it's too slow to display all lines in time,
-        // there is no need trying to write all of them - user won't see them
-        // anyway at that speed and VTE scrollback is usually limited and TTY will
-        // anyway drop them.
-        buf_queue.write(buf);
+/// Hidden subcommand run by `reclog follow` as its "command".
+const FOLLOW_HELPER: &str = "__follow";
+
+/// Run helper process of `reclog follow` and exit.
+fn follow_helper_main(path: &OsString) -> ! {
+    if let Err(err) = follow::follow(Path::new(path), io::stdout()) {
+        // Output is closed when reclog exits.
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            terminate!(EXIT_FAILURE; "can't follow \"{}\": {}", path.to_string_lossy(), err);
+        }
     }
 
-    debug!("leaving pty_2_queue_and_file thread");
+    terminate!(EXIT_SUCCESS);
 }
 
-/// Tell all threads to unblock and exit.
-fn initiate_shutdown(
-    stdin_reader: Arc<InterruptibleReader<Stdin>>,
-    pty_reader: Arc<InterruptibleReader<OwnedFd>>,
-    pty_writer: Arc<InterruptibleWriter<OwnedFd>>,
-    timeout: Duration,
-) {
-    // Set timeout for reading from child. After there is no data during timeout,
-    // pty_2_queue_and_file() gets EOF and exits. Timeout allows to be sure we've
-    // read all pending data buffered in the pty.
-    debug!("setting pty reader timeout to {:?}", timeout);
-    if let Err(err) = pty_reader.set_timeout(timeout) {
-        terminate!(EXIT_FAILURE; "can't set pty read timeout: {}", err);
-    }
+/// Hidden subcommand run by `reclog --stdin` as its "command".
+const STDIN_HELPER: &str = "__stdin";
 
-    // Interrupt stdin_2_pty().
-    // It may be blocked on stdin or pty.
-    // This will unblock pty writer and tell stdin reader to return EOF.
-    debug!("closing pty writer");
-    if let Err(err) = pty_writer.close() {
-        terminate!(EXIT_FAILURE; "can't close pty writer: {}", err);
-    }
-    debug!("closing stdin reader");
-    if let Err(err) = stdin_reader.close() {
-        terminate!(EXIT_FAILURE; "can't close stdin: {}", err);
+/// Run helper process of `reclog --stdin` and exit.
+fn stdin_helper_main() -> ! {
+    if let Err(err) = io::copy(&mut io::stdin().lock(), &mut io::stdout().lock()) {
+        // Output is closed when reclog exits.
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            terminate!(EXIT_FAILURE; "can't copy stdin: {}", err);
+        }
     }
+
+    terminate!(EXIT_SUCCESS);
 }
 
-/// Get child process exit code and exit with same code.
-fn forward_exit_status(pty_proc: Arc<PtyProc>, pending_interrupt: Option<Signal>) -> ! {
-    match pty_proc.child_status() {
-        // Command exited normally.
-        status if status.exited() => {
-            let exit_code = status.exit_status().unwrap();
-            if exit_code == EXIT_SUCCESS {
-                debug!("exiting with code {}", exit_code);
-                terminate!(exit_code);
-            } else {
-                terminate!(exit_code; "command exited with code {}", exit_code);
-            }
-        }
+/// Hidden subcommand run by reclog to kill command if reclog dies, where
+/// PR_SET_PDEATHSIG isn't available (--no-deathsig disables it).
+const WATCHDOG_HELPER: &str = "__watchdog";
 
-        // Command killed by signal.
-        status if status.signaled() => {
-            if let Some(sig) = pending_interrupt {
-                // Command was not killed by itself - we killed it because *we* received
-                // death signal from user (e.g. ^C) - then we don't need to print any error
-                // message, just process original signal and die.
-                debug!(
-                    "delivering pending signal {} to ourselves",
-                    signal::display_name(sig)
-                );
-                if let Err(err) = raise_signal(sig) {
-                    terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
-                }
-            }
+/// Run watchdog helper process of command with given PID and exit.
+fn watchdog_helper_main(arg: &OsString) -> ! {
+    let pid = arg
+        .to_str()
+        .and_then(|arg| arg.parse().ok())
+        .and_then(Pid::from_raw);
+    match pid {
+        Some(pid) => watchdog::watch(pid),
+        None => terminate!(EXIT_FAILURE; "invalid pid \"{}\"", arg.to_string_lossy()),
+    }
 
-            // Command was killed unexpectedly, not by us - then report error and
-            // forward death signal N as exit code 128+N.
-            let sig_number = status.terminating_signal().unwrap();
-            let exit_code = EXIT_COMMAND_SIGNALED + sig_number;
+    terminate!(EXIT_SUCCESS);
+}
 
-            if let Some(sig) = Signal::from_named_raw(sig_number) {
-                terminate!(exit_code;
-                           "command terminated by signal {}",
-                           signal::display_name(sig)
-                );
-            } else {
-                terminate!(exit_code;
-                    "command terminated by signal {}",
-                    sig_number
-                );
-            }
+/// Build command that runs hidden helper subcommand of our own executable.
+fn helper_command(helper: &str) -> Command {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => terminate!(EXIT_FAILURE; "can't find reclog executable: {}", err),
+    };
+    let mut cmd = Command::new(exe);
+    cmd.arg(helper);
+    cmd
+}
+
+fn main() {
+    // Run subcommand, if requested.
+    let argv: Vec<OsString> = env::args_os().collect();
+    if argv.len() > 1 && argv[1] == "render" {
+        render_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "concat" {
+        concat_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "slice" {
+        slice_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "export" {
+        export_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "list" {
+        list_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "show" {
+        show_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "prune" {
+        prune_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "attach" {
+        attach_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "decrypt" {
+        decrypt_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "verify" {
+        verify_main(argv[1..].to_vec());
+    }
+    if argv.len() > 1 && argv[1] == "multi" {
+        multi_main(argv[1..].to_vec());
+    }
+    if argv.len() > 2 && argv[1] == FOLLOW_HELPER {
+        follow_helper_main(&argv[2]);
+    }
+    if argv.len() == 2 && argv[1] == STDIN_HELPER {
+        stdin_helper_main();
+    }
+    if argv.len() == 3 && argv[1] == WATCHDOG_HELPER {
+        watchdog_helper_main(&argv[2]);
+    }
+
+    // Parse CLI arguments.
+    let args = parse_args(argv.len() > 1 && argv[1] == "follow");
+    if !args.watch.is_empty() {
+        watch_main(&args, &argv);
+    }
+    let out_path = choose_output(&args);
+    if let Some(path) = &args.follow {
+        // Recording into followed file would feed itself.
+        if !out_path.is_empty() && fs::canonicalize(path).ok() == fs::canonicalize(&out_path).ok() {
+            usage_error!("output file can't be the followed file");
         }
+    }
+    // Output may be a FIFO read by another program, which is opened without
+    // blocking and can't be replaced or removed.
+    let out_is_fifo = !out_path.is_empty()
+        && fs::metadata(&out_path).is_ok_and(|meta| meta.file_type().is_fifo());
+    if out_is_fifo && (args.atomic || args.lazy_output || args.keep_on_failure || args.review) {
+        usage_error!(
+            "--atomic, --lazy-output, --keep-on-failure, and --review can't be used with FIFO output"
+        );
+    }
+    if out_is_fifo && !args.encrypt.is_empty() {
+        usage_error!("--encrypt can't be used with FIFO output");
+    }
+    if out_is_fifo && args.sign.is_some() {
+        usage_error!("--sign can't be used with FIFO output");
+    }
+    let sig_path = args.sign.as_ref().map(|_| signature_path(&out_path));
+    let resume_info = args.resume.as_deref().map(inspect_resumed);
 
-        // Should not happen.
-        _ => {
-            terminate!(EXIT_COMMAND_FAILED; "command failed");
+    // Apply retention policy to previous recordings.
+    if !out_path.is_empty()
+        && (args.prune_keep_days.is_some() || args.prune_keep_total_size.is_some())
+    {
+        let dir = match Path::new(&out_path).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let retention = Retention {
+            keep_days: args.prune_keep_days,
+            keep_total_size: args.prune_keep_total_size,
+        };
+        debug!(File, "pruning directory: {}", dir.to_string_lossy());
+        // Don't remove file we're going to append to.
+        if let Err(err) = prune::prune(dir, &retention, &[PathBuf::from(&out_path)], false) {
+            let msg = format!("reclog: can't prune old recordings: {}\n", err);
+            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
         }
+    }
+
+    // Resolve formatting of sinks: --ts and stripping options are defaults,
+    // which may be overridden by --sink-format.
+    let style =
+        |sink, ts, strip| SinkStyle::resolve(&args.sink_format, sink, SinkStyle { ts, strip });
+    let sink_styles = SinkStyles {
+        stdout: style(
+            Sink::Stdout,
+            args.ts,
+            if args.strip_stdout {
+                StripLevel::All
+            } else {
+                StripLevel::None
+            },
+        ),
+        output: style(
+            Sink::Output,
+            args.ts,
+            if args.raw {
+                StripLevel::None
+            } else {
+                args.strip_level
+            },
+        ),
+        publish: style(Sink::Publish, args.ts, StripLevel::None),
+        gelf: style(Sink::Gelf, false, StripLevel::All),
+        mqtt: style(Sink::Mqtt, false, StripLevel::All),
     };
-}
 
-fn main() {
-    // Parse CLI arguments.
-    let args = parse_args();
-    let out_path = choose_output(&args);
+    // Prepare environment of child.
+    // Explicit --env assignments take precedence over recording variables.
+    let env_vars = [
+        environ::recording_vars(
+            (!args.null).then_some(out_path.as_str()),
+            sink_styles.output.ts,
+            args.buffer,
+        ),
+        args.user.as_ref().map_or(vec![], environ::user_vars),
+        args.env_vars.clone(),
+    ]
+    .concat();
+    let child_env = environ::child_env(args.clear_env, args.sanitize_env, &env_vars);
 
     // Global initialization.
     before_start(StartMode::Startup);
 
+    // All files written during recording.
+    let recording_files: Vec<&str> = [
+        (!args.null).then_some(&out_path),
+        args.timing.as_ref(),
+        args.raw_output.as_ref(),
+        args.ttyrec.as_ref(),
+        args.index.as_ref(),
+        args.meta_file.as_ref(),
+        args.checksum.as_ref(),
+        sig_path.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(String::as_str)
+    .collect();
+
+    // Set when command produces first output.
+    let first_output = Arc::new(OnceLock::new());
+    // Set when capture loop finished reading command output.
+    let stream_finished = Arc::new(AtomicBool::new(false));
+
     // Construct output file writer.
+    // Lines are counted after stripping, for --index.
+    let out_lines = Arc::new(AtomicU64::new(0));
+    let out_bytes = Arc::new(AtomicU64::new(0));
+    let out_hasher = Arc::new(Mutex::new(Sha256::new()));
+    let mut splice_file = None;
+    let mut out_has_data = false;
     let mut out_file;
-    let out_writer: &mut dyn Write = if args.null {
+    let mut encryptor = None;
+    let out_writer: &mut (dyn Write + Send) = if args.null {
         &mut io::empty()
     } else {
-        debug!("opening output file: {}", out_path);
-        out_file = match OpenOptions::new()
-            .write(true)
-            .create(args.force || args.append)
-            .create_new(!(args.force || args.append))
-            .append(args.append)
-            .truncate(!args.append)
-            .open(&out_path)
-        {
-            Ok(file) => file,
-            Err(err) => terminate!(
-                EXIT_FAILURE; "can't open output file \"{}\": {}",
-                out_path, err
-            ),
+        debug!(File, "opening output file: {}", out_path);
+        if args.append && args.index.is_some() {
+            out_lines.store(count_lines(&out_path), Ordering::Relaxed);
+        }
+        let file: Box<dyn Write + Send> = if out_is_fifo {
+            match FifoWriter::open(Path::new(&out_path), args.fifo_policy) {
+                Ok(fifo) => Box::new(fifo),
+                Err(err) => terminate!(
+                    EXIT_FAILURE; "can't open output file \"{}\": {}",
+                    out_path, err
+                ),
+            }
+        } else if !args.encrypt.is_empty() {
+            // Encrypted stream is written by age(1), we write plain data to it.
+            let file = open_output_file(&out_path, &args, "output");
+            match Encryptor::start(&args.encrypt, file) {
+                Ok(enc) => Box::new(encryptor.insert(enc).writer()),
+                Err(err) => terminate!(EXIT_FAILURE; "can't encrypt output file: {}", err),
+            }
+        } else if args.lazy_output {
+            // Report existing file now rather than on first output.
+            if !(args.force || args.append) && fs::symlink_metadata(&out_path).is_ok() {
+                terminate!(
+                    EXIT_FAILURE; "can't open output file \"{}\": {}",
+                    out_path, io::Error::from(Errno::EXIST)
+                );
+            }
+            out_has_data = args.append && fs::metadata(&out_path).is_ok_and(|meta| meta.len() > 0);
+            let path = out_path.clone();
+            let options = output_options(&args);
+            let (mode, group) = (args.mode, args.group);
+            Box::new(LazyFile::new(
+                Box::new(move || {
+                    let file = options.open(&path)?;
+                    perm::set_perm(&file, mode, group).map_err(io::Error::other)?;
+                    Ok(file)
+                }),
+                Arc::clone(&first_output),
+            ))
+        } else {
+            let file = open_output_file(&out_path, &args, "output");
+            out_has_data = args.append && file.metadata().is_ok_and(|meta| meta.len() > 0);
+            if can_splice(&args) {
+                splice_file = file.try_clone().ok();
+            }
+            Box::new(file)
+        };
+        let file: Box<dyn Write + Send> = if args.checksum.is_some() {
+            Box::new(HashingWriter::new(file, Arc::clone(&out_hasher)))
+        } else {
+            file
         };
-        if args.raw {
+        out_file = LineCounter::new(
+            BufWriter::with_capacity(args.write_buffer, file),
+            Arc::clone(&out_lines),
+            Arc::clone(&out_bytes),
+        );
+        // With jsonl, lines are stripped one by one before wrapping.
+        if sink_styles.output.strip == StripLevel::None || args.output_format == OutputFormat::Jsonl
+        {
             &mut out_file
         } else {
-            &mut AnsiStripper::new(out_file)
+            &mut AnsiStripper::with_modes(out_file, sink_styles.output.strip, args.cr_mode)
+        }
+    };
+
+    // Construct timing file writer.
+    let timing_writer = match &args.timing {
+        Some(timing_path) => {
+            debug!(File, "opening timing file: {}", timing_path);
+            let mut writer = TimingWriter::new(open_output_file(timing_path, &args, "timing"));
+            if let Err(err) = writer.write_header(Local::now()) {
+                terminate!(EXIT_FAILURE; "can't write timing file: {}", err);
+            }
+            Some(writer)
+        }
+        None => None,
+    };
+
+    // Construct raw capture and index writers.
+    let raw_writer = args.raw_output.as_ref().map(|raw_path| {
+        debug!(File, "opening raw output file: {}", raw_path);
+        BufWriter::with_capacity(
+            args.write_buffer,
+            open_output_file(raw_path, &args, "raw output"),
+        )
+    });
+    let ttyrec_writer = args.ttyrec.as_ref().map(|ttyrec_path| {
+        debug!(File, "opening ttyrec file: {}", ttyrec_path);
+        TtyrecWriter::new(open_output_file(ttyrec_path, &args, "ttyrec"))
+    });
+    let raw_offset = match &raw_writer {
+        Some(raw_writer) if args.append => match raw_writer.get_ref().metadata() {
+            Ok(meta) => meta.len(),
+            Err(err) => terminate!(EXIT_FAILURE; "can't stat raw output file: {}", err),
+        },
+        _ => 0,
+    };
+    // Metadata is written when recording is finished, but file is opened
+    // now to report errors early.
+    let out_lines_start = out_lines.load(Ordering::Relaxed);
+    let meta_file = args.meta_file.as_ref().map(|meta_path| {
+        debug!(File, "opening meta file: {}", meta_path);
+        open_output_file(meta_path, &args, "meta")
+    });
+    let checksum_file = args.checksum.as_ref().map(|checksum_path| {
+        debug!(File, "opening checksum file: {}", checksum_path);
+        open_output_file(checksum_path, &args, "checksum")
+    });
+    let sig_file = sig_path.as_ref().map(|sig_path| {
+        debug!(File, "opening signature file: {}", sig_path);
+        open_output_file(sig_path, &args, "signature")
+    });
+    let metrics_server = args.metrics_listen.as_ref().map(|addr| {
+        debug!(Net, "listening for metrics requests: {}", addr);
+        match MetricsServer::bind(addr) {
+            Ok(server) => server,
+            Err(err) => terminate!(EXIT_FAILURE; "can't listen on {}: {}", addr, err),
+        }
+    });
+    let gelf_sender = args.gelf.as_ref().map(|url| {
+        debug!(Net, "connecting to graylog: {:?}", url);
+        match GelfSender::connect(url, &args.command.join(" ")) {
+            Ok(sender) => sender,
+            Err(err) => terminate!(EXIT_FAILURE; "can't connect to graylog: {}", err),
+        }
+    });
+    #[cfg(feature = "mqtt")]
+    let mqtt_sender = args.mqtt.as_ref().map(|url| {
+        debug!(Net, "connecting to mqtt broker: {:?}", url);
+        let topic = args.mqtt_topic.as_deref().unwrap_or_default();
+        match MqttSender::start(url, topic, args.mqtt_batch) {
+            Ok(sender) => sender,
+            Err(err) => terminate!(EXIT_FAILURE; "can't connect to mqtt broker: {}", err),
+        }
+    });
+    let publisher = args.publish.as_ref().map(|socket_path| {
+        debug!(Net, "creating publish socket: {}", socket_path);
+        match Publisher::bind(Path::new(socket_path)) {
+            Ok(publisher) => publisher,
+            Err(err) => terminate!(
+                EXIT_FAILURE; "can't create socket \"{}\": {}",
+                socket_path, err
+            ),
         }
+    });
+    let index_writer = args.index.as_ref().map(|index_path| {
+        debug!(File, "opening index file: {}", index_path);
+        IndexWriter::new(open_output_file(index_path, &args, "index"))
+    });
+
+    // With --header-once-per-file, header goes only to stdout.
+    let header_to_files = !(args.header_once_per_file && args.resume.is_none() && out_has_data);
+    let mut output_files = OutputFiles {
+        out_writer,
+        out_lines,
+        timing_writer,
+        raw_writer,
+        ttyrec_writer,
+        raw_offset,
+        index_writer,
+        jsonl: (args.output_format == OutputFormat::Jsonl).then(|| {
+            JsonlFormatter::new(
+                sink_styles.output.strip,
+                args.cr_mode,
+                args.passthrough_json,
+            )
+        }),
+        style: sink_styles.output,
+        batch: args.write_buffer > 0,
+        header: header_to_files,
+        fail: fail_output,
+    };
+    let mut network_sinks = NetworkSinks {
+        gelf_sender,
+        publisher,
+        #[cfg(feature = "mqtt")]
+        mqtt_sender,
+        styles: sink_styles,
+        header: header_to_files,
     };
 
+    // Colorize extras only if stdout is a terminal, unless forced.
+    // Colors would be stripped anyway with --strip-stdout.
+    let need_color = sink_styles.stdout.strip != StripLevel::All
+        && match args.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => term::is_tty(stdio::stdout()),
+        };
+
     // Construct output formatter.
     let mut formatter = Formatter::new(
-        args.header,
-        args.ts,
+        args.header || args.header_json,
+        args.footer,
+        sink_styles.need_timestamp(),
         &args.ts_fmt,
         args.ts_src,
         &args.command,
     );
+    if let Some((start_time, need_newline)) = resume_info {
+        if start_time.is_none()
+            && sink_styles.need_timestamp()
+            && args.ts_src == TimeSource::Elapsed
+        {
+            terminate!(
+                EXIT_FAILURE; "can't resume \"{}\": no header to count elapsed time from",
+                out_path
+            );
+        }
+        formatter.set_resumed(start_time, need_newline);
+    }
+    let mut painter = Painter::new(need_color);
+    // Remind user where the recording went.
+    if !args.null && term::is_tty(stdio::stdout()) {
+        painter.set_display_path(&out_path);
+    }
+
+    // Construct per-line rules.
+    let max_line_bytes = (args.max_line_bytes > 0).then_some(args.max_line_bytes);
+    let mut line_rules = LineRules {
+        encoding: args.encoding,
+        max_line_bytes,
+        rate_limit: args.rate_limit,
+        sampler: Sampler::new(&args.sample),
+        display_filter: args.only_errors.then(|| args.error_regex.clone()),
+        highlights: args.highlight.clone(),
+        fail_filter: args.fail_on_match.clone(),
+        fail_matched: false,
+        expect: args.expect.clone(),
+    };
 
-    // Master/slave pty pair and child process attached to it.
-    debug!("opening pty pair");
-    let pty_proc = match PtyProc::open() {
-        Ok(pty) => Arc::new(pty),
-        Err(err) => terminate!(EXIT_FAILURE; "can't open pty: {}", err),
+    // Master/slave pty pair (or pipes) and child process attached to it.
+    let child_proc: Arc<dyn ChildProc> = if args.no_pty {
+        debug!(Pty, "opening pipes");
+        match PipeProc::open() {
+            Ok(pipe) => Arc::new(pipe),
+            Err(err) => terminate!(EXIT_FAILURE; "can't open pipes: {}", err),
+        }
+    } else {
+        debug!(Pty, "opening pty pair");
+        match PtyProc::open() {
+            Ok(pty) => Arc::new(pty),
+            Err(err) => terminate!(EXIT_FAILURE; "can't open pty: {}", err),
+        }
     };
 
-    // Writer for master pty (writes to child's stdin).
+    // Writer for master pty or pipe (writes to child's stdin).
     let pty_writer = {
-        let master_fd = match pty_proc.dup_master() {
+        let input_fd = match child_proc.dup_input() {
             Ok(fd) => fd,
-            Err(err) => terminate!(EXIT_FAILURE; "can't duplicate master fd: {}", err),
+            Err(err) => terminate!(EXIT_FAILURE; "can't duplicate input fd: {}", err),
         };
-        match InterruptibleWriter::open(master_fd) {
+        match InterruptibleWriter::open(input_fd) {
             Ok(writer) => Arc::new(writer),
-            Err(err) => terminate!(EXIT_FAILURE; "can't open master pty for writing: {}", err),
+            Err(err) => terminate!(EXIT_FAILURE; "can't open child input for writing: {}", err),
         }
     };
 
-    // Reader for master pty (reads from child's stdout+stderr).
+    // Reader for master pty or pipe (reads from child's stdout+stderr).
     let pty_reader = {
-        let master_fd = match pty_proc.dup_master() {
+        let output_fd = match child_proc.dup_output() {
             Ok(fd) => fd,
-            Err(err) => terminate!(EXIT_FAILURE; "can't duplicate master fd: {}", err),
+            Err(err) => terminate!(EXIT_FAILURE; "can't duplicate output fd: {}", err),
         };
-        match InterruptibleReader::open(master_fd) {
+        match InterruptibleReader::open(output_fd) {
             Ok(reader) => Arc::new(reader),
-            Err(err) => terminate!(EXIT_FAILURE; "can't open master pty for reading: {}", err),
+            Err(err) => terminate!(EXIT_FAILURE; "can't open child output for reading: {}", err),
         }
     };
 
     // Launch child process.
-    debug!("launching command: {:?}", args.command);
-    let mut cmd = Command::new(&args.command[0]);
-    if args.command.len() > 1 {
-        cmd.args(&args.command[1..]);
+    debug!(Child, "launching command: {:?}", args.command);
+    let start_ts = Instant::now();
+    let start_time = Local::now();
+    let mut cmd = if let Some(path) = &args.follow {
+        // Helper process follows file and writes it to its stdout.
+        let mut cmd = helper_command(FOLLOW_HELPER);
+        cmd.arg(path);
+        cmd
+    } else if args.stdin {
+        // Helper process copies its stdin, connected to ours, to its stdout.
+        helper_command(STDIN_HELPER)
+    } else if args.then.is_empty() {
+        let mut cmd = Command::new(&args.command[0]);
+        if args.command.len() > 1 {
+            cmd.args(&args.command[1..]);
+        }
+        cmd
+    } else {
+        // Steps are run by shell in the same session.
+        let script = chain::script(&args.command, &args.then, args.keep_going);
+        debug!(Child, "running steps with script:\n{}", script);
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg(script);
+        cmd
+    };
+    let cgroup = args.cgroup.then(|| {
+        debug!(Child, "creating cgroup");
+        match Cgroup::create(args.memory_max, args.cpu_max) {
+            Ok(cgroup) => cgroup,
+            Err(err) => terminate!(EXIT_FAILURE; "can't create cgroup: {}", err),
+        }
+    });
+    // Orphaned descendants of command become our children.
+    let reaper = args.subreaper.then(|| {
+        debug!(Child, "becoming subreaper");
+        match Reaper::enable() {
+            Ok(reaper) => reaper,
+            Err(err) => terminate!(EXIT_FAILURE; "can't become subreaper: {}", err),
+        }
+    });
+    // Descendants of command are found when it's time to kill them.
+    let kill_tree = args
+        .kill_tree
+        .then(|| KillTree::new(cgroup.as_ref().map(Cgroup::procs_path)));
+    let child_setup = ChildSetup {
+        cgroup_procs: cgroup.as_ref().map(Cgroup::procs_path),
+        rlimits: args.rlimit.clone(),
+        nice: args.nice,
+        ionice: args.ionice,
+        oom_score_adj: args.oom_score_adj,
+        run_as: args.user.clone(),
+        env: child_env,
+        death_signal: (!args.no_deathsig && cfg!(target_os = "linux")).then_some(Signal::KILL),
+    };
+    // With --status-line, command starts with terminal one row shorter,
+    // and report_status() draws status line in the bottom row.
+    if args.status_line
+        && !args.silent
+        && term::tty_size(stdio::stdout()).is_ok_and(|(_, rows)| rows >= 2)
+    {
+        child_proc.set_reserved_rows(1);
     }
-    if let Err(err) = pty_proc.spawn_child(&mut cmd) {
+    child_proc.set_child_echo(child_echo(&args));
+    if let Err(err) = child_proc.spawn_child(&mut cmd, &child_setup) {
+        let err = Error::Spawn(Box::new(err.into()));
         terminate!(EXIT_COMMAND_FAILED; "can't execute command: {}", err);
     }
+    // Size that command saw at start, for header and metadata.
+    let tty_size = child_proc.tty_size();
+
+    // Without PR_SET_PDEATHSIG, helper process kills command if we die.
+    let watchdog = if !args.no_deathsig && !cfg!(target_os = "linux") {
+        debug!(Child, "running watchdog");
+        match env::current_exe()
+            .and_then(|exe| Watchdog::spawn(&exe, WATCHDOG_HELPER, child_proc.child_pid().unwrap()))
+        {
+            Ok(watchdog) => Some(watchdog),
+            Err(err) => terminate!(EXIT_FAILURE; "can't run watchdog: {}", err),
+        }
+    } else {
+        None
+    };
+
+    // Tell PIDs to scripts that want to signal command directly.
+    if let Some(child_pid) = child_proc
+        .child_pid()
+        .map(|pid| pid.as_raw_nonzero().get() as u32)
+    {
+        if let Some(pid_path) = &args.pid_file {
+            if let Err(err) = write_pid_file(pid_path, child_pid) {
+                terminate!(EXIT_FAILURE; "can't write pid file \"{}\": {}", pid_path, err);
+            }
+        }
+        selftrace::record(TraceEvent::Spawn { pid: child_pid });
+        if args.print_pid {
+            let msg = format!(
+                "reclog: command pid {}, reclog pid {}\n",
+                child_pid,
+                process::id()
+            );
+            _ = shim::write_all(std::io::stderr(), msg.as_bytes());
+        }
+    }
+
+    // Header is formatted later, when child is already running.
+    if args.header || args.header_json {
+        let fields = if args.header_fmt.is_empty() {
+            HeaderField::defaults(args.header_json)
+        } else {
+            args.header_fmt.clone()
+        };
+        formatter.set_header_format(fields, args.header_json);
+        formatter.set_session_info(SessionInfo {
+            session_id: child_proc.child_pid().map(|pid| pid.as_raw_nonzero().get()),
+            tty_size,
+        });
+    }
+
+    // Separate sessions appended to one file.
+    if args.append && args.resume.is_none() && out_has_data && !args.separator.is_empty() {
+        formatter.set_separator(
+            &args.separator,
+            child_proc.child_pid().map(|pid| pid.as_raw_nonzero().get()),
+        );
+    }
 
     // Thread-safe buffer pool and queue.
-    let buf_pool = Arc::new(BufferPool::new());
-    let buf_queue = Arc::new(BufferQueue::new(args.buffer));
+    let buf_pool = Arc::new(BufferPool::new(max_line_bytes));
+    let buf_queue = Arc::new(match &args.spill {
+        Some(spill_dir) => match BufferQueue::with_spill(args.buffer, Path::new(spill_dir)) {
+            Ok(queue) => queue,
+            Err(err) => terminate!(
+                EXIT_FAILURE; "can't create spill file in \"{}\": {}",
+                spill_dir, err
+            ),
+        },
+        None => BufferQueue::new(args.buffer),
+    });
+    buf_queue.set_blocking(args.no_drop);
+
+    // Output statistics shared between threads.
+    let stats = Arc::new(Stats::new(start_ts));
 
     // Closed queue will silently discard everything written to it.
     if args.silent {
-        debug!("closing buffer queue");
+        debug!(Stdout, "closing buffer queue");
         buf_queue.close();
     }
 
     // Allows to read from stdin from one thread and interrupt it from another thread.
-    let stdin_reader = Arc::new(match InterruptibleReader::open(io::stdin()) {
+    // Input forwarded to command is either our stdin or --stdin-file.
+    // With --no-stdin, it's /dev/null, which gives EOF right away.
+    let stdin_fd = match &args.stdin_file {
+        Some(path) => match File::open(path) {
+            Ok(file) => OwnedFd::from(file),
+            Err(err) => terminate!(EXIT_FAILURE; "can't open stdin file \"{}\": {}", path, err),
+        },
+        None if args.no_stdin => match File::open("/dev/null") {
+            Ok(file) => OwnedFd::from(file),
+            Err(err) => terminate!(EXIT_FAILURE; "can't open /dev/null: {}", err),
+        },
+        None => match io::stdin().as_fd().try_clone_to_owned() {
+            Ok(fd) => fd,
+            Err(err) => terminate!(EXIT_FAILURE; "can't open stdin for reading: {}", err),
+        },
+    };
+    let stdin_reader = Arc::new(match InterruptibleReader::open(stdin_fd) {
         Ok(reader) => reader,
         Err(err) => terminate!(EXIT_FAILURE; "can't open stdin for reading: {}", err),
     });
@@ -846,40 +4642,105 @@ fn main() {
         Ok(writer) => writer,
         Err(err) => terminate!(EXIT_FAILURE; "can't open stdout for writing: {}", err),
     });
+    if let Some(timeout) = args.stdout_timeout {
+        if let Err(err) = stdout_writer.set_timeout(timeout.0) {
+            terminate!(EXIT_FAILURE; "can't set stdout timeout: {}", err);
+        }
+    }
+    // Serializes writes of command output and status line.
+    let stdout_lock = Arc::new(Mutex::new(()));
 
     // Process events on separate thread.
     let process_signals_thread = {
-        let pty_proc = Arc::clone(&pty_proc);
+        let child_proc = Arc::clone(&child_proc);
         let pty_reader = Arc::clone(&pty_reader);
         let pty_writer = Arc::clone(&pty_writer);
         let stdin_reader = Arc::clone(&stdin_reader);
-        let timeout = Duration::from_millis(args.quit);
+        let buf_queue = Arc::clone(&buf_queue);
+        let first_output = Arc::clone(&first_output);
+        let first_output_timeout = args.first_output_timeout.map(|period| period.0);
+        let eof_policy = args.eof_on;
+        let timeout = args.quit.0;
+        let drain_timeout = args.drain_stdout.0;
+        let resize_debounce = args.resize_debounce.0;
+        let wait_eof = args.wait_eof;
+        let stream_finished = Arc::clone(&stream_finished);
 
-        debug!("spawning control thread");
+        debug!(Main, "spawning control thread");
         thread::Builder::new()
             .name("process_signals".to_string())
-            .spawn(move || -> Option<Signal> {
+            .spawn(move || -> (Option<Signal>, Option<Signal>, bool) {
+                let clock = SystemClock;
                 // Process signals until child exits or graceful termination is requested.
-                let pending_interrupt = process_signals(pty_proc, timeout);
+                let (pending_interrupt, output_expired) = process_signals(
+                    &clock,
+                    Arc::clone(&child_proc),
+                    Arc::clone(&buf_queue),
+                    first_output,
+                    first_output_timeout,
+                    timeout,
+                    drain_timeout,
+                    resize_debounce,
+                    reaper,
+                    kill_tree,
+                );
+                // If we were interrupted, don't let slow stdout (--no-drop)
+                // delay termination, drop lines instead.
+                if pending_interrupt.is_some() {
+                    buf_queue.set_blocking(false);
+                }
                 // Proceed graceful termination.
-                initiate_shutdown(stdin_reader, pty_reader, pty_writer, timeout);
+                initiate_shutdown(
+                    stdin_reader,
+                    Arc::clone(&pty_reader),
+                    pty_writer,
+                    child_proc,
+                    eof_policy,
+                    timeout,
+                );
+                // Let descendants of command write to pty until they close it.
+                // Interruption at this point is handled like after capture
+                // is finished.
+                let mut late_interrupt = None;
+                if let Some(Limit(max_wait)) = wait_eof
+                    && pending_interrupt.is_none()
+                {
+                    late_interrupt =
+                        wait_stream_end(&clock, &pty_reader, &stream_finished, max_wait);
+                    if late_interrupt.is_some() {
+                        buf_queue.set_blocking(false);
+                    }
+                }
 
-                pending_interrupt
+                (pending_interrupt, late_interrupt, output_expired)
             })
             .unwrap()
     };
 
+    // State changed by keyboard commands.
+    let controls = Arc::new(Controls::new(args.pause_output, args.status_line));
+
     // Read from our stdin and write to child's stdin.
     let stdin_2_pty_thread = {
-        let pty_proc = Arc::clone(&pty_proc);
+        let child_proc = Arc::clone(&child_proc);
         let pty_writer = Arc::clone(&pty_writer);
         let stdin_reader = Arc::clone(&stdin_reader);
+        let line_delay = args.stdin_delay.map(|period| period.0);
+        let command_key = args.command_key;
+        let controls = Arc::clone(&controls);
 
-        debug!("spawning stdin_2_pty_thread thread");
+        debug!(Main, "spawning stdin_2_pty_thread thread");
         thread::Builder::new()
             .name("stdin_2_pty".to_string())
             .spawn(move || {
-                stdin_2_pty(pty_proc, pty_writer, stdin_reader);
+                stdin_2_pty(
+                    child_proc,
+                    pty_writer,
+                    stdin_reader,
+                    line_delay,
+                    command_key,
+                    controls,
+                );
             })
             .unwrap()
     };
@@ -888,56 +4749,493 @@ fn main() {
     let pty_2_stdout_thread = {
         let buf_queue = Arc::clone(&buf_queue);
         let stdout_writer = Arc::clone(&stdout_writer);
+        let stdout_lock = Arc::clone(&stdout_lock);
+        let strip_stdout = sink_styles.stdout.strip;
 
-        debug!("spawning pty_2_stdout_thread thread");
+        debug!(Main, "spawning pty_2_stdout_thread thread");
         thread::Builder::new()
             .name("pty_2_stdout".to_string())
             .spawn(move || {
-                queue_2_stdout(buf_queue, stdout_writer);
+                queue_2_stdout(buf_queue, stdout_writer, stdout_lock, strip_stdout);
             })
             .unwrap()
     };
 
-    // Read from child stdout and write to output file and to buffer queue.
-    // pty_2_stdout() will read from buffer queue and write to our stdout.
+    // Serve statistics over HTTP until we exit.
+    if let Some(metrics_server) = metrics_server {
+        let stats = Arc::clone(&stats);
+        let buf_queue = Arc::clone(&buf_queue);
+
+        debug!(Main, "spawning serve_metrics thread");
+        thread::Builder::new()
+            .name("serve_metrics".to_string())
+            .spawn(move || {
+                metrics_server.serve(&stats, &buf_queue);
+            })
+            .unwrap();
+    }
+
+    // Draw status line in the bottom row of terminal, if it may be enabled
+    // from command line or keyboard.
+    let status_enabled =
+        args.status_line || (args.command_key.0.is_some() && term::is_tty(stdio::stdin()));
+    let status_thread = if status_enabled && !args.silent && term::is_tty(stdio::stdout()) {
+        let child_proc = Arc::clone(&child_proc);
+        let stats = Arc::clone(&stats);
+        let buf_queue = Arc::clone(&buf_queue);
+        let controls = Arc::clone(&controls);
+        let stdout_writer = Arc::clone(&stdout_writer);
+        let stdout_lock = Arc::clone(&stdout_lock);
+        let out_path = out_path.clone();
+        let (stop, stop_rx) = mpsc::channel();
+
+        debug!(Main, "spawning report_status thread");
+        let thread = thread::Builder::new()
+            .name("report_status".to_string())
+            .spawn(move || {
+                report_status(
+                    child_proc,
+                    stats,
+                    buf_queue,
+                    controls,
+                    stdout_writer,
+                    stdout_lock,
+                    out_path,
+                    need_color,
+                    stop_rx,
+                );
+            })
+            .unwrap();
+        Some((thread, stop))
+    } else {
+        None
+    };
+
+    // Don't echo passwords that command hides in its terminal.
+    let input_thread = if LOCAL_ECHO.load(Ordering::Relaxed)
+        && !args.no_stdin
+        && !args.no_pty
+        && term::is_tty(stdio::stdin())
+    {
+        let child_proc = Arc::clone(&child_proc);
+        let (stop, stop_rx) = mpsc::channel();
+
+        debug!(Main, "spawning watch_input thread");
+        let thread = thread::Builder::new()
+            .name("watch_input".to_string())
+            .spawn(move || {
+                watch_input(child_proc, stop_rx);
+            })
+            .unwrap();
+        Some((thread, stop))
+    } else {
+        None
+    };
+
+    // Print statistics to stderr.
+    let progress_thread = if args.progress && term::is_tty(stdio::stderr()) {
+        let stats = Arc::clone(&stats);
+        let buf_queue = Arc::clone(&buf_queue);
+        let (stop, stop_rx) = mpsc::channel();
+
+        debug!(Main, "spawning report_progress thread");
+        let thread = thread::Builder::new()
+            .name("report_progress".to_string())
+            .spawn(move || {
+                report_progress(stats, buf_queue, stop_rx);
+            })
+            .unwrap();
+        Some((thread, stop))
+    } else {
+        None
+    };
+
+    // Read from child stdout and emit it to event bus, which delivers it to
+    // sinks: stdout (via buffer queue read by pty_2_stdout()), network,
+    // and files (via events_2_file() thread).
     //
-    // This function works until it reads EOF from child or is interrupted
+    // Capture works until it reads EOF from child or is interrupted
     // from initiate_shutdown().
-    debug!("running pty_2_queue_and_file thread");
-    pty_2_queue_and_file(
-        &pty_reader,
-        out_writer,
-        &buf_queue,
-        &buf_pool,
-        &mut formatter,
-    );
+    let (pending_interrupt, eof_interrupt, output_expired) = thread::scope(|scope| {
+        let splice_end = match &splice_file {
+            Some(out_file) => {
+                debug!(Main, "running pty_2_file_splice thread");
+                pty_2_file_splice(
+                    &pty_reader,
+                    out_file,
+                    &mut output_files,
+                    &mut formatter,
+                    &first_output,
+                )
+            }
+            None => SpliceEnd::Unsupported,
+        };
+
+        let mut bus = EventBus::default();
+        bus.subscribe(StdoutSink {
+            painter: &painter,
+            buf_pool: &buf_pool,
+            buf_queue: &buf_queue,
+            controls: &controls,
+            style: sink_styles.stdout,
+            dropped: 0,
+        });
+        bus.subscribe(&mut network_sinks);
+
+        // Files are written by separate thread, unless --file-sync is used.
+        let events_2_file_thread = if args.file_sync {
+            bus.subscribe(&mut output_files);
+            None
+        } else {
+            let (file_sink, file_events) = event::channel(args.file_buffer);
+            bus.subscribe(file_sink);
+            let files = &mut output_files;
+
+            debug!(Main, "spawning events_2_file thread");
+            let thread = thread::Builder::new()
+                .name("events_2_file".to_string())
+                .spawn_scoped(scope, move || {
+                    events_2_file(file_events, files);
+                })
+                .unwrap();
+            Some(thread)
+        };
+
+        let stream_end = match splice_end {
+            SpliceEnd::Finished(stream_end) => stream_end,
+            SpliceEnd::Unsupported => {
+                debug!(Main, "running pty_2_events thread");
+                pty_2_events(
+                    &child_proc,
+                    &pty_reader,
+                    &pty_writer,
+                    &mut bus,
+                    &buf_pool,
+                    &mut formatter,
+                    &mut line_rules,
+                    &stats,
+                    &controls,
+                    &first_output,
+                )
+            }
+        };
+
+        debug!(Pty, "stream finished by {}", stream_end.as_str());
+        selftrace::record(TraceEvent::Eof(stream_end.as_str()));
+        stream_finished.store(true, Ordering::SeqCst);
+
+        // Tell watch_input() to enable echo back and finish.
+        if let Some((input_thread, stop)) = input_thread {
+            drop(stop);
+            debug!(Main, "waiting for watch_input thread");
+            input_thread.join().unwrap();
+        }
+
+        // Tell report_progress() to clear its line and finish.
+        if let Some((progress_thread, stop)) = progress_thread {
+            drop(stop);
+            debug!(Main, "waiting for report_progress thread");
+            progress_thread.join().unwrap();
+        }
+
+        // Tell report_status() to remove status line and finish, so that
+        // footer and the rest of output use the whole terminal.
+        if let Some((status_thread, stop)) = status_thread {
+            drop(stop);
+            debug!(Main, "waiting for report_status thread");
+            status_thread.join().unwrap();
+        }
+
+        // Wait until child process exits or graceful termination is requested.
+        debug!(Main, "waiting for process_signals_thread");
+        let (pending_interrupt, eof_interrupt, output_expired) =
+            process_signals_thread.join().unwrap();
+        stats.set_child_status(child_proc.child_status());
+
+        // Traces recorded after end of stream go before footer.
+        bus.idle();
+        emit_traces(&mut bus, &buf_pool, &mut formatter);
+
+        // Write footer after child exited.
+        if formatter.need_footer() {
+            write_footer(&child_proc, stream_end, &mut bus, &buf_pool, &mut formatter);
+        }
+
+        // Closing bus tells events_2_file() to write pending events and finish.
+        drop(bus);
+        if let Some(thread) = events_2_file_thread {
+            debug!(Main, "waiting for events_2_file thread");
+            thread.join().unwrap();
+        }
+
+        (pending_interrupt, eof_interrupt, output_expired)
+    });
+    if let Err(err) = output_files.flush() {
+        terminate!(EXIT_FAILURE; "{}", err);
+    }
+
+    // Command exited, watchdog isn't needed anymore.
+    if let Some(watchdog) = watchdog {
+        debug!(Child, "stopping watchdog");
+        watchdog.disarm();
+    }
+
+    // Let age(1) write the rest of encrypted output file.
+    if let Some(encryptor) = encryptor {
+        debug!(File, "waiting for encryption");
+        if let Err(err) = encryptor.finish() {
+            terminate!(EXIT_FAILURE; "can't encrypt output file: {}", err);
+        }
+    }
+
+    // Let clients of --publish socket receive footer and disconnect.
+    if let Some(publisher) = network_sinks.publisher.take() {
+        debug!(Net, "closing publish socket");
+        publisher.close();
+    }
+
+    // Publish pending lines and disconnect from MQTT broker.
+    #[cfg(feature = "mqtt")]
+    if let Some(mqtt_sender) = network_sinks.mqtt_sender.take() {
+        debug!(Main, "waiting for mqtt_publish thread");
+        mqtt_sender.finish();
+    }
 
     // Tell pty_2_stdout() to finish.
     // The thread will process pending buffers, then see that queue is closed and exit.
-    debug!("closing buffer queue");
-    buf_queue.close();
-
-    // Wait until child process exits or graceful termination is requested.
-    debug!("waiting for process_signals_thread");
-    let pending_interrupt = process_signals_thread.join().unwrap();
+    // If we were interrupted, don't wait longer than --drain-stdout, because
+    // we're going to re-raise the signal anyway.
+    let stdout_drained = match pending_interrupt.or(eof_interrupt) {
+        Some(_) => drain_stdout(&buf_queue, args.drain_stdout.0),
+        None => {
+            debug!(Stdout, "closing buffer queue");
+            buf_queue.close();
+            true
+        }
+    };
 
     // At this point, process_signals() exited and leaved all signals blocked.
     // We're now in the process of graceful termination. Normally it will finish
-    // quickly after writing pending data to stdout, but we still want to give
-    // user a way of forcibly interrupting us in case of trouble. In this final
-    // stage we unblock and reset all signals, so that ^C or ^\ can kill us.
-    _ = signal::unblock_signals();
+    // quickly after writing pending data to stdout, but stdout may be a pipe
+    // that nobody reads. Keep signals blocked until stdout is drained, and if
+    // user asks us to quit meanwhile (e.g. ^C), abort blocked write instead of
+    // hanging, and then re-raise the signal.
+    let late_interrupt = match pending_interrupt.or(eof_interrupt) {
+        Some(_) => eof_interrupt,
+        None => wait_stdout_drained(&buf_queue),
+    };
 
     // At this point control thread already instructed all other threads to exit.
     // We just need to wait until all of them finish.
-    // stdin_2_pty_thread() should quit quickly, and pty_2_stdout_thread() may
-    // potentioally block if stdout is terminal or pipe - this is desired.
-    debug!("waiting for pty_2_stdout_thread");
+    // stdin_2_pty_thread() should quit quickly, and pty_2_stdout_thread() will
+    // quit as soon as it finishes or aborts writing to stdout.
+    if !stdout_drained || late_interrupt.is_some() {
+        // Abort blocked write, closed writer will discard pending lines.
+        debug!(Stdout, "closing stdout writer");
+        _ = stdout_writer.close();
+    }
+    debug!(Main, "waiting for pty_2_stdout_thread");
     pty_2_stdout_thread.join().unwrap();
-    debug!("waiting for stdin_2_pty_thread");
+
+    // Ring bell after everything else is printed, on terminal only.
+    // It's written bypassing --strip-stdout, which would remove it.
+    if let Some(bell) = args.bell {
+        let failed = child_proc.child_status().exit_status() != Some(EXIT_SUCCESS);
+        if (bell == BellMode::Always || failed) && !args.silent && term::is_tty(stdio::stdout()) {
+            // Closed writer discards it.
+            _ = stdout_writer.blocking_writer().write_all(&[BELL]);
+        }
+    }
+    debug!(Main, "waiting for stdin_2_pty_thread");
     stdin_2_pty_thread.join().unwrap();
 
+    // Command is finished, collect its stats and remove cgroup.
+    let peak_memory = cgroup.as_ref().and_then(Cgroup::peak_memory);
+    if let Some(cgroup) = &cgroup {
+        if let Err(err) = cgroup.remove() {
+            // Some descendants of command may be still running.
+            debug!(Child, "can't remove cgroup: {}", err);
+        }
+    }
+
+    // Recording is complete, write metadata.
+    if let Some(meta_file) = meta_file {
+        let child_status = child_proc.child_status();
+        let meta = SessionMeta {
+            start_time,
+            end_time: Local::now(),
+            command: args.command.join(" "),
+            status: describe_status(child_status),
+            exit_code: child_status.exit_status(),
+            output_path: (!args.null).then(|| out_path.clone()),
+            output_bytes: out_bytes.load(Ordering::Relaxed),
+            output_lines: output_files.out_lines.load(Ordering::Relaxed) - out_lines_start,
+            peak_memory,
+            tty_size,
+            term: env::var("TERM").ok(),
+            locale: format::locale(),
+        };
+        if let Err(err) = meta.write(meta_file) {
+            terminate!(EXIT_FAILURE; "can't write meta file: {}", err);
+        }
+    }
+
+    // Recording is complete, write checksum of output file.
+    // It's computed from the bytes we wrote, not by re-reading the file.
+    if let Some(mut checksum_file) = checksum_file {
+        let digest = out_hasher.lock().unwrap().finalize_reset();
+        if let Err(err) = writeln!(checksum_file, "{:x}  {}", digest, out_path) {
+            terminate!(EXIT_FAILURE; "can't write checksum file: {}", err);
+        }
+    }
+
+    // Recording is complete, sign output file as it was written to disk.
+    if let (Some(key_path), Some(sig_file)) = (&args.sign, sig_file) {
+        let written_path = if args.atomic {
+            temp_path(&out_path)
+        } else {
+            out_path.clone()
+        };
+        let result = File::open(&written_path)
+            .and_then(|file| sign::sign(Path::new(key_path), file, sig_file));
+        if let Err(err) = result {
+            terminate!(EXIT_FAILURE; "can't sign output file: {}", err);
+        }
+    }
+
+    // Recording is complete, move files to final paths.
+    if args.atomic {
+        finalize_recording(&recording_files);
+    }
+
+    // In this final stage we unblock and reset all signals, so that ^C or ^\
+    // can kill us in case of trouble (e.g. in --review pager).
+    _ = signal::unblock_signals();
+
+    // Report completion to user's hooks.
+    if args.notify || args.notify_exec.is_some() || args.notify_webhook.is_some() {
+        let child_status = child_proc.child_status();
+        notify_completion(
+            &args,
+            Completion {
+                command: args.command.join(" "),
+                status: describe_status(child_status),
+                exit_code: child_status.exit_status(),
+                duration: start_ts.elapsed(),
+                output_path: (!args.null).then(|| out_path.clone()),
+            },
+        );
+    }
+
+    if let Some(sig) = late_interrupt {
+        if let Err(err) = raise_signal(sig) {
+            terminate!(EXIT_FAILURE; "can't raise signal: {}", err);
+        }
+    }
+    let pending_interrupt = pending_interrupt.or(late_interrupt);
+
+    // Show recording to user, unless we were interrupted or there is no
+    // recording (with --lazy-output).
+    if args.review
+        && pending_interrupt.is_none()
+        && (!args.lazy_output || first_output.get().is_some())
+        && term::is_tty(stdio::stdout())
+    {
+        let failed = child_proc.child_status().exit_status() != Some(EXIT_SUCCESS);
+        review_output(&out_path, failed.then_some(&args.error_regex));
+    }
+
+    // Check whether command produced output in time, for --fail-if-silent.
+    let silent = match (args.fail_if_silent, first_output.get().copied()) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(Period(period)), Some(_)) if period.is_zero() => false,
+        (Some(Period(period)), Some(ts)) => ts - start_ts > period,
+    };
+
+    // Files to remove if command succeeds, for --keep-on-failure.
+    let remove_files = if args.keep_on_failure {
+        recording_files.clone()
+    } else {
+        vec![]
+    };
+
     // Forward exit status or pending interruption signal.
-    debug!("forwarding exit status");
-    forward_exit_status(pty_proc, pending_interrupt);
+    debug!(Child, "forwarding exit status");
+    forward_exit_status(
+        child_proc,
+        args.exit_status,
+        pending_interrupt,
+        output_expired,
+        silent,
+        line_rules.fail_matched,
+        &args.map_exit,
+        args.ignore_exit_code,
+        &remove_files,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    // Read all lines with given limit, truncating them like capture loop.
+    fn read_lines(input: &str, limit: usize, capacity: usize) -> Vec<String> {
+        let mut reader = BufReader::with_capacity(capacity, input.as_bytes());
+        let mut lines = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            let (size, dropped) = read_line_bounded(&mut reader, &mut buf, Some(limit)).unwrap();
+            if size == 0 {
+                break;
+            }
+            if dropped > 0 {
+                truncate_line(&mut buf, 0, dropped, Encoding::Utf8);
+            }
+            lines.push(String::from_utf8(buf).unwrap());
+        }
+        lines
+    }
+
+    #[test]
+    fn line_limit() {
+        for capacity in [1, 2, 3, 64] {
+            assert_eq!(read_lines("abc\nde\n", 5, capacity), ["abc\n", "de\n"]);
+            assert_eq!(
+                read_lines("abcdefgh\nx", 5, capacity),
+                ["abcde [truncated, 3 bytes dropped]\n", "x"]
+            );
+            assert_eq!(
+                read_lines("abcdefgh\r\n", 5, capacity),
+                ["abcde [truncated, 3 bytes dropped]\r\n"]
+            );
+            assert_eq!(
+                read_lines("abcdefgh", 5, capacity),
+                ["abcde [truncated, 3 bytes dropped]\n"]
+            );
+        }
+    }
+
+    #[test]
+    fn line_limit_boundary() {
+        for capacity in [1, 2, 3, 64] {
+            // Content fits the limit, terminator crosses it.
+            assert_eq!(read_lines("short\nx\n", 5, capacity), ["short\n", "x\n"]);
+            assert_eq!(
+                read_lines("short\r\nx\n", 5, capacity),
+                ["short\r\n", "x\n"]
+            );
+            assert_eq!(read_lines("shor\r\nx\n", 5, capacity), ["shor\r\n", "x\n"]);
+            assert_eq!(read_lines("short", 5, capacity), ["short"]);
+        }
+    }
+
+    #[test]
+    fn line_limit_utf8() {
+        assert_eq!(
+            read_lines("aaaéé\n", 4, 64),
+            ["aaa [truncated, 4 bytes dropped]\n"]
+        );
+    }
 }
@@ -0,0 +1,55 @@
+use crate::format::{HEADER_TIME_FMT, VERSION, json_number, json_string};
+use chrono::{DateTime, Local};
+use std::io::{self, Write};
+
+/// Session metadata written to --meta-file.
+pub struct SessionMeta {
+    pub start_time: DateTime<Local>,
+    pub end_time: DateTime<Local>,
+    pub command: String,
+    /// Exit code or signal name, like in footer.
+    pub status: String,
+    /// Exit code, if command exited normally.
+    pub exit_code: Option<i32>,
+    /// Path of --output file, unless --null is used.
+    pub output_path: Option<String>,
+    /// Bytes and lines written to --output file during this session.
+    pub output_bytes: u64,
+    pub output_lines: u64,
+    /// Peak memory usage of command, with --cgroup.
+    pub peak_memory: Option<u64>,
+    /// Terminal size of command when it was started, columns and rows.
+    pub tty_size: Option<(u16, u16)>,
+    /// TERM and locale environment, which affect how output is rendered.
+    pub term: Option<String>,
+    pub locale: Option<String>,
+}
+
+impl SessionMeta {
+    /// Write metadata as one-line JSON object.
+    pub fn write<W: Write>(&self, mut output: W) -> io::Result<()> {
+        writeln!(
+            output,
+            "{{\"start_time\":{},\"end_time\":{},\"cmd\":{},\"status\":{},\"exit_code\":{},\
+             \"output\":{},\"output_bytes\":{},\"output_lines\":{},\"peak_memory\":{},\
+             \"tty_cols\":{},\"tty_rows\":{},\"term\":{},\"locale\":{},\"version\":{}}}",
+            json_string(&self.start_time.format(HEADER_TIME_FMT).to_string()),
+            json_string(&self.end_time.format(HEADER_TIME_FMT).to_string()),
+            json_string(&self.command),
+            json_string(&self.status),
+            json_number(self.exit_code),
+            self.output_path
+                .as_deref()
+                .map_or("null".into(), json_string),
+            self.output_bytes,
+            self.output_lines,
+            json_number(self.peak_memory),
+            json_number(self.tty_size.map(|(cols, _)| cols)),
+            json_number(self.tty_size.map(|(_, rows)| rows)),
+            self.term.as_deref().map_or("null".into(), json_string),
+            self.locale.as_deref().map_or("null".into(), json_string),
+            json_string(VERSION),
+        )?;
+        output.flush()
+    }
+}
@@ -0,0 +1,151 @@
+use crate::buffer::BufferQueue;
+use crate::stats::Stats;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// How long to wait for client to send request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum size of request head, larger requests are rejected.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Minimal HTTP server exposing capture statistics in Prometheus text
+/// format (--metrics-listen).
+/// Connections are served one by one, which is enough for a scraper.
+pub struct MetricsServer {
+    listener: TcpListener,
+}
+
+impl MetricsServer {
+    /// Bind to address, e.g. "127.0.0.1:9300".
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        Ok(MetricsServer {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    /// Serve requests forever.
+    pub fn serve(&self, stats: &Stats, buf_queue: &BufferQueue) {
+        for stream in self.listener.incoming() {
+            // Misbehaving client shouldn't affect recording, ignore errors.
+            _ = stream.and_then(|stream| handle_client(stream, stats, buf_queue));
+        }
+    }
+}
+
+fn handle_client(mut stream: TcpStream, stats: &Stats, buf_queue: &BufferQueue) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    // Read request head, body is not expected.
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            return write_response(&mut stream, "431 Request Header Fields Too Large", "");
+        }
+        match stream.read(&mut buf)? {
+            0 => return Ok(()),
+            n => request.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    // Request line looks like: "GET /metrics HTTP/1.1".
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split(' ');
+    let (method, path) = (parts.next(), parts.next());
+
+    match (method, path) {
+        (Some("GET"), Some("/metrics")) => {
+            write_response(&mut stream, "200 OK", &format_metrics(stats, buf_queue))
+        }
+        (Some("GET"), _) => write_response(&mut stream, "404 Not Found", ""),
+        _ => write_response(&mut stream, "405 Method Not Allowed", ""),
+    }
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// Format metrics in Prometheus text exposition format.
+fn format_metrics(stats: &Stats, buf_queue: &BufferQueue) -> String {
+    let mut out = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        _ = writeln!(out, "# HELP {} {}", name, help);
+        _ = writeln!(out, "# TYPE {} {}", name, kind);
+        _ = writeln!(out, "{} {}", name, value);
+    };
+
+    metric(
+        "reclog_output_bytes_total",
+        "counter",
+        "Bytes read from command.",
+        stats.bytes().to_string(),
+    );
+    metric(
+        "reclog_output_lines_total",
+        "counter",
+        "Lines read from command.",
+        stats.lines().to_string(),
+    );
+    metric(
+        "reclog_stdout_dropped_lines_total",
+        "counter",
+        "Lines not printed to stdout because it was too slow.",
+        buf_queue.dropped().to_string(),
+    );
+    metric(
+        "reclog_stdout_queue_lines",
+        "gauge",
+        "Lines waiting to be printed to stdout.",
+        buf_queue.pending().to_string(),
+    );
+    metric(
+        "reclog_uptime_seconds",
+        "gauge",
+        "Time since start of command.",
+        format!("{:.3}", stats.elapsed().as_secs_f64()),
+    );
+
+    let status = stats.child_status();
+    metric(
+        "reclog_child_running",
+        "gauge",
+        "Whether command is still running.",
+        (status.is_none() as u8).to_string(),
+    );
+    if let Some(code) = status.and_then(|status| status.exit_status()) {
+        metric(
+            "reclog_child_exit_code",
+            "gauge",
+            "Exit code of command, if it exited normally.",
+            code.to_string(),
+        );
+    }
+    if let Some(sig) = status.and_then(|status| status.terminating_signal()) {
+        metric(
+            "reclog_child_signal",
+            "gauge",
+            "Number of signal that terminated command.",
+            sig.to_string(),
+        );
+    }
+
+    out
+}
@@ -0,0 +1,338 @@
+use crate::format::json_string;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Default port of MQTT broker.
+const DEFAULT_PORT: u16 = 1883;
+
+/// Keep alive interval announced to broker; ping is sent after half of it
+/// without other packets.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// Timeout for connecting and for network I/O.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delays between reconnection attempts, doubled after every failure.
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// How many lines may wait for sending; when broker is slow or
+/// unreachable, further lines are dropped.
+const QUEUE_SIZE: usize = 10000;
+
+/// MQTT broker endpoint (--mqtt).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttUrl {
+    host: String,
+    port: u16,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl FromStr for MqttUrl {
+    type Err = String;
+
+    /// Parse "mqtt://[USER[:PASSWORD]@]HOST[:PORT]".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = match s.strip_prefix("mqtt://") {
+            Some(rest) => rest.trim_end_matches('/'),
+            None if s.starts_with("mqtts://") => {
+                return Err("mqtts is not supported, use a TLS-terminating proxy".into());
+            }
+            None => {
+                return Err(format!(
+                    "invalid URL '{}', expected mqtt://[USER[:PASSWORD]@]HOST[:PORT]",
+                    s
+                ));
+            }
+        };
+
+        let (credentials, authority) = match rest.rsplit_once('@') {
+            Some((credentials, authority)) => (Some(credentials), authority),
+            None => (None, rest),
+        };
+        let (username, password) = match credentials {
+            Some(credentials) => match credentials.split_once(':') {
+                Some((user, password)) => (Some(user.into()), Some(password.into())),
+                None => (Some(credentials.into()), None),
+            },
+            None => (None, None),
+        };
+
+        // IPv6 address is enclosed in brackets, e.g. "[::1]:1883".
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => match port.parse::<u16>() {
+                Ok(port) => (host, port),
+                Err(_) => return Err(format!("invalid port '{}' in URL", port)),
+            },
+            _ => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in URL '{}'", s));
+        }
+
+        Ok(MqttUrl {
+            host: host.into(),
+            port,
+            username,
+            password,
+        })
+    }
+}
+
+/// Publishes lines to MQTT broker (--mqtt).
+/// Lines are passed to background thread, which keeps connection to broker
+/// and reconnects when it's lost. Publishing never blocks: if the queue is
+/// full, lines are dropped.
+pub struct MqttSender {
+    sender: SyncSender<String>,
+    finishing: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl MqttSender {
+    /// Connect to broker and start background thread.
+    /// Fails if first connection fails, to report misconfiguration early.
+    /// If `batch` is greater than 1, lines that are ready at the same time
+    /// are published together as JSON array, up to `batch` lines.
+    pub fn start(url: &MqttUrl, topic: &str, batch: usize) -> io::Result<Self> {
+        let mut client = Client {
+            url: url.clone(),
+            topic: topic.into(),
+            client_id: format!("reclog-{}", process::id()),
+            stream: None,
+        };
+        client.connect()?;
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let finishing = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let finishing = Arc::clone(&finishing);
+            thread::Builder::new()
+                .name("mqtt_publish".to_string())
+                .spawn(move || publish_lines(client, receiver, batch.max(1), &finishing))?
+        };
+
+        Ok(MqttSender {
+            sender,
+            finishing,
+            thread,
+        })
+    }
+
+    /// Queue line (without ANSI escape codes and newline) for publishing.
+    pub fn send_line(&self, line: &str) {
+        let line = line.trim_end_matches(['\r', '\n']);
+        // Line is lost if queue is full.
+        _ = self.sender.try_send(line.into());
+    }
+
+    /// Publish queued lines, disconnect, and wait until thread exits.
+    /// If broker is unreachable at this point, queued lines are dropped.
+    pub fn finish(self) {
+        self.finishing.store(true, Ordering::SeqCst);
+        drop(self.sender);
+        // Interrupt waiting before reconnection.
+        self.thread.thread().unpark();
+        _ = self.thread.join();
+    }
+}
+
+/// Thread that publishes lines from queue until it's closed.
+fn publish_lines(
+    mut client: Client,
+    receiver: Receiver<String>,
+    batch: usize,
+    finishing: &AtomicBool,
+) {
+    let mut reconnect_delay = MIN_RECONNECT_DELAY;
+    let mut pending: Vec<String> = Vec::new();
+
+    loop {
+        // Wait for lines, or ping broker if there are none for a while.
+        if pending.is_empty() {
+            match receiver.recv_timeout(KEEP_ALIVE / 2) {
+                Ok(line) => pending.push(line),
+                Err(RecvTimeoutError::Timeout) => {
+                    if client.stream.is_some() {
+                        _ = client.ping();
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        // Take more lines that are already waiting.
+        while pending.len() < batch {
+            match receiver.try_recv() {
+                Ok(line) => pending.push(line),
+                Err(_) => break,
+            }
+        }
+
+        if client.stream.is_none() {
+            if client.connect().is_err() {
+                // Don't wait for broker that went away.
+                if finishing.load(Ordering::SeqCst) {
+                    break;
+                }
+                // Lines keep accumulating in queue meanwhile, and are
+                // dropped when it's full.
+                thread::park_timeout(reconnect_delay);
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+            reconnect_delay = MIN_RECONNECT_DELAY;
+        }
+
+        let result = if batch > 1 {
+            let items: Vec<String> = pending.iter().map(|line| json_string(line)).collect();
+            client.publish(format!("[{}]", items.join(",")).as_bytes())
+        } else {
+            client.publish(pending[0].as_bytes())
+        };
+        match result {
+            // Retry the same lines after reconnecting.
+            Err(_) => client.stream = None,
+            Ok(()) => pending.clear(),
+        }
+    }
+
+    client.disconnect();
+}
+
+/// Minimal MQTT 3.1.1 client, publishing with QoS 0.
+struct Client {
+    url: MqttUrl,
+    topic: String,
+    client_id: String,
+    stream: Option<TcpStream>,
+}
+
+impl Client {
+    fn connect(&mut self) -> io::Result<()> {
+        self.stream = None;
+
+        let host = self.url.host.trim_start_matches('[').trim_end_matches(']');
+        let addr = match (host, self.url.port).to_socket_addrs()?.next() {
+            Some(addr) => addr,
+            None => return Err(Error::new(ErrorKind::NotFound, "can't resolve host")),
+        };
+        let mut stream = TcpStream::connect_timeout(&addr, NETWORK_TIMEOUT)?;
+        stream.set_read_timeout(Some(NETWORK_TIMEOUT))?;
+        stream.set_write_timeout(Some(NETWORK_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+
+        // Variable header: protocol name and level, flags, keep alive.
+        let mut flags = 0x02; // clean session
+        if self.url.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.url.password.is_some() {
+            flags |= 0x40;
+        }
+        let mut body = Vec::new();
+        put_string(&mut body, "MQTT");
+        body.push(4);
+        body.push(flags);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        // Payload: client ID and credentials.
+        put_string(&mut body, &self.client_id);
+        if let Some(username) = &self.url.username {
+            put_string(&mut body, username);
+        }
+        if let Some(password) = &self.url.password {
+            put_string(&mut body, password);
+        }
+        write_packet(&mut stream, 0x10, &body)?;
+
+        // CONNACK: fixed header, flags, return code.
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[1] != 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "invalid response from broker",
+            ));
+        }
+        match connack[3] {
+            0 => {}
+            4 | 5 => {
+                return Err(Error::new(
+                    ErrorKind::PermissionDenied,
+                    "broker refused credentials",
+                ));
+            }
+            code => {
+                return Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("broker refused connection with code {}", code),
+                ));
+            }
+        }
+
+        // From now on, broker only sends ping responses, which aren't
+        // read; don't wait for them.
+        stream.set_read_timeout(None)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn publish(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut body = Vec::with_capacity(self.topic.len() + payload.len() + 2);
+        put_string(&mut body, &self.topic);
+        body.extend_from_slice(payload);
+        self.write(0x30, &body)
+    }
+
+    fn ping(&mut self) -> io::Result<()> {
+        self.write(0xc0, &[])
+    }
+
+    fn disconnect(&mut self) {
+        _ = self.write(0xe0, &[]);
+        self.stream = None;
+    }
+
+    fn write(&mut self, packet_type: u8, body: &[u8]) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => write_packet(stream, packet_type, body),
+            None => Err(Error::from(ErrorKind::NotConnected)),
+        }
+    }
+}
+
+/// Write packet with fixed header, where remaining length is encoded as
+/// variable-length integer.
+fn write_packet<W: Write>(output: &mut W, packet_type: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(packet_type);
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    output.write_all(&packet)?;
+    output.flush()
+}
+
+/// Append UTF-8 string with 2-byte length prefix.
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
@@ -0,0 +1,195 @@
+use crate::signal;
+use crate::status::*;
+use rustix::process::{self as rprocess, Pid};
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{self, Child, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Colors of prefixes, cycled between commands.
+const PREFIX_COLORS: &[&str] = &[
+    "\x1b[36m", // cyan
+    "\x1b[35m", // magenta
+    "\x1b[33m", // yellow
+    "\x1b[32m", // green
+    "\x1b[34m", // blue
+    "\x1b[31m", // red
+];
+const PREFIX_END: &str = "\x1b[0m";
+
+/// Command run by `reclog multi`.
+pub struct MultiCommand {
+    /// Shell command line.
+    pub command: String,
+    /// Name shown in prefix of lines, same as stem of log file.
+    pub label: String,
+    /// Path of log file.
+    pub output: String,
+}
+
+impl MultiCommand {
+    /// Choose label and log file for every command.
+    /// Log file is named after first word of command, like in main
+    /// command, with suffix added to keep names unique. Unless `force` is
+    /// set, existing files are skipped too.
+    pub fn plan(commands: &[String], dir: &str, force: bool) -> Result<Vec<Self>, String> {
+        let mut labels = HashSet::new();
+        let mut result = Vec::new();
+
+        for command in commands {
+            let base_name = command
+                .split_whitespace()
+                .next()
+                .and_then(|word| Path::new(word).file_stem())
+                .and_then(|name| name.to_str())
+                .ok_or_else(|| format!("invalid command '{}'", command))?;
+
+            let mut label = base_name.to_string();
+            let mut suffix = 1;
+            while labels.contains(&label)
+                || (!force && Path::new(dir).join(format!("{}.log", label)).exists())
+            {
+                label = format!("{}-{}", base_name, suffix);
+                suffix += 1;
+            }
+            labels.insert(label.clone());
+
+            let output = Path::new(dir).join(format!("{}.log", label));
+            result.push(MultiCommand {
+                command: command.clone(),
+                label,
+                output: output.to_string_lossy().into(),
+            });
+        }
+
+        Ok(result)
+    }
+}
+
+/// Run commands concurrently, each under its own reclog process with its
+/// own pty and log file, and print their output to stdout, prefixed with
+/// command label.
+/// Child processes write to pipes, so they see non-tty stdout: their ptys
+/// have no window size, and tty-only features are off (see MANUAL.rst).
+/// `reclog_args` are passed to every reclog process before the command.
+/// Returns exit statuses of reclog processes, in order of commands.
+pub fn run(
+    commands: &[MultiCommand],
+    reclog_args: &[String],
+    color: bool,
+) -> io::Result<Vec<ExitStatus>> {
+    let exe = std::env::current_exe()?;
+
+    let mut children: Vec<Child> = Vec::new();
+    for cmd in commands {
+        let child = process::Command::new(&exe)
+            .args(reclog_args)
+            .arg("--output")
+            .arg(&cmd.output)
+            .args(["--", "/bin/sh", "-c", &cmd.command])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn();
+        match child {
+            Ok(child) => children.push(child),
+            Err(err) => {
+                for child in &mut children {
+                    _ = child.kill();
+                    _ = child.wait();
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    // Forward termination requests to children, which forward them to
+    // commands. Thread exits together with process.
+    let pids: Vec<Pid> = children
+        .iter()
+        .filter_map(|child| Pid::from_raw(child.id() as i32))
+        .collect();
+    thread::Builder::new()
+        .name("forward_signals".to_string())
+        .spawn(move || forward_signals(pids))?;
+
+    let width = commands
+        .iter()
+        .map(|cmd| cmd.label.len())
+        .max()
+        .unwrap_or(0);
+    let stdout = Arc::new(Mutex::new(io::stdout()));
+
+    let mut threads = Vec::new();
+    for (n, (cmd, child)) in commands.iter().zip(&mut children).enumerate() {
+        let prefix = if color {
+            let color = PREFIX_COLORS[n % PREFIX_COLORS.len()];
+            format!("{}{:<width$} |{} ", color, cmd.label, PREFIX_END)
+        } else {
+            format!("{:<width$} | ", cmd.label)
+        };
+        let child_stdout = child.stdout.take().unwrap();
+        let stdout = Arc::clone(&stdout);
+
+        threads.push(
+            thread::Builder::new()
+                .name(format!("multi_{}", n))
+                .spawn(move || copy_lines(child_stdout, &prefix, &stdout))?,
+        );
+    }
+
+    // Keep first error, if any.
+    let mut result = Ok(());
+    for thread in threads {
+        result = result.and(thread.join().unwrap());
+    }
+
+    let mut statuses = Vec::new();
+    for child in &mut children {
+        statuses.push(child.wait()?);
+    }
+    result.map(|_| statuses)
+}
+
+/// Copy lines from child to stdout, adding prefix to every line.
+/// Whole lines are written at once, so lines of different commands don't
+/// get mixed.
+fn copy_lines<R: Read>(input: R, prefix: &str, stdout: &Mutex<io::Stdout>) -> io::Result<()> {
+    let mut reader = BufReader::new(input);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        line.extend_from_slice(prefix.as_bytes());
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        if line.last() != Some(&b'\n') {
+            line.push(b'\n');
+        }
+
+        let mut stdout = stdout.lock().unwrap();
+        stdout.write_all(&line)?;
+        stdout.flush()?;
+    }
+}
+
+fn forward_signals(pids: Vec<Pid>) {
+    while let Ok(sig) = signal::wait_forwarded_signal() {
+        for pid in &pids {
+            _ = rprocess::kill_process(*pid, sig);
+        }
+    }
+}
+
+/// Convert exit status of child to our exit code, using the same
+/// conventions as main command.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(sig)) => EXIT_COMMAND_SIGNALED + sig,
+        _ => EXIT_FAILURE,
+    }
+}
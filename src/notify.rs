@@ -0,0 +1,235 @@
+use crate::format::{json_number, json_string};
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long to wait for webhook server.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Summary of finished command, passed to notification hooks.
+pub struct Completion {
+    pub command: String,
+    /// Exit code or signal name, like in footer.
+    pub status: String,
+    /// Exit code, if command exited normally.
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+    /// Path of --output file, unless --null is used.
+    pub output_path: Option<String>,
+}
+
+impl Completion {
+    /// Environment variables for --notify-exec.
+    fn env_vars(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("RECLOG_COMMAND", self.command.clone()),
+            ("RECLOG_STATUS", self.status.clone()),
+            (
+                "RECLOG_EXIT_CODE",
+                self.exit_code
+                    .map_or(String::new(), |code| code.to_string()),
+            ),
+            (
+                "RECLOG_DURATION",
+                format!("{:.3}", self.duration.as_secs_f64()),
+            ),
+            ("RECLOG_LOG", self.output_path.clone().unwrap_or_default()),
+        ]
+    }
+
+    /// JSON body for --notify-webhook.
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"cmd\":{},\"status\":{},\"exit_code\":{},\"duration\":{:.3},\"output\":{}}}",
+            json_string(&self.command),
+            json_string(&self.status),
+            json_number(self.exit_code),
+            self.duration.as_secs_f64(),
+            self.output_path
+                .as_deref()
+                .map_or("null".into(), json_string),
+        )
+    }
+}
+
+/// Run shell command with completion info in environment (--notify-exec).
+pub fn run_exec(command: &str, completion: &Completion) -> io::Result<()> {
+    let status = process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .envs(completion.env_vars())
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!("command failed with {}", status)))
+    }
+}
+
+/// Show desktop notification (--notify), using notify-send(1) on Linux and
+/// BSDs, and osascript(1) on macOS.
+pub fn send_desktop(completion: &Completion) -> io::Result<()> {
+    let success = completion.exit_code == Some(0);
+    let title = format!("reclog: {}", completion.command);
+    let body = format!(
+        "{} with status {} after {}",
+        if success { "Finished" } else { "Failed" },
+        completion.status,
+        format_duration(completion.duration),
+    );
+
+    let mut command = if cfg!(target_os = "macos") {
+        // Pass strings as arguments to avoid escaping them in script.
+        let mut command = process::Command::new("osascript");
+        command.args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display notification (item 2 of argv) with title (item 1 of argv)",
+            "-e",
+            "end run",
+            &title,
+            &body,
+        ]);
+        command
+    } else {
+        let mut command = process::Command::new("notify-send");
+        command.args([
+            "--app-name=reclog",
+            if success {
+                "--urgency=normal"
+            } else {
+                "--urgency=critical"
+            },
+            &title,
+            &body,
+        ]);
+        command
+    };
+
+    let status = command
+        .stdin(process::Stdio::null())
+        .status()
+        .map_err(|err| {
+            let program = command.get_program().to_string_lossy();
+            Error::new(err.kind(), format!("can't run {}: {}", program, err))
+        })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!("command failed with {}", status)))
+    }
+}
+
+/// Format duration for humans, e.g. "1h 2m 3s".
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{:.1}s", duration.as_secs_f64()),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m {}s", secs / 3600, secs / 60 % 60, secs % 60),
+    }
+}
+
+/// Webhook URL (--notify-webhook).
+/// Only plain HTTP is supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for WebhookUrl {
+    type Err = String;
+
+    /// Parse "http://HOST[:PORT][/PATH]".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = match s.strip_prefix("http://") {
+            Some(rest) => rest,
+            None if s.starts_with("https://") => {
+                return Err("https is not supported, use --notify-exec with curl(1)".into());
+            }
+            None => {
+                return Err(format!(
+                    "invalid URL '{}', expected http://HOST[:PORT]/PATH",
+                    s
+                ));
+            }
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        // IPv6 address is enclosed in brackets, e.g. "[::1]:8080".
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => match port.parse::<u16>() {
+                Ok(port) => (host, port),
+                Err(_) => return Err(format!("invalid port '{}' in URL", port)),
+            },
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in URL '{}'", s));
+        }
+
+        Ok(WebhookUrl {
+            host: host.into(),
+            port,
+            path: path.into(),
+        })
+    }
+}
+
+/// Send completion info as JSON in HTTP POST request (--notify-webhook).
+/// Fails if server doesn't respond with 2xx status.
+pub fn post_webhook(url: &WebhookUrl, completion: &Completion) -> io::Result<()> {
+    let host = url.host.trim_start_matches('[').trim_end_matches(']');
+    let addr = match (host, url.port).to_socket_addrs()?.next() {
+        Some(addr) => addr,
+        None => return Err(Error::new(ErrorKind::NotFound, "can't resolve host")),
+    };
+
+    let mut stream = TcpStream::connect_timeout(&addr, WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+
+    let body = completion.to_json();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\n\
+         Host: {}:{}\r\n\
+         User-Agent: reclog/{}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        url.path,
+        url.host,
+        url.port,
+        env!("CARGO_PKG_VERSION"),
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    // Status line looks like: "HTTP/1.1 200 OK".
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    match status_line.split(' ').nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some(_) => Err(Error::other(format!(
+            "server responded with \"{}\"",
+            status_line.trim_end()
+        ))),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            "invalid response from server",
+        )),
+    }
+}
@@ -0,0 +1,72 @@
+use crate::error::SysError;
+use crate::shim;
+use rustix::fs::{self, Gid, Mode};
+use std::os::fd::AsFd;
+use std::str::FromStr;
+
+/// Permissions of files written during recording (--mode).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileMode(u32);
+
+impl FileMode {
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+impl FromStr for FileMode {
+    type Err = String;
+
+    /// Parse octal mode, e.g. "0600" or "640".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match u32::from_str_radix(s, 8) {
+            Ok(mode) if !s.starts_with('+') && mode <= 0o7777 => Ok(FileMode(mode)),
+            _ => Err(format!(
+                "invalid mode '{}', expected octal number like 0600",
+                s
+            )),
+        }
+    }
+}
+
+/// Group of files written during recording (--group).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileGroup(u32);
+
+impl FromStr for FileGroup {
+    type Err = String;
+
+    /// Parse group name or numeric ID.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match shim::getgrnam(s) {
+            Ok(Some(gid)) => return Ok(FileGroup(gid)),
+            Ok(None) => {}
+            Err(err) => return Err(format!("can't resolve group '{}': {}", s, err)),
+        }
+        match s.parse::<u32>() {
+            Ok(gid) if gid != u32::MAX => Ok(FileGroup(gid)),
+            _ => Err(format!("unknown group '{}'", s)),
+        }
+    }
+}
+
+/// Change permissions and group of opened file, if requested.
+pub fn set_perm<Fd: AsFd>(
+    fd: Fd,
+    mode: Option<FileMode>,
+    group: Option<FileGroup>,
+) -> Result<(), SysError> {
+    if let Some(group) = group {
+        if let Err(err) = fs::fchown(&fd, None, Some(Gid::from_raw(group.0))) {
+            return Err(SysError("fchown()", err));
+        }
+    }
+    // Set mode after changing group, because fchown() may reset setgid bit.
+    if let Some(mode) = mode {
+        if let Err(err) = fs::fchmod(&fd, Mode::from_raw_mode(mode.0 as _)) {
+            return Err(SysError("fchmod()", err));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use crate::child::{self, Child, ChildProc, ChildSetup, ChildWait};
+use crate::error::SysError;
+use crate::signal;
+use exec::Command;
+use rustix::io::{self, retry_on_intr};
+use rustix::net::{self, AddressFamily, Shutdown, SocketFlags, SocketType};
+use rustix::pipe;
+use rustix::process::{Pid, Signal, WaitStatus};
+use rustix::stdio;
+use std::io::{Error, Write};
+use std::os::fd::OwnedFd;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Allows to spawn child process connected to parent via plain pipes
+/// instead of pty. Child's stdout and stderr are redirected to the same
+/// pipe. Child's stdin is a unix socket, so that parent can propagate EOF
+/// via shutdown() regardless of how many duplicates of the descriptor
+/// exist in parent.
+pub struct PipeProc {
+    input_fd: OwnedFd,
+    output_fd: OwnedFd,
+    child_fds: Mutex<Option<(OwnedFd, OwnedFd)>>,
+    child: Child,
+}
+
+impl PipeProc {
+    /// Open pipes.
+    pub fn open() -> Result<Self, SysError> {
+        let (input_fd, child_input_fd) = match retry_on_intr(|| {
+            net::socketpair(
+                AddressFamily::UNIX,
+                SocketType::STREAM,
+                SocketFlags::empty(),
+                None,
+            )
+        }) {
+            Ok(fds) => fds,
+            Err(err) => return Err(SysError("socketpair()", err)),
+        };
+        // SocketFlags::CLOEXEC is not available on macOS.
+        for fd in [&input_fd, &child_input_fd] {
+            if let Err(err) = io::fcntl_setfd(fd, io::FdFlags::CLOEXEC) {
+                return Err(SysError("fcntl()", err));
+            }
+        }
+
+        let (output_fd, child_output_fd) = match retry_on_intr(|| pipe::pipe()) {
+            Ok(fds) => fds,
+            Err(err) => return Err(SysError("pipe()", err)),
+        };
+
+        Ok(PipeProc {
+            input_fd,
+            output_fd,
+            child_fds: Mutex::new(Some((child_input_fd, child_output_fd))),
+            child: Child::new(),
+        })
+    }
+
+    fn prepare_child(
+        &self,
+        child_input_fd: &OwnedFd,
+        child_output_fd: &OwnedFd,
+    ) -> Result<(), SysError> {
+        // restore signal dispositions and mask
+        signal::init_child_signals()?;
+
+        // create new session and become session leader
+        child::new_session()?;
+
+        // redirect stdin to socket, and stdout/stderr to pipe
+        if let Err(err) = retry_on_intr(|| stdio::dup2_stdin(child_input_fd)) {
+            return Err(SysError("dup2()", err));
+        }
+        for dup_fn in &[
+            stdio::dup2_stdout::<&OwnedFd>,
+            stdio::dup2_stderr::<&OwnedFd>,
+        ] {
+            if let Err(err) = retry_on_intr(|| dup_fn(child_output_fd)) {
+                return Err(SysError("dup2()", err));
+            }
+        }
+
+        // close file descriptors except stdin/stdout/stderr
+        child::close_extra_fds()
+    }
+}
+
+impl ChildProc for PipeProc {
+    fn dup_input(&self) -> Result<OwnedFd, SysError> {
+        retry_on_intr(|| io::dup(&self.input_fd)).map_err(|err| SysError("dup()", err))
+    }
+
+    fn dup_output(&self) -> Result<OwnedFd, SysError> {
+        retry_on_intr(|| io::dup(&self.output_fd)).map_err(|err| SysError("dup()", err))
+    }
+
+    /// Shutdown writing side of the socket.
+    /// Child will get EOF after reading all pending input.
+    fn send_eof(&self, input_writer: &mut dyn Write) -> Result<(), Error> {
+        input_writer.flush()?;
+
+        retry_on_intr(|| net::shutdown(&self.input_fd, Shutdown::Write))
+            .map_err(|err| Error::other(SysError("shutdown()", err)))
+    }
+
+    fn spawn_child(&self, command: &mut Command, setup: &ChildSetup) -> Result<(), SysError> {
+        let mut locked_fds = self.child_fds.lock().unwrap();
+
+        let (child_input_fd, child_output_fd) = match locked_fds.as_ref() {
+            Some(fds) => fds,
+            None => panic!("attempt to call spawn_child() twice"),
+        };
+        self.child.spawn(command, setup, || {
+            self.prepare_child(child_input_fd, child_output_fd)
+        })?;
+
+        // Close child's ends of pipes in parent, so that we get EOF
+        // when child and its descendants close them.
+        *locked_fds = None;
+
+        Ok(())
+    }
+
+    /// No-op, child's ends are closed in parent right after spawning child.
+    fn release_child_side(&self) -> Result<(), SysError> {
+        Ok(())
+    }
+
+    /// No-op, there is no terminal to resize.
+    fn resize_child(&self) -> Result<(), SysError> {
+        Ok(())
+    }
+
+    /// No-op, there is no terminal to resize.
+    fn set_reserved_rows(&self, _rows: u16) {}
+
+    /// No-op, pipes don't echo.
+    fn set_child_echo(&self, _echo: bool) {}
+
+    /// Child has no terminal.
+    fn input_hidden(&self) -> bool {
+        false
+    }
+
+    /// Child has no terminal.
+    fn tty_size(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// Never resized.
+    fn take_resize(&self) -> Option<(Instant, u16, u16)> {
+        None
+    }
+
+    fn kill_child(&self, sig: Signal) -> Result<(), SysError> {
+        self.child.kill(sig)
+    }
+
+    fn wait_child(&self, wait_mode: ChildWait) -> Result<Option<WaitStatus>, SysError> {
+        self.child.wait(wait_mode)
+    }
+
+    fn child_status(&self) -> WaitStatus {
+        self.child.status()
+    }
+
+    fn child_pid(&self) -> Option<Pid> {
+        self.child.pid()
+    }
+}
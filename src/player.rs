@@ -0,0 +1,322 @@
+use crate::timing::{TimingEntry, TimingReader};
+use chrono::{DateTime, Local};
+use std::io::{self, BufRead, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::Duration;
+
+/// Chunk of recorded output with the moment it was produced.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Time since beginning of the recording.
+    pub time: Duration,
+    /// Raw bytes, as written to the recording.
+    pub data: Vec<u8>,
+}
+
+/// Position of a chunk inside recording.
+struct FrameIndex {
+    time: Duration,
+    offset: u64,
+    size: usize,
+}
+
+/// Replays raw recording according to timing file.
+///
+/// The player only provides access to frames and computes delays between
+/// them; the caller decides when and where to output them. Use play() for
+/// simple blocking playback.
+pub struct Player<R: Read + Seek> {
+    recording: R,
+    frames: Vec<FrameIndex>,
+    start_time: Option<DateTime<Local>>,
+    position: usize,
+    clock: Duration,
+    speed: f64,
+    max_delay: Option<Duration>,
+}
+
+impl<R: Read + Seek> Player<R> {
+    /// Open recording and parse its timing file.
+    /// Timing file can be in script(1) "classic" or "advanced" format.
+    pub fn open<T: BufRead>(recording: R, timing: T) -> io::Result<Self> {
+        let mut timing_reader = TimingReader::new(timing);
+
+        let mut frames = Vec::new();
+        let mut start_time = None;
+        let mut time = Duration::ZERO;
+        let mut offset = 0;
+
+        while let Some(entry) = timing_reader.read_entry()? {
+            match entry {
+                TimingEntry::StartTime(ts) => {
+                    // Appended sessions are played continuously.
+                    if start_time.is_none() {
+                        start_time = Some(ts);
+                    }
+                }
+                TimingEntry::Other { delay, .. } => {
+                    time += delay;
+                }
+                TimingEntry::Output(delay, size) => {
+                    time += delay;
+                    frames.push(FrameIndex { time, offset, size });
+                    offset += size as u64;
+                }
+            }
+        }
+
+        Ok(Player {
+            recording,
+            frames,
+            start_time,
+            position: 0,
+            clock: Duration::ZERO,
+            speed: 1.0,
+            max_delay: None,
+        })
+    }
+
+    /// Wallclock time when recording was started, if known.
+    pub fn start_time(&self) -> Option<DateTime<Local>> {
+        self.start_time
+    }
+
+    /// Total duration of the recording.
+    pub fn duration(&self) -> Duration {
+        self.frames.last().map_or(Duration::ZERO, |f| f.time)
+    }
+
+    /// Number of frames in the recording.
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Current playback time since beginning of the recording.
+    pub fn position(&self) -> Duration {
+        self.clock
+    }
+
+    /// Number of the next frame.
+    pub fn frame_position(&self) -> usize {
+        self.position
+    }
+
+    /// True if all frames were played.
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.frames.len()
+    }
+
+    /// Set playback speed multiplier, e.g. 2.0 plays twice faster.
+    pub fn set_speed(&mut self, speed: f64) {
+        assert!(speed > 0.0 && speed.is_finite(), "speed should be positive");
+        self.speed = speed;
+    }
+
+    /// Limit delay between frames (like scriptreplay --maxdelay).
+    pub fn set_max_delay(&mut self, max_delay: Option<Duration>) {
+        self.max_delay = max_delay;
+    }
+
+    /// Move playback position to the first frame produced at or after
+    /// given time since beginning of the recording.
+    ///
+    /// Note that frames before the new position may contain escape codes
+    /// which affect the rest of the output; use seek_frame() and read all
+    /// frames if the caller needs to reconstruct terminal state.
+    pub fn seek(&mut self, time: Duration) {
+        self.position = self.frames.partition_point(|f| f.time < time);
+        self.clock = time.min(self.duration());
+    }
+
+    /// Move playback position to given frame number.
+    pub fn seek_frame(&mut self, index: usize) {
+        self.position = index.min(self.frames.len());
+        self.clock = match self.position {
+            0 => Duration::ZERO,
+            n => self.frames[n - 1].time,
+        };
+    }
+
+    /// How long to wait before next frame, taking into account speed
+    /// and max delay. Returns None if playback is finished.
+    pub fn next_delay(&self) -> Option<Duration> {
+        let next = self.frames.get(self.position)?;
+
+        let mut delay = next.time.saturating_sub(self.clock).div_f64(self.speed);
+        if let Some(max_delay) = self.max_delay {
+            delay = delay.min(max_delay);
+        }
+
+        Some(delay)
+    }
+
+    /// Read next frame and advance playback position.
+    /// Returns None if playback is finished.
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        let index = match self.frames.get(self.position) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let mut data = vec![0; index.size];
+        self.recording.seek(SeekFrom::Start(index.offset))?;
+        if let Err(err) = self.recording.read_exact(&mut data) {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "recording is shorter than timing file",
+                ));
+            }
+            return Err(err);
+        }
+
+        let frame = Frame {
+            time: index.time,
+            data,
+        };
+        self.position += 1;
+        self.clock = frame.time;
+
+        Ok(Some(frame))
+    }
+
+    /// Play remaining frames to writer, sleeping between them.
+    pub fn play<W: Write>(&mut self, mut output: W) -> io::Result<()> {
+        while let Some(delay) = self.next_delay() {
+            thread::sleep(delay);
+
+            if let Some(frame) = self.next_frame()? {
+                output.write_all(&frame.data)?;
+                output.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const TIMING: &str = "H 0.000000 START_TIME 2024-01-02 03:04:05.000000 +0000\n\
+                          O 0.5 3\n\
+                          S 1.0 SIGWINCH ROWS=24 COLS=80\n\
+                          O 0.5 4\n\
+                          O 3.0 2\n";
+
+    fn open() -> Player<Cursor<Vec<u8>>> {
+        Player::open(Cursor::new(b"abcdefghi".to_vec()), TIMING.as_bytes()).unwrap()
+    }
+
+    fn frame(player: &mut Player<Cursor<Vec<u8>>>) -> (Duration, Vec<u8>) {
+        let frame = player.next_frame().unwrap().unwrap();
+        (frame.time, frame.data)
+    }
+
+    #[test]
+    fn frames() {
+        let mut player = open();
+        assert!(player.start_time().is_some());
+        assert_eq!(player.frame_count(), 3);
+        assert_eq!(player.duration(), Duration::from_secs(5));
+
+        assert_eq!(
+            frame(&mut player),
+            (Duration::from_millis(500), b"abc".to_vec())
+        );
+        assert_eq!(
+            frame(&mut player),
+            (Duration::from_secs(2), b"defg".to_vec())
+        );
+        assert_eq!(frame(&mut player), (Duration::from_secs(5), b"hi".to_vec()));
+        assert!(player.is_finished());
+        assert!(player.next_frame().unwrap().is_none());
+        assert!(player.next_delay().is_none());
+    }
+
+    #[test]
+    fn next_delay() {
+        let mut player = open();
+        assert_eq!(player.next_delay(), Some(Duration::from_millis(500)));
+        player.next_frame().unwrap();
+        // Includes delay of the resize entry.
+        assert_eq!(player.next_delay(), Some(Duration::from_millis(1500)));
+        player.next_frame().unwrap();
+        assert_eq!(player.next_delay(), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn speed() {
+        let mut player = open();
+        player.set_speed(2.0);
+        assert_eq!(player.next_delay(), Some(Duration::from_millis(250)));
+        player.next_frame().unwrap();
+        assert_eq!(player.next_delay(), Some(Duration::from_millis(750)));
+    }
+
+    #[test]
+    fn max_delay() {
+        let mut player = open();
+        player.set_max_delay(Some(Duration::from_secs(1)));
+        player.seek_frame(2);
+        assert_eq!(player.next_delay(), Some(Duration::from_secs(1)));
+
+        player.set_speed(4.0);
+        assert_eq!(player.next_delay(), Some(Duration::from_millis(750)));
+    }
+
+    #[test]
+    fn seek() {
+        let mut player = open();
+
+        player.seek(Duration::from_secs(1));
+        assert_eq!(player.frame_position(), 1);
+        assert_eq!(player.position(), Duration::from_secs(1));
+        assert_eq!(player.next_delay(), Some(Duration::from_secs(1)));
+        assert_eq!(frame(&mut player).1, b"defg".to_vec());
+
+        // Frame produced exactly at seek time is not skipped.
+        player.seek(Duration::from_secs(2));
+        assert_eq!(player.frame_position(), 1);
+        assert_eq!(player.next_delay(), Some(Duration::ZERO));
+
+        player.seek(Duration::from_secs(60));
+        assert!(player.is_finished());
+        assert_eq!(player.position(), player.duration());
+
+        player.seek(Duration::ZERO);
+        assert_eq!(frame(&mut player).1, b"abc".to_vec());
+    }
+
+    #[test]
+    fn seek_frame() {
+        let mut player = open();
+
+        player.seek_frame(2);
+        assert_eq!(player.position(), Duration::from_secs(2));
+        assert_eq!(frame(&mut player).1, b"hi".to_vec());
+
+        player.seek_frame(10);
+        assert_eq!(player.frame_position(), 3);
+        assert!(player.is_finished());
+    }
+
+    #[test]
+    fn short_recording() {
+        let mut player = Player::open(Cursor::new(b"abcde".to_vec()), TIMING.as_bytes()).unwrap();
+        player.next_frame().unwrap();
+        let err = player.next_frame().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn play() {
+        let mut player = open();
+        player.set_max_delay(Some(Duration::ZERO));
+        let mut output = Vec::new();
+        player.play(&mut output).unwrap();
+        assert_eq!(output, b"abcdefghi");
+    }
+}
@@ -0,0 +1,157 @@
+use rustix::io::Errno;
+use rustix::process::{self, Pid, Signal};
+use std::collections::HashSet;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// How many times to look for new descendants, which could be forked while
+/// we were signaling previous ones.
+const MAX_PASSES: usize = 5;
+
+/// Signals whole process tree of command (--kill-tree), including processes
+/// that left its process group or session. Descendants are found via /proc,
+/// and if command runs in cgroup (--cgroup), all processes of the cgroup are
+/// signaled too, even if they were reparented to init. Linux only.
+pub struct KillTree {
+    cgroup_procs: Option<CString>,
+}
+
+impl KillTree {
+    /// `cgroup_procs` is path to "cgroup.procs" of command's cgroup.
+    pub fn new(cgroup_procs: Option<CString>) -> Self {
+        KillTree { cgroup_procs }
+    }
+
+    /// Send signal to command's process group using `kill_group`, and to
+    /// given children of ours and all their descendants that aren't in that
+    /// group. `kill_group` returns the group, or None if it wasn't signaled.
+    /// Descendants are listed before signaling the group, otherwise those
+    /// whose parent dies would be reparented before we find them.
+    pub fn kill<F>(&self, children: &[Pid], sig: Signal, kill_group: F) -> io::Result<()>
+    where
+        F: FnOnce() -> Option<Pid>,
+    {
+        let pending = self.list(children);
+        let group = kill_group();
+        let mut pending = pending?;
+
+        // Processes may fork while we're signaling their parents, so repeat
+        // until no new ones are found.
+        let mut signaled = HashSet::new();
+        for _ in 0..MAX_PASSES {
+            let mut found = false;
+            for pid in pending {
+                if !signaled.insert(pid) {
+                    continue;
+                }
+                found = true;
+                if group.is_some() && process::getpgid(Some(pid)).ok() == group {
+                    continue;
+                }
+                match process::kill_process(pid, sig) {
+                    // Process exited meanwhile.
+                    Ok(()) | Err(Errno::SRCH) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            if !found {
+                break;
+            }
+            pending = self.list(children)?;
+        }
+
+        Ok(())
+    }
+
+    /// List processes of the tree, parents before children.
+    /// Children that aren't ours anymore are skipped, e.g. if command was
+    /// reaped and its PID was reused.
+    fn list(&self, children: &[Pid]) -> io::Result<Vec<Pid>> {
+        let procs = list_processes()?;
+        let self_pid = process::getpid();
+
+        let mut roots: Vec<Pid> = children
+            .iter()
+            .copied()
+            .filter(|pid| procs.contains(&(*pid, self_pid)))
+            .collect();
+        if let Some(procs_path) = &self.cgroup_procs {
+            roots.extend(read_pids(Path::new(OsStr::from_bytes(
+                procs_path.as_bytes(),
+            )))?);
+        }
+
+        Ok(descendants(&procs, &roots))
+    }
+}
+
+/// List all processes, as PID and parent PID, including zombies.
+pub fn list_processes() -> io::Result<Vec<(Pid, Pid)>> {
+    let mut procs = Vec::new();
+
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse().ok())
+            .and_then(Pid::from_raw)
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // Process may exit while we're reading.
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // Parent of init and kernel threads is 0.
+        if let Some(parent) = parent_pid(&stat).and_then(Pid::from_raw) {
+            procs.push((pid, parent));
+        }
+    }
+
+    Ok(procs)
+}
+
+/// Get given processes and all their descendants, parents before children.
+fn descendants(procs: &[(Pid, Pid)], roots: &[Pid]) -> Vec<Pid> {
+    let mut result: Vec<Pid> = Vec::new();
+    for &root in roots {
+        if !result.contains(&root) {
+            result.push(root);
+        }
+    }
+
+    let mut next = 0;
+    while next < result.len() {
+        let parent = result[next];
+        for &(pid, _) in procs.iter().filter(|&&(_, ppid)| ppid == parent) {
+            if !result.contains(&pid) {
+                result.push(pid);
+            }
+        }
+        next += 1;
+    }
+
+    result
+}
+
+/// Get parent PID from contents of /proc/<pid>/stat:
+/// "PID (COMM) STATE PPID ...", where COMM may contain anything.
+fn parent_pid(stat: &str) -> Option<i32> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Read PIDs from "cgroup.procs".
+fn read_pids(path: &Path) -> io::Result<Vec<Pid>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .filter_map(Pid::from_raw)
+        .collect())
+}
@@ -0,0 +1,190 @@
+use crate::catalog;
+use crate::rlimit;
+use chrono::{DateTime, Local, TimeDelta};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Total size of recordings to keep (--keep-total-size).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TotalSize(pub u64);
+
+impl FromStr for TotalSize {
+    type Err = String;
+
+    /// Parse size with optional K, M, G, or T suffix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match rlimit::parse_limit(s)? {
+            Some(n) => Ok(TotalSize(n)),
+            None => Err("size can't be unlimited".into()),
+        }
+    }
+}
+
+/// Which recordings to keep; others are removed.
+pub struct Retention {
+    /// Keep recordings not older than this many days.
+    pub keep_days: Option<u64>,
+    /// Keep newest recordings that fit into this size.
+    pub keep_total_size: Option<TotalSize>,
+}
+
+/// Files of one recording, removed together.
+struct Recording {
+    files: Vec<PathBuf>,
+    time: DateTime<Local>,
+    size: u64,
+}
+
+/// Remove recordings in directory (found like `reclog list` does), that
+/// don't match retention policy. Files in `exclude` are never removed.
+/// Returns removed files; with `dry_run`, files are only reported.
+pub fn prune(
+    dir: &Path,
+    retention: &Retention,
+    exclude: &[PathBuf],
+    dry_run: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let exclude: Vec<PathBuf> = exclude
+        .iter()
+        .filter_map(|path| fs::canonicalize(path).ok())
+        .collect();
+
+    let mut recordings = collect_recordings(dir)?;
+    recordings.retain(|rec| {
+        !rec.files
+            .iter()
+            .any(|path| fs::canonicalize(path).is_ok_and(|path| exclude.contains(&path)))
+    });
+    // Newest first.
+    recordings.sort_by_key(|rec| std::cmp::Reverse(rec.time));
+
+    let min_time = retention
+        .keep_days
+        .and_then(|days| min_time(Local::now(), days));
+    let mut total_size = 0;
+    let mut removed = Vec::new();
+
+    for rec in recordings {
+        total_size += rec.size;
+        let expired = min_time.is_some_and(|min_time| rec.time < min_time);
+        let oversized = retention
+            .keep_total_size
+            .is_some_and(|TotalSize(max_size)| total_size > max_size);
+        if !expired && !oversized {
+            continue;
+        }
+        for path in rec.files {
+            if !dry_run {
+                match fs::remove_file(&path) {
+                    // Already removed with another recording.
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => {}
+                }
+            }
+            removed.push(path);
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Oldest time of recordings kept by --keep-days.
+/// If it's before the earliest representable time, nothing is expired
+/// and None is returned.
+fn min_time(now: DateTime<Local>, keep_days: u64) -> Option<DateTime<Local>> {
+    let days = TimeDelta::try_days(keep_days.try_into().ok()?)?;
+    now.checked_sub_signed(days)
+}
+
+/// Group files of sessions into recordings: meta file (with all its
+/// sessions), log file, and its signature.
+fn collect_recordings(dir: &Path) -> io::Result<Vec<Recording>> {
+    let dir_path = fs::canonicalize(dir)?;
+    let mut recordings: BTreeMap<PathBuf, Recording> = BTreeMap::new();
+
+    for session in catalog::scan(dir)? {
+        let rec = recordings
+            .entry(session.path.clone())
+            .or_insert_with(|| Recording {
+                files: vec![session.path.clone()],
+                time: DateTime::<Local>::MIN_UTC.into(),
+                size: 0,
+            });
+
+        // Log file described by meta file is removed with it, but only if
+        // it's in the same directory.
+        if let Some(output) = session
+            .output
+            .as_ref()
+            .filter(|path| **path != session.path)
+        {
+            let output = dir.join(output);
+            let in_dir = fs::canonicalize(&output)
+                .is_ok_and(|path| path.parent() == Some(dir_path.as_path()));
+            if in_dir && !rec.files.contains(&output) {
+                rec.files.push(output);
+            }
+        }
+
+        // Recording in progress or interrupted has no end time, use time of
+        // last modification instead.
+        let time = match session.end_time {
+            Some(time) => time.with_timezone(&Local),
+            None => fs::metadata(&session.path)?.modified()?.into(),
+        };
+        rec.time = rec.time.max(time);
+    }
+
+    for rec in recordings.values_mut() {
+        let signatures: Vec<PathBuf> = rec
+            .files
+            .iter()
+            .map(|path| PathBuf::from(format!("{}.sig", path.to_string_lossy())))
+            .filter(|path| path.is_file())
+            .collect();
+        rec.files.extend(signatures);
+        rec.size = rec
+            .files
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|meta| meta.len())
+            .sum();
+    }
+
+    Ok(recordings.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_days() {
+        let now = Local::now();
+        assert_eq!(min_time(now, 0), Some(now));
+        assert_eq!(min_time(now, 2), Some(now - TimeDelta::days(2)));
+    }
+
+    #[test]
+    fn keep_days_overflow() {
+        let now = Local::now();
+        assert_eq!(min_time(now, 1_000_000_000), None);
+        assert_eq!(min_time(now, u64::MAX), None);
+
+        // Huge retention keeps everything instead of panicking.
+        let dir = std::env::temp_dir().join(format!("reclog-prune-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("build.log"), "output\n").unwrap();
+        let retention = Retention {
+            keep_days: Some(1_000_000_000),
+            keep_total_size: None,
+        };
+        let removed = prune(&dir, &retention, &[], false).unwrap();
+        assert!(removed.is_empty());
+        assert!(dir.join("build.log").exists());
+        _ = fs::remove_dir_all(&dir);
+    }
+}
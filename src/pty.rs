@@ -1,18 +1,21 @@
+use crate::child::{self, Child, ChildProc, ChildSetup, ChildWait};
 use crate::error::SysError;
-use crate::shim::{self, Fork};
+use crate::shim;
 use crate::signal;
-use crate::status::*;
 use crate::term::{self, TtyMode};
 use exec::Command;
 use rustix::fs::{self, Mode, OFlags};
-use rustix::io::{self, Errno, retry_on_intr};
-use rustix::process::{self, Pid, Signal, WaitOptions, WaitStatus};
+use rustix::io::{self, retry_on_intr};
+use rustix::process::{self, Pid, Signal, WaitStatus};
 use rustix::pty::{self, OpenptFlags};
 use rustix::stdio;
-use std::os::fd::{OwnedFd, RawFd};
+use rustix::termios::LocalModes;
+use std::io::{Error, Write};
+use std::os::fd::OwnedFd;
 use std::path::Path;
 use std::sync::Mutex;
-use sysconf::raw::{SysconfVariable, sysconf};
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::time::Instant;
 
 /// Allows to create PTY pair and spawn child process.
 /// I haven't found existing create for PTY that would allow keeping slave_fd
@@ -20,21 +23,12 @@ use sysconf::raw::{SysconfVariable, sysconf};
 /// exits (to avoid EIO). Hence we have our own implementation.
 pub struct PtyProc {
     master_fd: OwnedFd,
-    slave_fd: OwnedFd,
-    child: Mutex<Child>,
-}
-
-struct Child {
-    pid: Option<Pid>,
-    last_status: Option<WaitStatus>,
-    final_status: Option<WaitStatus>,
-}
-
-/// Wait mode.
-#[derive(PartialEq)]
-pub enum PtyWait {
-    Hang,
-    NoHang,
+    slave_fd: Mutex<Option<OwnedFd>>,
+    child: Child,
+    reserved_rows: AtomicU16,
+    child_echo: AtomicBool,
+    initial_modes: Mutex<Option<LocalModes>>,
+    resized: Mutex<Option<Instant>>,
 }
 
 impl PtyProc {
@@ -73,187 +67,222 @@ impl PtyProc {
 
         Ok(PtyProc {
             master_fd,
-            slave_fd,
-            child: Mutex::new(Child {
-                pid: None,
-                last_status: None,
-                final_status: None,
-            }),
+            slave_fd: Mutex::new(Some(slave_fd)),
+            child: Child::new(),
+            reserved_rows: AtomicU16::new(0),
+            child_echo: AtomicBool::new(false),
+            initial_modes: Mutex::new(None),
+            resized: Mutex::new(None),
         })
     }
 
-    /// Duplicate master fd.
-    pub fn dup_master(&self) -> Result<OwnedFd, SysError> {
-        retry_on_intr(|| io::dup(&self.master_fd)).map_err(|err| SysError("dup()", err))
-    }
+    fn prepare_parent(&self) -> Result<(), SysError> {
+        // Kernel will update slave pty as well.
+        // By default, input is echoed by our tty, not by child's one.
+        let mode = if self.child_echo.load(Ordering::Relaxed) {
+            TtyMode::CanonEcho
+        } else {
+            TtyMode::CanonNoEcho
+        };
+        term::set_tty_mode(&self.master_fd, mode)?;
+        *self.initial_modes.lock().unwrap() = Some(term::get_local_modes(&self.master_fd)?);
+
+        if term::is_tty(stdio::stdout()) {
+            let reserved_rows = self.reserved_rows.load(Ordering::Relaxed);
+            term::copy_tty_size(&self.master_fd, stdio::stdout(), reserved_rows)?;
+        }
 
-    /// Duplicate slave fd.
-    pub fn dup_slave(&self) -> Result<OwnedFd, SysError> {
-        retry_on_intr(|| io::dup(&self.slave_fd)).map_err(|err| SysError("dup()", err))
+        Ok(())
     }
 
-    /// Fork child process, attach to pty slave, and exec command.
-    pub fn spawn_child(&self, command: &mut Command) -> Result<(), SysError> {
-        let mut locked_child = self.child.lock().unwrap();
+    fn prepare_child(&self, slave_fd: &OwnedFd) -> Result<(), SysError> {
+        // restore signal dispositions and mask
+        signal::init_child_signals()?;
 
-        if locked_child.pid.is_some() {
-            panic!("attempt to call spawn_child() twice");
-        }
+        // create new session and become session leader
+        child::new_session()?;
 
-        self.prepare_parent()?;
+        // set pty slave as controlling terminal
+        if let Err(err) = retry_on_intr(|| process::ioctl_tiocsctty(slave_fd)) {
+            return Err(SysError("ioctl(TIOCSCTTY)", err));
+        }
 
-        // SAFETY: we ensure that the child process does not run any code other
-        // than setup code from prepare_child() followed by exec(). Parent
-        // continues execution normally.
-        unsafe {
-            match shim::fork() {
-                Ok(Fork::Parent(pid)) => {
-                    locked_child.pid = Some(pid);
-                }
-                Ok(Fork::Child) => {
-                    // In case of error, use fast_exit() to avoid execution
-                    // of any registered exit handlers.
-                    if let Err(_) = self.prepare_child() {
-                        shim::fast_exit(EXIT_FAILURE);
-                    }
-
-                    // This will call execvp() and replace child process.
-                    _ = command.exec();
-                    shim::fast_exit(EXIT_COMMAND_FAILED);
-                }
-                Err(err) => {
-                    return Err(SysError("fork()", err));
-                }
+        // redirect stdin/stdout/stderr to pty slave
+        for dup_fn in &[
+            stdio::dup2_stdin::<&OwnedFd>,
+            stdio::dup2_stdout::<&OwnedFd>,
+            stdio::dup2_stderr::<&OwnedFd>,
+        ] {
+            if let Err(err) = retry_on_intr(|| dup_fn(slave_fd)) {
+                return Err(SysError("dup2()", err));
             }
-        };
+        }
 
-        Ok(())
+        // close file descriptors except stdin/stdout/stderr
+        child::close_extra_fds()
+    }
+}
+
+impl ChildProc for PtyProc {
+    /// Duplicate master fd.
+    fn dup_input(&self) -> Result<OwnedFd, SysError> {
+        retry_on_intr(|| io::dup(&self.master_fd)).map_err(|err| SysError("dup()", err))
     }
 
-    /// Resize pty according to current parent's tty.
-    pub fn resize_child(&self) -> Result<(), SysError> {
-        let _locked_child = self.child.lock().unwrap();
+    /// Duplicate master fd.
+    fn dup_output(&self) -> Result<OwnedFd, SysError> {
+        retry_on_intr(|| io::dup(&self.master_fd)).map_err(|err| SysError("dup()", err))
+    }
 
-        if term::is_tty(stdio::stdout()) {
-            // Kernel will update slave pty and send SIGWINCH to child process.
-            term::copy_tty_size(&self.master_fd, stdio::stdout())?;
+    /// Write VEOF to master pty.
+    /// In canonical mode, slave pty translates this symbol to end-of-file
+    /// condition after all pending input is read.
+    fn send_eof(&self, input_writer: &mut dyn Write) -> Result<(), Error> {
+        let tty_codes = match self.slave_fd.lock().unwrap().as_ref() {
+            Some(slave_fd) => term::get_tty_codes(slave_fd),
+            None => term::get_tty_codes(&self.master_fd),
         }
+        .map_err(Error::other)?;
 
-        Ok(())
+        let mut buf = [0u8; 4];
+        input_writer.write_all(tty_codes.VEOF.encode_utf8(&mut buf).as_bytes())?;
+        input_writer.flush()
     }
 
-    /// Send signal to child's process group.
-    pub fn kill_child(&self, sig: Signal) -> Result<(), SysError> {
-        let locked_child = self.child.lock().unwrap();
+    fn spawn_child(&self, command: &mut Command, setup: &ChildSetup) -> Result<(), SysError> {
+        let locked_slave = self.slave_fd.lock().unwrap();
 
-        if !locked_child.pid.is_some() {
-            panic!("attempt to call kill_child() before spawn_child()");
-        }
-        if locked_child.final_status.is_some() {
-            panic!("attempt to call kill_child() after wait_child()");
-        }
+        let slave_fd = match locked_slave.as_ref() {
+            Some(fd) => fd,
+            None => panic!("attempt to call spawn_child() after release_child_side()"),
+        };
 
-        if let Err(err) = process::kill_process_group(locked_child.pid.unwrap(), sig) {
-            return Err(SysError("kill()", err));
-        }
+        self.prepare_parent()?;
+        self.child
+            .spawn(command, setup, || self.prepare_child(slave_fd))
+    }
 
+    /// Close our copy of slave fd.
+    /// After that, reading from master returns EIO when all processes
+    /// close their slave fds.
+    fn release_child_side(&self) -> Result<(), SysError> {
+        *self.slave_fd.lock().unwrap() = None;
         Ok(())
     }
 
-    /// Wait until spawned child exits.
-    pub fn wait_child(&self, wait_mode: PtyWait) -> Result<Option<WaitStatus>, SysError> {
-        let mut locked_child = self.child.lock().unwrap();
+    fn resize_child(&self) -> Result<(), SysError> {
+        self.child.with_lock(|| {
+            if term::is_tty(stdio::stdout()) {
+                // Kernel will update slave pty and send SIGWINCH to child process.
+                let reserved_rows = self.reserved_rows.load(Ordering::Relaxed);
+                term::copy_tty_size(&self.master_fd, stdio::stdout(), reserved_rows)?;
+                *self.resized.lock().unwrap() = Some(Instant::now());
+            }
+            Ok(())
+        })
+    }
 
-        if !locked_child.pid.is_some() {
-            panic!("attempt to call wait_child() before spawn_child()");
-        }
-        if let Some(final_status) = locked_child.final_status {
-            return Ok(Some(final_status));
-        }
+    fn set_reserved_rows(&self, rows: u16) {
+        self.reserved_rows.store(rows, Ordering::Relaxed);
+    }
 
-        let mut wait_opts = WaitOptions::UNTRACED | WaitOptions::CONTINUED;
-        if wait_mode == PtyWait::NoHang {
-            wait_opts |= WaitOptions::NOHANG;
-        }
+    fn set_child_echo(&self, echo: bool) {
+        self.child_echo.store(echo, Ordering::Relaxed);
+    }
 
-        loop {
-            let wait_status = match process::waitpid(locked_child.pid, wait_opts) {
-                Ok(Some((_, status))) => status,
-                Ok(None) => return Ok(None),
-                Err(Errno::INTR) => continue,
-                Err(err) => return Err(SysError("waitpid()", err)),
-            };
-
-            locked_child.last_status = Some(wait_status);
-            if wait_status.exited() || wait_status.signaled() {
-                locked_child.final_status = Some(wait_status);
-            }
-            return Ok(Some(wait_status));
+    /// Compare local modes of slave pty with ones it had when command was
+    /// spawned.
+    fn input_hidden(&self) -> bool {
+        let Some(initial_modes) = *self.initial_modes.lock().unwrap() else {
+            return false;
+        };
+        match term::get_local_modes(&self.master_fd) {
+            Ok(modes) => term::input_hidden(initial_modes, modes),
+            Err(_) => false,
         }
     }
 
-    /// Get child exit status.
-    pub fn child_status(&self) -> WaitStatus {
-        let locked_child = self.child.lock().unwrap();
-
-        if !locked_child.last_status.is_some() {
-            panic!("attempt to call child_status() before wait_child()");
-        }
+    /// Size is unknown if it was never set, e.g. when our stdout is not
+    /// a tty.
+    fn tty_size(&self) -> Option<(u16, u16)> {
+        term::tty_size(&self.master_fd)
+            .ok()
+            .filter(|&(cols, rows)| cols != 0 || rows != 0)
+    }
 
-        locked_child.last_status.unwrap()
+    fn take_resize(&self) -> Option<(Instant, u16, u16)> {
+        let ts = self.resized.lock().unwrap().take()?;
+        let (cols, rows) = self.tty_size()?;
+        Some((ts, cols, rows))
     }
 
-    fn prepare_parent(&self) -> Result<(), SysError> {
-        // Kernel will update slave pty as well.
-        term::set_tty_mode(&self.master_fd, TtyMode::CanonNoEcho)?;
+    fn kill_child(&self, sig: Signal) -> Result<(), SysError> {
+        self.child.kill(sig)
+    }
 
-        if term::is_tty(stdio::stdout()) {
-            term::copy_tty_size(&self.master_fd, stdio::stdout())?;
-        }
+    fn wait_child(&self, wait_mode: ChildWait) -> Result<Option<WaitStatus>, SysError> {
+        self.child.wait(wait_mode)
+    }
 
-        Ok(())
+    fn child_status(&self) -> WaitStatus {
+        self.child.status()
     }
 
-    fn prepare_child(&self) -> Result<(), SysError> {
-        // restore signal dispositions and mask
-        signal::init_child_signals()?;
+    fn child_pid(&self) -> Option<Pid> {
+        self.child.pid()
+    }
+}
 
-        // create new session and become session leader
-        if let Err(err) = retry_on_intr(|| process::setsid()) {
-            return Err(SysError("setsid()", err));
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustix::termios::{self, OptionalActions};
+
+    /// Change local modes of slave pty, like command does.
+    fn update_modes(pty: &PtyProc, f: impl FnOnce(&mut LocalModes)) {
+        let locked_slave = pty.slave_fd.lock().unwrap();
+        let slave_fd = locked_slave.as_ref().unwrap();
+        let mut term = termios::tcgetattr(slave_fd).unwrap();
+        f(&mut term.local_modes);
+        termios::tcsetattr(slave_fd, OptionalActions::Now, &term).unwrap();
+    }
 
-        // set pty slave as controlling terminal
-        if let Err(err) = retry_on_intr(|| process::ioctl_tiocsctty(&self.slave_fd)) {
-            return Err(SysError("ioctl(TIOCSCTTY)", err));
-        }
+    fn open_pty(echo: bool) -> PtyProc {
+        let pty = PtyProc::open().unwrap();
+        pty.set_child_echo(echo);
+        pty.prepare_parent().unwrap();
+        pty
+    }
 
-        // redirect stdin/stdout/stderr to pty slave
-        for dup_fn in &[
-            stdio::dup2_stdin::<&OwnedFd>,
-            stdio::dup2_stdout::<&OwnedFd>,
-            stdio::dup2_stderr::<&OwnedFd>,
-        ] {
-            if let Err(err) = retry_on_intr(|| dup_fn(&self.slave_fd)) {
-                return Err(SysError("dup2()", err));
-            }
+    #[test]
+    fn input_hidden() {
+        for echo in [true, false] {
+            let pty = open_pty(echo);
+            assert!(!pty.input_hidden());
+
+            // getpass(3)
+            update_modes(&pty, |modes| *modes -= LocalModes::ECHO | LocalModes::ISIG);
+            assert!(pty.input_hidden());
+            update_modes(&pty, |modes| *modes |= LocalModes::ISIG);
+            assert_eq!(pty.input_hidden(), echo);
+            update_modes(&pty, |modes| modes.set(LocalModes::ECHO, echo));
+            assert!(!pty.input_hidden());
+
+            // sudo(8)
+            let hidden = LocalModes::ECHO | LocalModes::ECHOE | LocalModes::ECHOK;
+            update_modes(&pty, |modes| *modes -= hidden);
+            assert!(pty.input_hidden());
+            update_modes(&pty, |modes| *modes |= hidden);
+            update_modes(&pty, |modes| modes.set(LocalModes::ECHO, echo));
+            assert!(!pty.input_hidden());
         }
+    }
 
-        // close file descriptors except stdin/stdout/stderr
-        let max_fd = match sysconf(SysconfVariable::ScOpenMax) {
-            Ok(n) => n,
-            Err(_) => return Err(SysError("sysconf(_SC_OPEN_MAX)", Errno::NOTSUP)),
-        };
-        unsafe {
-            for fd in 3..=max_fd {
-                // SAFETY: this breaks invariants of opened OwnedFd, BorrowFd, etc.
-                // However, we call this function right before exec(), in the context
-                // where we guaranteedely have only one thread (after forking), so
-                // these broken invariants don't have a chance to have any effect.
-                shim::close_raw(fd as RawFd);
-            }
-        };
-
-        Ok(())
+    #[test]
+    fn input_hidden_raw() {
+        let pty = open_pty(false);
+        let raw = LocalModes::ICANON | LocalModes::ECHO | LocalModes::ISIG | LocalModes::ECHOK;
+        update_modes(&pty, |modes| *modes -= raw);
+        assert!(!pty.input_hidden());
     }
 }
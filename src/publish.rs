@@ -0,0 +1,145 @@
+use crate::buffer::{BufferPool, BufferQueue};
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many buffers may wait for every client; when client is too slow,
+/// oldest buffers are dropped.
+const CLIENT_QUEUE_SIZE: usize = 1000;
+
+/// How long to wait for clients to receive pending data when recording
+/// is finished.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Streams recording to clients connected to unix socket (--publish).
+/// Every client gets its own bounded queue and thread, so that a slow
+/// client doesn't affect recording and other clients.
+pub struct Publisher {
+    path: PathBuf,
+    buf_pool: BufferPool,
+    clients: Arc<Mutex<ClientList>>,
+}
+
+struct ClientList {
+    queues: Vec<Arc<BufferQueue>>,
+    closed: bool,
+}
+
+impl Publisher {
+    /// Create socket and start accepting clients.
+    /// Stale socket left by a crashed reclog is replaced, but socket of
+    /// running one is not.
+    pub fn bind(path: &Path) -> io::Result<Self> {
+        let listener = match UnixListener::bind(path) {
+            Ok(listener) => listener,
+            Err(err) if err.kind() == ErrorKind::AddrInUse && is_stale_socket(path) => {
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            Err(err) => return Err(err),
+        };
+
+        let clients = Arc::new(Mutex::new(ClientList {
+            queues: Vec::new(),
+            closed: false,
+        }));
+
+        {
+            let clients = Arc::clone(&clients);
+            thread::Builder::new()
+                .name("publish_accept".to_string())
+                .spawn(move || accept_clients(listener, clients))?;
+        }
+
+        Ok(Publisher {
+            path: path.into(),
+            buf_pool: BufferPool::new(None),
+            clients,
+        })
+    }
+
+    /// Send data to all connected clients.
+    /// Never blocks.
+    pub fn publish(&self, data: &[u8]) {
+        let clients = self.clients.lock().unwrap();
+        for queue in &clients.queues {
+            let mut buf = self.buf_pool.alloc();
+            buf.extend_from_slice(data);
+            queue.write(buf);
+        }
+    }
+
+    /// Stop accepting clients, give connected clients some time to receive
+    /// pending data, and remove socket.
+    pub fn close(&self) {
+        let queues = {
+            let mut clients = self.clients.lock().unwrap();
+            clients.closed = true;
+            std::mem::take(&mut clients.queues)
+        };
+
+        for queue in &queues {
+            queue.close();
+        }
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        for queue in &queues {
+            _ = queue.wait_drained(deadline.saturating_duration_since(Instant::now()));
+        }
+
+        _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Check if there is a socket at path that nobody listens on.
+fn is_stale_socket(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket())
+        && UnixStream::connect(path).is_err_and(|err| err.kind() == ErrorKind::ConnectionRefused)
+}
+
+fn accept_clients(listener: UnixListener, clients: Arc<Mutex<ClientList>>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+
+        let queue = Arc::new(BufferQueue::new(CLIENT_QUEUE_SIZE));
+        {
+            let mut clients = clients.lock().unwrap();
+            if clients.closed {
+                return;
+            }
+            clients.queues.push(Arc::clone(&queue));
+        }
+
+        let clients = Arc::clone(&clients);
+        _ = thread::Builder::new()
+            .name("publish_client".to_string())
+            .spawn(move || {
+                serve_client(stream, &queue);
+                // Client went away, stop queueing data for it.
+                queue.close();
+                clients
+                    .lock()
+                    .unwrap()
+                    .queues
+                    .retain(|q| !Arc::ptr_eq(q, &queue));
+                // Drain remaining buffers, if any.
+                while queue.read().is_some() {}
+            });
+    }
+}
+
+/// Write buffers from queue to client until queue is closed and drained,
+/// or client disconnects.
+fn serve_client(mut stream: UnixStream, queue: &BufferQueue) {
+    while let Some(buf) = queue.read() {
+        if stream.write_all(&buf).is_err() {
+            return;
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use crate::error::SysError;
-use crate::shim::{self, SelectFd};
+use crate::shim::{self, WaitFd};
+use clap::ValueEnum;
 use rustix::io::{Errno, retry_on_intr};
 use rustix::pipe;
 use std::io::{Error, Read};
 use std::os::fd::{AsFd, OwnedFd};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(PartialEq)]
 enum ReaderMode {
@@ -14,10 +15,28 @@ enum ReaderMode {
     Closed,
 }
 
+/// Why reader reported end of stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReaderEnd {
+    Eof,     // Got EOF from fd
+    Timeout, // Read timeout expired
+    Closed,  // Reader was closed
+}
+
+/// When to consider child's output finished after child exits.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum EofPolicy {
+    Eio,
+    Timeout,
+    Both,
+}
+
 /// Allows to read from fd in one thread and interrupt read or change
 /// read timeout from another thread.
 pub struct InterruptibleReader<Fd: AsFd> {
     mode: Mutex<ReaderMode>,
+    end: Mutex<Option<ReaderEnd>>,
     fd: Fd,
     pipe_rd: OwnedFd,
     pipe_wr: OwnedFd,
@@ -38,6 +57,7 @@ impl<Fd: AsFd> InterruptibleReader<Fd> {
 
         Ok(InterruptibleReader {
             mode: Mutex::new(ReaderMode::NoTimeout),
+            end: Mutex::new(None),
             fd,
             pipe_rd,
             pipe_wr,
@@ -88,6 +108,44 @@ impl<Fd: AsFd> InterruptibleReader<Fd> {
         Ok(())
     }
 
+    /// Sleep for given duration, or until reader is closed.
+    /// Returns false if reader was closed.
+    pub fn pause(&self, duration: Duration) -> Result<bool, Error> {
+        let deadline = Instant::now() + duration;
+        loop {
+            if *self.mode.lock().unwrap() == ReaderMode::Closed {
+                return Ok(false);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(true);
+            }
+
+            // wait until woken up by set_timeout() or close(), or deadline
+            let mut pipe_fd = WaitFd {
+                fd: self.pipe_rd.as_fd(),
+                mask: WaitFd::READABLE,
+            };
+            shim::wait_fds(&mut [&mut pipe_fd], Some(deadline - now))?;
+
+            if pipe_fd.mask != 0 {
+                // drain bytes from pipe
+                _ = shim::read(&self.pipe_rd, &mut [0u8; 128]);
+            }
+        }
+    }
+
+    /// Get reason of the last end of stream reported by reader.
+    pub fn end_reason(&self) -> Option<ReaderEnd> {
+        *self.end.lock().unwrap()
+    }
+
+    /// Remember and report end of stream.
+    fn report_end(&self, reason: ReaderEnd) -> Result<usize, Error> {
+        *self.end.lock().unwrap() = Some(reason);
+        Ok(0)
+    }
+
     /// Construct blocking reader.
     /// Waits until there is *some* data, OR reader is closed, OR read timeout
     /// is set and expires.
@@ -97,6 +155,33 @@ impl<Fd: AsFd> InterruptibleReader<Fd> {
 
     /// Invoked by ArcTimeoutReader::read().
     fn read_imp(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.transfer_imp(|fd| shim::read(fd, buf))
+    }
+
+    /// Like reading from blocking_reader(), but moves data from fd to pipe
+    /// using splice(), without copying it to userspace.
+    /// Fails with EINVAL if fd doesn't support splice.
+    #[cfg(target_os = "linux")]
+    pub fn splice_to<PipeFd: AsFd>(&self, pipe_fd: PipeFd, len: usize) -> Result<usize, Error> {
+        self.transfer_imp(|fd| {
+            retry_on_intr(|| {
+                pipe::splice(
+                    fd,
+                    None,
+                    &pipe_fd,
+                    None,
+                    len,
+                    pipe::SpliceFlags::NONBLOCK | pipe::SpliceFlags::MOVE,
+                )
+            })
+        })
+    }
+
+    /// Wait until fd is readable and perform given transfer operation.
+    fn transfer_imp<F>(&self, mut transfer: F) -> Result<usize, Error>
+    where
+        F: FnMut(&Fd) -> Result<usize, Errno>,
+    {
         loop {
             // re-read mode
             let timeout = {
@@ -106,23 +191,23 @@ impl<Fd: AsFd> InterruptibleReader<Fd> {
                     ReaderMode::Timeout(d) => Some(d),
                     // read without timeout
                     ReaderMode::NoTimeout => None,
-                    // closeed, return EOF
+                    // closed, return EOF
                     ReaderMode::Closed => {
-                        return Ok(0);
+                        return self.report_end(ReaderEnd::Closed);
                     }
                 }
             };
 
             // wait until descriptor is ready or timeout expires
-            let mut pipe_fd = SelectFd {
+            let mut pipe_fd = WaitFd {
                 fd: self.pipe_rd.as_fd(),
-                mask: SelectFd::READABLE,
+                mask: WaitFd::READABLE,
             };
-            let mut data_fd = SelectFd {
+            let mut data_fd = WaitFd {
                 fd: self.fd.as_fd(),
-                mask: SelectFd::READABLE,
+                mask: WaitFd::READABLE,
             };
-            shim::select(&mut [&mut pipe_fd, &mut data_fd], timeout)?;
+            shim::wait_fds(&mut [&mut pipe_fd, &mut data_fd], timeout)?;
 
             if pipe_fd.mask != 0 {
                 // wake up from set_timeout() or close()
@@ -132,17 +217,17 @@ impl<Fd: AsFd> InterruptibleReader<Fd> {
 
             if data_fd.mask != 0 {
                 // file is readable
-                match shim::read(&self.fd, buf) {
-                    Ok(0) => return Ok(0), // EOF
+                match transfer(&self.fd) {
+                    Ok(0) => return self.report_end(ReaderEnd::Eof),
                     Ok(n) => return Ok(n),
                     Err(Errno::AGAIN) => continue,
-                    Err(err) => return Err(Error::new(err.kind(), err)),
+                    Err(err) => return Err(Error::from(err)),
                 }
             }
 
             if pipe_fd.mask == 0 && data_fd.mask == 0 && timeout.is_some() {
                 // timeout expired, return EOF
-                return Ok(0);
+                return self.report_end(ReaderEnd::Timeout);
             }
         }
     }
@@ -0,0 +1,61 @@
+use crate::proctree;
+use crate::shim;
+use rustix::io::Errno;
+use rustix::process::{self, Pid, WaitOptions};
+use std::io;
+
+/// Descendants of command that were reparented to us after their parent
+/// exited (--subreaper). Daemonized processes escape wait for command, but
+/// not for us as subreaper. Linux only.
+pub struct Reaper {
+    // Our children that existed before command and aren't its descendants,
+    // e.g. age(1) process of --encrypt.
+    foreign: Vec<Pid>,
+}
+
+impl Reaper {
+    /// Mark current process as subreaper.
+    /// Should be called before spawning command.
+    pub fn enable() -> io::Result<Self> {
+        shim::set_child_subreaper()?;
+        Ok(Reaper {
+            foreign: list_children()?,
+        })
+    }
+
+    /// Get running and exited descendants adopted by us.
+    /// `child_pid` is command itself, it's not included.
+    pub fn adopted(&self, child_pid: Pid) -> io::Result<Vec<Pid>> {
+        Ok(list_children()?
+            .into_iter()
+            .filter(|pid| *pid != child_pid && !self.foreign.contains(pid))
+            .collect())
+    }
+
+    /// Reap adopted descendants that exited, and return number of those that
+    /// are still running. `child_pid` is command itself, it's not touched.
+    pub fn reap(&self, child_pid: Pid) -> io::Result<usize> {
+        let mut running = 0;
+        for pid in self.adopted(child_pid)? {
+            match process::waitpid(Some(pid), WaitOptions::NOHANG) {
+                Ok(Some(_)) => {}
+                Ok(None) => running += 1,
+                // Reaped by someone else or not our child anymore.
+                Err(Errno::CHILD) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(running)
+    }
+}
+
+/// List PIDs of children of current process, including zombies.
+fn list_children() -> io::Result<Vec<Pid>> {
+    let self_pid = process::getpid();
+    Ok(proctree::list_processes()?
+        .into_iter()
+        .filter(|&(_, parent)| parent == self_pid)
+        .map(|(pid, _)| pid)
+        .collect())
+}
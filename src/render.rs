@@ -0,0 +1,165 @@
+use crate::clock::VirtualClock;
+use crate::format::{Formatter, TimeSource};
+use crate::term::AnsiStripper;
+use chrono::{DateTime, Local};
+use reclog::timing::{TimingEntry, TimingReader};
+use std::io::{self, BufRead, Error, ErrorKind, Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Render raw recording into stripped text, prepending each line with
+/// timestamp re-computed from the timing file.
+/// Timestamps are formatted by the formatter as if the lines were
+/// captured live.
+/// If `start_time` is set, clock starts from it and START_TIME entries of
+/// timing file are ignored (--virtual-time).
+pub fn render<R: Read, T: BufRead, W: Write>(
+    mut recording: R,
+    timing: T,
+    output: W,
+    fm: &mut Formatter,
+    start_time: Option<DateTime<Local>>,
+) -> io::Result<()> {
+    let mut timing_reader = TimingReader::new(timing);
+    let mut stripper = AnsiStripper::new(output);
+
+    // Virtual clock, advanced by delays from timing file.
+    let clock = Rc::new(VirtualClock::new());
+    fm.set_clock(clock.clone());
+    if let Some(start_time) = start_time {
+        clock.restart(start_time);
+    }
+
+    let mut chunk = Vec::new();
+    let mut prefix = String::new();
+    let mut line_start = true;
+
+    while let Some(entry) = timing_reader.read_entry()? {
+        let size = match entry {
+            TimingEntry::StartTime(ts) => {
+                // New session (e.g. recording was appended).
+                if start_time.is_none() {
+                    clock.restart(ts);
+                }
+                continue;
+            }
+            TimingEntry::Other { delay, .. } => {
+                advance(&clock, delay)?;
+                continue;
+            }
+            TimingEntry::Output(delay, size) => {
+                advance(&clock, delay)?;
+                size
+            }
+        };
+
+        chunk.resize(size, 0);
+        if let Err(err) = recording.read_exact(&mut chunk) {
+            if err.kind() == ErrorKind::UnexpectedEof {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "recording is shorter than timing file",
+                ));
+            }
+            return Err(err);
+        }
+
+        for line in chunk.split_inclusive(|&b| b == b'\n') {
+            if line_start {
+                if !clock.has_start_time() && fm.time_source() == TimeSource::Wall {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "timing file has no START_TIME, use --ts-src=elapsed or delta",
+                    ));
+                }
+
+                prefix.clear();
+                if let Err(err) = fm.format_timestamp(&mut prefix) {
+                    return Err(Error::new(ErrorKind::InvalidInput, err));
+                }
+                stripper.write_all(prefix.as_bytes())?;
+            }
+
+            stripper.write_all(line)?;
+            line_start = line.ends_with(b"\n");
+        }
+    }
+
+    stripper.flush()
+}
+
+fn advance(clock: &VirtualClock, delay: Duration) -> io::Result<()> {
+    if !clock.advance(delay) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "timing file delays exceed supported time range",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualTime;
+
+    fn run(recording: &str, timing: &str, time_source: TimeSource) -> io::Result<String> {
+        run_at(recording, timing, "%H:%M:%S", time_source, None)
+    }
+
+    fn run_at(
+        recording: &str,
+        timing: &str,
+        time_format: &str,
+        time_source: TimeSource,
+        start_time: Option<&str>,
+    ) -> io::Result<String> {
+        let mut fm = Formatter::new(false, false, true, time_format, time_source, &[]);
+        let start_time = start_time.map(|s| s.parse::<VirtualTime>().unwrap().0);
+        let mut output = Vec::new();
+        render(
+            recording.as_bytes(),
+            timing.as_bytes(),
+            &mut output,
+            &mut fm,
+            start_time,
+        )?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn elapsed() {
+        let output = run("a\nb\n", "O 1 2\nO 2.5 2\n", TimeSource::Elapsed).unwrap();
+        assert_eq!(output, "00:00:00a\n00:00:02b\n");
+    }
+
+    #[test]
+    fn wall() {
+        let timing = "H 0 START_TIME 2024-01-01 00:00:00 +0000\nO 1 2\nO 2.5 2\n";
+        let output = run_at("a\nb\n", timing, "%s ", TimeSource::Wall, None).unwrap();
+        assert_eq!(output, "1704067201 a\n1704067203 b\n");
+    }
+
+    #[test]
+    fn virtual_time() {
+        let timing = "H 0 START_TIME 2024-01-01 00:00:00 +0000\nO 1 2\n\
+                      H 0 START_TIME 2024-06-01 00:00:00 +0000\nO 2.5 2\n";
+        let start = Some("2000-01-01 00:00:00 +0100");
+        let output = run_at("a\nb\n", timing, "%s ", TimeSource::Wall, start).unwrap();
+        assert_eq!(output, "946681201 a\n946681203 b\n");
+
+        // Timing file without START_TIME.
+        let output = run_at("a\n", "O 1 2\n", "%s ", TimeSource::Wall, start).unwrap();
+        assert_eq!(output, "946681201 a\n");
+        assert!(run_at("a\n", "O 1 2\n", "%s ", TimeSource::Wall, None).is_err());
+    }
+
+    #[test]
+    fn huge_delay() {
+        for time_source in [TimeSource::Wall, TimeSource::Elapsed] {
+            let timing = "H 0 START_TIME 2024-01-01 00:00:00 +0000\nO 1e18 2\n";
+            let err = run("a\n", timing, time_source).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
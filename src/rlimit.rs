@@ -0,0 +1,106 @@
+use crate::error::SysError;
+use crate::shim;
+use std::str::FromStr;
+
+/// Names of supported resources, as in setrlimit(2) without "RLIMIT_".
+const RESOURCES: &[(&str, i32)] = &[
+    #[cfg(not(target_os = "openbsd"))]
+    ("as", libc::RLIMIT_AS as i32),
+    ("core", libc::RLIMIT_CORE as i32),
+    ("cpu", libc::RLIMIT_CPU as i32),
+    ("data", libc::RLIMIT_DATA as i32),
+    ("fsize", libc::RLIMIT_FSIZE as i32),
+    ("memlock", libc::RLIMIT_MEMLOCK as i32),
+    ("nofile", libc::RLIMIT_NOFILE as i32),
+    ("nproc", libc::RLIMIT_NPROC as i32),
+    ("stack", libc::RLIMIT_STACK as i32),
+];
+
+/// Resource limit of command (--rlimit).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResourceLimit {
+    resource: i32,
+    soft: Option<u64>,
+    hard: Option<u64>,
+}
+
+impl FromStr for ResourceLimit {
+    type Err = String;
+
+    /// Parse "NAME=SOFT[:HARD]", e.g. "core=0" or "nofile=1024:4096".
+    /// If HARD is omitted, both limits are set to SOFT.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = match s.split_once('=') {
+            Some(parts) => parts,
+            None => return Err("expected NAME=VALUE".into()),
+        };
+
+        let resource = match RESOURCES.iter().find(|(res_name, _)| *res_name == name) {
+            Some((_, resource)) => *resource,
+            None => {
+                let names: Vec<&str> = RESOURCES.iter().map(|(name, _)| *name).collect();
+                return Err(format!(
+                    "unknown resource '{}', expected one of: {}",
+                    name,
+                    names.join(", ")
+                ));
+            }
+        };
+
+        let (soft, hard) = match value.split_once(':') {
+            Some((soft, hard)) => (parse_limit(soft)?, parse_limit(hard)?),
+            None => (parse_limit(value)?, parse_limit(value)?),
+        };
+        // None is unlimited.
+        let exceeds = match (soft, hard) {
+            (Some(soft), Some(hard)) => soft > hard,
+            (None, Some(_)) => true,
+            _ => false,
+        };
+        if exceeds {
+            return Err(format!("soft limit of '{}' exceeds hard limit", name));
+        }
+
+        Ok(ResourceLimit {
+            resource,
+            soft,
+            hard,
+        })
+    }
+}
+
+impl ResourceLimit {
+    /// Set limit of current process.
+    pub fn apply(&self) -> Result<(), SysError> {
+        shim::setrlimit(self.resource, self.soft, self.hard)
+            .map_err(|err| SysError("setrlimit()", err))
+    }
+}
+
+/// Parse limit value: number with optional K, M, G, or T suffix (powers
+/// of 1024), or "unlimited" (returned as None).
+pub fn parse_limit(s: &str) -> Result<Option<u64>, String> {
+    if s == "unlimited" || s == "infinity" {
+        return Ok(None);
+    }
+
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((pos, 'k' | 'K')) => (&s[..pos], 1 << 10),
+        Some((pos, 'm' | 'M')) => (&s[..pos], 1 << 20),
+        Some((pos, 'g' | 'G')) => (&s[..pos], 1 << 30),
+        Some((pos, 't' | 'T')) => (&s[..pos], 1 << 40),
+        _ => (s, 1),
+    };
+
+    match digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(n) => Ok(Some(n)),
+        None => Err(format!(
+            "invalid limit '{}', expected number (with optional K, M, G, T suffix) or 'unlimited'",
+            s
+        )),
+    }
+}
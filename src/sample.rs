@@ -0,0 +1,219 @@
+use regex_lite::Regex;
+use std::str::FromStr;
+
+/// Sampling rule, parsed from "REGEX=1/N".
+/// Lines matching the regex are thinned out to every Nth line.
+#[derive(Clone, Debug)]
+pub struct SampleRule {
+    pattern: Regex,
+    every: u64,
+}
+
+impl FromStr for SampleRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (regex, ratio) = match s.rsplit_once('=') {
+            Some(parts) => parts,
+            None => return Err("expected REGEX=1/N".into()),
+        };
+        let every = match ratio.strip_prefix("1/").map(|n| n.parse::<u64>()) {
+            Some(Ok(n)) if n > 0 => n,
+            _ => return Err(format!("invalid ratio '{}', expected 1/N", ratio)),
+        };
+        if regex.is_empty() {
+            return Err("regex can't be empty".into());
+        }
+
+        Ok(SampleRule {
+            pattern: Regex::new(regex).map_err(|err| err.to_string())?,
+            every,
+        })
+    }
+}
+
+/// What to do with a line.
+#[derive(Debug, PartialEq)]
+pub enum SampleAction {
+    /// Line doesn't match any rule, keep it.
+    Keep,
+    /// Line matches a rule and is the next sample; number of
+    /// lines skipped since previous sample is reported.
+    Sample(u64),
+    /// Line matches a rule and should be dropped.
+    Skip,
+}
+
+/// Decides which lines to keep according to sampling rules.
+pub struct Sampler {
+    rules: Vec<SampleRule>,
+    counters: Vec<u64>,
+}
+
+impl Sampler {
+    pub fn new(rules: &[SampleRule]) -> Self {
+        Sampler {
+            rules: rules.to_vec(),
+            counters: vec![0; rules.len()],
+        }
+    }
+
+    /// True if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Check line against rules.
+    /// First matching rule wins. The first matching line is always kept,
+    /// then every Nth line is kept.
+    pub fn sample(&mut self, line: &str) -> SampleAction {
+        for (rule, counter) in self.rules.iter().zip(self.counters.iter_mut()) {
+            if !rule.pattern.is_match(line) {
+                continue;
+            }
+
+            let skipped = *counter % rule.every;
+            *counter += 1;
+
+            if skipped == 0 {
+                let total_skipped = if *counter == 1 { 0 } else { rule.every - 1 };
+                return SampleAction::Sample(total_skipped);
+            }
+            return SampleAction::Skip;
+        }
+
+        SampleAction::Keep
+    }
+
+    /// Get number of lines skipped after the last sample of every rule,
+    /// and start counting from scratch.
+    /// Called at end of stream, when no more samples will report them.
+    pub fn take_skipped(&mut self) -> Vec<u64> {
+        let mut skipped = Vec::new();
+        for (rule, counter) in self.rules.iter().zip(self.counters.iter_mut()) {
+            if *counter > 0 && (*counter - 1) % rule.every != 0 {
+                skipped.push((*counter - 1) % rule.every);
+            }
+            *counter = 0;
+        }
+        skipped
+    }
+}
+
+/// Append annotation to sampled line, before line terminator.
+pub fn annotate_line(line: &mut Vec<u8>, skipped: u64) {
+    if skipped == 0 {
+        return;
+    }
+
+    let body_len = line.len()
+        - line
+            .iter()
+            .rev()
+            .take_while(|&&b| b == b'\r' || b == b'\n')
+            .count();
+    let ending = line.split_off(body_len);
+
+    line.extend_from_slice(format!(" [sampled, {} similar lines skipped]", skipped).as_bytes());
+    line.extend_from_slice(&ending);
+}
+
+/// Append line reporting lines skipped at the end of stream.
+pub fn skipped_line(line: &mut Vec<u8>, skipped: u64) {
+    line.extend_from_slice(format!("[sampled, {} similar lines skipped]\n", skipped).as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampler(rules: &[&str]) -> Sampler {
+        let rules: Vec<SampleRule> = rules.iter().map(|r| r.parse().unwrap()).collect();
+        Sampler::new(&rules)
+    }
+
+    #[test]
+    fn parse_rule() {
+        assert!("^GET =1/10".parse::<SampleRule>().is_ok());
+        // Regex may contain "=", ratio is after the last one.
+        assert!("a=b=1/2".parse::<SampleRule>().is_ok());
+
+        assert!("GET".parse::<SampleRule>().is_err());
+        assert!("=1/10".parse::<SampleRule>().is_err());
+        assert!("GET=10".parse::<SampleRule>().is_err());
+        assert!("GET=1/0".parse::<SampleRule>().is_err());
+        assert!("GET=1/x".parse::<SampleRule>().is_err());
+        assert!("(GET=1/10".parse::<SampleRule>().is_err());
+    }
+
+    #[test]
+    fn every_nth() {
+        let mut sampler = sampler(&["^GET=1/3"]);
+
+        let actions: Vec<_> = (0..7).map(|_| sampler.sample("GET /")).collect();
+        assert_eq!(
+            actions,
+            [
+                SampleAction::Sample(0),
+                SampleAction::Skip,
+                SampleAction::Skip,
+                SampleAction::Sample(2),
+                SampleAction::Skip,
+                SampleAction::Skip,
+                SampleAction::Sample(2),
+            ]
+        );
+        assert_eq!(sampler.sample("POST /"), SampleAction::Keep);
+    }
+
+    #[test]
+    fn first_rule_wins() {
+        let mut sampler = sampler(&["GET=1/2", "GET|POST=1/5"]);
+
+        assert_eq!(sampler.sample("GET /"), SampleAction::Sample(0));
+        assert_eq!(sampler.sample("GET /"), SampleAction::Skip);
+        assert_eq!(sampler.sample("POST /"), SampleAction::Sample(0));
+        assert_eq!(sampler.sample("POST /"), SampleAction::Skip);
+        assert_eq!(sampler.sample("GET /"), SampleAction::Sample(1));
+    }
+
+    #[test]
+    fn skipped_at_end() {
+        let mut sampler = sampler(&["GET=1/3", "POST=1/3", "PUT=1/3"]);
+
+        // GET: sampled, then 2 skipped.
+        for _ in 0..3 {
+            sampler.sample("GET /");
+        }
+        // POST: sampled, then sampled again, nothing skipped after it.
+        for _ in 0..4 {
+            sampler.sample("POST /");
+        }
+        // PUT: never matched.
+
+        assert_eq!(sampler.take_skipped(), [2]);
+        assert_eq!(sampler.take_skipped(), Vec::<u64>::new());
+
+        // Counting starts from scratch.
+        assert_eq!(sampler.sample("GET /"), SampleAction::Sample(0));
+    }
+
+    #[test]
+    fn annotate() {
+        let mut line = b"GET /\r\n".to_vec();
+        annotate_line(&mut line, 5);
+        assert_eq!(line, b"GET / [sampled, 5 similar lines skipped]\r\n");
+
+        let mut line = b"GET /".to_vec();
+        annotate_line(&mut line, 5);
+        assert_eq!(line, b"GET / [sampled, 5 similar lines skipped]");
+
+        let mut line = b"GET /\n".to_vec();
+        annotate_line(&mut line, 0);
+        assert_eq!(line, b"GET /\n");
+
+        let mut line = Vec::new();
+        skipped_line(&mut line, 2);
+        assert_eq!(line, b"[sampled, 2 similar lines skipped]\n");
+    }
+}
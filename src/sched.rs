@@ -0,0 +1,110 @@
+use crate::error::SysError;
+use crate::shim;
+use std::str::FromStr;
+
+/// Scheduling priority of command (--nice).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Niceness(i32);
+
+impl FromStr for Niceness {
+    type Err = String;
+
+    /// Parse niceness, from -20 (highest priority) to 19 (lowest).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<i32>() {
+            Ok(n) if (-20..=19).contains(&n) => Ok(Niceness(n)),
+            _ => Err(format!("invalid niceness '{}', expected -20..19", s)),
+        }
+    }
+}
+
+impl Niceness {
+    /// Set niceness of current process.
+    pub fn apply(&self) -> Result<(), SysError> {
+        shim::setpriority(self.0).map_err(|err| SysError("setpriority()", err))
+    }
+}
+
+/// I/O scheduling class and priority of command (--ionice).
+/// Linux only.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoPriority {
+    class: u32,
+    level: u32,
+}
+
+/// Classes of I/O scheduling, as in ioprio_set(2).
+const IOPRIO_CLASSES: &[(&str, u32)] = &[
+    ("realtime", 1),
+    ("best-effort", 2),
+    ("idle", IOPRIO_CLASS_IDLE),
+];
+const IOPRIO_CLASS_IDLE: u32 = 3;
+
+impl FromStr for IoPriority {
+    type Err = String;
+
+    /// Parse "CLASS[:LEVEL]", e.g. "idle" or "best-effort:7".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (class_name, level) = match s.split_once(':') {
+            Some((class_name, level)) => (class_name, Some(level)),
+            None => (s, None),
+        };
+
+        let class = match IOPRIO_CLASSES.iter().find(|(name, _)| *name == class_name) {
+            Some((_, class)) => *class,
+            None => {
+                return Err(format!(
+                    "invalid class '{}', expected realtime, best-effort, or idle",
+                    class_name
+                ));
+            }
+        };
+        // Idle class doesn't have levels, and default level for other
+        // classes is 4, like in ionice(1).
+        let level = match level {
+            None if class == IOPRIO_CLASS_IDLE => 0,
+            None => 4,
+            Some(_) if class == IOPRIO_CLASS_IDLE => {
+                return Err("idle class doesn't have levels".into());
+            }
+            Some(level) => match level.parse::<u32>() {
+                Ok(level) if level <= 7 => level,
+                _ => return Err(format!("invalid level '{}', expected 0..7", level)),
+            },
+        };
+
+        Ok(IoPriority { class, level })
+    }
+}
+
+impl IoPriority {
+    /// Set I/O priority of current process.
+    pub fn apply(&self) -> Result<(), SysError> {
+        shim::ioprio_set(self.class, self.level).map_err(|err| SysError("ioprio_set()", err))
+    }
+}
+
+/// Adjustment of OOM killer score of command (--oom-score-adj).
+/// Linux only.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OomScoreAdj(i32);
+
+impl FromStr for OomScoreAdj {
+    type Err = String;
+
+    /// Parse adjustment, from -1000 (never kill) to 1000 (kill first).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<i32>() {
+            Ok(n) if (-1000..=1000).contains(&n) => Ok(OomScoreAdj(n)),
+            _ => Err(format!("invalid score '{}', expected -1000..1000", s)),
+        }
+    }
+}
+
+impl OomScoreAdj {
+    /// Set OOM score adjustment of current process.
+    pub fn apply(&self) -> Result<(), SysError> {
+        shim::set_oom_score_adj(self.0).map_err(|err| SysError("oom_score_adj", err))
+    }
+}
@@ -0,0 +1,72 @@
+use crate::signal;
+use chrono::{DateTime, Local};
+use rustix::process::Signal;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Lifecycle event of reclog itself, recorded to output file (--trace-self).
+pub enum TraceEvent {
+    /// Command was started.
+    Spawn { pid: u32 },
+    /// We received signal.
+    Signal(Signal),
+    /// We sent signal to command.
+    Kill(Signal),
+    /// Command exited, with human-readable status.
+    Exit(String),
+    /// Lines were dropped from stdout because it was too slow.
+    Drop(u64),
+    /// Command output finished, with reason (see StreamEnd).
+    Eof(&'static str),
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceEvent::Spawn { pid } => write!(f, "EVENT=[spawn] PID=[{}]", pid),
+            TraceEvent::Signal(sig) => {
+                write!(f, "EVENT=[signal] SIGNAL=[{}]", signal::display_name(*sig))
+            }
+            TraceEvent::Kill(sig) => {
+                write!(f, "EVENT=[kill] SIGNAL=[{}]", signal::display_name(*sig))
+            }
+            TraceEvent::Exit(status) => write!(f, "EVENT=[exit] STATUS=[{}]", status),
+            TraceEvent::Drop(lines) => write!(f, "EVENT=[drop] LINES=[{}]", lines),
+            TraceEvent::Eof(reason) => write!(f, "EVENT=[eof] REASON=[{}]", reason),
+        }
+    }
+}
+
+/// Whether events are recorded.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Recorded events that weren't written to output file yet.
+static PENDING: Mutex<Vec<(DateTime<Local>, TraceEvent)>> = Mutex::new(Vec::new());
+
+/// Start recording events.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Check if events are recorded.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record event with current time, if enabled.
+/// Can be called from any thread; events are written to output file by
+/// capture loop.
+pub fn record(event: TraceEvent) {
+    if is_enabled() {
+        PENDING.lock().unwrap().push((Local::now(), event));
+    }
+}
+
+/// Get events recorded since previous call.
+pub fn take() -> Vec<(DateTime<Local>, TraceEvent)> {
+    if !is_enabled() {
+        return Vec::new();
+    }
+    std::mem::take(&mut *PENDING.lock().unwrap())
+}
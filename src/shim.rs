@@ -4,7 +4,7 @@ use libc::{self, FD_ISSET, FD_SET, FD_ZERO};
 use rustix::io::Errno;
 use rustix::process::{Pid, Signal};
 use std::cmp::max;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::io::Error;
 use std::mem::{self, MaybeUninit};
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
@@ -17,20 +17,248 @@ fn last_errno() -> Errno {
     Errno::from_io_error(&Error::last_os_error()).unwrap()
 }
 
-pub struct SelectFd<'fd> {
+/// Descriptor and events to wait for, see wait_fds().
+pub struct WaitFd<'fd> {
     pub fd: BorrowedFd<'fd>,
     pub mask: u32,
 }
 
-impl SelectFd<'_> {
+impl WaitFd<'_> {
     pub const READABLE: u32 = 0x1;
     pub const WRITEABLE: u32 = 0x2;
     pub const EXCEPTION: u32 = 0x4;
 }
 
-/// Safe shim for libc::select().
-/// Handles EINTR.
-pub fn select(select_fds: &mut [&mut SelectFd], timeout: Option<Duration>) -> Result<(), Errno> {
+/// Wait until any of the descriptors is ready or timeout expires.
+/// On return, mask of every descriptor is replaced with events that
+/// are ready. Handles EINTR.
+///
+/// Backend is chosen per platform:
+///  - kqueue() on macOS and BSDs
+///  - select() on macOS if any of descriptors is a tty, because neither
+///    poll() nor kqueue() work with TTYs there
+///  - poll() on other platforms
+///
+/// Unlike select(), poll() and kqueue() work with descriptors >= FD_SETSIZE,
+/// which is important when reclog is embedded into processes with many
+/// open descriptors.
+pub fn wait_fds(wait_fds: &mut [&mut WaitFd], timeout: Option<Duration>) -> Result<(), Errno> {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    if wait_fds.iter().any(|w| rustix::termios::isatty(w.fd)) {
+        return select_fds(wait_fds, timeout);
+    }
+
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    return kqueue_fds(wait_fds, timeout);
+
+    #[cfg(not(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    )))]
+    return poll_fds(wait_fds, timeout);
+}
+
+/// wait_fds() backend using libc::poll().
+#[allow(dead_code)]
+fn poll_fds(wait_fds: &mut [&mut WaitFd], timeout: Option<Duration>) -> Result<(), Errno> {
+    // Round up, so that we don't wake up before timeout and spin.
+    let ms_timeout = match timeout {
+        Some(d) => d
+            .as_micros()
+            .div_ceil(1000)
+            .try_into()
+            .unwrap_or(libc::c_int::MAX),
+        None => -1,
+    };
+
+    let mut poll_fds: Vec<libc::pollfd> = wait_fds
+        .iter()
+        .map(|w| libc::pollfd {
+            fd: w.fd.as_raw_fd(),
+            events: {
+                let mut events = 0;
+                if w.mask & WaitFd::READABLE != 0 {
+                    events |= libc::POLLIN;
+                }
+                if w.mask & WaitFd::WRITEABLE != 0 {
+                    events |= libc::POLLOUT;
+                }
+                if w.mask & WaitFd::EXCEPTION != 0 {
+                    events |= libc::POLLPRI;
+                }
+                events
+            },
+            revents: 0,
+        })
+        .collect();
+
+    // SAFETY: We're holding an BorrowedFd (via WaitFd) for every descriptor
+    // during the call, so they're guaranteed to be valid, and pointer and
+    // length refer to a live vector.
+    //
+    // NOTE: We use libc::poll() instead of rustix::event::poll() to have
+    // same EINTR and timeout handling in all backends.
+    loop {
+        let ret = unsafe {
+            libc::poll(
+                poll_fds.as_mut_ptr(),
+                poll_fds.len() as libc::nfds_t,
+                ms_timeout,
+            )
+        };
+        if ret < 0 {
+            if last_errno() == Errno::INTR {
+                continue;
+            }
+            return Err(last_errno());
+        }
+        break;
+    }
+
+    for (w, p) in wait_fds.iter_mut().zip(&poll_fds) {
+        if p.revents & libc::POLLNVAL != 0 {
+            return Err(Errno::BADF);
+        }
+        // Like select(), report hangup and error as readiness, so that
+        // following read() or write() gets EOF or error.
+        let failed = p.revents & (libc::POLLHUP | libc::POLLERR) != 0;
+        let wanted = w.mask;
+        w.mask = 0;
+        if wanted & WaitFd::READABLE != 0 && (p.revents & libc::POLLIN != 0 || failed) {
+            w.mask |= WaitFd::READABLE;
+        }
+        if wanted & WaitFd::WRITEABLE != 0 && (p.revents & libc::POLLOUT != 0 || failed) {
+            w.mask |= WaitFd::WRITEABLE;
+        }
+        if p.revents & libc::POLLPRI != 0 {
+            w.mask |= WaitFd::EXCEPTION;
+        }
+    }
+
+    Ok(())
+}
+
+/// wait_fds() backend using libc::kqueue().
+/// EXCEPTION events are not supported and never reported.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+fn kqueue_fds(wait_fds: &mut [&mut WaitFd], timeout: Option<Duration>) -> Result<(), Errno> {
+    let ts_timeout = timeout.map(|d| libc::timespec {
+        tv_sec: d.as_secs() as libc::time_t,
+        tv_nsec: d.subsec_nanos() as libc::c_long,
+    });
+
+    let mut changes = Vec::new();
+    for w in wait_fds.iter() {
+        for (flag, filter) in [
+            (WaitFd::READABLE, libc::EVFILT_READ),
+            (WaitFd::WRITEABLE, libc::EVFILT_WRITE),
+        ] {
+            if w.mask & flag != 0 {
+                // SAFETY: kevent is a plain C struct, all-zero is a valid value.
+                let mut ev: libc::kevent = unsafe { mem::zeroed() };
+                ev.ident = w.fd.as_raw_fd() as libc::uintptr_t;
+                ev.filter = filter;
+                ev.flags = libc::EV_ADD | libc::EV_ONESHOT;
+                changes.push(ev);
+            }
+        }
+    }
+
+    // SAFETY: kqueue() has no preconditions; the returned descriptor is
+    // owned by us and closed below.
+    let kq = unsafe { libc::kqueue() };
+    if kq < 0 {
+        return Err(last_errno());
+    }
+
+    // SAFETY: We're holding an BorrowedFd (via WaitFd) for every descriptor
+    // during the call, so they're guaranteed to be valid. Pointers and
+    // lengths refer to live vectors.
+    let result = unsafe {
+        let ret = libc::kevent(
+            kq,
+            changes.as_ptr(),
+            changes.len() as _,
+            null_mut(),
+            0,
+            std::ptr::null(),
+        );
+        if ret < 0 {
+            Err(last_errno())
+        } else {
+            let mut events: Vec<libc::kevent> = vec![mem::zeroed(); changes.len()];
+            loop {
+                let ret = libc::kevent(
+                    kq,
+                    std::ptr::null(),
+                    0,
+                    events.as_mut_ptr(),
+                    events.len() as _,
+                    match &ts_timeout {
+                        Some(ts) => ts as *const libc::timespec,
+                        None => std::ptr::null(),
+                    },
+                );
+                if ret < 0 {
+                    if last_errno() == Errno::INTR {
+                        continue;
+                    }
+                    break Err(last_errno());
+                }
+                events.truncate(ret as usize);
+                break Ok(events);
+            }
+        }
+    };
+
+    // SAFETY: kq is a valid descriptor owned by us.
+    unsafe {
+        libc::close(kq);
+    }
+
+    let events = result?;
+
+    for w in wait_fds.iter_mut() {
+        let raw_fd = w.fd.as_raw_fd() as libc::uintptr_t;
+        w.mask = 0;
+        for ev in events.iter().filter(|ev| ev.ident == raw_fd) {
+            if ev.flags & libc::EV_ERROR != 0 {
+                return Err(Errno::from_raw_os_error(ev.data as i32));
+            }
+            if ev.filter == libc::EVFILT_READ {
+                w.mask |= WaitFd::READABLE;
+            }
+            if ev.filter == libc::EVFILT_WRITE {
+                w.mask |= WaitFd::WRITEABLE;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// wait_fds() backend using libc::select().
+/// Fails with EINVAL if any descriptor is >= FD_SETSIZE.
+#[allow(dead_code)]
+fn select_fds(select_fds: &mut [&mut WaitFd], timeout: Option<Duration>) -> Result<(), Errno> {
     let mut tv_timeout = timeout.map(|d| libc::timeval {
         tv_sec: d.as_secs() as libc::time_t,
         tv_usec: d.subsec_micros() as libc::suseconds_t,
@@ -39,14 +267,16 @@ pub fn select(select_fds: &mut [&mut SelectFd], timeout: Option<Duration>) -> Re
     let max_fd = select_fds
         .iter()
         .fold(0, |max_fd, sel_fd| max(max_fd, sel_fd.fd.as_raw_fd()));
+    if max_fd >= libc::FD_SETSIZE as RawFd {
+        return Err(Errno::INVAL);
+    }
 
-    // SAFETY: We're holding an BorrowedFd (via SelectFd) for every descriptor
-    // during the call, so they're guaranteed to be valid.
+    // SAFETY: We're holding an BorrowedFd (via WaitFd) for every descriptor
+    // during the call, so they're guaranteed to be valid, and all of them
+    // are below FD_SETSIZE.
     //
-    // NOTE: We use libc::select() instead of rustix::event::select() or
-    // rustix::event::poll() because:
-    //  - rustix::event::select() is not available on all platforms
-    //  - rustix::event::poll() does not work with TTYs on macOS
+    // NOTE: We use libc::select() instead of rustix::event::select() because
+    // the latter is not available on all platforms.
     unsafe {
         let mut rd_fds = MaybeUninit::<libc::fd_set>::uninit();
         let mut wr_fds = MaybeUninit::<libc::fd_set>::uninit();
@@ -61,13 +291,13 @@ pub fn select(select_fds: &mut [&mut SelectFd], timeout: Option<Duration>) -> Re
         ex_fds.assume_init();
 
         for sel_fd in select_fds.iter() {
-            if sel_fd.mask & SelectFd::READABLE != 0 {
+            if sel_fd.mask & WaitFd::READABLE != 0 {
                 FD_SET(sel_fd.fd.as_raw_fd(), rd_fds.as_mut_ptr());
             }
-            if sel_fd.mask & SelectFd::WRITEABLE != 0 {
+            if sel_fd.mask & WaitFd::WRITEABLE != 0 {
                 FD_SET(sel_fd.fd.as_raw_fd(), wr_fds.as_mut_ptr());
             }
-            if sel_fd.mask & SelectFd::EXCEPTION != 0 {
+            if sel_fd.mask & WaitFd::EXCEPTION != 0 {
                 FD_SET(sel_fd.fd.as_raw_fd(), ex_fds.as_mut_ptr());
             }
         }
@@ -98,13 +328,13 @@ pub fn select(select_fds: &mut [&mut SelectFd], timeout: Option<Duration>) -> Re
         for sel_fd in select_fds.iter_mut() {
             sel_fd.mask = 0;
             if FD_ISSET(sel_fd.fd.as_raw_fd(), rd_fds.as_mut_ptr()) {
-                sel_fd.mask |= SelectFd::READABLE;
+                sel_fd.mask |= WaitFd::READABLE;
             }
             if FD_ISSET(sel_fd.fd.as_raw_fd(), wr_fds.as_mut_ptr()) {
-                sel_fd.mask |= SelectFd::WRITEABLE;
+                sel_fd.mask |= WaitFd::WRITEABLE;
             }
             if FD_ISSET(sel_fd.fd.as_raw_fd(), ex_fds.as_mut_ptr()) {
-                sel_fd.mask |= SelectFd::EXCEPTION;
+                sel_fd.mask |= WaitFd::EXCEPTION;
             }
         }
     };
@@ -138,6 +368,268 @@ pub fn ptsname<Fd: AsFd>(fd: Fd) -> Result<String, Errno> {
     Ok(s_ref.to_string())
 }
 
+/// Safe shim for libc::getgrnam_r().
+/// Returns group ID, or None if there is no such group.
+pub fn getgrnam(name: &str) -> Result<Option<u32>, Errno> {
+    let c_name = match CString::new(name) {
+        Ok(c_name) => c_name,
+        Err(_) => return Ok(None),
+    };
+
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut group = MaybeUninit::<libc::group>::uninit();
+        let mut result = null_mut();
+
+        let err = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                group.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match err {
+            0 if result.is_null() => return Ok(None),
+            0 => return Ok(Some(unsafe { group.assume_init() }.gr_gid as u32)),
+            libc::EINTR => continue,
+            // Buffer is too small for group entry.
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            // Some systems report missing group as error.
+            libc::ENOENT | libc::ESRCH | libc::EBADF | libc::EPERM => return Ok(None),
+            err => return Err(Errno::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// User entry returned by getpwnam() and getpwuid().
+pub struct Passwd {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+}
+
+/// Safe shim for libc::getpwnam_r().
+/// Returns None if there is no such user.
+pub fn getpwnam(name: &str) -> Result<Option<Passwd>, Errno> {
+    let c_name = match CString::new(name) {
+        Ok(c_name) => c_name,
+        Err(_) => return Ok(None),
+    };
+
+    getpw(|passwd, buf, result| unsafe {
+        libc::getpwnam_r(c_name.as_ptr(), passwd, buf.as_mut_ptr(), buf.len(), result)
+    })
+}
+
+/// Safe shim for libc::getpwuid_r().
+/// Returns None if there is no such user.
+pub fn getpwuid(uid: u32) -> Result<Option<Passwd>, Errno> {
+    getpw(|passwd, buf, result| unsafe {
+        libc::getpwuid_r(uid as _, passwd, buf.as_mut_ptr(), buf.len(), result)
+    })
+}
+
+fn getpw<F>(lookup: F) -> Result<Option<Passwd>, Errno>
+where
+    F: Fn(*mut libc::passwd, &mut [libc::c_char], *mut *mut libc::passwd) -> libc::c_int,
+{
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = null_mut();
+
+        match lookup(passwd.as_mut_ptr(), &mut buf, &mut result) {
+            0 if result.is_null() => return Ok(None),
+            0 => {
+                let passwd = unsafe { passwd.assume_init() };
+                let (name, home) = unsafe {
+                    (
+                        CStr::from_ptr(passwd.pw_name)
+                            .to_string_lossy()
+                            .into_owned(),
+                        CStr::from_ptr(passwd.pw_dir).to_string_lossy().into_owned(),
+                    )
+                };
+                return Ok(Some(Passwd {
+                    name,
+                    uid: passwd.pw_uid as u32,
+                    gid: passwd.pw_gid as u32,
+                    home,
+                }));
+            }
+            libc::EINTR => continue,
+            // Buffer is too small for user entry.
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            // Some systems report missing user as error.
+            libc::ENOENT | libc::ESRCH | libc::EBADF | libc::EPERM => return Ok(None),
+            err => return Err(Errno::from_raw_os_error(err)),
+        }
+    }
+}
+
+/// Safe shim for libc::getgrouplist().
+/// Returns groups of the user, including `gid`.
+pub fn getgrouplist(name: &str, gid: u32) -> Result<Vec<u32>, Errno> {
+    let c_name = match CString::new(name) {
+        Ok(c_name) => c_name,
+        Err(_) => return Err(Errno::INVAL),
+    };
+
+    let mut groups = vec![0 as libc::gid_t; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        // Signature differs between platforms (gid_t vs int), hence casts.
+        let ret = unsafe {
+            libc::getgrouplist(
+                c_name.as_ptr(),
+                gid as _,
+                groups.as_mut_ptr() as *mut _,
+                &mut count,
+            )
+        };
+        if ret >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups.into_iter().map(|g| g as u32).collect());
+        }
+        // Array is too small, count is set to required size on some
+        // systems and left unchanged on others.
+        let new_len = max(count as usize, groups.len() * 2);
+        if new_len > 65536 {
+            return Err(Errno::RANGE);
+        }
+        groups.resize(new_len, 0);
+    }
+}
+
+/// Shim for libc::setgroups().
+/// Doesn't allocate, so can be called in child after fork().
+pub fn setgroups(groups: &[u32]) -> Result<(), Errno> {
+    // gid_t is u32 on all supported platforms.
+    match unsafe { libc::setgroups(groups.len() as _, groups.as_ptr() as *const libc::gid_t) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+/// Shim for libc::setgid().
+pub fn setgid(gid: u32) -> Result<(), Errno> {
+    match unsafe { libc::setgid(gid as libc::gid_t) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+/// Shim for libc::setuid().
+pub fn setuid(uid: u32) -> Result<(), Errno> {
+    match unsafe { libc::setuid(uid as libc::uid_t) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+/// Shim for libc::setrlimit() for current process.
+/// None means unlimited.
+pub fn setrlimit(resource: i32, soft: Option<u64>, hard: Option<u64>) -> Result<(), Errno> {
+    let limit = libc::rlimit {
+        rlim_cur: soft.map_or(libc::RLIM_INFINITY, |n| n as libc::rlim_t),
+        rlim_max: hard.map_or(libc::RLIM_INFINITY, |n| n as libc::rlim_t),
+    };
+    match unsafe { libc::setrlimit(resource as _, &limit) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+/// Shim for libc::setpriority() for current process.
+pub fn setpriority(niceness: i32) -> Result<(), Errno> {
+    match unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, niceness) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+/// Shim for ioprio_set() syscall for current process.
+/// There is no libc wrapper, hence raw syscall.
+#[cfg(target_os = "linux")]
+pub fn ioprio_set(class: u32, level: u32) -> Result<(), Errno> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+    let prio = (class << IOPRIO_CLASS_SHIFT) | level;
+    match unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn ioprio_set(_class: u32, _level: u32) -> Result<(), Errno> {
+    Err(Errno::NOSYS)
+}
+
+/// Shim for prctl(PR_SET_PDEATHSIG): ask kernel to send signal to current
+/// process when its parent dies.
+#[cfg(target_os = "linux")]
+pub fn set_pdeathsig(sig: Signal) -> Result<(), Errno> {
+    match unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, sig.as_raw() as libc::c_ulong) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_pdeathsig(_sig: Signal) -> Result<(), Errno> {
+    Err(Errno::NOSYS)
+}
+
+/// Shim for prctl(PR_SET_CHILD_SUBREAPER): make orphaned descendants of
+/// current process its children instead of children of init.
+#[cfg(target_os = "linux")]
+pub fn set_child_subreaper() -> Result<(), Errno> {
+    match unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1 as libc::c_ulong) } {
+        0 => Ok(()),
+        _ => Err(last_errno()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_child_subreaper() -> Result<(), Errno> {
+    Err(Errno::NOSYS)
+}
+
+/// Write OOM score adjustment of current process to procfs.
+/// Doesn't allocate, so can be called in child after fork().
+#[cfg(target_os = "linux")]
+pub fn set_oom_score_adj(score: i32) -> Result<(), Errno> {
+    use std::io::{Cursor, Write};
+
+    let mut buf = [0u8; 16];
+    let mut cursor = Cursor::new(&mut buf[..]);
+    _ = write!(cursor, "{}", score);
+    let len = cursor.position() as usize;
+
+    write_file(c"/proc/self/oom_score_adj", &buf[..len])
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_oom_score_adj(_score: i32) -> Result<(), Errno> {
+    Err(Errno::NOSYS)
+}
+
+/// Open existing file and write data to it.
+/// Doesn't allocate, so can be called in child after fork().
+pub fn write_file(path: &CStr, data: &[u8]) -> Result<(), Errno> {
+    use rustix::fs::{self, Mode, OFlags};
+
+    let fd = rustix::io::retry_on_intr(|| {
+        fs::open(path, OFlags::WRONLY | OFlags::CLOEXEC, Mode::empty())
+    })?;
+    write_all(&fd, data).map(|_| ())
+}
+
 pub enum Fork {
     Parent(Pid),
     Child,
@@ -163,6 +655,18 @@ pub unsafe fn fast_exit(code: i32) {
     unsafe { libc::_exit(code) }
 }
 
+unsafe extern "C" {
+    static mut environ: *const *const libc::c_char;
+}
+
+/// Replace environment of current process with given null-terminated array
+/// of "NAME=VALUE" strings, which is then passed to exec().
+/// Unlike setenv(), it only assigns a pointer and doesn't allocate, so it
+/// can be used in child after fork(). The array must outlive exec().
+pub unsafe fn set_environ(envp: &[*const libc::c_char]) {
+    unsafe { environ = envp.as_ptr() }
+}
+
 /// Safe shim for libc::read().
 /// Handles EINTR.
 /// Unlike rustix version, doesn't consume the buffer.
@@ -288,7 +792,7 @@ pub fn sigaction(sig: Signal, action: SigAction) -> Result<(), Errno> {
     let handler = match action {
         SigAction::Default => libc::SIG_DFL,
         SigAction::Ignore => libc::SIG_IGN,
-        SigAction::Noop => noop as libc::sighandler_t,
+        SigAction::Noop => noop as *const () as libc::sighandler_t,
     };
 
     let ret = unsafe {
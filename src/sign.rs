@@ -0,0 +1,77 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Error};
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+
+/// Program used for signing and verification.
+/// Signatures are created by ssh-keygen(1) (-Y sign), so they can be
+/// verified with OpenSSH tools as well.
+const SSH_KEYGEN_PROGRAM: &str = "ssh-keygen";
+
+/// Signature namespace; signature made for one namespace isn't valid
+/// for another, so it can't be reused e.g. for git commits.
+const NAMESPACE: &str = "reclog";
+
+/// Principal used in temporary allowed signers file.
+const PRINCIPAL: &str = "reclog";
+
+/// Sign contents of input using private key file, writing detached
+/// signature to output (--sign).
+pub fn sign(key: &Path, input: File, output: File) -> io::Result<()> {
+    let mut command = Command::new(SSH_KEYGEN_PROGRAM);
+    command
+        .args(["-q", "-Y", "sign", "-n", NAMESPACE, "-f"])
+        .arg(key);
+
+    let status = command
+        .stdin(input)
+        .stdout(output)
+        .status()
+        .map_err(|err| spawn_error(&err))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!(
+            "{} failed with {}",
+            SSH_KEYGEN_PROGRAM, status
+        )))
+    }
+}
+
+/// Check that signature of input was made by private key matching public
+/// key file (`reclog verify`).
+/// Returns Ok(false) if signature doesn't match.
+pub fn verify(public_key: &Path, signature: &Path, input: File) -> io::Result<bool> {
+    // ssh-keygen(1) accepts keys only via allowed signers file, where each
+    // line is "PRINCIPAL [OPTIONS] KEYTYPE KEY".
+    let key = fs::read_to_string(public_key)?;
+    let key = key.split_whitespace().take(2).collect::<Vec<_>>().join(" ");
+    let allowed_signers =
+        env::temp_dir().join(format!("reclog-allowed-signers.{}.tmp", process::id()));
+    fs::write(
+        &allowed_signers,
+        format!("{} namespaces=\"{}\" {}\n", PRINCIPAL, NAMESPACE, key),
+    )?;
+
+    let result = Command::new(SSH_KEYGEN_PROGRAM)
+        .args(["-Y", "verify", "-n", NAMESPACE, "-I", PRINCIPAL, "-f"])
+        .arg(&allowed_signers)
+        .arg("-s")
+        .arg(signature)
+        .stdin(input)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|err| spawn_error(&err));
+    _ = fs::remove_file(&allowed_signers);
+
+    Ok(result?.success())
+}
+
+fn spawn_error(err: &Error) -> Error {
+    Error::new(
+        err.kind(),
+        format!("can't run {}: {}", SSH_KEYGEN_PROGRAM, err),
+    )
+}
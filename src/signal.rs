@@ -41,6 +41,14 @@ pub enum SignalEvent {
     Timeout,
 }
 
+/// Check if signal stops process rather than terminates it.
+pub fn is_stop_signal(sig: Signal) -> bool {
+    matches!(to_event(sig), SignalEvent::Stop(_)) || sig == Signal::STOP
+}
+
+/// Signals sent by tty to the whole foreground process group.
+const TTY_SIGNALS: [Signal; 2] = [Signal::INT, Signal::QUIT];
+
 /// Categorize signals into higher-level event types.
 fn to_event(sig: Signal) -> SignalEvent {
     match sig {
@@ -58,7 +66,10 @@ fn to_event(sig: Signal) -> SignalEvent {
 /// Get human-readable name for signal.
 pub fn display_name(sig: Signal) -> String {
     if let Some(sig_name) = Signal::from_named_raw(sig.as_raw()) {
-        format!("{:?}", sig_name).replace("Signal::", "SIG")
+        // Debug output of rustix signal is quoted, e.g. "\"Signal::TERM\"".
+        format!("{:?}", sig_name)
+            .trim_matches('"')
+            .replace("Signal::", "SIG")
     } else {
         format!("[{}]", sig.as_raw())
     }
@@ -142,6 +153,71 @@ pub fn unblock_signals() -> Result<(), SysError> {
     Ok(())
 }
 
+/// Block tty signals while another program (e.g. pager) is running in
+/// foreground, so that ^C addressed to it doesn't kill us.
+pub fn block_tty_signals() -> Result<(), SysError> {
+    if let Err(err) = shim::sigmask(&TTY_SIGNALS, SigMask::Block) {
+        return Err(SysError("sigmask()", err));
+    }
+
+    Ok(())
+}
+
+/// Drop tty signals received while they were blocked, and unblock them.
+pub fn unblock_tty_signals() -> Result<(), SysError> {
+    for sig in TTY_SIGNALS {
+        drop_signal(sig)?;
+    }
+    if let Err(err) = shim::sigmask(&TTY_SIGNALS, SigMask::Unblock) {
+        return Err(SysError("sigmask()", err));
+    }
+
+    Ok(())
+}
+
+/// Signals that `reclog multi` and `reclog --watch` forward to their
+/// children.
+const FORWARDED_SIGNALS: [Signal; 2] = [Signal::TERM, Signal::HUP];
+
+/// Initialize signal mask in `reclog multi` and `reclog --watch`.
+/// Tty signals are blocked, because tty sends them to children too, which
+/// handle them and exit. Forwarded signals are blocked and then fetched
+/// with wait_forwarded_signal().
+pub fn init_multi_signals() -> Result<(), SysError> {
+    if let Err(err) = shim::sigmask(&TTY_SIGNALS, SigMask::Block) {
+        return Err(SysError("sigmask()", err));
+    }
+    if let Err(err) = shim::sigmask(&FORWARDED_SIGNALS, SigMask::Block) {
+        return Err(SysError("sigmask()", err));
+    }
+
+    Ok(())
+}
+
+/// Wait next signal that should be forwarded to children.
+pub fn wait_forwarded_signal() -> Result<Signal, SysError> {
+    loop {
+        match shim::sigwait(&FORWARDED_SIGNALS, None) {
+            Ok(Some(sig)) => return Ok(sig),
+            Ok(None) => continue,
+            Err(err) => return Err(SysError("sigwait()", err)),
+        }
+    }
+}
+
+/// Wait next tty or forwarded signal, which stops `reclog --watch`.
+/// Returns None if timeout expires.
+pub fn wait_watch_signal(timeout: Duration) -> Result<Option<Signal>, SysError> {
+    let signals = [TTY_SIGNALS, FORWARDED_SIGNALS].concat();
+    shim::sigwait(&signals, Some(timeout)).map_err(|err| SysError("sigwait()", err))
+}
+
+/// Check if signal is sent by tty to the whole foreground process group,
+/// so children get it without forwarding.
+pub fn is_tty_signal(sig: Signal) -> bool {
+    TTY_SIGNALS.contains(&sig)
+}
+
 /// Wait next event signal.
 pub fn wait_signal(timeout: Option<Duration>) -> Result<SignalEvent, SysError> {
     loop {
@@ -0,0 +1,434 @@
+use crate::buffer::{BufferPool, BufferQueue};
+use crate::error::Error;
+use crate::event::{CaptureEvent, EventSink, Stream};
+use crate::format::Painter;
+use crate::gelf::GelfSender;
+use crate::jsonl::JsonlFormatter;
+use crate::keys::Controls;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttSender;
+use crate::publish::Publisher;
+use crate::selftrace::{self, TraceEvent};
+use crate::term::{self, StripLevel};
+use crate::ttyrec::TtyrecWriter;
+use clap::ValueEnum;
+use reclog::index::{IndexWriter, LineSpan};
+use reclog::timing::TimingWriter;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Destination of command output that has its own formatting.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum Sink {
+    /// Terminal (our stdout).
+    Stdout,
+    /// --output file.
+    Output,
+    /// Clients of --publish socket.
+    Publish,
+    /// Graylog server (--gelf).
+    Gelf,
+    /// MQTT broker (--mqtt).
+    Mqtt,
+}
+
+/// Formatting overrides for one sink (--sink-format).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkFormat {
+    sink: Sink,
+    ts: Option<bool>,
+    strip: Option<StripLevel>,
+}
+
+impl FromStr for SinkFormat {
+    type Err = String;
+
+    /// Parse "SINK:KEY=VALUE[,KEY=VALUE]...", e.g. "stdout:ts=no,strip=none".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sink, options) = match s.split_once(':') {
+            Some(parts) => parts,
+            None => return Err("expected SINK:KEY=VALUE[,KEY=VALUE]".into()),
+        };
+        let mut format = SinkFormat {
+            sink: Sink::from_str(sink, false).map_err(|_| {
+                format!(
+                    "unknown sink '{}', expected one of: {}",
+                    sink,
+                    value_names(Sink::value_variants())
+                )
+            })?,
+            ts: None,
+            strip: None,
+        };
+
+        for option in options.split(',') {
+            let (key, value) = match option.split_once('=') {
+                Some(parts) => parts,
+                None => return Err(format!("invalid option '{}', expected KEY=VALUE", option)),
+            };
+            match key {
+                "ts" => {
+                    format.ts = Some(match value {
+                        "yes" => true,
+                        "no" => false,
+                        _ => return Err(format!("invalid ts '{}', expected yes or no", value)),
+                    })
+                }
+                "strip" => {
+                    format.strip = Some(StripLevel::from_str(value, false).map_err(|_| {
+                        format!(
+                            "invalid strip '{}', expected one of: {}",
+                            value,
+                            value_names(StripLevel::value_variants())
+                        )
+                    })?)
+                }
+                _ => return Err(format!("unknown option '{}', expected ts or strip", key)),
+            }
+        }
+
+        Ok(format)
+    }
+}
+
+/// How lines are formatted for a sink.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SinkStyle {
+    /// Keep timestamp prefix added by formatter.
+    pub ts: bool,
+    /// Which ANSI escape codes to strip.
+    pub strip: StripLevel,
+}
+
+impl SinkStyle {
+    /// Apply --sink-format overrides for the sink on top of defaults.
+    /// If the sink is specified several times, later options win.
+    pub fn resolve(formats: &[SinkFormat], sink: Sink, default: SinkStyle) -> Self {
+        let mut style = default;
+        for format in formats.iter().filter(|format| format.sink == sink) {
+            style.ts = format.ts.unwrap_or(style.ts);
+            style.strip = format.strip.unwrap_or(style.strip);
+        }
+        style
+    }
+
+    /// Get part of buffer that goes to sink: without timestamp prefix
+    /// (first `ts_len` bytes) if it's disabled.
+    pub fn select<'a>(&self, buf: &'a [u8], ts_len: usize) -> &'a [u8] {
+        if self.ts { buf } else { &buf[ts_len..] }
+    }
+
+    /// Get text that goes to sink, with escape codes stripped.
+    pub fn format<'a>(&self, buf: &'a [u8], ts_len: usize) -> Cow<'a, str> {
+        term::strip_ansi_level(self.select(buf, ts_len), self.strip)
+    }
+}
+
+/// Formatting of all sinks.
+#[derive(Debug, Clone, Copy)]
+pub struct SinkStyles {
+    pub stdout: SinkStyle,
+    pub output: SinkStyle,
+    pub publish: SinkStyle,
+    pub gelf: SinkStyle,
+    pub mqtt: SinkStyle,
+}
+
+impl SinkStyles {
+    /// Check if any sink needs timestamps, so they should be formatted.
+    pub fn need_timestamp(&self) -> bool {
+        [self.stdout, self.output, self.publish, self.gelf, self.mqtt]
+            .iter()
+            .any(|style| style.ts)
+    }
+}
+
+fn value_names<T: ValueEnum>(variants: &[T]) -> String {
+    variants
+        .iter()
+        .filter_map(|value| value.to_possible_value())
+        .map(|value| value.get_name().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Files written by events_2_file(), besides stdout.
+pub struct OutputFiles<'a> {
+    pub out_writer: &'a mut (dyn Write + Send),    // --output
+    pub out_lines: Arc<AtomicU64>,                 // lines written to --output
+    pub timing_writer: Option<TimingWriter<File>>, // --timing
+    pub raw_writer: Option<BufWriter<File>>,       // --raw-output
+    pub ttyrec_writer: Option<TtyrecWriter<File>>, // --ttyrec
+    pub raw_offset: u64,                           // bytes written to --raw-output
+    pub index_writer: Option<IndexWriter<File>>,   // --index
+    pub jsonl: Option<JsonlFormatter>,             // --output-format=jsonl
+    pub style: SinkStyle,                          // --sink-format
+    pub batch: bool,                               // --write-buffer
+    pub header: bool,                              // write header to files
+    pub fail: fn(Error) -> !,                      // invoked on write error
+}
+
+impl OutputFiles<'_> {
+    /// Write event to output file, raw output, ttyrec, and timing files.
+    pub fn write_event(&mut self, event: &CaptureEvent) -> Result<(), Error> {
+        // With --header-once-per-file, header goes only to stdout.
+        if event.stream == Stream::Header && !self.header {
+            return Ok(());
+        }
+
+        // Resize is recorded only in formats that replay terminal.
+        if let Stream::Resize { cols, rows } = event.stream {
+            return self.write_resize(event, cols, rows);
+        }
+
+        // Traces aren't part of command output, so they go only to output file.
+        if event.stream == Stream::Trace {
+            self.write_line(event, &event.bytes)?;
+            if !self.batch {
+                self.flush()?;
+            }
+            return Ok(());
+        }
+
+        let buf = &event.bytes;
+        let first_line = self.out_lines.load(Ordering::Relaxed);
+
+        // Write buffer to output file, synchronously.
+        // If stripping is enabled, this writer will also remove ANSI escape codes.
+        // If batching is enabled, data is written out when the batch is full,
+        // or from events_2_file() when there are no more pending events.
+        let out_buf = self.style.select(buf, event.ts_len);
+        self.write_line(event, out_buf)?;
+
+        // Write unmodified buffer to raw capture and map it to written lines.
+        if let Some(raw_writer) = &mut self.raw_writer {
+            raw_writer
+                .write_all(buf)
+                .map_err(write_error("raw output file"))?;
+            if let Some(index_writer) = &mut self.index_writer {
+                let span = LineSpan {
+                    line: first_line + 1,
+                    line_count: self.out_lines.load(Ordering::Relaxed) - first_line,
+                    raw_offset: self.raw_offset,
+                    raw_len: buf.len() as u64,
+                };
+                index_writer
+                    .write_span(&span)
+                    .map_err(write_error("index file"))?;
+            }
+            self.raw_offset += buf.len() as u64;
+        }
+
+        if !self.batch {
+            self.flush()?;
+        }
+
+        // Write unmodified buffer to ttyrec with wall-clock time of the event.
+        if let Some(ttyrec_writer) = &mut self.ttyrec_writer {
+            let wall_ts = SystemTime::now() - event.ts.elapsed();
+            ttyrec_writer
+                .write_output(wall_ts, buf)
+                .map_err(write_error("ttyrec file"))?;
+        }
+
+        // Record when buffer was produced.
+        if let Some(timing_writer) = &mut self.timing_writer {
+            timing_writer
+                .write_output(event.ts, out_buf.len())
+                .map_err(write_error("timing file"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Write resize event to ttyrec and timing files.
+    fn write_resize(&mut self, event: &CaptureEvent, cols: u16, rows: u16) -> Result<(), Error> {
+        if let Some(ttyrec_writer) = &mut self.ttyrec_writer {
+            let wall_ts = SystemTime::now() - event.ts.elapsed();
+            ttyrec_writer
+                .write_resize(wall_ts, cols, rows)
+                .map_err(write_error("ttyrec file"))?;
+        }
+
+        if let Some(timing_writer) = &mut self.timing_writer {
+            timing_writer
+                .write_resize(event.ts, cols, rows)
+                .map_err(write_error("timing file"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Write line of event to --output, as is or, with jsonl, wrapped into
+    /// JSON object (without timestamp prefix, which is replaced by time field).
+    fn write_line(&mut self, event: &CaptureEvent, line: &[u8]) -> Result<(), Error> {
+        let result = match &mut self.jsonl {
+            Some(jsonl) => {
+                let time = SystemTime::now() - event.ts.elapsed();
+                let mut json = String::new();
+                jsonl.format_line(
+                    &mut json,
+                    time.into(),
+                    event.stream,
+                    &event.bytes[event.ts_len..],
+                )?;
+                self.out_writer.write_all(json.as_bytes())
+            }
+            None => self.out_writer.write_all(line),
+        };
+        result.map_err(write_error("output file"))
+    }
+
+    /// Write out data buffered in --output and --raw-output writers.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.out_writer
+            .flush()
+            .map_err(write_error("output file"))?;
+        if let Some(raw_writer) = &mut self.raw_writer {
+            raw_writer.flush().map_err(write_error("raw output file"))?;
+        }
+        Ok(())
+    }
+}
+
+impl EventSink for OutputFiles<'_> {
+    fn consume(&mut self, event: &Arc<CaptureEvent>) {
+        if let Err(err) = self.write_event(event) {
+            (self.fail)(err);
+        }
+    }
+
+    fn idle(&mut self) {
+        // When batching, write out pending data before we may block
+        // waiting for more output.
+        if self.batch {
+            if let Err(err) = self.flush() {
+                (self.fail)(err);
+            }
+        }
+    }
+}
+
+/// Wrap I/O error with name of the file being written.
+fn write_error(file: &str) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |err| Error::from(err).context(format!("can't write {}", file))
+}
+
+/// Network destinations of recording.
+/// They never block: each of them either has its own queue, or drops
+/// data that can't be sent right away.
+pub struct NetworkSinks {
+    pub gelf_sender: Option<GelfSender>, // --gelf
+    pub publisher: Option<Publisher>,    // --publish
+    #[cfg(feature = "mqtt")]
+    pub mqtt_sender: Option<MqttSender>, // --mqtt
+    pub styles: SinkStyles,              // --sink-format
+    pub header: bool,                    // publish header
+}
+
+impl EventSink for NetworkSinks {
+    fn consume(&mut self, event: &Arc<CaptureEvent>) {
+        // Header goes to clients together with files (--header-once-per-file).
+        if event.stream == Stream::Header && !self.header {
+            return;
+        }
+
+        // Resize carries no text.
+        if let Stream::Resize { .. } = event.stream {
+            return;
+        }
+
+        // Traces go only to output file.
+        if event.stream == Stream::Trace {
+            return;
+        }
+
+        // Stream recording to connected clients.
+        if let Some(publisher) = &self.publisher {
+            let data = self.styles.publish.format(&event.bytes, event.ts_len);
+            publisher.publish(data.as_bytes());
+        }
+
+        // Only lines of command output are forwarded as messages.
+        if event.stream != Stream::Output {
+            return;
+        }
+
+        // Forward line to Graylog according to --gelf.
+        if let Some(gelf_sender) = &mut self.gelf_sender {
+            gelf_sender.send_line(&self.styles.gelf.format(&event.bytes, event.ts_len));
+        }
+
+        // Publish line to MQTT broker according to --mqtt.
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt_sender) = &self.mqtt_sender {
+            mqtt_sender.send_line(&self.styles.mqtt.format(&event.bytes, event.ts_len));
+        }
+    }
+}
+
+/// Renders events on terminal and moves them to queue for queue_2_stdout().
+pub struct StdoutSink<'a> {
+    pub painter: &'a Painter,
+    pub buf_pool: &'a BufferPool,
+    pub buf_queue: &'a BufferQueue,
+    pub controls: &'a Controls,
+    pub style: SinkStyle, // --sink-format
+    pub dropped: u64,     // dropped lines already traced (--trace-self)
+}
+
+impl EventSink for StdoutSink<'_> {
+    fn consume(&mut self, event: &Arc<CaptureEvent>) {
+        let mut style = match event.display {
+            Some(style) => style,
+            None => return,
+        };
+
+        // Only command output is paused, our own lines are still shown.
+        if event.stream == Stream::Output && self.controls.stdout_paused() {
+            return;
+        }
+
+        // Event is shared with other sinks, so terminal variant of the line
+        // is built in a copy.
+        let ts_len = if self.style.ts { 0 } else { event.ts_len };
+        let mut buf = self.buf_pool.alloc();
+        buf.extend_from_slice(&event.bytes[ts_len..]);
+        style.prefix_len = style.prefix_len.saturating_sub(ts_len);
+
+        // File gets plain line, terminal gets colorized one.
+        if style.success.is_some() {
+            self.painter.extend_footer(&mut buf);
+            style.prefix_len = buf.len();
+        }
+        if self.painter.need_color() {
+            self.painter.colorize_line(&mut buf, &style);
+        }
+
+        // Move buffer to queue.
+        // pty_2_stdout_thread will fetch it, write to stdout, and return buffer to pool.
+        // If queue is full, oldest elements are removed. That's fine - our stdout is
+        // supposed to be a TTY, and if it's too slow to display all lines in time,
+        // there is no need trying to write all of them - user won't see them
+        // anyway at that speed and VTE scrollback is usually limited and TTY will
+        // anyway drop them.
+        self.buf_queue.write(buf);
+    }
+
+    fn idle(&mut self) {
+        // Report dropped lines once in a while rather than for every line.
+        if selftrace::is_enabled() {
+            let dropped = self.buf_queue.dropped();
+            if dropped > self.dropped {
+                selftrace::record(TraceEvent::Drop(dropped - self.dropped));
+                self.dropped = dropped;
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::buffer::BufferQueue;
+use rustix::process::WaitStatus;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Statistics of captured output, updated by pty_2_events() and
+/// read by other threads (--progress, --metrics-listen).
+pub struct Stats {
+    start_ts: Instant,
+    bytes: AtomicU64,
+    lines: AtomicU64,
+    child_status: OnceLock<WaitStatus>,
+}
+
+impl Stats {
+    pub fn new(start_ts: Instant) -> Self {
+        Stats {
+            start_ts,
+            bytes: AtomicU64::new(0),
+            lines: AtomicU64::new(0),
+            child_status: OnceLock::new(),
+        }
+    }
+
+    /// Account line read from command.
+    pub fn add_line(&self, size: usize) {
+        self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Bytes read from command.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Lines read from command.
+    pub fn lines(&self) -> u64 {
+        self.lines.load(Ordering::Relaxed)
+    }
+
+    /// Remember exit status of command.
+    pub fn set_child_status(&self, status: WaitStatus) {
+        _ = self.child_status.set(status);
+    }
+
+    /// Exit status of command, or None if it's still running.
+    pub fn child_status(&self) -> Option<WaitStatus> {
+        self.child_status.get().copied()
+    }
+
+    /// Time since start of command.
+    pub fn elapsed(&self) -> Duration {
+        self.start_ts.elapsed()
+    }
+}
+
+/// Formats single-line status for --progress.
+/// Remembers previous update to compute rate.
+#[derive(Default)]
+pub struct Progress {
+    last_ts: Duration,
+    last_lines: u64,
+}
+
+impl Progress {
+    /// Format status line, e.g.:
+    /// "00:01:05  12345 lines  1.2 MiB  250 lines/s  queue 3/1024".
+    pub fn format(&mut self, stats: &Stats, buf_queue: &BufferQueue) -> String {
+        let elapsed = stats.elapsed();
+        let lines = stats.lines();
+
+        let period = (elapsed - self.last_ts).as_secs_f64();
+        let rate = if period > 0.0 {
+            (lines - self.last_lines) as f64 / period
+        } else {
+            0.0
+        };
+        self.last_ts = elapsed;
+        self.last_lines = lines;
+
+        let secs = elapsed.as_secs();
+        let mut line = format!(
+            "{:02}:{:02}:{:02}  {} lines  {}  {:.0} lines/s  queue {}/{}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            lines,
+            format_size(stats.bytes()),
+            rate,
+            buf_queue.pending(),
+            buf_queue.capacity(),
+        );
+        // Lines not shown on stdout because it was too slow.
+        let dropped = buf_queue.dropped();
+        if dropped > 0 {
+            line.push_str(&format!("  dropped {}", dropped));
+        }
+        line
+    }
+}
+
+/// Format size in bytes using binary units, e.g. "1.2 MiB".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
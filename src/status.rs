@@ -1,3 +1,6 @@
+use clap::ValueEnum;
+use std::str::FromStr;
+
 // These constants follow bash conventions for exit codes.
 // They are not standartizied, but are quite common.
 
@@ -12,6 +15,18 @@ pub const EXIT_FAILURE: i32 = 1;
 /// E.g. missing required option.
 pub const EXIT_USAGE: i32 = 2;
 
+/// Command succeeded, but produced no output.
+/// Reported only with --fail-if-silent.
+pub const EXIT_COMMAND_SILENT: i32 = 3;
+
+/// Command succeeded, but its output matched regex.
+/// Reported only with --fail-on-match.
+pub const EXIT_OUTPUT_MATCHED: i32 = 4;
+
+/// Command produced no output in time and was killed.
+/// Reported only with --first-output-timeout.
+pub const EXIT_OUTPUT_TIMEOUT: i32 = 5;
+
 /// Command invoked cannot execute.
 /// E.g. execvp() returned error.
 pub const EXIT_COMMAND_FAILED: i32 = 126;
@@ -20,3 +35,55 @@ pub const EXIT_COMMAND_FAILED: i32 = 126;
 /// The actual exit code is EXIT_COMMAND_SIGNALED + N, where
 /// N is the signal number.
 pub const EXIT_COMMAND_SIGNALED: i32 = 128;
+
+/// What exit code of reclog reflects.
+/// Set by --exit-status.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum ExitPolicy {
+    /// Exit status of command.
+    Child,
+    /// Always success, unless reclog itself fails.
+    AlwaysZero,
+    /// Whether command output matched --fail-on-match.
+    FromMatch,
+}
+
+/// Exit code remapping, parsed from "FROM=TO".
+/// Set by --map-exit.
+#[derive(Clone, Debug)]
+pub struct ExitMapping {
+    from: i32,
+    to: i32,
+}
+
+impl ExitMapping {
+    /// Apply first matching mapping to exit code.
+    pub fn apply(mappings: &[ExitMapping], code: i32) -> i32 {
+        match mappings.iter().find(|m| m.from == code) {
+            Some(m) => m.to,
+            None => code,
+        }
+    }
+}
+
+impl FromStr for ExitMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = match s.split_once('=') {
+            Some(parts) => parts,
+            None => return Err("expected FROM=TO".into()),
+        };
+
+        let parse_code = |code: &str| match code.parse::<u8>() {
+            Ok(n) => Ok(n as i32),
+            Err(_) => Err(format!("invalid exit code {:?}, expected 0..255", code)),
+        };
+
+        Ok(ExitMapping {
+            from: parse_code(from)?,
+            to: parse_code(to)?,
+        })
+    }
+}
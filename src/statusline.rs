@@ -0,0 +1,104 @@
+use crate::buffer::BufferQueue;
+use crate::stats::Stats;
+use std::fmt::Write;
+
+/// Status line in the bottom row of terminal (--status-line).
+/// Other rows become scrolling region, so that command output scrolls
+/// above status line; command is told that terminal is one row shorter.
+/// All sequences save and restore cursor position and attributes, so that
+/// they can be written between any two writes of command output.
+pub struct StatusLine {
+    reverse: bool, // render in reverse video
+    rows: u16,     // terminal height, or 0 if status line isn't shown
+    text: String,  // last drawn text
+}
+
+impl StatusLine {
+    pub fn new(reverse: bool) -> Self {
+        StatusLine {
+            reverse,
+            rows: 0,
+            text: String::new(),
+        }
+    }
+
+    /// Terminal height for which status line is set up, or 0 if not shown.
+    pub fn rows(&self) -> u16 {
+        self.rows
+    }
+
+    /// Reserve bottom row of terminal with given size, or adjust it after
+    /// resize, and draw status. Terminal should have at least 2 rows.
+    pub fn show(&mut self, out: &mut String, cols: u16, rows: u16, text: &str) {
+        if self.rows != rows {
+            if self.rows == 0 {
+                // If cursor is in the bottom row, scroll its line up, so
+                // that status line doesn't overwrite it.
+                out.push_str("\x1bD\x1bM");
+            }
+            _ = write!(out, "\x1b7\x1b[1;{}r\x1b8", rows - 1);
+            self.rows = rows;
+            self.text.clear();
+        }
+
+        // Status is redrawn only when it changes.
+        let text: String = text.chars().take(cols as usize).collect();
+        if text == self.text {
+            return;
+        }
+        _ = write!(out, "\x1b7\x1b[{};1H\x1b[2K", rows);
+        if self.reverse {
+            _ = write!(out, "\x1b[7m{}\x1b[27m", text);
+        } else {
+            out.push_str(&text);
+        }
+        out.push_str("\x1b8");
+        self.text = text;
+    }
+
+    /// Forget that status line is shown, after it was removed from
+    /// terminal by someone else, so that next show() sets it up again.
+    pub fn forget(&mut self) {
+        self.rows = 0;
+    }
+
+    /// Release bottom row and clear it.
+    pub fn hide(&mut self, out: &mut String) {
+        if self.rows == 0 {
+            return;
+        }
+        out.push_str(&hide_sequence(self.rows));
+        self.rows = 0;
+    }
+}
+
+/// Sequence that removes status line from terminal with given height.
+pub fn hide_sequence(rows: u16) -> String {
+    format!("\x1b7\x1b[r\x1b[{};1H\x1b[2K\x1b8", rows)
+}
+
+/// Format status, e.g.:
+/// "[reclog] 00:01:05  build.log  12345 lines  dropped 3  paused".
+pub fn format_status(stats: &Stats, buf_queue: &BufferQueue, path: &str, paused: bool) -> String {
+    let secs = stats.elapsed().as_secs();
+    let mut text = format!(
+        "[reclog] {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    // Path is empty with --null.
+    if !path.is_empty() {
+        _ = write!(text, "  {}", path);
+    }
+    _ = write!(text, "  {} lines", stats.lines());
+    // Lines not shown on stdout because it was too slow.
+    let dropped = buf_queue.dropped();
+    if dropped > 0 {
+        _ = write!(text, "  dropped {}", dropped);
+    }
+    if paused {
+        text.push_str("  paused");
+    }
+    text
+}
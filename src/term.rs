@@ -1,9 +1,10 @@
 use crate::error::SysError;
+use clap::ValueEnum;
 use rustix::io::retry_on_intr;
 use rustix::termios::{self, LocalModes, OptionalActions, SpecialCodeIndex, Termios};
+use std::borrow::Cow;
 use std::io::{Error, LineWriter, Write};
 use std::os::fd::AsFd;
-use std::slice;
 
 /// Check if descriptor is a tty.
 pub fn is_tty<Fd: AsFd>(fd: Fd) -> bool {
@@ -13,6 +14,7 @@ pub fn is_tty<Fd: AsFd>(fd: Fd) -> bool {
 /// Input mode of a tty.
 pub enum TtyMode {
     Canon,
+    CanonEcho,
     CanonNoEcho,
 }
 
@@ -25,6 +27,7 @@ pub fn set_tty_mode<Fd: AsFd>(tty_fd: Fd, mode: TtyMode) -> Result<(), SysError>
 
     match mode {
         TtyMode::Canon => term.local_modes |= LocalModes::ICANON,
+        TtyMode::CanonEcho => term.local_modes |= LocalModes::ICANON | LocalModes::ECHO,
         TtyMode::CanonNoEcho => {
             term.local_modes |= LocalModes::ICANON;
             term.local_modes &= !LocalModes::ECHO;
@@ -38,6 +41,29 @@ pub fn set_tty_mode<Fd: AsFd>(tty_fd: Fd, mode: TtyMode) -> Result<(), SysError>
     Ok(())
 }
 
+/// Local modes that programs clear, along with ECHO, while reading a
+/// password: getpass(3) clears ISIG, sudo(8) and `read -s` clear ECHOK.
+const PASSWORD_MODES: LocalModes = LocalModes::ECHO
+    .union(LocalModes::ECHOE)
+    .union(LocalModes::ECHOK)
+    .union(LocalModes::ISIG);
+
+/// Get local modes of a tty.
+pub fn get_local_modes<Fd: AsFd>(tty_fd: Fd) -> Result<LocalModes, SysError> {
+    match retry_on_intr(|| termios::tcgetattr(&tty_fd)) {
+        Ok(term) => Ok(term.local_modes),
+        Err(err) => Err(SysError("tcgetattr()", err)),
+    }
+}
+
+/// Check if a program has hidden input of a tty, e.g. to read a password,
+/// given local modes of the tty before and now.
+/// Full-screen programs leave canonical mode and echo input themselves,
+/// so they don't count.
+pub fn input_hidden(before: LocalModes, now: LocalModes) -> bool {
+    now.contains(LocalModes::ICANON) && !(before & PASSWORD_MODES).difference(now).is_empty()
+}
+
 /// Table of tty special codes.
 #[allow(non_snake_case)]
 pub struct TtyCodes {
@@ -58,15 +84,25 @@ pub fn get_tty_codes<Fd: AsFd>(tty_fd: Fd) -> Result<TtyCodes, SysError> {
     Ok(codes)
 }
 
-/// Copy win size from src to dst.
+/// Get win size as columns and rows.
+pub fn tty_size<Fd: AsFd>(tty_fd: Fd) -> Result<(u16, u16), SysError> {
+    match retry_on_intr(|| termios::tcgetwinsize(&tty_fd)) {
+        Ok(win_size) => Ok((win_size.ws_col, win_size.ws_row)),
+        Err(err) => Err(SysError("tcgetwinsize()", err)),
+    }
+}
+
+/// Copy win size from src to dst, minus `reserved_rows` at the bottom.
 pub fn copy_tty_size<DstFd: AsFd, SrcFd: AsFd>(
     dst_tty_fd: DstFd,
     src_tty_fd: SrcFd,
+    reserved_rows: u16,
 ) -> Result<(), SysError> {
-    let win_size = match retry_on_intr(|| termios::tcgetwinsize(&src_tty_fd)) {
+    let mut win_size = match retry_on_intr(|| termios::tcgetwinsize(&src_tty_fd)) {
         Ok(win_size) => win_size,
         Err(err) => return Err(SysError("tcgetwinsize()", err)),
     };
+    win_size.ws_row = win_size.ws_row.saturating_sub(reserved_rows);
 
     if let Err(err) = retry_on_intr(|| termios::tcsetwinsize(&dst_tty_fd, win_size)) {
         return Err(SysError("tcsetwinsize()", err));
@@ -91,6 +127,75 @@ pub fn restore_tty_state<Fd: AsFd>(tty_fd: Fd, term: &Termios) -> Result<(), Sys
     Ok(())
 }
 
+/// Who echoes input forwarded to command (--echo).
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum EchoMode {
+    /// Child's terminal echoes input, so it's recorded; our tty doesn't.
+    On,
+    /// Our tty echoes input, so it's not recorded; child's terminal doesn't.
+    Off,
+    /// On with --stdin-file, off otherwise.
+    Auto,
+}
+
+/// How to decode command output.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum Encoding {
+    /// UTF-8, invalid sequences are replaced with U+FFFD.
+    Utf8,
+    /// ISO-8859-1, every byte is a character.
+    Latin1,
+    /// Bytes are passed through as is.
+    Binary,
+}
+
+/// Convert line (from given offset) to UTF-8 according to encoding.
+pub fn decode_line(line: &mut Vec<u8>, start: usize, encoding: Encoding) {
+    match encoding {
+        Encoding::Utf8 => {
+            if let Cow::Owned(text) = String::from_utf8_lossy(&line[start..]) {
+                line.truncate(start);
+                line.extend_from_slice(text.as_bytes());
+            }
+        }
+        Encoding::Latin1 => {
+            if line[start..].is_ascii() {
+                return;
+            }
+            let text: String = line[start..].iter().map(|&b| b as char).collect();
+            line.truncate(start);
+            line.extend_from_slice(text.as_bytes());
+        }
+        Encoding::Binary => {}
+    }
+}
+
+/// Which ANSI escape codes to strip.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum StripLevel {
+    /// Strip all escape codes.
+    All,
+    /// Strip cursor movement, screen clears, titles, etc., but keep colors.
+    Control,
+    /// Don't strip anything.
+    None,
+}
+
+/// How to handle carriage return (CR) when stripping.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum CrMode {
+    /// Text after CR overwrites the beginning of the line, like on terminal.
+    Overwrite,
+    /// CR starts a new line.
+    Newline,
+    /// CR is removed.
+    Drop,
+}
+
 /// Wrapper writer that strips ANSI escape codes from text and passes the
 /// stripped text to the underlying writer.
 /// Use of full-fledged VTE parser (from `vte` crate) instead of a naive
@@ -102,11 +207,24 @@ pub struct AnsiStripper<W: Write> {
 }
 
 impl<W: Write> AnsiStripper<W> {
+    /// Create stripper that removes all escape codes.
     pub fn new(output: W) -> Self {
+        Self::with_modes(output, StripLevel::All, CrMode::Drop)
+    }
+
+    /// Create stripper that removes escape codes according to level and
+    /// handles carriage returns according to mode.
+    pub fn with_modes(output: W, level: StripLevel, cr_mode: CrMode) -> Self {
         AnsiStripper {
             parser: vte::Parser::new(),
             performer: AnsiPerformer {
                 line_writer: LineWriter::new(output),
+                level,
+                cr_mode,
+                line: Vec::new(),
+                cursor: 0,
+                pending_seq: Vec::new(),
+                pending_cr: false,
                 last_err: None,
             },
         }
@@ -115,9 +233,21 @@ impl<W: Write> AnsiStripper<W> {
 
 impl<W: Write> Write for AnsiStripper<W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.performer.level == StripLevel::None {
+            return self.performer.line_writer.write(buf);
+        }
+
         // We write bytes to parser, parse invokes performer,
         // performer writes bytes to output vector.
-        self.parser.advance(&mut self.performer, buf);
+        // Invalid UTF-8 sequences bypass parser and are passed through as is
+        // (parser would replace them with U+FFFD).
+        for chunk in buf.utf8_chunks() {
+            self.parser
+                .advance(&mut self.performer, chunk.valid().as_bytes());
+            if !chunk.invalid().is_empty() {
+                self.performer.write_text(chunk.invalid(), true);
+            }
+        }
 
         if let Some(err) = self.performer.last_err.take() {
             return Err(err);
@@ -127,6 +257,13 @@ impl<W: Write> Write for AnsiStripper<W> {
     }
 
     fn flush(&mut self) -> Result<(), Error> {
+        // Incomplete line can't be overwritten after this point.
+        self.performer.flush_line(false);
+
+        if let Some(err) = self.performer.last_err.take() {
+            return Err(err);
+        }
+
         self.performer.line_writer.flush()
     }
 }
@@ -134,27 +271,312 @@ impl<W: Write> Write for AnsiStripper<W> {
 /// Implements callbacks for vte::Parser.
 struct AnsiPerformer<W: Write> {
     line_writer: LineWriter<W>,
+    level: StripLevel,
+    cr_mode: CrMode,
+    // In overwrite mode, current line is assembled here, one cell per
+    // character, together with preceding kept escape codes.
+    line: Vec<Vec<u8>>,
+    cursor: usize,
+    pending_seq: Vec<u8>,
+    // In newline mode, set when CR was seen and not yet followed by LF.
+    pending_cr: bool,
     last_err: Option<Error>,
 }
 
+impl<W: Write> AnsiPerformer<W> {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.last_err.is_none() {
+            self.last_err = self.line_writer.write_all(bytes).err();
+        }
+    }
+
+    /// Write regular text (character or kept escape code).
+    fn write_text(&mut self, text: &[u8], is_char: bool) {
+        match self.cr_mode {
+            CrMode::Overwrite => {
+                self.pending_seq.extend_from_slice(text);
+                if is_char {
+                    let cell = std::mem::take(&mut self.pending_seq);
+                    if self.cursor < self.line.len() {
+                        self.line[self.cursor] = cell;
+                    } else {
+                        self.line.push(cell);
+                    }
+                    self.cursor += 1;
+                }
+            }
+            CrMode::Newline => {
+                if self.pending_cr {
+                    self.pending_cr = false;
+                    self.write_bytes(b"\n");
+                }
+                self.write_bytes(text);
+            }
+            CrMode::Drop => {
+                self.write_bytes(text);
+            }
+        }
+    }
+
+    /// In overwrite mode, write assembled line.
+    fn flush_line(&mut self, newline: bool) {
+        if self.cr_mode != CrMode::Overwrite {
+            return;
+        }
+
+        let mut text = self.line.concat();
+        text.extend_from_slice(&self.pending_seq);
+        if newline {
+            text.push(b'\n');
+        }
+        self.write_bytes(&text);
+
+        self.line.clear();
+        self.cursor = 0;
+        self.pending_seq.clear();
+    }
+
+    /// In overwrite mode, handle "erase in line" sequence.
+    fn erase_line(&mut self, mode: u16) {
+        match mode {
+            // From cursor to end.
+            0 => self.line.truncate(self.cursor),
+            // From beginning to cursor.
+            1 => {
+                for n in 0..self.cursor.min(self.line.len()) {
+                    self.line[n] = b" ".to_vec();
+                }
+            }
+            // Whole line.
+            _ => {
+                self.line.clear();
+                self.line.resize(self.cursor, b" ".to_vec());
+            }
+        }
+    }
+}
+
 impl<W: Write> vte::Perform for AnsiPerformer<W> {
     /// Called for each regular character.
     fn print(&mut self, c: char) {
         // Write all regular characters as-is.
-        self.last_err = self
-            .line_writer
-            .write_all(slice::from_ref(&(c as u8)))
-            .err();
+        let mut buf = [0u8; 4];
+        self.write_text(c.encode_utf8(&mut buf).as_bytes(), true);
     }
 
     /// Called for each special character.
     fn execute(&mut self, b: u8) {
         // Handle only selected special characters and ignore others.
-        if b == b'\t' || b == b'\n' {
-            self.last_err = self.line_writer.write_all(slice::from_ref(&b)).err();
+        match b {
+            b'\t' => self.write_text(b"\t", true),
+            b'\n' => match self.cr_mode {
+                CrMode::Overwrite => self.flush_line(true),
+                _ => {
+                    self.pending_cr = false;
+                    self.write_bytes(b"\n");
+                }
+            },
+            b'\r' => match self.cr_mode {
+                CrMode::Overwrite => self.cursor = 0,
+                CrMode::Newline => self.pending_cr = true,
+                CrMode::Drop => {}
+            },
+            // Backspace.
+            0x08 if self.cr_mode == CrMode::Overwrite => {
+                self.cursor = self.cursor.saturating_sub(1);
+            }
+            _ => {}
         }
     }
 
-    // For all other sequences, keep default no-op implementation
-    // from vte::Perform trait.
+    /// Called for each CSI sequence.
+    fn csi_dispatch(
+        &mut self,
+        params: &vte::Params,
+        intermediates: &[u8],
+        ignore: bool,
+        action: char,
+    ) {
+        if ignore || !intermediates.is_empty() {
+            return;
+        }
+
+        // Erasing is needed to get the final state of the line.
+        if action == 'K' && self.cr_mode == CrMode::Overwrite {
+            let mode = params.iter().next().and_then(|p| p.first().copied());
+            self.erase_line(mode.unwrap_or(0));
+            return;
+        }
+
+        // Keep only SGR sequences (colors and text attributes), if requested.
+        if self.level != StripLevel::Control || action != 'm' {
+            return;
+        }
+
+        let mut seq = String::from("\x1b[");
+        for (n, param) in params.iter().enumerate() {
+            if n != 0 {
+                seq.push(';');
+            }
+            for (k, subparam) in param.iter().enumerate() {
+                if k != 0 {
+                    seq.push(':');
+                }
+                seq.push_str(&subparam.to_string());
+            }
+        }
+        seq.push('m');
+
+        self.write_text(seq.as_bytes(), false);
+    }
+
+    // For all other sequences (cursor movement, OSC, etc.), keep default
+    // no-op implementation from vte::Perform trait.
+}
+
+/// Strip ANSI escape codes from text.
+/// Convenient for matching lines against patterns.
+pub fn strip_ansi(text: &[u8]) -> String {
+    strip_ansi_level(text, StripLevel::All).into_owned()
+}
+
+/// Strip ANSI escape codes from text according to level.
+/// Text isn't copied if nothing is stripped.
+pub fn strip_ansi_level(text: &[u8], level: StripLevel) -> Cow<'_, str> {
+    strip_ansi_modes(text, level, CrMode::Drop)
+}
+
+/// Strip ANSI escape codes from text according to level, and handle
+/// carriage returns according to mode.
+pub fn strip_ansi_modes(text: &[u8], level: StripLevel, cr_mode: CrMode) -> Cow<'_, str> {
+    if level == StripLevel::None {
+        return String::from_utf8_lossy(text);
+    }
+
+    let mut stripper = AnsiStripper::with_modes(Vec::new(), level, cr_mode);
+    _ = stripper.write_all(text);
+    _ = stripper.flush();
+
+    let bytes = stripper
+        .performer
+        .line_writer
+        .into_inner()
+        .unwrap_or_default();
+    Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(input: &[u8], level: StripLevel, cr_mode: CrMode) -> String {
+        let mut stripper = AnsiStripper::with_modes(Vec::new(), level, cr_mode);
+        stripper.write_all(input).unwrap();
+        stripper.flush().unwrap();
+        let bytes = stripper.performer.line_writer.into_inner().unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn strip_levels() {
+        let input = b"\x1b[1;31mred\x1b[0m \x1b[2Kplain\x1b]0;title\x07\n";
+        assert_eq!(strip(input, StripLevel::All, CrMode::Drop), "red plain\n");
+        assert_eq!(
+            strip(input, StripLevel::Control, CrMode::Drop),
+            "\x1b[1;31mred\x1b[0m plain\n"
+        );
+        assert_eq!(
+            strip(input, StripLevel::None, CrMode::Drop).as_bytes(),
+            input
+        );
+    }
+
+    #[test]
+    fn cr_drop() {
+        let input = b"10%\r50%\r100%\n";
+        assert_eq!(strip(input, StripLevel::All, CrMode::Drop), "10%50%100%\n");
+    }
+
+    #[test]
+    fn cr_overwrite() {
+        let input = b"10%\r50%\r100%\ndone\n";
+        assert_eq!(
+            strip(input, StripLevel::All, CrMode::Overwrite),
+            "100%\ndone\n"
+        );
+
+        // Shorter text overwrites only beginning of the line.
+        let input = b"loading...\rok\n";
+        assert_eq!(
+            strip(input, StripLevel::All, CrMode::Overwrite),
+            "okading...\n"
+        );
+
+        // Erase in line.
+        let input = b"loading...\r\x1b[Kok\n";
+        assert_eq!(strip(input, StripLevel::All, CrMode::Overwrite), "ok\n");
+
+        // Kept escape codes are attached to the following character.
+        let input = b"\x1b[31mab\x1b[0m\r\x1b[32mc\x1b[0m\n";
+        assert_eq!(
+            strip(input, StripLevel::Control, CrMode::Overwrite),
+            "\x1b[0m\x1b[32mcb\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn cr_overwrite_incomplete() {
+        // Incomplete line is written on flush.
+        let input = b"10%\r50%";
+        assert_eq!(strip(input, StripLevel::All, CrMode::Overwrite), "50%");
+    }
+
+    #[test]
+    fn cr_newline() {
+        let input = b"10%\r50%\r100%\n";
+        assert_eq!(
+            strip(input, StripLevel::All, CrMode::Newline),
+            "10%\n50%\n100%\n"
+        );
+
+        // CRLF is a single line ending.
+        let input = b"one\r\ntwo\r\n";
+        assert_eq!(strip(input, StripLevel::All, CrMode::Newline), "one\ntwo\n");
+    }
+
+    #[test]
+    fn backspace() {
+        let input = b"abc\x08\x08XY\n";
+        assert_eq!(strip(input, StripLevel::All, CrMode::Overwrite), "aXY\n");
+        // Backspace is removed in other modes.
+        assert_eq!(strip(input, StripLevel::All, CrMode::Drop), "abcXY\n");
+
+        // Backspace at beginning of line stays there.
+        let input = b"\x08\x08ab\n";
+        assert_eq!(strip(input, StripLevel::All, CrMode::Overwrite), "ab\n");
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let input = b"a\xffb\x1b[1mc\n";
+        assert_eq!(
+            strip_ansi_level(input, StripLevel::All),
+            String::from_utf8_lossy(b"a\xffbc\n")
+        );
+    }
+
+    #[test]
+    fn decode() {
+        let mut line = b"ts \xffa\xe9".to_vec();
+        decode_line(&mut line, 3, Encoding::Latin1);
+        assert_eq!(line, "ts \u{ff}a\u{e9}".as_bytes());
+
+        let mut line = b"ts \xffa".to_vec();
+        decode_line(&mut line, 3, Encoding::Utf8);
+        assert_eq!(line, "ts \u{fffd}a".as_bytes());
+
+        let mut line = b"ts \xffa".to_vec();
+        decode_line(&mut line, 3, Encoding::Binary);
+        assert_eq!(line, b"ts \xffa");
+    }
 }
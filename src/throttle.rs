@@ -0,0 +1,130 @@
+use crate::clock::Clock;
+use crate::rlimit;
+use std::io::{self, Read};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Don't sleep for less than this; delays of small reads are accumulated
+/// instead, so that rate isn't skewed by sleep granularity.
+const MIN_SLEEP: Duration = Duration::from_millis(10);
+
+/// How many reads per second are done at max rate; limits how long reader
+/// sleeps at once.
+const READS_PER_SEC: u64 = 10;
+
+/// Max bytes per second read from command (--rate-limit).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit(pub u64);
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parse number with optional K, M, G, or T suffix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match rlimit::parse_limit(s)? {
+            Some(0) => Err("rate can't be zero".into()),
+            Some(n) => Ok(RateLimit(n)),
+            None => Err("rate can't be unlimited".into()),
+        }
+    }
+}
+
+/// Reader that paces reads so that average rate doesn't exceed the limit.
+/// While reader sleeps, command output accumulates in pty until it's full,
+/// and then the command blocks on write.
+/// Without limit, reads are passed through as is.
+pub struct ThrottledReader<R: Read> {
+    input: R,
+    limit: Option<RateLimit>,
+    clock: Rc<dyn Clock>,
+    // When reading may proceed, given bytes read so far.
+    deadline: Instant,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(input: R, limit: Option<RateLimit>, clock: Rc<dyn Clock>) -> Self {
+        ThrottledReader {
+            input,
+            limit,
+            deadline: clock.now(),
+            clock,
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let rate = match self.limit {
+            Some(RateLimit(rate)) => rate,
+            None => return self.input.read(buf),
+        };
+
+        let chunk = (rate / READS_PER_SEC).max(1).min(buf.len() as u64) as usize;
+        let size = self.input.read(&mut buf[..chunk])?;
+
+        // Time when command was idle isn't saved up for later bursts.
+        let now = self.clock.now();
+        if self.deadline < now {
+            self.deadline = now;
+        }
+        self.deadline += Duration::from_secs_f64(size as f64 / rate as f64);
+
+        let delay = self.deadline - now;
+        if delay >= MIN_SLEEP {
+            self.clock.sleep(delay);
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    #[test]
+    fn rate() {
+        let clock = Rc::new(VirtualClock::new());
+        let start = clock.now();
+        let input = vec![b'x'; 10_000];
+        let mut reader = ThrottledReader::new(&input[..], Some(RateLimit(1000)), clock.clone());
+
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+
+        assert_eq!(output, input);
+        // Last read isn't followed by sleep.
+        let elapsed = clock.now() - start;
+        assert!(elapsed >= Duration::from_millis(9_900), "{:?}", elapsed);
+        assert!(elapsed <= Duration::from_secs(10), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn idle_time_not_saved() {
+        let clock = Rc::new(VirtualClock::new());
+        let input = [b'x'; 100];
+        let mut reader = ThrottledReader::new(&input[..], Some(RateLimit(100)), clock.clone());
+
+        // Command was idle for a while.
+        clock.advance(Duration::from_secs(60));
+        let start = clock.now();
+
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert!(clock.now() - start >= Duration::from_millis(900));
+    }
+
+    #[test]
+    fn unlimited() {
+        let clock = Rc::new(VirtualClock::new());
+        let start = clock.now();
+        let input = vec![b'x'; 10_000];
+        let mut reader = ThrottledReader::new(&input[..], None, clock.clone());
+
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+        assert_eq!(clock.now(), start);
+    }
+}
@@ -0,0 +1,264 @@
+use chrono::{DateTime, Local};
+use std::io::{self, BufRead, Error, ErrorKind, Write};
+use std::time::{Duration, Instant};
+
+/// Format of START_TIME header entry.
+const START_TIME_FMT: &str = "%F %T%.6f %z";
+
+/// Writes timing file in util-linux script(1) "advanced" format.
+/// Each entry is a line "TYPE DELAY SIZE", where DELAY is seconds since
+/// previous entry, and SIZE is number of bytes written to the recording.
+/// Header entries have form "H DELAY NAME VALUE".
+/// The file can be used with scriptreplay(1) and with `reclog render`.
+pub struct TimingWriter<W: Write> {
+    output: W,
+    last_ts: Option<Instant>,
+}
+
+impl<W: Write> TimingWriter<W> {
+    pub fn new(output: W) -> Self {
+        TimingWriter {
+            output,
+            last_ts: None,
+        }
+    }
+
+    /// Write header entries.
+    pub fn write_header(&mut self, start_time: DateTime<Local>) -> io::Result<()> {
+        writeln!(
+            &mut self.output,
+            "H 0.000000 START_TIME {}",
+            start_time.format(START_TIME_FMT)
+        )?;
+        self.output.flush()
+    }
+
+    /// Write entry for output chunk of given size, produced at given moment.
+    pub fn write_output(&mut self, ts: Instant, size: usize) -> io::Result<()> {
+        let delay = self.advance(ts);
+
+        self.write_entry(delay, size)
+    }
+
+    /// Write entry for terminal resize to given size, happened at given
+    /// moment, like script(1) does on SIGWINCH.
+    pub fn write_resize(&mut self, ts: Instant, cols: u16, rows: u16) -> io::Result<()> {
+        let delay = self.advance(ts);
+
+        writeln!(
+            &mut self.output,
+            "S {}.{:06} SIGWINCH ROWS={} COLS={}",
+            delay.as_secs(),
+            delay.subsec_micros(),
+            rows,
+            cols
+        )?;
+        self.output.flush()
+    }
+
+    /// Write entry of given type, with given delay since previous entry,
+    /// e.g. one read as TimingEntry::Other.
+    pub fn write_other(&mut self, delay: Duration, kind: &str, data: &str) -> io::Result<()> {
+        writeln!(
+            &mut self.output,
+            "{} {}.{:06} {}",
+            kind,
+            delay.as_secs(),
+            delay.subsec_micros(),
+            data
+        )?;
+        self.output.flush()
+    }
+
+    /// Get delay since previous entry and remember moment of the new one.
+    fn advance(&mut self, ts: Instant) -> Duration {
+        let delay = match self.last_ts {
+            Some(last_ts) => ts.saturating_duration_since(last_ts),
+            None => Duration::ZERO,
+        };
+        self.last_ts = Some(ts);
+        delay
+    }
+
+    /// Write entry for output chunk of given size, with given delay since
+    /// previous entry.
+    pub fn write_entry(&mut self, delay: Duration, size: usize) -> io::Result<()> {
+        writeln!(
+            &mut self.output,
+            "O {}.{:06} {}",
+            delay.as_secs(),
+            delay.subsec_micros(),
+            size
+        )?;
+        self.output.flush()
+    }
+}
+
+/// Parsed timing entry.
+#[derive(Debug)]
+pub enum TimingEntry {
+    /// Start time of the recording.
+    StartTime(DateTime<Local>),
+    /// Output chunk: delay since previous entry and size in bytes.
+    Output(Duration, usize),
+    /// Any other entry (input, signal, unknown header): delay since
+    /// previous entry, type, and the rest of the entry after delay.
+    Other {
+        delay: Duration,
+        kind: String,
+        data: String,
+    },
+}
+
+/// Reads timing file in script(1) "classic" or "advanced" format.
+pub struct TimingReader<R: BufRead> {
+    input: R,
+    line: String,
+    line_num: usize,
+}
+
+impl<R: BufRead> TimingReader<R> {
+    pub fn new(input: R) -> Self {
+        TimingReader {
+            input,
+            line: String::new(),
+            line_num: 0,
+        }
+    }
+
+    /// Read next entry.
+    /// Returns None on EOF.
+    pub fn read_entry(&mut self) -> io::Result<Option<TimingEntry>> {
+        loop {
+            self.line.clear();
+            if self.input.read_line(&mut self.line)? == 0 {
+                return Ok(None);
+            }
+            self.line_num += 1;
+
+            let line = self.line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+
+            return self.parse_entry(line).map(Some);
+        }
+    }
+
+    fn parse_entry(&self, line: &str) -> io::Result<TimingEntry> {
+        let fields: Vec<&str> = line.splitn(4, ' ').collect();
+
+        // Classic format: "DELAY SIZE".
+        if fields.len() == 2 {
+            return Ok(TimingEntry::Output(
+                self.parse_delay(fields[0])?,
+                self.parse_size(fields[1])?,
+            ));
+        }
+
+        // Advanced format: "TYPE DELAY ...".
+        if fields.len() < 3 {
+            return Err(self.bad_entry());
+        }
+        let delay = self.parse_delay(fields[1])?;
+
+        match fields[0] {
+            "O" => Ok(TimingEntry::Output(delay, self.parse_size(fields[2])?)),
+            "H" if fields[2] == "START_TIME" && fields.len() == 4 => {
+                match DateTime::parse_from_str(fields[3], START_TIME_FMT)
+                    .or_else(|_| DateTime::parse_from_str(fields[3], "%F %T %z"))
+                {
+                    Ok(ts) => Ok(TimingEntry::StartTime(ts.with_timezone(&Local))),
+                    Err(_) => Err(self.bad_entry()),
+                }
+            }
+            kind => Ok(TimingEntry::Other {
+                delay,
+                kind: kind.into(),
+                data: line.splitn(3, ' ').nth(2).unwrap_or_default().into(),
+            }),
+        }
+    }
+
+    fn parse_delay(&self, s: &str) -> io::Result<Duration> {
+        match s.parse::<f64>() {
+            Ok(secs) if secs >= 0.0 => {
+                Duration::try_from_secs_f64(secs).map_err(|_| self.bad_entry())
+            }
+            _ => Err(self.bad_entry()),
+        }
+    }
+
+    fn parse_size(&self, s: &str) -> io::Result<usize> {
+        s.parse::<usize>().map_err(|_| self.bad_entry())
+    }
+
+    fn bad_entry(&self) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("malformed timing entry at line {}", self.line_num),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(input: &str) -> io::Result<Vec<TimingEntry>> {
+        let mut reader = TimingReader::new(input.as_bytes());
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.read_entry()? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    #[test]
+    fn delay() {
+        let entries = read_all("0.5 3\nO 1.25 4\n").unwrap();
+        assert!(matches!(entries[0], TimingEntry::Output(d, 3) if d == Duration::from_millis(500)));
+        assert!(
+            matches!(entries[1], TimingEntry::Output(d, 4) if d == Duration::from_millis(1250))
+        );
+    }
+
+    #[test]
+    fn bad_delay() {
+        for input in [
+            "O 1e30 5\n",
+            "O -1 5\n",
+            "O inf 5\n",
+            "O NaN 5\n",
+            "1e30 5\n",
+        ] {
+            let err = read_all(input).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "input: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn other() {
+        let input = "I 0.5 3\nS 1.000000 SIGWINCH ROWS=24 COLS=80\n";
+        let entries = read_all(input).unwrap();
+        assert!(matches!(&entries[0],
+            TimingEntry::Other { delay, kind, data }
+                if *delay == Duration::from_millis(500) && kind == "I" && data == "3"));
+        assert!(matches!(&entries[1],
+            TimingEntry::Other { delay, kind, data }
+                if *delay == Duration::from_secs(1) && kind == "S"
+                    && data == "SIGWINCH ROWS=24 COLS=80"));
+
+        // Entries are written back as is.
+        let mut writer = TimingWriter::new(Vec::new());
+        for entry in &entries {
+            if let TimingEntry::Other { delay, kind, data } = entry {
+                writer.write_other(*delay, kind, data).unwrap();
+            }
+        }
+        assert_eq!(
+            String::from_utf8(writer.output).unwrap(),
+            "I 0.500000 3\nS 1.000000 SIGWINCH ROWS=24 COLS=80\n"
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Writes recording in classic ttyrec format, used by ttyrec(1), ttyplay(1),
+/// ipbt(1), and other players.
+/// Each record is a header of three little-endian 32-bit integers (seconds
+/// and microseconds of wall-clock time, and data length), followed by data.
+pub struct TtyrecWriter<W: Write> {
+    output: W,
+    record: Vec<u8>,
+}
+
+impl<W: Write> TtyrecWriter<W> {
+    pub fn new(output: W) -> Self {
+        TtyrecWriter {
+            output,
+            record: Vec::new(),
+        }
+    }
+
+    /// Write record for output chunk, produced at given moment.
+    pub fn write_output(&mut self, ts: SystemTime, data: &[u8]) -> io::Result<()> {
+        let time = ts.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let len = match u32::try_from(data.len()) {
+            Ok(len) => len,
+            Err(_) => return Err(Error::new(ErrorKind::InvalidInput, "chunk too large")),
+        };
+
+        // Header and data are written at once, so that a reader never sees
+        // partial header.
+        self.record.clear();
+        self.record
+            .extend_from_slice(&(time.as_secs() as u32).to_le_bytes());
+        self.record
+            .extend_from_slice(&time.subsec_micros().to_le_bytes());
+        self.record.extend_from_slice(&len.to_le_bytes());
+        self.record.extend_from_slice(data);
+
+        self.output.write_all(&self.record)?;
+        self.output.flush()
+    }
+
+    /// Write record for terminal resize at given moment.
+    /// Format has no special record for that, so it's recorded as xterm
+    /// sequence that asks terminal to resize window, which is honored by
+    /// some players and terminals, and ignored by others.
+    pub fn write_resize(&mut self, ts: SystemTime, cols: u16, rows: u16) -> io::Result<()> {
+        self.write_output(ts, format!("\x1b[8;{};{}t", rows, cols).as_bytes())
+    }
+}
+
+/// Reads recording in ttyrec format, written by TtyrecWriter or ttyrec(1).
+pub struct TtyrecReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> TtyrecReader<R> {
+    pub fn new(input: R) -> Self {
+        TtyrecReader { input }
+    }
+
+    /// Read next record: wall-clock time and data.
+    /// Returns None on EOF.
+    pub fn read_record(&mut self) -> io::Result<Option<(SystemTime, Vec<u8>)>> {
+        let mut header = [0; 12];
+        match self.input.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.input.read_exact(&mut header[1..]).map_err(truncated)?,
+        }
+
+        let field = |n: usize| u32::from_le_bytes(header[n * 4..n * 4 + 4].try_into().unwrap());
+        let (sec, usec, len) = (field(0), field(1), field(2));
+        if usec >= 1_000_000 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "malformed ttyrec header",
+            ));
+        }
+
+        let mut data = vec![0; len as usize];
+        self.input.read_exact(&mut data).map_err(truncated)?;
+
+        let ts = UNIX_EPOCH + Duration::new(sec as u64, usec * 1000);
+        Ok(Some((ts, data)))
+    }
+}
+
+fn truncated(err: Error) -> Error {
+    if err.kind() == ErrorKind::UnexpectedEof {
+        Error::new(ErrorKind::InvalidData, "truncated ttyrec record")
+    } else {
+        err
+    }
+}
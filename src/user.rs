@@ -0,0 +1,82 @@
+use crate::error::SysError;
+use crate::shim;
+use std::str::FromStr;
+
+/// User and group to run command as (--user).
+/// Resolved in parent, so that child only needs to call setuid() and
+/// friends, which are safe after fork().
+#[derive(Debug, Clone)]
+pub struct RunAs {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+    groups: Vec<u32>,
+}
+
+impl FromStr for RunAs {
+    type Err = String;
+
+    /// Parse "USER[:GROUP]", where user and group are names or numeric IDs.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+
+        let passwd = match shim::getpwnam(user) {
+            Ok(Some(passwd)) => passwd,
+            Ok(None) => match user.parse::<u32>().map(shim::getpwuid) {
+                Ok(Ok(Some(passwd))) => passwd,
+                Ok(Err(err)) => return Err(format!("can't resolve user '{}': {}", user, err)),
+                _ => return Err(format!("unknown user '{}'", user)),
+            },
+            Err(err) => return Err(format!("can't resolve user '{}': {}", user, err)),
+        };
+
+        let gid = match group {
+            Some(group) => match shim::getgrnam(group) {
+                Ok(Some(gid)) => gid,
+                Ok(None) => match group.parse::<u32>() {
+                    Ok(gid) if gid != u32::MAX => gid,
+                    _ => return Err(format!("unknown group '{}'", group)),
+                },
+                Err(err) => return Err(format!("can't resolve group '{}': {}", group, err)),
+            },
+            None => passwd.gid,
+        };
+
+        // Supplementary groups of the user, like initgroups() does.
+        let groups = match shim::getgrouplist(&passwd.name, gid) {
+            Ok(groups) => groups,
+            Err(err) => return Err(format!("can't get groups of user '{}': {}", user, err)),
+        };
+
+        Ok(RunAs {
+            name: passwd.name,
+            uid: passwd.uid,
+            gid,
+            home: passwd.home,
+            groups,
+        })
+    }
+}
+
+impl RunAs {
+    /// Switch credentials of current process.
+    /// Should be called in child after fork().
+    /// Groups are changed first, while we still have privileges.
+    pub fn apply(&self) -> Result<(), SysError> {
+        if let Err(err) = shim::setgroups(&self.groups) {
+            return Err(SysError("setgroups()", err));
+        }
+        if let Err(err) = shim::setgid(self.gid) {
+            return Err(SysError("setgid()", err));
+        }
+        if let Err(err) = shim::setuid(self.uid) {
+            return Err(SysError("setuid()", err));
+        }
+
+        Ok(())
+    }
+}
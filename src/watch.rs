@@ -0,0 +1,99 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{self, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often watched files are checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Modification time and size of every watched file.
+type Snapshot = HashMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Detects changes of watched files and directories (--watch).
+/// Directories are scanned recursively, skipping hidden entries, like
+/// ".git". Files are polled rather than watched with inotify or kqueue,
+/// which is portable and fast enough for source trees of moderate size.
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    ignored: HashSet<PathBuf>,
+    snapshot: Snapshot,
+}
+
+impl Watcher {
+    /// Start watching given paths.
+    pub fn new(paths: &[String]) -> Self {
+        let mut watcher = Watcher {
+            paths: paths.iter().map(absolute_path).collect(),
+            ignored: HashSet::new(),
+            snapshot: Snapshot::new(),
+        };
+        watcher.snapshot = watcher.scan();
+        watcher
+    }
+
+    /// Don't report changes of given file, e.g. of log written by us.
+    pub fn ignore(&mut self, path: &str) {
+        let path = absolute_path(path);
+        self.snapshot.remove(&path);
+        self.ignored.insert(path);
+    }
+
+    /// Check if files were changed, added, or removed since previous call.
+    pub fn changed(&mut self) -> bool {
+        let snapshot = self.scan();
+        if snapshot == self.snapshot {
+            return false;
+        }
+        self.snapshot = snapshot;
+        true
+    }
+
+    fn scan(&self) -> Snapshot {
+        let mut snapshot = Snapshot::new();
+        for path in &self.paths {
+            self.scan_path(path, &mut snapshot);
+        }
+        snapshot
+    }
+
+    fn scan_path(&self, path: &PathBuf, snapshot: &mut Snapshot) {
+        if self.ignored.contains(path) {
+            return;
+        }
+        // Missing or unreadable entry is not in snapshot, so its removal
+        // is reported as a change too.
+        let Ok(meta) = fs::metadata(path) else {
+            return;
+        };
+        if !meta.is_dir() {
+            snapshot.insert(path.clone(), (meta.modified().ok(), meta.len()));
+            return;
+        }
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            self.scan_path(&entry.path(), snapshot);
+        }
+    }
+}
+
+/// Path of output file of given run: "-N" is inserted before extension,
+/// e.g. "build.log" becomes "build-2.log".
+pub fn run_path(path: &str, run: u64) -> String {
+    let name_start = path.rfind('/').map_or(0, |pos| pos + 1);
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, ext) = path.split_at(name_start + dot);
+            format!("{}-{}{}", stem, run, ext)
+        }
+        _ => format!("{}-{}", path, run),
+    }
+}
+
+fn absolute_path<P: AsRef<path::Path>>(path: P) -> PathBuf {
+    path::absolute(&path).unwrap_or_else(|_| path.as_ref().to_path_buf())
+}
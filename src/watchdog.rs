@@ -0,0 +1,48 @@
+use rustix::process::{self, Pid, Signal};
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+/// Helper process that kills command if we die without cleanup, e.g. from
+/// SIGKILL. Used where PR_SET_PDEATHSIG isn't available.
+/// Watchdog waits on a pipe whose only writer is us: it gets EOF when we
+/// exit for any reason, unless we disarm it first.
+pub struct Watchdog {
+    child: Child,
+    pipe: ChildStdin,
+}
+
+impl Watchdog {
+    /// Run watchdog of given command as hidden `helper` subcommand of `exe`.
+    /// Watchdog gets its own process group, so that signals from terminal,
+    /// like ^C, don't kill it before us.
+    pub fn spawn(exe: &Path, helper: &str, command_pid: Pid) -> io::Result<Self> {
+        let mut child = Command::new(exe)
+            .arg(helper)
+            .arg(command_pid.as_raw_nonzero().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .process_group(0)
+            .spawn()?;
+        let pipe = child.stdin.take().unwrap();
+        Ok(Watchdog { child, pipe })
+    }
+
+    /// Tell watchdog that command is finished, and wait until it exits.
+    pub fn disarm(mut self) {
+        _ = self.pipe.write_all(b"\n");
+        drop(self.pipe);
+        _ = self.child.wait();
+    }
+}
+
+/// Body of watchdog helper process.
+/// Blocks until we disarm it or die; in the latter case kills process
+/// group of command.
+pub fn watch(command_pid: Pid) {
+    let mut buf = [0u8; 1];
+    if io::stdin().read_exact(&mut buf).is_err() {
+        _ = process::kill_process_group(command_pid, Signal::KILL);
+    }
+}
@@ -1,14 +1,23 @@
 use crate::error::SysError;
-use crate::shim::{self, SelectFd};
+use crate::shim::{self, WaitFd};
+use clap::ValueEnum;
 use rustix::io::{Errno, retry_on_intr};
 use rustix::pipe;
-use std::io::{Error, Write};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
 use std::os::fd::{AsFd, OwnedFd};
-use std::sync::{Arc, Mutex};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(PartialEq)]
 enum WriterMode {
-    Open,
+    Timeout(Duration),
+    NoTimeout,
     Closed,
 }
 
@@ -35,7 +44,7 @@ impl<Fd: AsFd> InterruptibleWriter<Fd> {
         shim::fcntl_nonblock(&pipe_wr, true).map_err(|err| SysError("fcntl(pipe)", err))?;
 
         Ok(InterruptibleWriter {
-            mode: Mutex::new(WriterMode::Open),
+            mode: Mutex::new(WriterMode::NoTimeout),
             fd,
             pipe_rd,
             pipe_wr,
@@ -64,8 +73,31 @@ impl<Fd: AsFd> InterruptibleWriter<Fd> {
         Ok(())
     }
 
+    /// Set write timeout.
+    /// Will wake up and restart ongoing writes.
+    pub fn set_timeout(&self, duration: Duration) -> Result<(), SysError> {
+        {
+            // update mode
+            let mut locked_mode = self.mode.lock().unwrap();
+            if *locked_mode == WriterMode::Closed {
+                return Ok(());
+            }
+            *locked_mode = WriterMode::Timeout(duration);
+        }
+
+        // wake up and restart blocked write
+        if let Err(err) = shim::write(&self.pipe_wr, &[0u8]) {
+            if err != Errno::AGAIN {
+                return Err(SysError("write(pipe)", err));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Construct blocking writer.
-    /// Waits until descriptor is writable, or writer is closed.
+    /// Waits until descriptor is writable, OR writer is closed, OR write
+    /// timeout is set and expires (then write fails with TimedOut).
     pub fn blocking_writer(self: &Arc<Self>) -> ArcTimeoutWriter<Fd> {
         ArcTimeoutWriter(Arc::clone(self))
     }
@@ -74,24 +106,28 @@ impl<Fd: AsFd> InterruptibleWriter<Fd> {
     fn write_imp(&self, buf: &[u8]) -> Result<usize, Error> {
         loop {
             // re-read mode
-            {
+            let timeout = {
                 let locked_mode = self.mode.lock().unwrap();
-                if *locked_mode == WriterMode::Closed {
+                match *locked_mode {
+                    // write with timeout
+                    WriterMode::Timeout(d) => Some(d),
+                    // write without timeout
+                    WriterMode::NoTimeout => None,
                     // closed, silently discard all bytes
-                    return Ok(buf.len());
+                    WriterMode::Closed => return Ok(buf.len()),
                 }
             };
 
-            // wait until descriptor is ready
-            let mut pipe_fd = SelectFd {
+            // wait until descriptor is ready or timeout expires
+            let mut pipe_fd = WaitFd {
                 fd: self.pipe_rd.as_fd(),
-                mask: SelectFd::READABLE,
+                mask: WaitFd::READABLE,
             };
-            let mut data_fd = SelectFd {
+            let mut data_fd = WaitFd {
                 fd: self.fd.as_fd(),
-                mask: SelectFd::WRITEABLE,
+                mask: WaitFd::WRITEABLE,
             };
-            shim::select(&mut [&mut pipe_fd, &mut data_fd], None)?;
+            shim::wait_fds(&mut [&mut pipe_fd, &mut data_fd], timeout)?;
 
             if pipe_fd.mask != 0 {
                 // wake up from close()
@@ -105,9 +141,14 @@ impl<Fd: AsFd> InterruptibleWriter<Fd> {
                     Ok(0) => continue, // someone else filled tty/pty
                     Ok(n) => return Ok(n),
                     Err(Errno::AGAIN) => continue,
-                    Err(err) => return Err(Error::new(err.kind(), err)),
+                    Err(err) => return Err(Error::from(err)),
                 }
             }
+
+            if pipe_fd.mask == 0 && data_fd.mask == 0 && timeout.is_some() {
+                // timeout expired, fd is stalled
+                return Err(Error::from(ErrorKind::TimedOut));
+            }
         }
     }
 }
@@ -124,3 +165,287 @@ impl<Fd: AsFd> Write for ArcTimeoutWriter<Fd> {
         Ok(())
     }
 }
+
+/// Passes data to wrapped writer and counts lines and bytes written.
+/// Counters are shared, so that they remain accessible when the writer
+/// is wrapped into another one.
+pub struct LineCounter<W: Write> {
+    output: W,
+    lines: Arc<AtomicU64>,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<W: Write> LineCounter<W> {
+    pub fn new(output: W, lines: Arc<AtomicU64>, bytes: Arc<AtomicU64>) -> Self {
+        LineCounter {
+            output,
+            lines,
+            bytes,
+        }
+    }
+}
+
+impl<W: Write> Write for LineCounter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let size = self.output.write(buf)?;
+        let lines = buf[..size].iter().filter(|&&b| b == b'\n').count();
+        self.lines.fetch_add(lines as u64, Ordering::Relaxed);
+        self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()
+    }
+}
+
+/// Computes SHA-256 of written data (--checksum).
+pub struct HashingWriter<W: Write> {
+    output: W,
+    hasher: Arc<Mutex<Sha256>>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(output: W, hasher: Arc<Mutex<Sha256>>) -> Self {
+        HashingWriter { output, hasher }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        // Only bytes accepted by output are hashed.
+        let size = self.output.write(buf)?;
+        self.hasher.lock().unwrap().update(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.output.flush()
+    }
+}
+
+/// Creates file only when command produces first output (--lazy-output).
+/// Until then, written data (e.g. header) is kept in memory. If command
+/// doesn't produce any output, file is never created.
+pub struct LazyFile {
+    open_file: Box<dyn FnMut() -> Result<File, Error> + Send>,
+    first_output: Arc<OnceLock<Instant>>,
+    file: Option<File>,
+    pending: Vec<u8>,
+}
+
+impl LazyFile {
+    /// `open_file` is invoked to create file on first output.
+    pub fn new(
+        open_file: Box<dyn FnMut() -> Result<File, Error> + Send>,
+        first_output: Arc<OnceLock<Instant>>,
+    ) -> Self {
+        LazyFile {
+            open_file,
+            first_output,
+            file: None,
+            pending: Vec::new(),
+        }
+    }
+
+    fn open(&mut self) -> Result<&mut File, Error> {
+        if self.file.is_none() {
+            let mut file = (self.open_file)()?;
+            file.write_all(&self.pending)?;
+            self.pending = Vec::new();
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl Write for LazyFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.file.is_none() && self.first_output.get().is_none() {
+            self.pending.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+
+        self.open()?.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What to do when --output is a FIFO and there is no reader, or the
+/// reader is too slow.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[clap(rename_all = "kebab_case")]
+pub enum FifoPolicy {
+    /// Block until reader appears and reads data.
+    Wait,
+    /// Drop data that can't be written right away.
+    Drop,
+    /// Fail if there is no reader or reader goes away; block when FIFO is
+    /// full.
+    Fail,
+}
+
+/// How often FifoWriter checks whether reader has appeared, with wait
+/// policy.
+const FIFO_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Writes to FIFO (named pipe) according to policy.
+/// Unlike regular open(), doesn't block when there is no reader, so that
+/// command can be started anyway.
+pub struct FifoWriter {
+    path: PathBuf,
+    policy: FifoPolicy,
+    writer: Option<InterruptibleWriter<OwnedFd>>,
+}
+
+impl FifoWriter {
+    /// Open FIFO.
+    /// With fail policy, fails if there is no reader. With other policies,
+    /// FIFO is opened later, when reader appears.
+    pub fn open(path: &Path, policy: FifoPolicy) -> Result<Self, Error> {
+        let mut fifo = FifoWriter {
+            path: path.into(),
+            policy,
+            writer: None,
+        };
+        match fifo.reopen() {
+            Err(err) if policy != FifoPolicy::Fail && is_no_reader(&err) => {}
+            result => result?,
+        }
+        Ok(fifo)
+    }
+
+    /// Open FIFO for writing; fails with ENXIO if there is no reader.
+    fn reopen(&mut self) -> Result<(), Error> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)?;
+
+        let writer = InterruptibleWriter::open(OwnedFd::from(file)).map_err(Error::other)?;
+        if self.policy == FifoPolicy::Drop {
+            // Give up immediately if FIFO is full.
+            writer.set_timeout(Duration::ZERO).map_err(Error::other)?;
+        }
+        self.writer = Some(writer);
+        Ok(())
+    }
+}
+
+impl Write for FifoWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        loop {
+            // Check whether reader has appeared.
+            if self.writer.is_none() {
+                match (self.reopen(), self.policy) {
+                    (Ok(()), _) => {}
+                    (Err(err), FifoPolicy::Drop) if is_no_reader(&err) => return Ok(buf.len()),
+                    (Err(err), FifoPolicy::Wait) if is_no_reader(&err) => {
+                        thread::sleep(FIFO_POLL_INTERVAL);
+                        continue;
+                    }
+                    (Err(err), _) => return Err(err),
+                }
+            }
+
+            let writer = self.writer.as_ref().unwrap();
+            return match (writer.write_imp(buf), self.policy) {
+                (Ok(size), _) => Ok(size),
+                // Reader went away, wait for next one.
+                (Err(err), FifoPolicy::Wait | FifoPolicy::Drop)
+                    if err.kind() == ErrorKind::BrokenPipe =>
+                {
+                    self.writer = None;
+                    continue;
+                }
+                // FIFO is full.
+                (Err(err), FifoPolicy::Drop) if err.kind() == ErrorKind::TimedOut => Ok(buf.len()),
+                (Err(err), _) => Err(err),
+            };
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn is_no_reader(err: &Error) -> bool {
+    err.raw_os_error() == Some(libc::ENXIO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn read_pipe(pipe_rd: &OwnedFd) -> Vec<u8> {
+        shim::fcntl_nonblock(pipe_rd, true).unwrap();
+        let mut result = Vec::new();
+        let mut buf = [0u8; 65536];
+        while let Ok(size) = shim::read(pipe_rd, &mut buf) {
+            if size == 0 {
+                break;
+            }
+            result.extend_from_slice(&buf[..size]);
+        }
+        result
+    }
+
+    #[test]
+    fn close_discards_later_writes() {
+        let (pipe_rd, pipe_wr) = pipe::pipe().unwrap();
+        let writer = Arc::new(InterruptibleWriter::open(pipe_wr).unwrap());
+
+        let mut output = writer.blocking_writer();
+        output.write_all(b"first\n").unwrap();
+        output.write_all(b"second\n").unwrap();
+        writer.close().unwrap();
+        output.write_all(b"third\n").unwrap();
+
+        assert_eq!(read_pipe(&pipe_rd), b"first\nsecond\n");
+    }
+
+    #[test]
+    fn close_aborts_blocked_write() {
+        let (pipe_rd, pipe_wr) = pipe::pipe().unwrap();
+        let writer = Arc::new(InterruptibleWriter::open(pipe_wr).unwrap());
+
+        // Nobody reads pipe, so write blocks when it's full.
+        let thread = thread::spawn({
+            let writer = writer.clone();
+            move || writer.blocking_writer().write_all(&vec![b'x'; 1 << 20])
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert!(!thread.is_finished());
+
+        writer.close().unwrap();
+        thread.join().unwrap().unwrap();
+
+        // Only part of data that fit into pipe was written.
+        let mut data = Vec::new();
+        drop(writer);
+        File::from(pipe_rd).read_to_end(&mut data).unwrap();
+        assert!(!data.is_empty() && data.len() < 1 << 20);
+    }
+
+    #[test]
+    fn timeout_on_stalled_write() {
+        let (_pipe_rd, pipe_wr) = pipe::pipe().unwrap();
+        let writer = Arc::new(InterruptibleWriter::open(pipe_wr).unwrap());
+        writer.set_timeout(Duration::from_millis(10)).unwrap();
+
+        let err = writer
+            .blocking_writer()
+            .write_all(&vec![b'x'; 1 << 20])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,18 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Create empty temporary directory, unique for test and process.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("reclog-{}-{}", name, std::process::id()));
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Command running reclog under test, isolated from user's config.
+pub fn reclog() -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_reclog"));
+    cmd.arg("--no-config").stdin(Stdio::null());
+    cmd
+}
@@ -0,0 +1,114 @@
+//! Subcommands are recognized only by the first argument, so commands with
+//! the same names can be recorded after "--" or any option. Subcommands
+//! don't clash with common programs, so those are recorded as is.
+
+mod common;
+
+use common::{reclog, temp_dir};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Create executable "list" in given directory, named like subcommand.
+fn list_program(dir: &Path) -> String {
+    let path = dir.join("list");
+    fs::write(&path, "#!/bin/sh\necho listed\n").unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default())
+}
+
+#[test]
+fn common_program_first() {
+    let dir = temp_dir("dispatch-program");
+    let input = dir.join("input.txt");
+    let out_path = dir.join("out.log");
+    fs::write(&input, "first\nsecond\n").unwrap();
+
+    for (args, expected) in [
+        (&["tail", "-n", "1"][..], "second\n"),
+        (&["cut", "-c", "1-3"][..], "fir\nsec\n"),
+    ] {
+        let result = Command::new(env!("CARGO_BIN_EXE_reclog"))
+            .args(["--no-config", "--force", "--output"])
+            .arg(&out_path)
+            .args(args)
+            .arg(&input)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{:?}", result);
+        assert_eq!(fs::read_to_string(&out_path).unwrap(), expected);
+    }
+
+    // Without any options too.
+    let result = Command::new(env!("CARGO_BIN_EXE_reclog"))
+        .args(["tail", "-n", "1"])
+        .arg(&input)
+        .current_dir(&dir)
+        .env("RECLOG_NO_CONFIG", "1")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{:?}", result);
+    assert!(
+        String::from_utf8_lossy(&result.stdout).contains("second"),
+        "{:?}",
+        result
+    );
+
+    _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn subcommand_name_after_separator() {
+    let dir = temp_dir("dispatch-separator");
+    let out_path = dir.join("out.log");
+    let path = list_program(&dir);
+
+    let result = reclog()
+        .arg("--output")
+        .arg(&out_path)
+        .args(["--", "list"])
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(fs::read_to_string(&out_path).unwrap(), "listed\n");
+
+    _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn subcommand_name_after_option() {
+    let dir = temp_dir("dispatch-option");
+    let out_path = dir.join("out.log");
+    let path = list_program(&dir);
+
+    let result = reclog()
+        .arg("--output")
+        .arg(&out_path)
+        .arg("list")
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(result.status.success(), "{:?}", result);
+    assert_eq!(fs::read_to_string(&out_path).unwrap(), "listed\n");
+
+    _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn subcommand_name_first() {
+    for name in ["attach", "slice"] {
+        let result = Command::new(env!("CARGO_BIN_EXE_reclog"))
+            .args([name, "--help"])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(result.status.success(), "{:?}", result);
+        let help = String::from_utf8(result.stdout).unwrap();
+        assert!(help.contains(&format!("reclog {}", name)), "{}", help);
+    }
+}
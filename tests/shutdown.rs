@@ -0,0 +1,119 @@
+//! Order of shutdown steps: command output is flushed first, then footer
+//! with exit status is written, and sinks are finalized last.
+
+mod common;
+
+use common::{reclog, temp_dir};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn assert_footer_last(text: &str, status: &str) {
+    let lines: Vec<&str> = text.lines().collect();
+    let footer = lines
+        .iter()
+        .position(|line| line.starts_with("# TIME="))
+        .unwrap();
+    assert_eq!(footer, lines.len() - 1, "footer is not last:\n{}", text);
+    assert!(
+        lines[footer].contains(&format!("STATUS=[{}]", status)),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn output_before_footer() {
+    let dir = temp_dir("output-before-footer");
+    let out_path = dir.join("out.log");
+    let sum_path = dir.join("out.sha256");
+
+    let result = reclog()
+        .arg("--footer")
+        .arg("-o")
+        .arg(&out_path)
+        .arg("--checksum")
+        .arg(&sum_path)
+        .args(["--", "sh", "-c", "seq 1 5000; echo last; exit 3"])
+        .output()
+        .unwrap();
+    assert_eq!(result.status.code(), Some(3));
+
+    // Stdout got all output, then footer.
+    let stdout = String::from_utf8(result.stdout).unwrap().replace('\r', "");
+    assert!(stdout.contains("\n5000\nlast\n# TIME="), "{}", stdout);
+    assert_footer_last(&stdout, "3");
+
+    // File got all output, then footer.
+    let file = fs::read(&out_path).unwrap();
+    let text = String::from_utf8(file.clone()).unwrap();
+    assert!(text.starts_with("1\n2\n"), "{}", text);
+    assert!(text.contains("\n5000\nlast\n# TIME="), "{}", text);
+    assert_footer_last(&text, "3");
+
+    // Checksum was computed after file was complete.
+    let digest = format!("{:x}", Sha256::digest(&file));
+    let sum = fs::read_to_string(&sum_path).unwrap();
+    assert!(sum.starts_with(&digest), "{} != {}", sum, digest);
+
+    _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn stalled_stdout_interrupted() {
+    let dir = temp_dir("stalled-stdout");
+    let out_path = dir.join("out.log");
+
+    // Stdout is a pipe that nobody reads, so it stalls after pipe buffer
+    // is filled.
+    let (pipe_rd, pipe_wr) = rustix::pipe::pipe().unwrap();
+    let mut child = reclog()
+        .arg("--footer")
+        .arg("-o")
+        .arg(&out_path)
+        .args(["--", "sh", "-c", "seq 1 200000; echo last"])
+        .stdout(Stdio::from(pipe_wr))
+        .spawn()
+        .unwrap();
+
+    // Wait until command finishes and file is complete, while stdout
+    // is still stalled.
+    let deadline = Instant::now() + Duration::from_secs(30);
+    loop {
+        let text = fs::read_to_string(&out_path).unwrap_or_default();
+        if text.contains("# TIME=") {
+            assert!(text.contains("\n200000\nlast\n# TIME="), "{}", text);
+            assert_footer_last(&text, "0");
+            break;
+        }
+        assert!(Instant::now() < deadline, "footer not written");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert!(
+        child.try_wait().unwrap().is_none(),
+        "exited before stdout drained"
+    );
+
+    // Interrupt aborts blocked write to stdout.
+    unsafe { libc::kill(child.id() as i32, libc::SIGINT) };
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        assert!(Instant::now() < deadline, "stalled write not aborted");
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert!(!status.success());
+
+    // Stdout got beginning of output, but not footer.
+    let mut stdout = String::new();
+    fs::File::from(pipe_rd).read_to_string(&mut stdout).unwrap();
+    assert!(stdout.starts_with("1\r\n2\r\n"));
+    assert!(!stdout.contains("# TIME="));
+
+    _ = fs::remove_dir_all(&dir);
+}